@@ -1,6 +1,6 @@
 use debshrew_runtime::*;
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct MyTransform {
     // State fields
 }
@@ -19,6 +19,7 @@ impl DebTransform for MyTransform {
                 block_height: height,
                 block_hash: hex::encode(&hash),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -53,6 +54,7 @@ impl DebTransform for MyTransform {
                     block_height: height,
                     block_hash: hex::encode(&hash),
                     transaction_id: Some(transaction.id),
+                    event_id: None,
                 },
                 payload: CdcPayload {
                     operation: CdcOperation::Create,
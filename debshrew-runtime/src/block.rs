@@ -0,0 +1,220 @@
+//! Bitcoin block parsing for debshrew
+//!
+//! This module extracts the transaction IDs from a raw serialized Bitcoin
+//! block. Transaction IDs are always returned in block order (the order the
+//! transactions appear in the block, coinbase first), so transforms that
+//! iterate over them produce the same CDC messages on every run.
+
+use crate::error::Result;
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+
+/// The size of a serialized block header
+const BLOCK_HEADER_SIZE: u64 = 80;
+
+/// Get the IDs of the transactions in a raw block, in block order
+///
+/// Each ID is the double SHA-256 of the transaction without witness data,
+/// in the byte order used when displaying txids (reversed from the hash
+/// output), so `hex::encode` gives the familiar txid string.
+///
+/// # Arguments
+///
+/// * `raw_block` - The serialized block
+///
+/// # Returns
+///
+/// The transaction IDs, coinbase first
+///
+/// # Errors
+///
+/// Returns an error if the block is truncated or malformed
+pub fn transaction_ids(raw_block: &[u8]) -> Result<Vec<[u8; 32]>> {
+    let mut reader = Reader::new(raw_block);
+    reader.take(BLOCK_HEADER_SIZE)?;
+    
+    let count = reader.read_varint()?;
+    let mut txids = Vec::new();
+    for _ in 0..count {
+        txids.push(read_transaction_id(&mut reader)?);
+    }
+    
+    if !reader.is_empty() {
+        return Err(anyhow!("Unexpected trailing data after {} transactions", count));
+    }
+    
+    Ok(txids)
+}
+
+/// Read one transaction and compute its ID
+///
+/// # Arguments
+///
+/// * `reader` - The reader positioned at the start of the transaction
+///
+/// # Returns
+///
+/// The transaction ID in display byte order
+///
+/// # Errors
+///
+/// Returns an error if the transaction is truncated or malformed
+fn read_transaction_id(reader: &mut Reader<'_>) -> Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    hasher.update(reader.take(4)?);
+    
+    // A zero input count is the segwit marker, followed by the flag byte
+    let segwit = reader.peek()? == 0;
+    if segwit {
+        reader.take(2)?;
+    }
+    
+    // Inputs and outputs are hashed as-is; witness data is skipped
+    let body_start = reader.position();
+    let inputs = reader.read_varint()?;
+    for _ in 0..inputs {
+        reader.take(36)?;
+        let script_len = reader.read_varint()?;
+        reader.take(script_len)?;
+        reader.take(4)?;
+    }
+    
+    let outputs = reader.read_varint()?;
+    for _ in 0..outputs {
+        reader.take(8)?;
+        let script_len = reader.read_varint()?;
+        reader.take(script_len)?;
+    }
+    hasher.update(reader.since(body_start));
+    
+    if segwit {
+        for _ in 0..inputs {
+            let items = reader.read_varint()?;
+            for _ in 0..items {
+                let item_len = reader.read_varint()?;
+                reader.take(item_len)?;
+            }
+        }
+    }
+    
+    hasher.update(reader.take(4)?);
+    
+    let mut txid: [u8; 32] = Sha256::digest(hasher.finalize()).into();
+    txid.reverse();
+    Ok(txid)
+}
+
+/// A cursor over serialized block data
+struct Reader<'a> {
+    /// The data being read
+    data: &'a [u8],
+    
+    /// The offset of the next unread byte
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Create a reader at the start of the data
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+    
+    /// Get the offset of the next unread byte
+    fn position(&self) -> usize {
+        self.offset
+    }
+    
+    /// Check if all data has been read
+    fn is_empty(&self) -> bool {
+        self.offset == self.data.len()
+    }
+    
+    /// Get the data read since an earlier position
+    fn since(&self, start: usize) -> &'a [u8] {
+        &self.data[start..self.offset]
+    }
+    
+    /// Get the next byte without consuming it
+    fn peek(&self) -> Result<u8> {
+        self.data.get(self.offset)
+            .copied()
+            .ok_or_else(|| anyhow!("Unexpected end of block data at offset {}", self.offset))
+    }
+    
+    /// Consume the next `len` bytes
+    fn take(&mut self, len: u64) -> Result<&'a [u8]> {
+        let end = usize::try_from(len).ok()
+            .and_then(|len| self.offset.checked_add(len))
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| anyhow!("Unexpected end of block data reading {} bytes at offset {}", len, self.offset))?;
+        
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+    
+    /// Consume a Bitcoin compact-size integer
+    fn read_varint(&mut self) -> Result<u64> {
+        let prefix = self.take(1)?[0];
+        let width = match prefix {
+            0xfd => 2,
+            0xfe => 4,
+            0xff => 8,
+            _ => return Ok(prefix as u64),
+        };
+        
+        let mut bytes = [0u8; 8];
+        bytes[..width].copy_from_slice(self.take(width as u64)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// The genesis block header
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+    
+    /// The genesis coinbase transaction
+    const COINBASE_TX: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+    
+    /// A segwit transaction with a two-item witness
+    const SEGWIT_TX: &str = "0200000000010111111111111111111111111111111111111111111111111111111111111111110100000000fffffffe01e80300000000000001510202abcd01ef00000000";
+    
+    /// A legacy transaction spending the genesis coinbase
+    const LEGACY_TX: &str = "01000000013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a0000000000ffffffff010100000000000000015100000000";
+    
+    fn raw_block(transactions: &[&str]) -> Vec<u8> {
+        let mut block = hex::decode(GENESIS_HEADER).unwrap();
+        block.push(transactions.len() as u8);
+        for tx in transactions {
+            block.extend(hex::decode(tx).unwrap());
+        }
+        block
+    }
+    
+    #[test]
+    fn test_transaction_ids_in_block_order() {
+        let block = raw_block(&[COINBASE_TX, SEGWIT_TX, LEGACY_TX]);
+        
+        let txids: Vec<String> = transaction_ids(&block).unwrap().iter().map(hex::encode).collect();
+        assert_eq!(txids, vec![
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b".to_string(),
+            "9443ae87bd713d4b39d8b4cb5099608db35a52117e5f281f1ead29e1600fd81f".to_string(),
+            "b37b7e816bbb4b71d08696a3dbad07eb08fe5fc4c5a273e8c464da5f28128766".to_string(),
+        ]);
+        
+        // The same block always yields the same order
+        for _ in 0..3 {
+            assert_eq!(transaction_ids(&block).unwrap().iter().map(hex::encode).collect::<Vec<_>>(), txids);
+        }
+    }
+    
+    #[test]
+    fn test_truncated_block_is_rejected() {
+        let block = raw_block(&[COINBASE_TX, SEGWIT_TX]);
+        assert!(transaction_ids(&block[..block.len() - 1]).is_err());
+        assert!(transaction_ids(&block[..40]).is_err());
+    }
+}
\ No newline at end of file
@@ -19,9 +19,15 @@ pub enum Error {
     /// State error
     State(String),
     
+    /// Out-of-range WASM memory access
+    MemoryAllocation(String),
+    
     /// CDC message error
     CdcMessage(String),
     
+    /// Transform logic error
+    Transform(String),
+    
     /// Other error
     Other(String),
 }
@@ -33,7 +39,9 @@ impl fmt::Display for Error {
             Error::ViewAccess(msg) => write!(f, "View access error: {}", msg),
             Error::Serialization(msg) => write!(f, "Serialization error: {}", msg),
             Error::State(msg) => write!(f, "State error: {}", msg),
+            Error::MemoryAllocation(msg) => write!(f, "Memory allocation error: {}", msg),
             Error::CdcMessage(msg) => write!(f, "CDC message error: {}", msg),
+            Error::Transform(msg) => write!(f, "Transform error: {}", msg),
             Error::Other(msg) => write!(f, "{}", msg),
         }
     }
@@ -1,6 +1,8 @@
 //! WASM export functions for debshrew-runtime
 //!
 //! This module provides utility functions for exporting data from WASM modules.
+//! Every length prefix passed between the guest and the host is a
+//! little-endian u32, whatever the byte order of the machine.
 
 /// Convert a byte slice to an ArrayBuffer layout (length prefix + data)
 ///
@@ -14,6 +16,35 @@ pub fn to_arraybuffer_layout<T: AsRef<[u8]>>(v: T) -> Vec<u8> {
     buffer
 }
 
+/// Decode the length prefix of an ArrayBuffer layout
+///
+/// # Arguments
+///
+/// * `prefix` - The first four bytes of the layout
+///
+/// # Returns
+///
+/// The length of the data following the prefix
+pub fn arraybuffer_len(prefix: [u8; 4]) -> usize {
+    u32::from_le_bytes(prefix) as usize
+}
+
+/// Get the data of an ArrayBuffer layout
+///
+/// # Arguments
+///
+/// * `buffer` - The layout, starting with its length prefix
+///
+/// # Returns
+///
+/// The data following the prefix, or None if the buffer is shorter than its
+/// prefix says
+pub fn from_arraybuffer_layout(buffer: &[u8]) -> Option<&[u8]> {
+    let prefix = buffer.get(..4)?.try_into().ok()?;
+    let end = arraybuffer_len(prefix).checked_add(4)?;
+    buffer.get(4..end)
+}
+
 /// Export bytes to the host environment
 ///
 /// This function leaks memory, but that's okay because the host will
@@ -29,4 +60,26 @@ pub fn to_arraybuffer_layout<T: AsRef<[u8]>>(v: T) -> Vec<u8> {
 pub fn export_bytes(v: Vec<u8>) -> i32 {
     let response: Vec<u8> = to_arraybuffer_layout(&v);
     Box::leak(Box::new(response)).as_mut_ptr() as usize as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_arraybuffer_layout_round_trip() {
+        for data in [&b""[..], b"balance", &[0xab; 300]] {
+            let encoded = to_arraybuffer_layout(data);
+            assert_eq!(from_arraybuffer_layout(&encoded), Some(data));
+        }
+        
+        // The prefix is little-endian on every host
+        let encoded = to_arraybuffer_layout([0u8; 0x0102]);
+        assert_eq!(encoded[..4], [0x02, 0x01, 0x00, 0x00]);
+        assert_eq!(arraybuffer_len([0x02, 0x01, 0x00, 0x00]), 0x0102);
+        
+        // A prefix claiming more data than the buffer holds is rejected
+        assert_eq!(from_arraybuffer_layout(&encoded[..100]), None);
+        assert_eq!(from_arraybuffer_layout(&[1, 0]), None);
+    }
 }
\ No newline at end of file
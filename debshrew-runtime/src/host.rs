@@ -20,6 +20,11 @@ thread_local! {
     static CURRENT_HASH: RefCell<Vec<u8>> = RefCell::new(Vec::new());
 }
 
+// Thread-local storage for the previous block hash
+thread_local! {
+    static PREV_HASH: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+}
+
 // Thread-local storage for the transform state
 thread_local! {
     static TRANSFORM_STATE: RefCell<HashMap<Vec<u8>, Vec<u8>>> = RefCell::new(HashMap::new());
@@ -97,6 +102,41 @@ pub fn get_block_hash() -> Vec<u8> {
     CURRENT_HASH.with(|h| h.borrow().clone())
 }
 
+/// Set the previous block hash
+///
+/// This function is called by the runtime to set the hash of the parent of
+/// the current block before executing a transform module.
+///
+/// # Arguments
+///
+/// * `hash` - The previous block hash, empty at the genesis block
+pub fn set_prev_hash(hash: Vec<u8>) {
+    PREV_HASH.with(|h| {
+        *h.borrow_mut() = hash;
+    });
+}
+
+/// Get the previous block hash
+///
+/// This function is called by transform modules to get the hash of the
+/// parent of the current block.
+///
+/// # Returns
+///
+/// The previous block hash, empty at the genesis block
+///
+/// # Examples
+///
+/// ```
+/// use debshrew_runtime::host;
+///
+/// host::set_prev_hash(vec![4, 5, 6]);
+/// assert_eq!(host::get_prev_block_hash(), vec![4, 5, 6]);
+/// ```
+pub fn get_prev_block_hash() -> Vec<u8> {
+    PREV_HASH.with(|h| h.borrow().clone())
+}
+
 /// Set a value in the transform state
 ///
 /// This function is called by transform modules to set a value in the transform state.
@@ -450,6 +490,9 @@ mod tests {
 
         set_current_hash(vec![1, 2, 3]);
         assert_eq!(get_block_hash(), vec![1, 2, 3]);
+
+        set_prev_hash(vec![4, 5, 6]);
+        assert_eq!(get_prev_block_hash(), vec![4, 5, 6]);
     }
 
     #[test]
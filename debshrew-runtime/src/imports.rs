@@ -7,11 +7,19 @@
 extern "C" {
     pub fn __load(output: i32);
     pub fn __view(view_name: i32, input: i32) -> i32;
+    pub fn __view_status(view_name: i32, input: i32, length: i32) -> i32;
     pub fn __stdout(s: i32);
     pub fn __stderr(s: i32);
     pub fn __height() -> i32;
     pub fn __block_hash() -> i32;
+    pub fn __block_hash_status(length: i32) -> i32;
+    pub fn __prev_block_hash_status(length: i32) -> i32;
+    pub fn __get_block_raw() -> i32;
+    pub fn __get_block_txids() -> i32;
     pub fn __push_cdc_message(msg: i32) -> i32;
+    pub fn __mark_snapshot_complete(table: i32) -> i32;
+    pub fn __warn(message: i32) -> i32;
+    pub fn __request_retry(delay_ms: i32, reason: i32) -> i32;
     pub fn __get_state(key: i32) -> i32;
     pub fn __set_state(key: i32, value: i32) -> i32;
     pub fn __delete_state(key: i32) -> i32;
@@ -21,13 +29,67 @@ extern "C" {
 pub mod exports {
     // Test implementation
     
+    use debshrew_support::TransformState;
+    use std::cell::{Cell, RefCell};
+    
+    thread_local! {
+        /// Number of CDC messages pushed to the test host on this thread
+        static PUSHED_MESSAGES: Cell<usize> = const { Cell::new(0) };
+        
+        /// Transform state held by the test host on this thread
+        static STATE: RefCell<TransformState> = RefCell::new(TransformState::new());
+        
+        /// Status returned by `__view` on this thread
+        static VIEW_STATUS: Cell<i32> = const { Cell::new(0) };
+    }
+    
+    /// Set the status the test host returns from `__view` on this thread
+    pub fn set_view_status(status: i32) {
+        VIEW_STATUS.with(|view_status| view_status.set(status));
+    }
+    
+    /// Get the number of CDC messages pushed to the test host on this thread
+    pub fn pushed_message_count() -> usize {
+        PUSHED_MESSAGES.with(|count| count.get())
+    }
+    
+    /// Get a value from the test host's state
+    pub fn host_get_state(key: &[u8]) -> Option<Vec<u8>> {
+        STATE.with(|state| state.borrow().get(key).cloned())
+    }
+    
+    /// Set a value in the test host's state
+    pub fn host_set_state(key: &[u8], value: &[u8]) {
+        STATE.with(|state| state.borrow_mut().set(key.to_vec(), value.to_vec()));
+    }
+    
+    /// Delete a value from the test host's state
+    pub fn host_delete_state(key: &[u8]) -> bool {
+        STATE.with(|state| state.borrow_mut().delete(key))
+    }
+    
+    /// Snapshot the test host's state, as the host does after each block
+    pub fn host_state_snapshot() -> TransformState {
+        STATE.with(|state| state.borrow().clone())
+    }
+    
+    /// Replace the test host's state, as the host does when rolling back a reorg
+    pub fn host_restore_state(snapshot: TransformState) {
+        STATE.with(|state| *state.borrow_mut() = snapshot);
+    }
+    
     pub fn __load(_output: i32) {
         // Test implementation
     }
     
     pub fn __view(_view_name: i32, _input: i32) -> i32 {
         // Test implementation
-        0
+        VIEW_STATUS.with(|status| status.get())
+    }
+    
+    pub fn __view_status(_view_name: i32, _input: i32, _length: i32) -> i32 {
+        // Test implementation; the length is left at zero
+        VIEW_STATUS.with(|status| status.get())
     }
     
     pub fn __stdout(_s: i32) {
@@ -48,7 +110,43 @@ pub mod exports {
         0
     }
     
+    pub fn __block_hash_status(_length: i32) -> i32 {
+        // Test implementation; the length is left at zero
+        0
+    }
+    
+    pub fn __prev_block_hash_status(_length: i32) -> i32 {
+        // Test implementation; the length is left at zero
+        0
+    }
+    
+    pub fn __get_block_raw() -> i32 {
+        // Test implementation
+        0
+    }
+    
+    pub fn __get_block_txids() -> i32 {
+        // Test implementation
+        0
+    }
+    
     pub fn __push_cdc_message(_msg: i32) -> i32 {
+        // Test implementation
+        PUSHED_MESSAGES.with(|count| count.set(count.get() + 1));
+        0
+    }
+    
+    pub fn __mark_snapshot_complete(_table: i32) -> i32 {
+        // Test implementation
+        0
+    }
+    
+    pub fn __warn(_message: i32) -> i32 {
+        // Test implementation
+        0
+    }
+    
+    pub fn __request_retry(_delay_ms: i32, _reason: i32) -> i32 {
         // Test implementation
         0
     }
@@ -73,11 +171,14 @@ pub mod exports {
 pub use exports::*;
 
 /// Convert a pointer to a Vec<u8>
+///
+/// The pointer must point to an ArrayBuffer layout, whose length prefix is
+/// little-endian.
 pub fn ptr_to_vec(ptr: i32) -> Vec<u8> {
     unsafe {
         // First read the length (4 bytes)
         let p = ptr as *const u8;
-        let len = u32::from_le_bytes([*p, *p.offset(1), *p.offset(2), *p.offset(3)]) as usize;
+        let len = crate::exports::arraybuffer_len([*p, *p.offset(1), *p.offset(2), *p.offset(3)]);
 
         // Then read the actual data
         let mut result = Vec::with_capacity(len);
@@ -3,43 +3,155 @@
 //! This crate provides the runtime environment for debshrew transform modules,
 //! including the WASM host interface, transform traits, and CDC message generation.
 
+pub mod block;
 pub mod exports;
 pub mod imports;
 pub mod transform;
+pub mod view_cache;
 pub mod wasm;
 pub mod error;
 
 pub use crate::transform::{DebTransform, TransformResult};
 pub use crate::error::{Error, Result};
+pub use crate::view_cache::{ViewCache, ViewCacheStats};
 pub use crate::wasm::WasmRuntime;
+#[cfg(feature = "host")]
+pub use crate::wasm::{BlockFetcher, RollbackMode, ViewFetcher, WasmSource, HOST_FUNCTIONS};
 pub use anyhow;
 pub use debshrew_support::{CdcMessage, CdcHeader, CdcOperation, CdcPayload, TransformState};
 pub use serde::{Serialize, Deserialize};
 pub use serde_json;
 
-/// Safe wrapper for calling a view and loading its result
-pub fn view(view_name: String, input: Vec<u8>) -> Result<Vec<u8>> {
-    // Encode view name with length prefix
-    let name_bytes = view_name.as_bytes();
-    let mut encoded_name = Vec::with_capacity(4 + name_bytes.len());
-    encoded_name.extend_from_slice(&(name_bytes.len() as u32).to_le_bytes());
-    encoded_name.extend_from_slice(name_bytes);
-
-    // Encode input with length prefix
-    let mut encoded_input = Vec::with_capacity(4 + input.len());
-    encoded_input.extend_from_slice(&(input.len() as u32).to_ne_bytes());
-    encoded_input.extend_from_slice(&input);
-
-    let length = unsafe { imports::__view(encoded_name.as_ptr() as i32, encoded_input.as_ptr() as i32) };
-    if length <= 0 {
-        return Err(anyhow::anyhow!("View call failed with length {}", length));
+use std::cell::RefCell;
+
+/// Reserved state key under which `declare_transform!` stores the transform
+///
+/// The transform struct is serialized as JSON under this key on every save,
+/// so it is part of the host's state snapshots and a reorg restores it along
+/// with the rest of the state.
+pub const TRANSFORM_INSTANCE_KEY: &[u8] = b"__debshrew:transform";
+
+/// A host call held back until the current transform call commits
+enum StagedCall {
+    /// Push a CDC message
+    Message(Box<CdcMessage>),
+    
+    /// Mark the initial snapshot of a table as complete
+    SnapshotComplete(String),
+}
+
+thread_local! {
+    /// Host calls staged by the current transform call, in order
+    static STAGED_CALLS: RefCell<Vec<StagedCall>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Status returned by `__view_status` and `__block_hash_status` on success
+///
+/// The length of the staged result, which may be zero, is written separately
+/// as a little-endian u64, so an empty result is never mistaken for an error.
+pub const VIEW_OK: i32 = 0;
+
+/// Status returned by `__view` when no view function has the requested name
+pub const VIEW_NOT_REGISTERED: i32 = -1;
+
+/// Status returned by `__view` when the view function returned an error
+pub const VIEW_FAILED: i32 = -2;
+
+/// Status returned by `__view` when the view function did not answer within
+/// the host's view timeout
+pub const VIEW_TIMED_OUT: i32 = -3;
+
+/// The number of parameter bytes shown in view call errors
+const VIEW_PARAMS_SUMMARY_LEN: usize = 32;
+
+/// Summarize view parameters for an error message
+///
+/// # Arguments
+///
+/// * `params` - The serialized view parameters
+///
+/// # Returns
+///
+/// The hex-encoded parameters, truncated with their total length if long
+fn summarize_params(params: &[u8]) -> String {
+    if params.len() <= VIEW_PARAMS_SUMMARY_LEN {
+        return format!("0x{}", hex::encode(params));
     }
     
-    let mut buffer = vec![0u8; length as usize];
-    unsafe { imports::__load(buffer.as_mut_ptr() as i32) };
+    format!("0x{}... ({} bytes)", hex::encode(&params[..VIEW_PARAMS_SUMMARY_LEN]), params.len())
+}
+
+/// Convert the length of a staged host result to a buffer size
+///
+/// # Arguments
+///
+/// * `length` - The length reported by the host
+///
+/// # Returns
+///
+/// The length as a buffer size
+///
+/// # Errors
+///
+/// Returns an error if no buffer of that size can be allocated
+fn staged_len(length: u64) -> Result<usize> {
+    usize::try_from(length).ok()
+        .filter(|length| *length <= isize::MAX as usize)
+        .ok_or_else(|| anyhow::anyhow!("Result of {} bytes does not fit in memory", length))
+}
+
+/// Copy a result staged by the host into a new buffer
+///
+/// # Arguments
+///
+/// * `length` - The length of the staged result
+///
+/// # Returns
+///
+/// The staged result
+///
+/// # Errors
+///
+/// Returns an error if the result is too large to load
+fn load_staged(length: u64) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; staged_len(length)?];
+    if !buffer.is_empty() {
+        unsafe { imports::__load(buffer.as_mut_ptr() as i32) };
+    }
     Ok(buffer)
 }
 
+/// Safe wrapper for calling a view and loading its result
+///
+/// The host reports an explicit status and a u64 result length, so results
+/// of 2 GiB and more load normally and an empty result is returned as such.
+///
+/// # Errors
+///
+/// Returns an error naming the view and summarizing its parameters if the
+/// view is not registered, the view function fails or times out, or its
+/// result is too large to load
+pub fn view(view_name: String, input: Vec<u8>) -> Result<Vec<u8>> {
+    // Encode view name and input with little-endian length prefixes
+    let encoded_name = exports::to_arraybuffer_layout(view_name.as_bytes());
+    let encoded_input = exports::to_arraybuffer_layout(&input);
+
+    let mut length = [0u8; 8];
+    let status = unsafe {
+        imports::__view_status(encoded_name.as_ptr() as i32, encoded_input.as_ptr() as i32, length.as_mut_ptr() as i32)
+    };
+    
+    let params = || summarize_params(&input);
+    match status {
+        VIEW_OK => load_staged(u64::from_le_bytes(length))
+            .map_err(|e| anyhow::anyhow!("View {} failed to load: {} (params: {})", view_name, e, params())),
+        VIEW_NOT_REGISTERED => Err(anyhow::anyhow!("View {} is not registered (params: {})", view_name, params())),
+        VIEW_FAILED => Err(anyhow::anyhow!("View {} returned an error (params: {})", view_name, params())),
+        VIEW_TIMED_OUT => Err(anyhow::anyhow!("View {} timed out (params: {})", view_name, params())),
+        status => Err(anyhow::anyhow!("View {} failed with status {} (params: {})", view_name, status, params())),
+    }
+}
+
 /// Safe wrapper to get current block height
 pub fn get_height() -> u32 {
     unsafe { imports::__height() as u32 }
@@ -47,7 +159,33 @@ pub fn get_height() -> u32 {
 
 /// Safe wrapper to get current block hash
 pub fn get_block_hash() -> Vec<u8> {
-    let length = unsafe { imports::__block_hash() };
+    let mut length = [0u8; 8];
+    let status = unsafe { imports::__block_hash_status(length.as_mut_ptr() as i32) };
+    if status != VIEW_OK {
+        return Vec::new();
+    }
+    
+    load_staged(u64::from_le_bytes(length)).unwrap_or_default()
+}
+
+/// Safe wrapper to get the hash of the previous block
+///
+/// Returns an empty vector at the genesis block, which has no parent.
+pub fn get_prev_block_hash() -> Vec<u8> {
+    let mut length = [0u8; 8];
+    let status = unsafe { imports::__prev_block_hash_status(length.as_mut_ptr() as i32) };
+    if status != VIEW_OK {
+        return Vec::new();
+    }
+    
+    load_staged(u64::from_le_bytes(length)).unwrap_or_default()
+}
+
+/// Safe wrapper to get the raw bytes of the current block
+///
+/// Returns an empty vector if the host has no raw data for the block.
+pub fn get_block_raw() -> Vec<u8> {
+    let length = unsafe { imports::__get_block_raw() };
     if length <= 0 {
         return Vec::new();
     }
@@ -57,7 +195,26 @@ pub fn get_block_hash() -> Vec<u8> {
     buffer
 }
 
+/// Safe wrapper to get the transaction IDs of the current block
+///
+/// The IDs are in block order (coinbase first), so iterating over them is
+/// deterministic. Each ID is in display byte order. Returns an empty vector if
+/// the host has no raw data for the block or cannot parse it.
+pub fn get_block_txids() -> Vec<[u8; 32]> {
+    let length = unsafe { imports::__get_block_txids() };
+    if length <= 0 {
+        return Vec::new();
+    }
+    
+    let mut buffer = vec![0u8; length as usize];
+    unsafe { imports::__load(buffer.as_mut_ptr() as i32) };
+    buffer.chunks_exact(32)
+        .map(|txid| txid.try_into().expect("chunk is 32 bytes"))
+        .collect()
+}
+
 /// Get a value from the transform state
+#[cfg(not(feature = "test-utils"))]
 pub fn get_state(key: &[u8]) -> Option<Vec<u8>> {
     let encoded_key = exports::to_arraybuffer_layout(key);
     let length = unsafe { imports::__get_state(encoded_key.as_ptr() as i32) };
@@ -70,19 +227,39 @@ pub fn get_state(key: &[u8]) -> Option<Vec<u8>> {
     Some(buffer)
 }
 
+/// Get a value from the transform state held by the test host
+#[cfg(feature = "test-utils")]
+pub fn get_state(key: &[u8]) -> Option<Vec<u8>> {
+    imports::host_get_state(key)
+}
+
 /// Set a value in the transform state
+#[cfg(not(feature = "test-utils"))]
 pub fn set_state(key: &[u8], value: &[u8]) {
     let encoded_key = exports::to_arraybuffer_layout(key);
     let encoded_value = exports::to_arraybuffer_layout(value);
     unsafe { imports::__set_state(encoded_key.as_ptr() as i32, encoded_value.as_ptr() as i32) };
 }
 
+/// Set a value in the transform state held by the test host
+#[cfg(feature = "test-utils")]
+pub fn set_state(key: &[u8], value: &[u8]) {
+    imports::host_set_state(key, value);
+}
+
 /// Delete a value from the transform state
+#[cfg(not(feature = "test-utils"))]
 pub fn delete_state(key: &[u8]) -> bool {
     let encoded_key = exports::to_arraybuffer_layout(key);
     unsafe { imports::__delete_state(encoded_key.as_ptr() as i32) > 0 }
 }
 
+/// Delete a value from the transform state held by the test host
+#[cfg(feature = "test-utils")]
+pub fn delete_state(key: &[u8]) -> bool {
+    imports::host_delete_state(key)
+}
+
 /// Push a CDC message to the host
 pub fn push_cdc_message(message: &CdcMessage) -> Result<()> {
     let serialized = serde_json::to_vec(message)
@@ -98,6 +275,86 @@ pub fn push_cdc_message(message: &CdcMessage) -> Result<()> {
     Ok(())
 }
 
+/// Mark the initial snapshot of a table as complete
+///
+/// Emits a `snapshot_complete` control message for the table after any CDC
+/// messages already pushed for this block, so consumers know every row of the
+/// snapshot has been delivered.
+pub fn mark_snapshot_complete(table: &str) -> Result<()> {
+    let encoded = exports::to_arraybuffer_layout(table.as_bytes());
+    
+    let result = unsafe { imports::__mark_snapshot_complete(encoded.as_ptr() as i32) };
+    if result < 0 {
+        return Err(anyhow::anyhow!("Failed to mark snapshot complete for table {}", table));
+    }
+    
+    Ok(())
+}
+
+/// Report a warning to the host
+///
+/// The host logs the warning with the block it was reported for. Unlike an
+/// error, a warning does not stop the block from being processed.
+pub fn warn(message: &str) -> Result<()> {
+    let encoded = exports::to_arraybuffer_layout(message.as_bytes());
+    
+    let result = unsafe { imports::__warn(encoded.as_ptr() as i32) };
+    if result < 0 {
+        return Err(anyhow::anyhow!("Failed to report warning"));
+    }
+    
+    Ok(())
+}
+
+/// Ask the host to retry the current block after a delay
+///
+/// Use this when the block cannot be processed correctly yet, for example
+/// because a view is temporarily unavailable. The block is not committed:
+/// its CDC messages and state changes are discarded, and the host processes
+/// it again from the same state after `delay_ms` milliseconds. `reason` is
+/// logged as a warning.
+pub fn request_retry(delay_ms: u32, reason: &str) -> Result<()> {
+    let encoded = exports::to_arraybuffer_layout(reason.as_bytes());
+    
+    let result = unsafe { imports::__request_retry(delay_ms as i32, encoded.as_ptr() as i32) };
+    if result < 0 {
+        return Err(anyhow::anyhow!("Failed to request a retry after {} ms", delay_ms));
+    }
+    
+    Ok(())
+}
+
+/// Stage a CDC message to be pushed when the current transform call commits
+#[doc(hidden)]
+pub fn stage_cdc_message(message: CdcMessage) {
+    STAGED_CALLS.with(|staged| staged.borrow_mut().push(StagedCall::Message(Box::new(message))));
+}
+
+/// Stage a snapshot marker to be emitted when the current transform call commits
+#[doc(hidden)]
+pub fn stage_snapshot_complete(table: &str) {
+    STAGED_CALLS.with(|staged| staged.borrow_mut().push(StagedCall::SnapshotComplete(table.to_string())));
+}
+
+/// Commit or discard the host calls staged by the current transform call
+///
+/// Staged calls are made only if `outcome` is Ok, so a call whose state fails
+/// to save leaves no CDC messages behind. The staging buffer is always emptied.
+#[doc(hidden)]
+pub fn commit_staged(outcome: Result<()>) -> Result<()> {
+    let staged = STAGED_CALLS.with(|staged| staged.take());
+    outcome?;
+    
+    for call in staged {
+        match call {
+            StagedCall::Message(message) => push_cdc_message(&message)?,
+            StagedCall::SnapshotComplete(table) => mark_snapshot_complete(&table)?,
+        }
+    }
+    
+    Ok(())
+}
+
 /// Serialize parameters for a view function
 pub fn serialize_params<T: Serialize>(params: &T) -> Result<Vec<u8>> {
     serde_json::to_vec(params)
@@ -110,12 +367,47 @@ pub fn deserialize_result<T: for<'de> Deserialize<'de>>(result: &[u8]) -> Result
         .map_err(|e| anyhow::anyhow!("Failed to deserialize result: {}", e))
 }
 
+/// Add an amount to a balance, failing instead of wrapping on overflow
+///
+/// # Arguments
+///
+/// * `balance` - The current balance
+/// * `amount` - The amount to add
+///
+/// # Returns
+///
+/// The new balance
+///
+/// # Errors
+///
+/// Returns `Error::Transform` if the new balance does not fit in a u64
+pub fn checked_add_balance(balance: u64, amount: u64) -> Result<u64> {
+    balance.checked_add(amount)
+        .ok_or_else(|| Error::Transform(format!("Balance overflow adding {} to {}", amount, balance)).into())
+}
+
+/// Subtract an amount from a balance, failing instead of wrapping on underflow
+///
+/// # Arguments
+///
+/// * `balance` - The current balance
+/// * `amount` - The amount to subtract
+///
+/// # Returns
+///
+/// The new balance
+///
+/// # Errors
+///
+/// Returns `Error::Transform` if the amount is larger than the balance
+pub fn checked_sub_balance(balance: u64, amount: u64) -> Result<u64> {
+    balance.checked_sub(amount)
+        .ok_or_else(|| Error::Transform(format!("Balance underflow subtracting {} from {}", amount, balance)).into())
+}
+
 /// Safely write to stdout
 pub fn write_stdout(msg: &str) {
-    let bytes = msg.as_bytes();
-    let mut encoded = Vec::with_capacity(4 + bytes.len());
-    encoded.extend_from_slice(&(bytes.len() as u32).to_ne_bytes());
-    encoded.extend_from_slice(bytes);
+    let encoded = exports::to_arraybuffer_layout(msg.as_bytes());
     unsafe {
         imports::__stdout(encoded.as_ptr() as i32);
     }
@@ -123,10 +415,7 @@ pub fn write_stdout(msg: &str) {
 
 /// Safely write to stderr
 pub fn write_stderr(msg: &str) {
-    let bytes = msg.as_bytes();
-    let mut encoded = Vec::with_capacity(4 + bytes.len());
-    encoded.extend_from_slice(&(bytes.len() as u32).to_ne_bytes());
-    encoded.extend_from_slice(bytes);
+    let encoded = exports::to_arraybuffer_layout(msg.as_bytes());
     unsafe {
         imports::__stderr(encoded.as_ptr() as i32);
     }
@@ -160,105 +449,312 @@ macro_rules! eprint {
     }};
 }
 
-/// Declare a transform module
+/// Declare a transform module whose rollbacks are handled by the host
+///
+/// Like [`declare_transform!`], but without the `rollback` export. On a reorg
+/// the host inverts the CDC messages it cached for the rolled-back blocks, so
+/// a transform that only pushes row changes gets correct reorg handling
+/// without implementing `rollback`; an implementation it does have is never
+/// called.
 ///
 /// This macro generates the necessary WASM exports for a transform module.
+/// The transform type must implement `Serialize` and `Deserialize`: `save`
+/// stores it in the transform state under [`TRANSFORM_INSTANCE_KEY`] and
+/// `load` reads it back, so its fields are rolled back with the state on a
+/// reorg. CDC messages pushed with `push_message` are held back until the
+/// call has saved its state, so a block either commits all of its messages
+/// or none.
 #[macro_export]
-macro_rules! declare_transform {
+macro_rules! declare_transform_auto_rollback {
     ($transform:ty) => {
-        use debshrew_runtime::push_cdc_message;
-        use debshrew_runtime::Result;
+        use $crate::Result;
         
-        static mut INSTANCE: Option<$transform> = None;
-
         impl $transform {
             pub fn save(&self) -> Result<()> {
-                unsafe {
-                    INSTANCE = Some(self.clone());
-                    Ok(())
-                }
+                let encoded = $crate::serde_json::to_vec(self)
+                    .map_err(|e| $crate::anyhow::anyhow!("Failed to serialize transform: {}", e))?;
+                $crate::set_state($crate::TRANSFORM_INSTANCE_KEY, &encoded);
+                Ok(())
             }
 
             pub fn load() -> Result<Self> {
-                unsafe {
-                    if let Some(instance) = &INSTANCE {
-                        return Ok(instance.clone());
-                    }
-                    
-                    let instance = Self::default();
-                    INSTANCE = Some(instance.clone());
-                    Ok(instance)
+                match $crate::get_state($crate::TRANSFORM_INSTANCE_KEY) {
+                    Some(encoded) => $crate::serde_json::from_slice(&encoded)
+                        .map_err(|e| $crate::anyhow::anyhow!("Failed to deserialize transform: {}", e)),
+                    None => Ok(Self::default()),
                 }
             }
             
-            // Helper method to push CDC messages
-            pub fn push_message(&self, message: debshrew_runtime::CdcMessage) -> Result<()> {
-                push_cdc_message(&message)
+            // Helper method to push CDC messages once the call commits
+            pub fn push_message(&self, message: $crate::CdcMessage) -> Result<()> {
+                $crate::stage_cdc_message(message);
+                Ok(())
+            }
+            
+            // Helper method to mark a table's snapshot complete once the call commits
+            pub fn mark_snapshot_complete(&self, table: &str) -> Result<()> {
+                $crate::stage_snapshot_complete(table);
+                Ok(())
             }
         }
 
         #[no_mangle]
         pub fn process_block() -> i32 {
-            unsafe {
-                // Load instance
-                let mut instance = match <$transform>::load() {
-                    Ok(instance) => instance,
-                    Err(e) => {
-                        $crate::eprintln!("Failed to load transform state: {}", e);
-                        return -1;
-                    }
-                };
-                
-                // Process block
-                match instance.process_block() {
-                    Ok(()) => {
-                        // Save state after successful processing
-                        if let Err(e) = instance.save() {
-                            $crate::eprintln!("Failed to save transform state: {}", e);
-                            return -1;
-                        }
-                        
-                        // Success - CDC messages were pushed via push_message
-                        0
-                    }
-                    Err(e) => {
-                        $crate::eprintln!("Transform failed: {}", e);
-                        -1
-                    }
+            // Load instance
+            let mut instance = match <$transform>::load() {
+                Ok(instance) => instance,
+                Err(e) => {
+                    $crate::eprintln!("Failed to load transform state: {}", e);
+                    return -1;
+                }
+            };
+            
+            // Process block, then save state before committing the
+            // CDC messages staged via push_message
+            let outcome = instance.process_block()
+                .map_err(|e| $crate::anyhow::anyhow!("Transform failed: {}", e))
+                .and_then(|()| instance.save()
+                    .map_err(|e| $crate::anyhow::anyhow!("Failed to save transform state: {}", e)));
+            
+            match $crate::commit_staged(outcome) {
+                Ok(()) => 0,
+                Err(e) => {
+                    $crate::eprintln!("{}", e);
+                    -1
                 }
             }
         }
 
+        #[no_mangle]
+        pub fn on_rollback_complete() -> i32 {
+            // Load instance
+            let mut instance = match <$transform>::load() {
+                Ok(instance) => instance,
+                Err(e) => {
+                    $crate::eprintln!("Failed to load transform state: {}", e);
+                    return -1;
+                }
+            };
+            
+            // Run the hook at the common ancestor, then save state
+            let outcome = instance.on_rollback_complete($crate::get_height())
+                .map_err(|e| $crate::anyhow::anyhow!("Rollback hook failed: {}", e))
+                .and_then(|()| instance.save()
+                    .map_err(|e| $crate::anyhow::anyhow!("Failed to save transform state: {}", e)));
+            
+            match $crate::commit_staged(outcome) {
+                Ok(()) => 0,
+                Err(e) => {
+                    $crate::eprintln!("{}", e);
+                    -1
+                }
+            }
+        }
+    };
+}
+
+/// Declare a transform module
+///
+/// This macro generates the necessary WASM exports for a transform module,
+/// including `rollback`, which calls the transform's own rollback. The
+/// transform type and its messages are handled as described for
+/// [`declare_transform_auto_rollback!`].
+#[macro_export]
+macro_rules! declare_transform {
+    ($transform:ty) => {
+        $crate::declare_transform_auto_rollback!($transform);
+
         #[no_mangle]
         pub fn rollback() -> i32 {
-            unsafe {
-                // Load instance
-                let mut instance = match <$transform>::load() {
-                    Ok(instance) => instance,
-                    Err(e) => {
-                        $crate::eprintln!("Failed to load transform state: {}", e);
-                        return -1;
-                    }
-                };
-                
-                // Process rollback
-                match instance.rollback() {
-                    Ok(()) => {
-                        // Save state after successful rollback
-                        if let Err(e) = instance.save() {
-                            $crate::eprintln!("Failed to save transform state: {}", e);
-                            return -1;
-                        }
-                        
-                        // Success - CDC messages were pushed via push_message
-                        0
-                    }
-                    Err(e) => {
-                        $crate::eprintln!("Rollback failed: {}", e);
-                        -1
-                    }
+            // Load instance
+            let mut instance = match <$transform>::load() {
+                Ok(instance) => instance,
+                Err(e) => {
+                    $crate::eprintln!("Failed to load transform state: {}", e);
+                    return -1;
+                }
+            };
+            
+            // Process rollback, then save state before committing the
+            // CDC messages staged via push_message
+            let outcome = instance.rollback()
+                .map_err(|e| $crate::anyhow::anyhow!("Rollback failed: {}", e))
+                .and_then(|()| instance.save()
+                    .map_err(|e| $crate::anyhow::anyhow!("Failed to save transform state: {}", e)));
+            
+            match $crate::commit_staged(outcome) {
+                Ok(()) => 0,
+                Err(e) => {
+                    $crate::eprintln!("{}", e);
+                    -1
                 }
             }
         }
     };
+}
+
+#[cfg(all(test, feature = "test-utils"))]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    
+    fn test_message(key: &str) -> CdcMessage {
+        CdcMessage {
+            header: CdcHeader {
+                source: "test".to_string(),
+                timestamp: Utc::now(),
+                block_height: 1,
+                block_hash: "00".to_string(),
+                transaction_id: None,
+                event_id: None,
+            },
+            payload: CdcPayload {
+                operation: CdcOperation::Create,
+                table: "test_table".to_string(),
+                key: key.to_string(),
+                before: None,
+                after: Some(serde_json::json!({ "value": 1 })),
+            },
+        }
+    }
+    
+    #[test]
+    fn test_failed_save_commits_no_messages() {
+        stage_cdc_message(test_message("a"));
+        stage_cdc_message(test_message("b"));
+        
+        let outcome = commit_staged(Err(anyhow::anyhow!("Failed to save transform state")));
+        assert!(outcome.is_err());
+        assert_eq!(imports::pushed_message_count(), 0);
+        
+        // The discarded messages don't leak into the next call
+        stage_cdc_message(test_message("c"));
+        commit_staged(Ok(())).unwrap();
+        assert_eq!(imports::pushed_message_count(), 1);
+    }
+    
+    #[test]
+    fn test_checked_balance_arithmetic() {
+        assert_eq!(checked_add_balance(100, 50).unwrap(), 150);
+        assert_eq!(checked_sub_balance(100, 50).unwrap(), 50);
+        assert_eq!(checked_add_balance(u64::MAX - 1, 1).unwrap(), u64::MAX);
+        assert_eq!(checked_sub_balance(50, 50).unwrap(), 0);
+        
+        let overflow = checked_add_balance(u64::MAX, 1).unwrap_err();
+        assert!(matches!(overflow.downcast_ref::<Error>(), Some(Error::Transform(_))));
+        assert_eq!(
+            overflow.to_string(),
+            format!("Transform error: Balance overflow adding 1 to {}", u64::MAX)
+        );
+        
+        let underflow = checked_sub_balance(10, 11).unwrap_err();
+        assert!(matches!(underflow.downcast_ref::<Error>(), Some(Error::Transform(_))));
+        assert_eq!(underflow.to_string(), "Transform error: Balance underflow subtracting 11 from 10");
+    }
+    
+    #[test]
+    fn test_view_errors_name_the_view() {
+        let cases = [
+            (VIEW_NOT_REGISTERED, "View balance is not registered"),
+            (VIEW_FAILED, "View balance returned an error"),
+            (VIEW_TIMED_OUT, "View balance timed out"),
+            (-4, "View balance failed with status -4"),
+        ];
+        
+        for (status, expected) in cases {
+            imports::set_view_status(status);
+            let error = view("balance".to_string(), b"alice".to_vec()).unwrap_err().to_string();
+            assert_eq!(error, format!("{} (params: 0x616c696365)", expected));
+        }
+        
+        // Long parameters are truncated
+        imports::set_view_status(VIEW_FAILED);
+        let error = view("balance".to_string(), vec![0xab; 100]).unwrap_err().to_string();
+        assert!(error.ends_with(&format!("0x{}... (100 bytes))", "ab".repeat(32))), "{}", error);
+    }
+    
+    #[test]
+    fn test_empty_view_result_is_not_an_error() {
+        imports::set_view_status(VIEW_OK);
+        assert_eq!(view("balance".to_string(), b"alice".to_vec()).unwrap(), Vec::<u8>::new());
+        
+        imports::set_view_status(VIEW_FAILED);
+        assert!(view("balance".to_string(), b"alice".to_vec()).is_err());
+    }
+    
+    #[test]
+    fn test_staged_lengths_are_unsigned() {
+        // Lengths past i32::MAX were negative, and so errors, in the old
+        // protocol; they are now ordinary lengths
+        let length = i32::MAX as u64 + 1;
+        if cfg!(target_pointer_width = "64") {
+            assert_eq!(staged_len(length).unwrap(), length as usize);
+            assert_eq!(staged_len(3_000_000_000).unwrap(), 3_000_000_000);
+        }
+        assert_eq!(staged_len(0).unwrap(), 0);
+        
+        // Lengths no buffer could hold are rejected rather than truncated
+        let error = staged_len(u64::MAX).unwrap_err();
+        assert_eq!(error.to_string(), format!("Result of {} bytes does not fit in memory", u64::MAX));
+    }
+    
+    mod counter {
+        use super::test_message;
+        use crate::{DebTransform, Deserialize, Serialize};
+        
+        /// A transform counting the blocks it has processed
+        #[derive(Debug, Default, Clone, Serialize, Deserialize)]
+        pub struct CounterTransform {
+            pub blocks: u32,
+        }
+        
+        impl DebTransform for CounterTransform {
+            fn process_block(&mut self) -> Result<()> {
+                self.blocks += 1;
+                self.push_message(test_message(&self.blocks.to_string()))?;
+                
+                if self.blocks == 1 {
+                    self.mark_snapshot_complete("test_table")?;
+                }
+                
+                Ok(())
+            }
+        }
+        
+        crate::declare_transform!(CounterTransform);
+    }
+    
+    #[test]
+    fn test_declared_transform_accumulates_state() {
+        for _ in 0..3 {
+            assert_eq!(counter::process_block(), 0);
+        }
+        
+        assert_eq!(counter::CounterTransform::load().unwrap().blocks, 3);
+        assert_eq!(imports::pushed_message_count(), 3);
+        
+        // A rollback keeps the saved instance
+        assert_eq!(counter::rollback(), 0);
+        assert_eq!(counter::CounterTransform::load().unwrap().blocks, 3);
+    }
+    
+    #[test]
+    fn test_transform_fields_survive_reorg() {
+        assert_eq!(counter::process_block(), 0);
+        assert_eq!(counter::process_block(), 0);
+        let snapshot = imports::host_state_snapshot();
+        
+        // The transform is stored in the host state under the reserved key
+        let stored: serde_json::Value = serde_json::from_slice(snapshot.get(TRANSFORM_INSTANCE_KEY).unwrap()).unwrap();
+        assert_eq!(stored["blocks"], 2);
+        
+        // A block on the orphaned chain, then the host rolls back to the snapshot
+        assert_eq!(counter::process_block(), 0);
+        assert_eq!(counter::CounterTransform::load().unwrap().blocks, 3);
+        imports::host_restore_state(snapshot);
+        
+        assert_eq!(counter::CounterTransform::load().unwrap().blocks, 2);
+        assert_eq!(counter::process_block(), 0);
+        assert_eq!(counter::CounterTransform::load().unwrap().blocks, 3);
+    }
 }
\ No newline at end of file
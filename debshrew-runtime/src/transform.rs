@@ -7,6 +7,7 @@
 use crate::error::Result;
 use debshrew_support::{CdcMessage, TransformState};
 use std::fmt::Debug;
+use std::time::Duration;
 
 /// Transform trait
 ///
@@ -48,6 +49,29 @@ pub trait DebTransform: Default + Debug + Clone {
         // The runtime will automatically generate inverse CDC messages
         Ok(())
     }
+    
+    /// Handle the end of a reorg
+    ///
+    /// This method is called once a reorg has restored the transform state to
+    /// the common ancestor, before any replacement block is processed. It can
+    /// be used to rebuild caches or derived data that the restored state does
+    /// not cover. Changes to the state are kept; CDC messages pushed from this
+    /// method are discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The height of the common ancestor
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the hook was successful
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hook fails, which fails the reorg
+    fn on_rollback_complete(&mut self, _height: u32) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Transform module result
@@ -61,6 +85,13 @@ pub struct TransformResult {
     
     /// Snapshot of the transform state after the operation
     pub state_snapshot: TransformState,
+    
+    /// Warnings reported by the transform module during the operation
+    pub warnings: Vec<String>,
+    
+    /// Delay after which the transform module asked for the block to be
+    /// retried, if it could not process it yet
+    pub retry_after: Option<Duration>,
 }
 
 impl TransformResult {
@@ -78,6 +109,8 @@ impl TransformResult {
         Self {
             cdc_messages,
             state_snapshot,
+            warnings: Vec::new(),
+            retry_after: None,
         }
     }
 }
@@ -122,6 +155,7 @@ mod tests {
                 block_height: 123,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -0,0 +1,250 @@
+//! View result cache for debshrew
+//!
+//! This module provides a bounded least-recently-used cache of view results.
+//! Entries are keyed by view name, serialized parameters and the block height
+//! the view was called at, so a result stays valid across blocks until a
+//! reorg replaces the block it was computed at.
+
+use std::collections::{BTreeMap, HashMap};
+
+/// The default number of view results kept by a `WasmRuntime`
+pub const DEFAULT_VIEW_CACHE_CAPACITY: usize = 1024;
+
+/// The key of a cached view result
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ViewKey {
+    /// The name of the view function
+    name: String,
+    
+    /// The serialized view parameters
+    params: Vec<u8>,
+    
+    /// The block height the view was called at
+    height: u32,
+}
+
+/// Counters describing how a view cache has been used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ViewCacheStats {
+    /// Calls answered from the cache
+    pub hits: u64,
+    
+    /// Calls that had to run the view function
+    pub misses: u64,
+    
+    /// Results currently cached
+    pub entries: usize,
+}
+
+/// Bounded LRU cache of view results
+#[derive(Debug)]
+pub struct ViewCache {
+    /// The maximum number of cached results; 0 disables caching
+    capacity: usize,
+    
+    /// Cached results with the tick of their last use
+    entries: HashMap<ViewKey, (Vec<u8>, u64)>,
+    
+    /// Keys ordered by the tick of their last use, oldest first
+    recency: BTreeMap<u64, ViewKey>,
+    
+    /// Monotonic use counter
+    tick: u64,
+    
+    /// Calls answered from the cache
+    hits: u64,
+    
+    /// Calls that had to run the view function
+    misses: u64,
+}
+
+impl ViewCache {
+    /// Create a new view cache
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of cached results; 0 disables caching
+    ///
+    /// # Returns
+    ///
+    /// A new, empty view cache
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+    
+    /// Get the maximum number of cached results
+    ///
+    /// # Returns
+    ///
+    /// The capacity of the cache
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    
+    /// Change the maximum number of cached results
+    ///
+    /// The least recently used results are evicted if the cache is now over
+    /// capacity.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of cached results; 0 disables caching
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+    
+    /// Look up a cached view result, marking it as recently used
+    ///
+    /// Every lookup counts as either a hit or a miss.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the view function
+    /// * `params` - The serialized view parameters
+    /// * `height` - The block height the view is called at
+    ///
+    /// # Returns
+    ///
+    /// The cached result, or None if it is not cached
+    pub fn get(&mut self, name: &str, params: &[u8], height: u32) -> Option<Vec<u8>> {
+        let key = ViewKey { name: name.to_string(), params: params.to_vec(), height };
+        self.tick += 1;
+        
+        match self.entries.get_mut(&key) {
+            Some((result, last_used)) => {
+                self.recency.remove(last_used);
+                *last_used = self.tick;
+                self.recency.insert(self.tick, key);
+                self.hits += 1;
+                Some(result.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+    
+    /// Cache a view result
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the view function
+    /// * `params` - The serialized view parameters
+    /// * `height` - The block height the view was called at
+    /// * `result` - The serialized view result
+    pub fn insert(&mut self, name: &str, params: &[u8], height: u32, result: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        
+        let key = ViewKey { name: name.to_string(), params: params.to_vec(), height };
+        self.tick += 1;
+        
+        if let Some((_, last_used)) = self.entries.insert(key.clone(), (result, self.tick)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(self.tick, key);
+        self.evict();
+    }
+    
+    /// Remove the results computed above a block height
+    ///
+    /// Called on a reorg, since blocks above the common ancestor are replaced
+    /// and views called at those heights may now return different results.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The highest block height whose results are kept
+    pub fn invalidate_above(&mut self, height: u32) {
+        self.retain(|key| key.height <= height);
+    }
+    
+    /// Remove all cached results
+    ///
+    /// The hit and miss counters are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+    
+    /// Remove the results of a view function
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the view function
+    pub fn invalidate_view(&mut self, name: &str) {
+        self.retain(|key| key.name != name);
+    }
+    
+    /// Get the cache usage counters
+    ///
+    /// # Returns
+    ///
+    /// The hit and miss counts and the number of cached results
+    pub fn stats(&self) -> ViewCacheStats {
+        ViewCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+        }
+    }
+    
+    /// Keep only the results whose key matches a predicate
+    fn retain<F: Fn(&ViewKey) -> bool>(&mut self, keep: F) {
+        self.entries.retain(|key, _| keep(key));
+        self.recency.retain(|_, key| keep(key));
+    }
+    
+    /// Evict the least recently used results until the cache fits its capacity
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, key)) => {
+                    self.entries.remove(&key);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl Default for ViewCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIEW_CACHE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_least_recently_used_result_is_evicted() {
+        let mut cache = ViewCache::new(2);
+        cache.insert("balance", b"alice", 1, b"10".to_vec());
+        cache.insert("balance", b"bob", 1, b"20".to_vec());
+        
+        // Touch alice so bob becomes the oldest entry
+        assert_eq!(cache.get("balance", b"alice", 1), Some(b"10".to_vec()));
+        cache.insert("balance", b"carol", 1, b"30".to_vec());
+        
+        assert_eq!(cache.get("balance", b"bob", 1), None);
+        assert_eq!(cache.get("balance", b"alice", 1), Some(b"10".to_vec()));
+        assert_eq!(cache.get("balance", b"carol", 1), Some(b"30".to_vec()));
+        assert_eq!(cache.stats(), ViewCacheStats { hits: 3, misses: 1, entries: 2 });
+        
+        // A zero capacity disables caching
+        cache.set_capacity(0);
+        cache.insert("balance", b"alice", 2, b"10".to_vec());
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
\ No newline at end of file
@@ -5,22 +5,165 @@
 //! and managing WASM memory.
 
 #[cfg(feature = "host")]
-use crate::error::Result;
+use crate::block;
+#[cfg(feature = "host")]
+use crate::error::{Error, Result};
 #[cfg(feature = "host")]
 use crate::transform::TransformResult;
 #[cfg(feature = "host")]
+use crate::view_cache::{ViewCache, ViewCacheStats};
+#[cfg(feature = "host")]
 use debshrew_support::{CdcMessage, CdcHeader, CdcOperation, CdcPayload, TransformState};
 #[cfg(feature = "host")]
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "host")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "host")]
+use std::path::{Path, PathBuf};
 #[cfg(feature = "host")]
-use std::path::Path;
+use std::sync::Arc;
 #[cfg(feature = "host")]
-use wasmtime::{Engine, Instance, Module, Store};
+use std::time::{Duration, Instant};
+#[cfg(feature = "host")]
+use wasmtime::{Caller, Engine, Linker, Memory, Module, Store, Trap};
 #[cfg(feature = "host")]
 use chrono::Utc;
 #[cfg(feature = "host")]
 use anyhow::anyhow;
 
+/// A host-side view function implementation
+///
+/// Takes the serialized view parameters and returns the serialized result.
+#[cfg(feature = "host")]
+pub type ViewFunction = Box<dyn Fn(&[u8]) -> Result<Vec<u8>> + Send>;
+
+/// A host-side source of view results
+///
+/// Takes the view name, the serialized view parameters, the height of the
+/// block being processed and how long the call may take, and returns the
+/// serialized result, or None if the view did not answer in time. Answers
+/// the views without a registered function, so it may block on a network
+/// request, which it should give up on once the time is up.
+#[cfg(feature = "host")]
+pub type ViewFetcher = Arc<dyn Fn(&str, &[u8], u32, Option<Duration>) -> Result<Option<Vec<u8>>> + Send + Sync>;
+
+/// A host-side source of raw blocks
+///
+/// Takes a block height and returns the raw block bytes. Called from inside
+/// the transform at most once per block, the first time it reads the raw
+/// block, so it may block on a network request.
+#[cfg(feature = "host")]
+pub type BlockFetcher = Arc<dyn Fn(u32) -> Result<Vec<u8>> + Send + Sync>;
+
+/// The functions the host provides to transforms in the `env` module
+///
+/// A module importing anything else was built against a different host ABI
+/// and fails to instantiate.
+#[cfg(feature = "host")]
+pub const HOST_FUNCTIONS: &[&str] = &[
+    "__load",
+    "__view",
+    "__view_status",
+    "__stdout",
+    "__stderr",
+    "__height",
+    "__block_hash",
+    "__block_hash_status",
+    "__prev_block_hash_status",
+    "__get_block_raw",
+    "__get_block_txids",
+    "__push_cdc_message",
+    "__mark_snapshot_complete",
+    "__warn",
+    "__request_retry",
+    "__get_state",
+    "__set_state",
+    "__delete_state",
+];
+
+/// Where a transform module is loaded from
+#[cfg(feature = "host")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WasmSource {
+    /// A module file on the filesystem
+    Path(PathBuf),
+    
+    /// A module already in memory, such as one fetched over HTTP or embedded
+    /// in the configuration
+    Bytes {
+        /// The name of the module, used in error messages
+        name: String,
+        
+        /// The WASM module bytes
+        bytes: Vec<u8>,
+    },
+}
+
+/// How the blocks rolled back by a reorg are undone
+#[cfg(feature = "host")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RollbackMode {
+    /// Invert the CDC messages cached for the rolled-back blocks
+    #[default]
+    Auto,
+    
+    /// Call the transform's `rollback` export, which pushes its own messages
+    Manual,
+}
+
+/// Host state available to the imported host functions during a WASM call
+#[cfg(feature = "host")]
+struct HostContext {
+    /// The current block height
+    height: u32,
+    
+    /// The current block hash
+    hash: Vec<u8>,
+    
+    /// The previous block hash, empty at the genesis block
+    prev_hash: Vec<u8>,
+    
+    /// The transform state
+    state: TransformState,
+    
+    /// CDC messages pushed during the call
+    cdc_messages: Vec<CdcMessage>,
+    
+    /// Registered view functions
+    view_functions: HashMap<String, ViewFunction>,
+    
+    /// Cached view results
+    view_cache: ViewCache,
+    
+    /// How long a fetched view may take before the call fails
+    view_timeout: Option<Duration>,
+    
+    /// When the fetched views of the call must have answered by
+    view_deadline: Option<Instant>,
+    
+    /// Answers the views without a registered function
+    view_fetcher: Option<ViewFetcher>,
+    
+    /// Raw bytes of a block, with its height
+    block_raw: Option<(u32, Vec<u8>)>,
+    
+    /// Fetches the raw block the first time the transform reads it
+    block_fetcher: Option<BlockFetcher>,
+    
+    /// Data staged by the last host call, copied out by `__load`
+    load_buffer: Vec<u8>,
+    
+    /// Warnings reported during the call
+    warnings: Vec<String>,
+    
+    /// Delay after which the transform asked for the block to be retried
+    retry_after: Option<Duration>,
+    
+    /// The last view call or block fetch that failed or timed out during the call
+    view_failure: Option<String>,
+}
+
 /// WASM runtime for executing transform modules
 #[cfg(feature = "host")]
 pub struct WasmRuntime {
@@ -30,12 +173,21 @@ pub struct WasmRuntime {
     /// The WASM module
     module: Module,
     
+    /// The name of the module, used in error messages
+    name: String,
+    
+    /// How rollbacks are handled
+    rollback_mode: RollbackMode,
+    
     /// The current block height
     current_height: u32,
     
     /// The current block hash
     current_hash: Vec<u8>,
     
+    /// The hash of the parent of the current block
+    prev_hash: Vec<u8>,
+    
     /// The transform state
     state: TransformState,
     
@@ -44,16 +196,70 @@ pub struct WasmRuntime {
     
     /// Buffer for CDC messages from the current operation
     cdc_messages: Vec<CdcMessage>,
+    
+    /// Warnings reported by the current operation
+    warnings: Vec<String>,
+    
+    /// Delay after which the current operation asked to be retried
+    retry_after: Option<Duration>,
+    
+    /// View functions available to the transform through `__view`
+    view_functions: HashMap<String, ViewFunction>,
+    
+    /// View results kept across blocks, keyed by the height they were called at
+    view_cache: ViewCache,
+    
+    /// How long a fetched view may take before the call fails
+    view_timeout: Option<Duration>,
+    
+    /// How long all the fetched views of one call may take together
+    view_budget: Option<Duration>,
+    
+    /// Answers the views without a registered function
+    view_fetcher: Option<ViewFetcher>,
+    
+    /// The last view call that failed or timed out during the current operation
+    view_failure: Option<String>,
+    
+    /// Raw bytes of the most recently provided or fetched block, with its height
+    block_raw: Option<(u32, Vec<u8>)>,
+    
+    /// Fetches raw blocks the transform reads but were not provided
+    block_fetcher: Option<BlockFetcher>,
+    
+    /// Whether message keys are prefixed with the message source
+    prefix_keys: bool,
+    
+    /// Whether message tables are prefixed with the message source
+    prefix_tables: bool,
+    
+    /// Whether `Create` and `Update` messages are checked against `table_schemas`
+    enforce_schemas: bool,
+    
+    /// Whether `Update` messages that change nothing are dropped
+    suppress_noop_updates: bool,
+    
+    /// Whether a panic in a host function fails the call instead of unwinding
+    catch_panics: bool,
+    
+    /// The `source` stamped on the messages of a table, by table
+    table_sources: HashMap<String, String>,
+    
+    /// The fields of each table, registered or as first seen
+    table_schemas: HashMap<String, BTreeSet<String>>,
 }
 
 #[cfg(feature = "host")]
 impl std::fmt::Debug for WasmRuntime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("WasmRuntime")
+            .field("name", &self.name)
             .field("current_height", &self.current_height)
             .field("current_hash", &self.current_hash)
             .field("state", &self.state)
             .field("cdc_cache", &self.cdc_cache.keys())
+            .field("view_functions", &self.view_functions.keys())
+            .field("view_cache", &self.view_cache.stats())
             .finish_non_exhaustive()
     }
 }
@@ -62,6 +268,8 @@ impl std::fmt::Debug for WasmRuntime {
 impl WasmRuntime {
     /// Create a new WASM runtime
     ///
+    /// The module is named after its path in error messages.
+    ///
     /// # Arguments
     ///
     /// * `wasm_path` - The path to the WASM module
@@ -74,23 +282,19 @@ impl WasmRuntime {
     ///
     /// Returns an error if the WASM module cannot be loaded
     pub fn new<P: AsRef<Path>>(wasm_path: P) -> Result<Self> {
+        let name = wasm_path.as_ref().display().to_string();
         let engine = Engine::default();
         let module = Module::from_file(&engine, wasm_path)
-            .map_err(|e| anyhow!("Failed to load WASM module: {}", e))?;
+            .map_err(|e| anyhow!("Failed to load WASM module {}: {}", name, e))?;
 
-        Ok(Self {
-            engine,
-            module,
-            current_height: 0,
-            current_hash: Vec::new(),
-            state: TransformState::new(),
-            cdc_cache: HashMap::new(),
-            cdc_messages: Vec::new(),
-        })
+        Ok(Self::with_module(engine, module, name))
     }
 
     /// Create a new WASM runtime from WASM bytes
     ///
+    /// The module is named `<bytes>` in error messages; use
+    /// `from_bytes_named` to tell several transforms apart.
+    ///
     /// # Arguments
     ///
     /// * `wasm_bytes` - The WASM module bytes
@@ -103,19 +307,114 @@ impl WasmRuntime {
     ///
     /// Returns an error if the WASM module cannot be loaded
     pub fn from_bytes(wasm_bytes: &[u8]) -> Result<Self> {
+        Self::from_bytes_named(wasm_bytes, "<bytes>")
+    }
+
+    /// Create a new WASM runtime from a module source
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The file or bytes to load the module from
+    ///
+    /// # Returns
+    ///
+    /// A new WASM runtime, named after the file path or the source's name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the WASM module cannot be loaded
+    pub fn from_source(source: WasmSource) -> Result<Self> {
+        match source {
+            WasmSource::Path(path) => Self::new(path),
+            WasmSource::Bytes { name, bytes } => Self::from_bytes_named(&bytes, &name),
+        }
+    }
+
+    /// Create a new named WASM runtime from WASM bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `wasm_bytes` - The WASM module bytes
+    /// * `name` - The name of the module, used in error messages
+    ///
+    /// # Returns
+    ///
+    /// A new WASM runtime
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the WASM module cannot be loaded
+    pub fn from_bytes_named(wasm_bytes: &[u8], name: &str) -> Result<Self> {
         let engine = Engine::default();
         let module = Module::from_binary(&engine, wasm_bytes)
-            .map_err(|e| anyhow!("Failed to load WASM module from bytes: {}", e))?;
+            .map_err(|e| anyhow!("Failed to load WASM module {}: {}", name, e))?;
+
+        Ok(Self::with_module(engine, module, name.to_string()))
+    }
 
-        Ok(Self {
+    /// Create a WASM runtime around a compiled module
+    fn with_module(engine: Engine, module: Module, name: String) -> Self {
+        let rollback_mode = match module.get_export("rollback") {
+            Some(_) => RollbackMode::Manual,
+            None => RollbackMode::Auto,
+        };
+        
+        Self {
             engine,
             module,
+            name,
+            rollback_mode,
             current_height: 0,
             current_hash: Vec::new(),
+            prev_hash: Vec::new(),
             state: TransformState::new(),
             cdc_cache: HashMap::new(),
             cdc_messages: Vec::new(),
-        })
+            warnings: Vec::new(),
+            retry_after: None,
+            view_functions: HashMap::new(),
+            view_cache: ViewCache::default(),
+            view_timeout: None,
+            view_budget: None,
+            view_fetcher: None,
+            view_failure: None,
+            block_raw: None,
+            block_fetcher: None,
+            prefix_keys: false,
+            prefix_tables: false,
+            enforce_schemas: false,
+            suppress_noop_updates: false,
+            catch_panics: true,
+            table_sources: HashMap::new(),
+            table_schemas: HashMap::new(),
+        }
+    }
+
+    /// Get the name of the module
+    ///
+    /// # Returns
+    ///
+    /// The module path, the name given to `from_bytes_named`, or `<bytes>`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Replace the transform module, keeping the runtime's state and settings
+    ///
+    /// The CDC messages recorded for processed blocks are kept, so blocks
+    /// that are not reprocessed can still be inverted on a reorg. A new
+    /// module without a `rollback` export switches to automatic rollback.
+    ///
+    /// # Arguments
+    ///
+    /// * `runtime` - A runtime holding the new module; only its module is used
+    pub fn replace_module(&mut self, runtime: WasmRuntime) {
+        if runtime.module.get_export("rollback").is_none() {
+            self.rollback_mode = RollbackMode::Auto;
+        }
+        self.engine = runtime.engine;
+        self.module = runtime.module;
+        self.name = runtime.name;
     }
 
     /// Set the current block height
@@ -135,7 +434,278 @@ impl WasmRuntime {
     pub fn set_current_hash(&mut self, hash: Vec<u8>) {
         self.current_hash = hash;
     }
+    
+    /// Set the hash of the parent of the next block to process
+    ///
+    /// The transform reads it with `__prev_block_hash_status`. It is kept until
+    /// replaced, so set it before each block.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The previous block hash, empty at the genesis block
+    pub fn set_prev_block_hash(&mut self, hash: Vec<u8>) {
+        self.prev_hash = hash;
+    }
+
+    /// Set the raw bytes of a block
+    ///
+    /// The transform can read them with `__get_block_raw`, or its transaction
+    /// IDs with `__get_block_txids`, while processing the block at `height`.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height
+    /// * `raw` - The raw block bytes
+    pub fn set_block_raw(&mut self, height: u32, raw: Vec<u8>) {
+        self.block_raw = Some((height, raw));
+    }
+    
+    /// Set where raw blocks are fetched from
+    ///
+    /// When the transform calls `__get_block_raw` or `__get_block_txids` for
+    /// a block whose raw bytes were not set with `set_block_raw`, the fetcher
+    /// is called and its result kept for the rest of the block, so blocks
+    /// are only fetched for transforms that read them, and at most once. A
+    /// failed fetch fails the call as an `Error::ViewAccess`, since it may
+    /// succeed if retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `fetcher` - The raw block source, or None to only use provided blocks
+    pub fn set_block_fetcher(&mut self, fetcher: Option<BlockFetcher>) {
+        self.block_fetcher = fetcher;
+    }
+    
+    /// Check whether the transform module reads raw block data
+    ///
+    /// Lets the caller skip fetching raw blocks for transforms that never
+    /// import `__get_block_raw` or `__get_block_txids`.
+    ///
+    /// # Returns
+    ///
+    /// True if the module imports either function, false otherwise
+    pub fn uses_block_raw(&self) -> bool {
+        self.module.imports().any(|import| {
+            import.module() == "env"
+                && (import.name() == "__get_block_raw" || import.name() == "__get_block_txids")
+        })
+    }
+
+    /// List the functions exported by the transform module
+    ///
+    /// # Returns
+    ///
+    /// The names of the exported functions, in module order
+    pub fn exported_functions(&self) -> Vec<String> {
+        self.module
+            .exports()
+            .filter(|export| export.ty().func().is_some())
+            .map(|export| export.name().to_string())
+            .collect()
+    }
+    
+    /// List the functions imported by the transform module
+    ///
+    /// # Returns
+    ///
+    /// The `(module, name)` pairs of the imported functions, in module order
+    pub fn imported_functions(&self) -> Vec<(String, String)> {
+        self.module
+            .imports()
+            .filter(|import| import.ty().func().is_some())
+            .map(|import| (import.module().to_string(), import.name().to_string()))
+            .collect()
+    }
+    
+    /// List the imported functions the host does not provide
+    ///
+    /// # Returns
+    ///
+    /// The `(module, name)` pairs of the imports missing from
+    /// [`HOST_FUNCTIONS`], empty if the module matches the host ABI
+    pub fn unexpected_imports(&self) -> Vec<(String, String)> {
+        self.imported_functions()
+            .into_iter()
+            .filter(|(module, name)| module != "env" || !HOST_FUNCTIONS.contains(&name.as_str()))
+            .collect()
+    }
+
+    /// Check whether the transform relies on automatic rollback
+    ///
+    /// Unless set with `set_rollback_mode`, transforms declared with
+    /// `declare_transform_auto_rollback!`, which have no `rollback` export,
+    /// use automatic rollback, and any other transform its `rollback` export.
+    ///
+    /// # Returns
+    ///
+    /// True if rollbacks invert the cached CDC messages of the rolled-back
+    /// blocks, false if they call the `rollback` export
+    pub fn uses_auto_rollback(&self) -> bool {
+        self.rollback_mode == RollbackMode::Auto
+    }
+    
+    /// Set how rollbacks are handled
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - Whether to invert cached messages or call the `rollback` export
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the mode was set
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the mode is manual and the module does not export `rollback`
+    pub fn set_rollback_mode(&mut self, mode: RollbackMode) -> Result<()> {
+        if mode == RollbackMode::Manual && self.module.get_export("rollback").is_none() {
+            return Err(anyhow!("Manual rollback requires {} to export a rollback function", self.name));
+        }
+        
+        self.rollback_mode = mode;
+        Ok(())
+    }
+    
+    /// Namespace the messages pushed by the transform by their source
+    ///
+    /// Keys become `{source}:{key}` and tables become `{source}_{table}`, so
+    /// transforms sharing a topic or schema cannot collide. The prefix is
+    /// applied before messages are cached, so inverse messages computed on a
+    /// reorg carry the same prefixed key and table. Control messages are left
+    /// unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - Whether to prefix message keys
+    /// * `tables` - Whether to prefix message tables
+    pub fn set_source_prefix(&mut self, keys: bool, tables: bool) {
+        self.prefix_keys = keys;
+        self.prefix_tables = tables;
+    }
 
+    /// Check that every row of a table has the same fields
+    ///
+    /// When enabled, the fields of the `after` object of the first `Create` or
+    /// `Update` message for a table become that table's schema, unless one
+    /// was registered with `register_table_schema`. A block pushing a message
+    /// whose fields differ from its table's schema then fails, and its state
+    /// changes are discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `enforce` - Whether to enforce table schemas
+    pub fn set_enforce_schemas(&mut self, enforce: bool) {
+        self.enforce_schemas = enforce;
+    }
+    
+    /// Drop `Update` messages whose `before` equals their `after`
+    ///
+    /// Transforms re-setting a row to the value it already has otherwise
+    /// create churn downstream. Suppressed messages are dropped before they
+    /// are cached, so they are not inverted on a reorg either.
+    ///
+    /// # Arguments
+    ///
+    /// * `suppress` - Whether to suppress no-op updates
+    pub fn set_suppress_noop_updates(&mut self, suppress: bool) {
+        self.suppress_noop_updates = suppress;
+    }
+    
+    /// Override the `source` of the messages of some tables
+    ///
+    /// Lets a transform covering several protocols tag each table for
+    /// downstream routing. Tables are matched by the name the transform
+    /// pushes them with, and messages of other tables keep the source the
+    /// transform set. The override is applied before any source prefix.
+    ///
+    /// # Arguments
+    ///
+    /// * `table_sources` - The source for each overridden table
+    pub fn set_table_sources(&mut self, table_sources: HashMap<String, String>) {
+        self.table_sources = table_sources;
+    }
+    
+    /// Set whether panics in host functions are caught
+    ///
+    /// Enabled by default, so a panic in a host function called by the
+    /// transform fails the call with `Error::Wasm` instead of unwinding
+    /// through the daemon. Disable it to get the panic and its backtrace
+    /// while debugging. Panicking view functions always fail the view call.
+    ///
+    /// # Arguments
+    ///
+    /// * `catch_panics` - Whether to catch panics
+    pub fn set_catch_panics(&mut self, catch_panics: bool) {
+        self.catch_panics = catch_panics;
+    }
+    
+    /// Register the fields of a table for schema enforcement
+    ///
+    /// The table name is matched after any source prefix has been applied.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The table name
+    /// * `fields` - The fields every row of the table must have
+    pub fn register_table_schema(&mut self, table: &str, fields: &[&str]) {
+        let fields = fields.iter().map(|field| field.to_string()).collect();
+        self.table_schemas.insert(table.to_string(), fields);
+    }
+    
+    /// Check CDC messages against the table schemas, learning new tables
+    ///
+    /// Nothing is learned unless all messages pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages of a block
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if every message matches its table's schema
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the table and both field sets on the first
+    /// message whose fields differ from its table's schema
+    fn check_schemas(&mut self, messages: &[CdcMessage]) -> Result<()> {
+        if !self.enforce_schemas {
+            return Ok(());
+        }
+        
+        let mut learned: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for message in messages {
+            if !matches!(message.payload.operation, CdcOperation::Create | CdcOperation::Update) {
+                continue;
+            }
+            let Some(serde_json::Value::Object(after)) = &message.payload.after else {
+                continue;
+            };
+            
+            let table = &message.payload.table;
+            let fields: BTreeSet<String> = after.keys().cloned().collect();
+            match self.table_schemas.get(table).or_else(|| learned.get(table)) {
+                Some(schema) if *schema != fields => {
+                    return Err(anyhow!(
+                        "Table {} in {} changed fields at block {}: expected {:?}, got {:?}",
+                        table,
+                        self.name,
+                        self.current_height,
+                        schema,
+                        fields
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    learned.insert(table.clone(), fields);
+                }
+            }
+        }
+        
+        self.table_schemas.extend(learned);
+        Ok(())
+    }
+    
     /// Set the transform state
     ///
     /// # Arguments
@@ -154,8 +724,32 @@ impl WasmRuntime {
         self.state.clone()
     }
     
+    /// Clear everything left over from earlier blocks
+    ///
+    /// Drops the CDC messages cached for reverting blocks, any buffered CDC
+    /// messages, warnings and retry request, the raw block, and the cached
+    /// view results, and resets the current height and hash. Call this
+    /// before replaying blocks so the replay starts clean without
+    /// reconstructing the runtime. The transform state and registered views
+    /// are kept; use `set_state` to replace the state.
+    pub fn reset(&mut self) {
+        self.current_height = 0;
+        self.current_hash.clear();
+        self.prev_hash.clear();
+        self.cdc_cache.clear();
+        self.cdc_messages.clear();
+        self.warnings.clear();
+        self.retry_after = None;
+        self.block_raw = None;
+        self.view_cache.clear();
+    }
+    
     /// Process a block
     ///
+    /// If the transform asks for the block to be retried, the block is not
+    /// committed: the state is left as it was and the result carries no CDC
+    /// messages, only the requested delay and any warnings.
+    ///
     /// # Arguments
     ///
     /// * `height` - The block height
@@ -167,7 +761,10 @@ impl WasmRuntime {
     ///
     /// # Errors
     ///
-    /// Returns an error if block processing fails
+    /// Returns an error if block processing fails. A failure after a view
+    /// call failed or timed out is an `Error::ViewAccess`, since it may
+    /// succeed once the view recovers; a transform returning an error code
+    /// otherwise is an `Error::Transform`.
     pub fn process_block(&mut self, height: u32, hash: Vec<u8>) -> Result<TransformResult> {
         // Set the current block height and hash
         self.set_current_height(height);
@@ -175,84 +772,495 @@ impl WasmRuntime {
         
         // Clear CDC message buffer
         self.cdc_messages.clear();
-        
-        // Create a new store and instance
-        let mut store = Store::new(&self.engine, ());
-        let instance = Instance::new(&mut store, &self.module, &[])
-            .map_err(|e| anyhow!("Failed to instantiate WASM module: {}", e))?;
-        
-        // Get the process_block function
-        let process_block = instance.get_typed_func::<(), i32>(&mut store, "process_block")
-            .map_err(|e| anyhow!("Failed to get process_block function: {}", e))?;
+        self.warnings.clear();
+        self.retry_after = None;
         
         // Call the process_block function
-        let result = process_block.call(&mut store, ())
-            .map_err(|e| anyhow!("Failed to call process_block function: {}", e))?;
+        let state = self.enforce_schemas.then(|| self.state.clone());
+        let result = self.call_export("process_block")
+            .map_err(|e| self.classify_failure(e))?;
         
         if result < 0 {
-            return Err(anyhow!("Process block failed with code {}", result));
+            let error = Error::Transform(format!("Process block in {} failed with code {}", self.name, result));
+            return Err(self.classify_failure(error.into()));
         }
         
         // Get the CDC messages that were pushed
         let cdc_messages = self.cdc_messages.clone();
         
-        // Cache CDC messages for this block
-        self.cdc_cache.insert(height, cdc_messages.clone());
-        
-        // Update state from WASM memory
-        // In a real implementation, we would extract the state from WASM memory
-        // For now, we'll just use the existing state
+        // Cache CDC messages for this block, unless it is to be retried
+        if self.retry_after.is_none() {
+            if let Err(e) = self.check_schemas(&cdc_messages) {
+                self.state = state.unwrap_or_default();
+                self.cdc_messages.clear();
+                return Err(e);
+            }
+            
+            self.cdc_cache.insert(height, cdc_messages.clone());
+        }
         
-        Ok(TransformResult::new(cdc_messages, self.state.clone()))
+        Ok(self.transform_result(cdc_messages))
     }
     
-    /// Handle a rollback
+    /// Process a block twice and check that both runs agree
+    ///
+    /// Each run instantiates the module afresh and starts from the same state.
+    /// The CDC messages of the two runs are compared by their canonical JSON,
+    /// so differing timestamps are ignored. Views are answered from the view
+    /// cache on the second run, so only the transform itself is checked.
     ///
     /// # Arguments
     ///
-    /// * `height` - The height to roll back to
-    /// * `hash` - The hash to roll back to
+    /// * `height` - The block height
+    /// * `hash` - The block hash
     ///
     /// # Returns
     ///
-    /// The result of the rollback, including CDC messages and a state snapshot
+    /// The result of processing the block, as for `process_block`
     ///
     /// # Errors
     ///
-    /// Returns an error if the rollback fails
-    pub fn rollback(&mut self, height: u32, hash: Vec<u8>) -> Result<TransformResult> {
-        // Set the current block height and hash
+    /// Returns an error if either run fails, or, with a diff of the outputs,
+    /// if the runs produce different CDC messages. The state is left as it
+    /// was before the block in that case.
+    pub fn process_block_twice(&mut self, height: u32, hash: Vec<u8>) -> Result<TransformResult> {
+        let state = self.state.clone();
+        let first = self.process_block(height, hash.clone())?;
+        if first.retry_after.is_some() {
+            return Ok(first);
+        }
+        
+        self.state = state.clone();
+        let second = self.process_block(height, hash)?;
+        
+        if let Some(diff) = diff_cdc_messages(&first.cdc_messages, &second.cdc_messages) {
+            self.state = state;
+            self.cdc_cache.remove(&height);
+            return Err(anyhow!(
+                "Transform {} is nondeterministic at block {}:\n{}",
+                self.name,
+                height,
+                diff
+            ));
+        }
+        
+        Ok(second)
+    }
+    
+    /// Handle a rollback
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The height to roll back to
+    /// * `hash` - The hash to roll back to
+    ///
+    /// # Returns
+    ///
+    /// The result of the rollback, including CDC messages and a state snapshot
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rollback fails
+    pub fn rollback(&mut self, height: u32, hash: Vec<u8>) -> Result<TransformResult> {
+        // Views called above the rollback height saw blocks that are gone
+        self.invalidate_view_cache_above(height);
+        
+        // Set the current block height and hash
         self.set_current_height(height);
         self.set_current_hash(hash);
         
         // Clear CDC message buffer
         self.cdc_messages.clear();
+        self.warnings.clear();
+        self.retry_after = None;
         
-        // Create a new store and instance
-        let mut store = Store::new(&self.engine, ());
-        let instance = Instance::new(&mut store, &self.module, &[])
-            .map_err(|e| anyhow!("Failed to instantiate WASM module: {}", e))?;
-        
-        // Get the rollback function
-        let rollback = instance.get_typed_func::<(), i32>(&mut store, "rollback")
-            .map_err(|e| anyhow!("Failed to get rollback function: {}", e))?;
+        // With automatic rollback, undo the cached messages of every block
+        // above the rollback height, newest first
+        if self.uses_auto_rollback() {
+            let mut heights: Vec<u32> = self.cdc_cache.keys()
+                .copied()
+                .filter(|block_height| *block_height > height)
+                .collect();
+            heights.sort_unstable_by(|a, b| b.cmp(a));
+            
+            let mut inverse_messages = Vec::new();
+            for block_height in heights {
+                inverse_messages.extend(self.compute_inverse_messages(block_height)?);
+            }
+            
+            return Ok(self.transform_result(inverse_messages));
+        }
         
         // Call the rollback function
-        let result = rollback.call(&mut store, ())
-            .map_err(|e| anyhow!("Failed to call rollback function: {}", e))?;
+        let result = self.call_export("rollback")?;
         
         if result < 0 {
-            return Err(anyhow!("Rollback failed with code {}", result));
+            return Err(anyhow!("Rollback in {} failed with code {}", self.name, result));
         }
         
         // Get the CDC messages that were pushed
         let cdc_messages = self.cdc_messages.clone();
         
-        // Update state from WASM memory
-        // In a real implementation, we would extract the state from WASM memory
-        // For now, we'll just use the existing state
+        Ok(self.transform_result(cdc_messages))
+    }
+    
+    /// Notify the transform that a reorg has restored its state
+    ///
+    /// Calls the `on_rollback_complete` export at the common ancestor height.
+    /// Transforms that do not export it are left untouched. State changes made
+    /// by the hook are kept; CDC messages it pushes are discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The height of the common ancestor
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the hook succeeded or the transform has no hook
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hook fails
+    pub fn on_rollback_complete(&mut self, height: u32) -> Result<()> {
+        if self.module.get_export("on_rollback_complete").is_none() {
+            return Ok(());
+        }
+        
+        self.set_current_height(height);
+        self.cdc_messages.clear();
+        
+        let result = self.call_export("on_rollback_complete");
         
-        Ok(TransformResult::new(cdc_messages, self.state.clone()))
+        self.cdc_messages.clear();
+        self.warnings.clear();
+        self.retry_after = None;
+        
+        let result = result?;
+        if result < 0 {
+            return Err(anyhow!("Rollback hook in {} failed with code {}", self.name, result));
+        }
+        
+        Ok(())
+    }
+    
+    /// Build the result of the current operation
+    ///
+    /// Each CDC message is stamped with its content-addressed event ID.
+    fn transform_result(&mut self, mut cdc_messages: Vec<CdcMessage>) -> TransformResult {
+        stamp_event_ids(&mut cdc_messages);
+        let mut result = TransformResult::new(cdc_messages, self.state.clone());
+        result.warnings = std::mem::take(&mut self.warnings);
+        result.retry_after = self.retry_after;
+        result
+    }
+    
+    /// Instantiate the module and call one of its exported functions
+    ///
+    /// The call runs against a copy of the transform state. State changes and
+    /// pushed CDC messages are only kept if the call completes without trapping
+    /// and returns a non-negative status.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the exported function
+    ///
+    /// # Returns
+    ///
+    /// The status code returned by the function
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module cannot be instantiated or the call traps
+    fn call_export(&mut self, name: &str) -> Result<i32> {
+        let context = HostContext {
+            height: self.current_height,
+            hash: self.current_hash.clone(),
+            prev_hash: self.prev_hash.clone(),
+            state: self.state.clone(),
+            cdc_messages: Vec::new(),
+            view_functions: std::mem::take(&mut self.view_functions),
+            view_cache: std::mem::take(&mut self.view_cache),
+            view_timeout: self.view_timeout,
+            view_deadline: self.view_budget.map(|budget| Instant::now() + budget),
+            view_fetcher: self.view_fetcher.clone(),
+            block_raw: self.block_raw.take(),
+            block_fetcher: self.block_fetcher.clone(),
+            load_buffer: Vec::new(),
+            warnings: Vec::new(),
+            retry_after: None,
+            view_failure: None,
+        };
+        
+        let mut store = Store::new(&self.engine, context);
+        let result = if self.catch_panics {
+            panic::catch_unwind(AssertUnwindSafe(|| self.instantiate_and_call(&mut store, name)))
+                .unwrap_or_else(|payload| Err(Error::Wasm(format!(
+                    "Host function panicked during {} of {}: {}",
+                    name,
+                    self.name,
+                    panic_message(payload.as_ref())
+                )).into()))
+        } else {
+            self.instantiate_and_call(&mut store, name)
+        };
+        
+        // Hand the view functions back whether or not the call succeeded
+        let context = store.into_data();
+        self.view_functions = context.view_functions;
+        self.view_cache = context.view_cache;
+        self.block_raw = context.block_raw;
+        self.view_failure = context.view_failure;
+        
+        if matches!(result, Ok(code) if code >= 0) {
+            self.warnings.extend(context.warnings);
+            self.retry_after = context.retry_after;
+            
+            // A call that asks to be retried is not committed
+            if self.retry_after.is_some() {
+                return result;
+            }
+            
+            self.state = context.state;
+            let messages: Vec<CdcMessage> = context.cdc_messages
+                .into_iter()
+                .filter(|message| !(self.suppress_noop_updates && message.is_noop_update()))
+                .map(|message| self.apply_table_source(message))
+                .map(|message| self.apply_source_prefix(message))
+                .collect();
+            self.cdc_messages.extend(messages);
+        }
+        
+        result
+    }
+    
+    /// Classify a failed call by whether a view failed during it
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error the call failed with
+    ///
+    /// # Returns
+    ///
+    /// An `Error::ViewAccess` naming the failed view if a view call failed
+    /// or timed out, the error unchanged otherwise
+    fn classify_failure(&self, error: anyhow::Error) -> anyhow::Error {
+        match &self.view_failure {
+            Some(failure) => Error::ViewAccess(format!("{} after {}", error, failure)).into(),
+            None => error,
+        }
+    }
+    
+    /// Stamp a pushed message with the source configured for its table, as
+    /// configured by `set_table_sources`
+    fn apply_table_source(&self, mut message: CdcMessage) -> CdcMessage {
+        if let Some(source) = self.table_sources.get(&message.payload.table) {
+            message.header.source = source.clone();
+        }
+        
+        message
+    }
+    
+    /// Prefix the key and table of a pushed message with its source, as
+    /// configured by `set_source_prefix`
+    fn apply_source_prefix(&self, mut message: CdcMessage) -> CdcMessage {
+        if message.payload.operation.is_control() {
+            return message;
+        }
+        
+        if self.prefix_keys {
+            message.payload.key = format!("{}:{}", message.header.source, message.payload.key);
+        }
+        if self.prefix_tables {
+            message.payload.table = format!("{}_{}", message.header.source, message.payload.table);
+        }
+        
+        message
+    }
+    
+    /// Instantiate the module in a store and call an exported function
+    fn instantiate_and_call(&self, store: &mut Store<HostContext>, name: &str) -> Result<i32> {
+        let linker = Self::create_linker(&self.engine)?;
+        
+        let instance = linker.instantiate(&mut *store, &self.module)
+            .map_err(|e| anyhow!("Failed to instantiate WASM module {}: {}", self.name, e))?;
+        
+        let func = instance.get_typed_func::<(), i32>(&mut *store, name)
+            .map_err(|e| anyhow!("Failed to get {} function of {}: {}", name, self.name, e))?;
+        
+        // A trap, such as a panicking transform reaching `unreachable`, only
+        // fails this call; the next call starts from a fresh instance
+        func.call(&mut *store, ()).map_err(|e| match e.downcast_ref::<Trap>() {
+            Some(trap) => Error::Wasm(format!("{} function of {} trapped: {}", name, self.name, trap)).into(),
+            None => anyhow!("Failed to call {} function of {}: {}", name, self.name, e),
+        })
+    }
+    
+    /// Create a linker providing the `env` host functions
+    ///
+    /// Values passed from the guest use the arraybuffer layout (a little-endian
+    /// u32 length followed by the data). Functions returning variable-length
+    /// data stage it in the host and return its length; the guest then copies
+    /// it into its own memory with `__load`. The `_status` variants return a
+    /// status instead and write the length as a little-endian u64 to a guest
+    /// pointer, so lengths are never confused with errors.
+    fn create_linker(engine: &Engine) -> Result<Linker<HostContext>> {
+        let mut linker = Linker::new(engine);
+        
+        linker.func_wrap("env", "__load", |mut caller: Caller<'_, HostContext>, output: i32| -> Result<()> {
+            let data = std::mem::take(&mut caller.data_mut().load_buffer);
+            let memory = get_memory(&mut caller)?;
+            memory.write(&mut caller, output as u32 as usize, &data)?;
+            Ok(())
+        })?;
+        
+        linker.func_wrap("env", "__view", |mut caller: Caller<'_, HostContext>, view_name: i32, input: i32| -> Result<i32> {
+            match call_view(&mut caller, view_name, input)? {
+                Ok(data) => Ok(stage(caller.data_mut(), data)),
+                Err(status) => Ok(status),
+            }
+        })?;
+        
+        linker.func_wrap("env", "__view_status", |mut caller: Caller<'_, HostContext>, view_name: i32, input: i32, length: i32| -> Result<i32> {
+            match call_view(&mut caller, view_name, input)? {
+                Ok(data) => {
+                    write_length(&mut caller, length, data.len())?;
+                    stage(caller.data_mut(), data);
+                    Ok(crate::VIEW_OK)
+                }
+                Err(status) => Ok(status),
+            }
+        })?;
+        
+        linker.func_wrap("env", "__stdout", |mut caller: Caller<'_, HostContext>, s: i32| -> Result<()> {
+            let data = read_arraybuffer(&mut caller, s)?;
+            print!("{}", String::from_utf8_lossy(&data));
+            Ok(())
+        })?;
+        
+        linker.func_wrap("env", "__stderr", |mut caller: Caller<'_, HostContext>, s: i32| -> Result<()> {
+            let data = read_arraybuffer(&mut caller, s)?;
+            eprint!("{}", String::from_utf8_lossy(&data));
+            Ok(())
+        })?;
+        
+        linker.func_wrap("env", "__height", |caller: Caller<'_, HostContext>| -> i32 {
+            caller.data().height as i32
+        })?;
+        
+        linker.func_wrap("env", "__block_hash", |mut caller: Caller<'_, HostContext>| -> i32 {
+            let context = caller.data_mut();
+            let hash = context.hash.clone();
+            stage(context, hash)
+        })?;
+        
+        linker.func_wrap("env", "__block_hash_status", |mut caller: Caller<'_, HostContext>, length: i32| -> Result<i32> {
+            let hash = caller.data().hash.clone();
+            write_length(&mut caller, length, hash.len())?;
+            stage(caller.data_mut(), hash);
+            Ok(crate::VIEW_OK)
+        })?;
+        
+        linker.func_wrap("env", "__prev_block_hash_status", |mut caller: Caller<'_, HostContext>, length: i32| -> Result<i32> {
+            let hash = caller.data().prev_hash.clone();
+            write_length(&mut caller, length, hash.len())?;
+            stage(caller.data_mut(), hash);
+            Ok(crate::VIEW_OK)
+        })?;
+        
+        linker.func_wrap("env", "__get_block_raw", |mut caller: Caller<'_, HostContext>| -> Result<i32> {
+            let context = caller.data_mut();
+            
+            match current_block_raw(context)? {
+                Some(raw) => {
+                    let raw = raw.to_vec();
+                    Ok(stage(context, raw))
+                }
+                None => Ok(0),
+            }
+        })?;
+        
+        // Transaction IDs are staged in block order, 32 bytes each
+        linker.func_wrap("env", "__get_block_txids", |mut caller: Caller<'_, HostContext>| -> Result<i32> {
+            let context = caller.data_mut();
+            
+            match current_block_raw(context)?.map(block::transaction_ids) {
+                Some(Ok(txids)) => {
+                    let data = txids.concat();
+                    Ok(stage(context, data))
+                }
+                Some(Err(_)) => Ok(-1),
+                None => Ok(0),
+            }
+        })?;
+        
+        linker.func_wrap("env", "__push_cdc_message", |mut caller: Caller<'_, HostContext>, msg: i32| -> Result<i32> {
+            let data = read_arraybuffer(&mut caller, msg)?;
+            
+            match serde_json::from_slice::<CdcMessage>(&data) {
+                Ok(message) => {
+                    caller.data_mut().cdc_messages.push(message);
+                    Ok(0)
+                }
+                Err(_) => Ok(-1),
+            }
+        })?;
+        
+        // Snapshot markers are pushed like any other CDC message, in order
+        linker.func_wrap("env", "__mark_snapshot_complete", |mut caller: Caller<'_, HostContext>, table: i32| -> Result<i32> {
+            let table = read_arraybuffer(&mut caller, table)?;
+            let table = match String::from_utf8(table) {
+                Ok(table) if !table.is_empty() => table,
+                _ => return Ok(-1),
+            };
+            
+            let context = caller.data_mut();
+            let marker = snapshot_marker(&table, context.height, &context.hash);
+            context.cdc_messages.push(marker);
+            Ok(0)
+        })?;
+        
+        linker.func_wrap("env", "__warn", |mut caller: Caller<'_, HostContext>, message: i32| -> Result<i32> {
+            let message = read_arraybuffer(&mut caller, message)?;
+            caller.data_mut().warnings.push(String::from_utf8_lossy(&message).into_owned());
+            Ok(0)
+        })?;
+        
+        // The longest delay requested during a call wins
+        linker.func_wrap("env", "__request_retry", |mut caller: Caller<'_, HostContext>, delay_ms: i32, reason: i32| -> Result<i32> {
+            if delay_ms < 0 {
+                return Ok(-1);
+            }
+            
+            let reason = read_arraybuffer(&mut caller, reason)?;
+            let context = caller.data_mut();
+            context.warnings.push(String::from_utf8_lossy(&reason).into_owned());
+            context.retry_after = context.retry_after.max(Some(Duration::from_millis(delay_ms as u64)));
+            Ok(0)
+        })?;
+        
+        linker.func_wrap("env", "__get_state", |mut caller: Caller<'_, HostContext>, key: i32| -> Result<i32> {
+            let key = read_arraybuffer(&mut caller, key)?;
+            let context = caller.data_mut();
+            
+            match context.state.get(&key).cloned() {
+                Some(value) => Ok(stage(context, value)),
+                None => Ok(0),
+            }
+        })?;
+        
+        linker.func_wrap("env", "__set_state", |mut caller: Caller<'_, HostContext>, key: i32, value: i32| -> Result<i32> {
+            let key = read_arraybuffer(&mut caller, key)?;
+            let value = read_arraybuffer(&mut caller, value)?;
+            caller.data_mut().state.set(key, value);
+            Ok(0)
+        })?;
+        
+        linker.func_wrap("env", "__delete_state", |mut caller: Caller<'_, HostContext>, key: i32| -> Result<i32> {
+            let key = read_arraybuffer(&mut caller, key)?;
+            Ok(caller.data_mut().state.delete(&key) as i32)
+        })?;
+        
+        Ok(linker)
     }
     
     /// Compute inverse CDC messages for a block
@@ -272,11 +1280,12 @@ impl WasmRuntime {
         if let Some(messages) = self.cdc_cache.get(&height) {
             let mut inverse = Vec::new();
             
-            // Process messages in reverse order
-            for message in messages.iter().rev() {
+            // Process messages in reverse order; markers have no row change to undo
+            for message in messages.iter().rev().filter(|m| !m.payload.operation.is_control()) {
                 let inverse_message = self.invert_cdc_message(message, height - 1)?;
                 inverse.push(inverse_message);
             }
+            stamp_event_ids(&mut inverse);
             
             Ok(inverse)
         } else {
@@ -300,6 +1309,12 @@ impl WasmRuntime {
     /// Returns an error if the message cannot be inverted
     fn invert_cdc_message(&self, message: &CdcMessage, new_height: u32) -> Result<CdcMessage> {
         let (operation, before, after) = match message.payload.operation {
+            CdcOperation::SnapshotComplete
+            | CdcOperation::Heartbeat
+            | CdcOperation::ReorgBegin
+            | CdcOperation::ReorgEnd => {
+                return Err(anyhow!("Control messages cannot be inverted"));
+            }
             CdcOperation::Create => (
                 CdcOperation::Delete,
                 message.payload.after.clone(),
@@ -324,6 +1339,7 @@ impl WasmRuntime {
                 block_height: new_height,
                 block_hash: hex::encode(&self.current_hash),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation,
@@ -348,17 +1364,104 @@ impl WasmRuntime {
     
     /// Register a view function
     ///
+    /// Calls to `__view` from the transform with this name are answered by
+    /// `func`, which makes it possible to run a transform against canned view
+    /// responses without a live metashrew. Registering a name again replaces
+    /// the previous function.
+    ///
     /// # Arguments
     ///
     /// * `name` - The name of the view function
     /// * `func` - The view function implementation
-    pub fn register_view_function(
-        &self,
-        _name: &str,
-        _func: Box<dyn Fn(&[u8]) -> Result<Vec<u8>> + Send>,
-    ) {
-        // In a real implementation, we would register the view function
-        // For now, this is a stub
+    pub fn register_view<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>> + Send + 'static,
+    {
+        self.view_cache.invalidate_view(name);
+        self.view_functions.insert(name.to_string(), Box::new(func));
+    }
+    
+    /// Register a boxed view function
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the view function
+    /// * `func` - The view function implementation
+    pub fn register_view_function(&mut self, name: &str, func: ViewFunction) {
+        self.view_cache.invalidate_view(name);
+        self.view_functions.insert(name.to_string(), func);
+    }
+    
+    /// Set where the views without a registered function are answered from
+    ///
+    /// # Arguments
+    ///
+    /// * `fetcher` - The view source, or None to only answer registered views
+    pub fn set_view_fetcher(&mut self, fetcher: Option<ViewFetcher>) {
+        self.view_fetcher = fetcher;
+    }
+    
+    /// Set how long a fetched view may take
+    ///
+    /// The view fetcher is told how long it has, and a call that does not
+    /// answer in time returns `VIEW_TIMED_OUT` to the transform instead of
+    /// blocking the block indefinitely. Registered view functions are called
+    /// directly and are not bounded.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The maximum duration of a view call, or None to wait
+    ///   for as long as the view takes
+    pub fn set_view_timeout(&mut self, timeout: Option<Duration>) {
+        self.view_timeout = timeout;
+    }
+    
+    /// Set how long all the fetched views of one transform call may take
+    ///
+    /// Each view call may take at most what is left of the budget, and once
+    /// it is used up further calls return `VIEW_TIMED_OUT` without being
+    /// fetched, so a block with many slow views is bounded as a whole.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - The total duration of the view calls, or None for no limit
+    pub fn set_view_budget(&mut self, budget: Option<Duration>) {
+        self.view_budget = budget;
+    }
+    
+    /// Set the number of view results kept across blocks
+    ///
+    /// Results are keyed by view name, parameters and the height the view was
+    /// called at, so repeated calls for the same historical height are
+    /// answered without calling the view function again. A capacity of 0
+    /// disables the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of cached view results
+    pub fn set_view_cache_capacity(&mut self, capacity: usize) {
+        self.view_cache.set_capacity(capacity);
+    }
+    
+    /// Drop the cached view results computed above a block height
+    ///
+    /// Called on a reorg with the common ancestor height, since the blocks
+    /// above it are replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The highest block height whose results are kept
+    pub fn invalidate_view_cache_above(&mut self, height: u32) {
+        self.view_cache.invalidate_above(height);
+    }
+    
+    /// Get the view cache usage counters
+    ///
+    /// # Returns
+    ///
+    /// The hit and miss counts and the number of cached view results
+    pub fn view_cache_stats(&self) -> ViewCacheStats {
+        self.view_cache.stats()
     }
     
     /// Create a mock WasmRuntime for testing
@@ -394,6 +1497,281 @@ impl WasmRuntime {
     }
 }
 
+/// Get the exported memory of the calling instance
+#[cfg(feature = "host")]
+fn get_memory(caller: &mut Caller<'_, HostContext>) -> Result<Memory> {
+    caller.get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow!("WASM module does not export memory"))
+}
+
+/// Read a length-prefixed value from the calling instance's memory
+///
+/// The pointer and length are checked against the size of the memory before
+/// anything is allocated, so a module passing a bad pointer or an implausible
+/// length fails with `Error::MemoryAllocation`.
+#[cfg(feature = "host")]
+fn read_arraybuffer(caller: &mut Caller<'_, HostContext>, ptr: i32) -> Result<Vec<u8>> {
+    let memory = get_memory(caller)?;
+    let memory_size = memory.data_size(&*caller);
+    let offset = ptr as u32 as usize;
+    
+    let available = memory_size.checked_sub(offset)
+        .and_then(|available| available.checked_sub(4))
+        .ok_or_else(|| Error::MemoryAllocation(format!(
+            "Pointer {:#x} is outside the module's {} bytes of memory",
+            offset,
+            memory_size
+        )))?;
+    
+    let mut length = [0u8; 4];
+    memory.read(&mut *caller, offset, &mut length)?;
+    
+    let length = crate::exports::arraybuffer_len(length);
+    if length > available {
+        return Err(Error::MemoryAllocation(format!(
+            "Length {} at pointer {:#x} runs past the module's {} bytes of memory",
+            length,
+            offset,
+            memory_size
+        )).into());
+    }
+    
+    let mut data = vec![0u8; length];
+    memory.read(&mut *caller, offset + 4, &mut data)?;
+    
+    Ok(data)
+}
+
+/// Call a view function for the guest, answering from the view cache if possible
+///
+/// # Arguments
+///
+/// * `caller` - The calling instance
+/// * `view_name` - Pointer to the view name in the guest's memory
+/// * `input` - Pointer to the view parameters in the guest's memory
+///
+/// # Returns
+///
+/// The view result, or the status to return to the guest if the view is not
+/// registered, fails or times out
+///
+/// # Errors
+///
+/// Returns an error if the view name or parameters cannot be read
+#[cfg(feature = "host")]
+fn call_view(caller: &mut Caller<'_, HostContext>, view_name: i32, input: i32) -> Result<std::result::Result<Vec<u8>, i32>> {
+    let view_name = String::from_utf8(read_arraybuffer(caller, view_name)?)
+        .map_err(|e| anyhow!("Invalid view name: {}", e))?;
+    let input = read_arraybuffer(caller, input)?;
+    
+    let context = caller.data_mut();
+    if let Some(data) = context.view_cache.get(&view_name, &input, context.height) {
+        return Ok(Ok(data));
+    }
+    
+    let result = if let Some(func) = context.view_functions.get(&view_name) {
+        match panic::catch_unwind(AssertUnwindSafe(|| func(&input))) {
+            Ok(result) => result.map_err(|_| crate::VIEW_FAILED),
+            Err(_) => Err(crate::VIEW_FAILED),
+        }
+    } else if let Some(fetcher) = &context.view_fetcher {
+        match view_call_timeout(context) {
+            Some(timeout) if timeout.is_zero() => Err(crate::VIEW_TIMED_OUT),
+            timeout => match panic::catch_unwind(AssertUnwindSafe(|| fetcher(&view_name, &input, context.height, timeout))) {
+                Ok(Ok(Some(data))) => Ok(data),
+                Ok(Ok(None)) => Err(crate::VIEW_TIMED_OUT),
+                Ok(Err(_)) | Err(_) => Err(crate::VIEW_FAILED),
+            },
+        }
+    } else {
+        return Ok(Err(crate::VIEW_NOT_REGISTERED));
+    };
+    
+    // Only successful results are cached, so failures are retried
+    match &result {
+        Ok(data) => context.view_cache.insert(&view_name, &input, context.height, data.clone()),
+        Err(status) => {
+            let reason = if *status == crate::VIEW_TIMED_OUT { "timed out" } else { "failed" };
+            context.view_failure = Some(format!("view {} {}", view_name, reason));
+        }
+    }
+    
+    Ok(result)
+}
+
+/// Get the raw bytes of the block being processed, fetching them if needed
+///
+/// The fetched block is kept in the context, so later reads during the same
+/// block do not fetch it again.
+///
+/// # Arguments
+///
+/// * `context` - The host context of the call
+///
+/// # Returns
+///
+/// The raw block, or None if it was not provided and there is no fetcher
+///
+/// # Errors
+///
+/// Returns an `Error::ViewAccess` if the fetcher fails, which traps the call
+#[cfg(feature = "host")]
+fn current_block_raw(context: &mut HostContext) -> Result<Option<&[u8]>> {
+    let height = context.height;
+    let provided = matches!(&context.block_raw, Some((raw_height, _)) if *raw_height == height);
+    
+    if !provided {
+        let Some(fetcher) = &context.block_fetcher else {
+            return Ok(None);
+        };
+        
+        match fetcher(height) {
+            Ok(raw) => context.block_raw = Some((height, raw)),
+            Err(e) => {
+                context.view_failure = Some(format!("fetch of raw block {} failed", height));
+                return Err(Error::ViewAccess(format!("Failed to fetch raw block {}: {}", height, e)).into());
+            }
+        }
+    }
+    
+    Ok(context.block_raw.as_ref().map(|(_, raw)| raw.as_slice()))
+}
+
+/// Get how long the next fetched view may take
+///
+/// # Arguments
+///
+/// * `context` - The host context of the call
+///
+/// # Returns
+///
+/// The view timeout, cut down to what is left of the view budget, or None
+/// if neither is set
+#[cfg(feature = "host")]
+fn view_call_timeout(context: &HostContext) -> Option<Duration> {
+    let remaining = context.view_deadline
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    
+    match (context.view_timeout, remaining) {
+        (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+        (timeout, remaining) => timeout.or(remaining),
+    }
+}
+
+/// Get the message of a caught panic
+///
+/// # Arguments
+///
+/// * `payload` - The panic payload
+///
+/// # Returns
+///
+/// The panic message, or a placeholder if the payload is not a string
+#[cfg(feature = "host")]
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload.downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Write the length of a staged result to the guest as a little-endian u64
+#[cfg(feature = "host")]
+fn write_length(caller: &mut Caller<'_, HostContext>, ptr: i32, length: usize) -> Result<()> {
+    let memory = get_memory(caller)?;
+    memory.write(&mut *caller, ptr as u32 as usize, &(length as u64).to_le_bytes())
+        .map_err(|e| Error::MemoryAllocation(format!("Failed to write result length to pointer {:#x}: {}", ptr as u32, e)))?;
+    Ok(())
+}
+
+/// Stage data for the guest's next `__load` call and return its length
+#[cfg(feature = "host")]
+fn stage(context: &mut HostContext, data: Vec<u8>) -> i32 {
+    let length = data.len() as i32;
+    context.load_buffer = data;
+    length
+}
+
+/// Stamp each CDC message with its content-addressed event ID
+///
+/// # Arguments
+///
+/// * `messages` - The CDC messages of one block, in the order they were pushed
+#[cfg(feature = "host")]
+fn stamp_event_ids(messages: &mut [CdcMessage]) {
+    for (sequence, message) in messages.iter_mut().enumerate() {
+        message.stamp_event_id(sequence as u32);
+    }
+}
+
+/// Describe how two runs' CDC messages differ
+///
+/// # Arguments
+///
+/// * `first` - The CDC messages of the first run
+/// * `second` - The CDC messages of the second run
+///
+/// # Returns
+///
+/// One entry per differing message position showing both canonical JSON
+/// forms, or None if the runs agree
+#[cfg(feature = "host")]
+fn diff_cdc_messages(first: &[CdcMessage], second: &[CdcMessage]) -> Option<String> {
+    let canonical = |messages: &[CdcMessage], index: usize| {
+        messages.get(index).map_or_else(|| "<none>".to_string(), CdcMessage::canonical_json)
+    };
+    
+    let diff: Vec<String> = (0..first.len().max(second.len()))
+        .map(|index| (index, canonical(first, index), canonical(second, index)))
+        .filter(|(_, a, b)| a != b)
+        .map(|(index, a, b)| format!("  message {}:\n    first:  {}\n    second: {}", index, a, b))
+        .collect();
+    
+    if diff.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "first run produced {} messages, second run produced {}\n{}",
+            first.len(),
+            second.len(),
+            diff.join("\n")
+        ))
+    }
+}
+
+/// Build the control message marking the end of a table's initial snapshot
+///
+/// # Arguments
+///
+/// * `table` - The table whose snapshot is complete
+/// * `height` - The current block height
+/// * `hash` - The current block hash
+///
+/// # Returns
+///
+/// The snapshot marker CDC message
+#[cfg(feature = "host")]
+fn snapshot_marker(table: &str, height: u32, hash: &[u8]) -> CdcMessage {
+    CdcMessage {
+        header: CdcHeader {
+            source: "debshrew".to_string(),
+            timestamp: Utc::now(),
+            block_height: height,
+            block_hash: hex::encode(hash),
+            transaction_id: None,
+            event_id: None,
+        },
+        payload: CdcPayload {
+            operation: CdcOperation::SnapshotComplete,
+            table: table.to_string(),
+            key: String::new(),
+            before: None,
+            after: None,
+        },
+    }
+}
+
 #[cfg(all(test, feature = "host"))]
 mod tests {
     use super::*;
@@ -410,6 +1788,7 @@ mod tests {
                 block_height: 123,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -439,6 +1818,7 @@ mod tests {
                 block_height: 123,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Update,
@@ -471,6 +1851,7 @@ mod tests {
                 block_height: 123,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Delete,
@@ -492,6 +1873,832 @@ mod tests {
         assert_eq!(inverse.payload.before, None);
         assert_eq!(inverse.payload.after, delete_message.payload.before);
     }
+    
+    /// A transform that calls the `balance` view with input `alice` and
+    /// stores the result under the `result` state key
+    fn view_calling_runtime() -> WasmRuntime {
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__view" (func $view (param i32 i32) (result i32)))
+                (import "env" "__load" (func $load (param i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\07\00\00\00balance")
+                (data (i32.const 16) "\05\00\00\00alice")
+                (data (i32.const 32) "\06\00\00\00result")
+                (func (export "process_block") (result i32)
+                    (local $len i32)
+                    (local.set $len (call $view (i32.const 0) (i32.const 16)))
+                    (if (i32.le_s (local.get $len) (i32.const 0))
+                        (then (return (i32.const -1))))
+                    (i32.store (i32.const 64) (local.get $len))
+                    (call $load (i32.const 68))
+                    (drop (call $set_state (i32.const 32) (i32.const 64)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+    }
+    
+    #[test]
+    fn test_exported_and_imported_functions() {
+        let runtime = WasmRuntime::for_testing().unwrap();
+        let exports = runtime.exported_functions();
+        assert!(exports.contains(&"process_block".to_string()));
+        assert!(exports.contains(&"rollback".to_string()));
+        assert!(!exports.contains(&"memory".to_string()));
+        assert!(runtime.imported_functions().is_empty());
+        
+        let runtime = view_calling_runtime();
+        assert_eq!(
+            runtime.imported_functions(),
+            vec![
+                ("env".to_string(), "__view".to_string()),
+                ("env".to_string(), "__load".to_string()),
+                ("env".to_string(), "__set_state".to_string()),
+            ]
+        );
+        assert!(runtime.unexpected_imports().is_empty());
+        
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__emit" (func $emit (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "process_block") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        // A module importing an unknown host function cannot be instantiated,
+        // so inspect it before any call
+        let runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        assert_eq!(runtime.unexpected_imports(), vec![("env".to_string(), "__emit".to_string())]);
+    }
+    
+    #[test]
+    fn test_registered_view_is_called_by_transform() {
+        let mut runtime = view_calling_runtime();
+        runtime.register_view("balance", |input| {
+            Ok([b"balance of ".as_slice(), input].concat())
+        });
+        
+        let result = runtime.process_block(1, vec![0; 32]).unwrap();
+        
+        assert_eq!(result.state_snapshot.get(b"result"), Some(&b"balance of alice".to_vec()));
+        assert_eq!(runtime.get_state().get(b"result"), Some(&b"balance of alice".to_vec()));
+    }
+    
+    #[test]
+    fn test_view_status_separates_empty_results_from_errors() {
+        // A transform storing the status and u64 length reported for the
+        // `balance` view under the `status` and `len` state keys
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__view_status" (func $view_status (param i32 i32 i32) (result i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\07\00\00\00balance")
+                (data (i32.const 16) "\00\00\00\00")
+                (data (i32.const 32) "\03\00\00\00len")
+                (data (i32.const 48) "\06\00\00\00status")
+                (func (export "process_block") (result i32)
+                    (i32.store (i32.const 60) (i32.const 8))
+                    (i32.store (i32.const 96) (i32.const 4))
+                    (i32.store (i32.const 100) (call $view_status (i32.const 0) (i32.const 16) (i32.const 64)))
+                    (drop (call $set_state (i32.const 32) (i32.const 60)))
+                    (drop (call $set_state (i32.const 48) (i32.const 96)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        let run = |view: Option<ViewFunction>| {
+            let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+            if let Some(view) = view {
+                runtime.view_functions.insert("balance".to_string(), view);
+            }
+            let state = runtime.process_block(1, vec![0; 32]).unwrap().state_snapshot;
+            let status = i32::from_le_bytes(state.get(b"status").unwrap()[..].try_into().unwrap());
+            let length = u64::from_le_bytes(state.get(b"len").unwrap()[..].try_into().unwrap());
+            (status, length)
+        };
+        
+        assert_eq!(run(Some(Box::new(|_| Ok(Vec::new())))), (crate::VIEW_OK, 0));
+        assert_eq!(run(Some(Box::new(|_| Ok(vec![7; 5])))), (crate::VIEW_OK, 5));
+        assert_eq!(run(Some(Box::new(|_| Err(anyhow!("metashrew unavailable"))))), (crate::VIEW_FAILED, 0));
+        assert_eq!(run(None), (crate::VIEW_NOT_REGISTERED, 0));
+    }
+    
+    #[test]
+    fn test_view_timeout_is_cut_to_view_budget() {
+        use std::sync::Mutex;
+        
+        // A transform calling the `balance` view three times and storing the
+        // last status under the `status` state key
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__view_status" (func $view_status (param i32 i32 i32) (result i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\07\00\00\00balance")
+                (data (i32.const 16) "\00\00\00\00")
+                (data (i32.const 48) "\06\00\00\00status")
+                (func (export "process_block") (result i32)
+                    (drop (call $view_status (i32.const 0) (i32.const 16) (i32.const 64)))
+                    (drop (call $view_status (i32.const 0) (i32.const 16) (i32.const 64)))
+                    (i32.store (i32.const 96) (i32.const 4))
+                    (i32.store (i32.const 100) (call $view_status (i32.const 0) (i32.const 16) (i32.const 64)))
+                    (drop (call $set_state (i32.const 48) (i32.const 96)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        // A hung view that uses up whatever time it is given
+        let timeouts = Arc::new(Mutex::new(Vec::new()));
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        let fetched = timeouts.clone();
+        runtime.set_view_fetcher(Some(Arc::new(move |_, _, _, timeout: Option<Duration>| {
+            fetched.lock().unwrap().push(timeout);
+            std::thread::sleep(timeout.unwrap());
+            Ok(None)
+        })));
+        runtime.set_view_timeout(Some(Duration::from_millis(200)));
+        runtime.set_view_budget(Some(Duration::from_millis(250)));
+        
+        let started = Instant::now();
+        let state = runtime.process_block(1, vec![0; 32]).unwrap().state_snapshot;
+        assert!(started.elapsed() < Duration::from_millis(400));
+        
+        // The second call only gets what is left of the budget, and the third is not made
+        let timeouts = timeouts.lock().unwrap();
+        assert_eq!(timeouts.len(), 2);
+        assert_eq!(timeouts[0], Some(Duration::from_millis(200)));
+        assert!(timeouts[1].unwrap() <= Duration::from_millis(50));
+        
+        let status = i32::from_le_bytes(state.get(b"status").unwrap()[..].try_into().unwrap());
+        assert_eq!(status, crate::VIEW_TIMED_OUT);
+    }
+    
+    #[test]
+    fn test_schema_drift_fails_block() {
+        let message = |after: serde_json::Value| {
+            let message = CdcMessage {
+                header: CdcHeader {
+                    source: "test".to_string(),
+                    timestamp: Utc::now(),
+                    block_height: 1,
+                    block_hash: "00".to_string(),
+                    transaction_id: None,
+                    event_id: None,
+                },
+                payload: CdcPayload {
+                    operation: CdcOperation::Create,
+                    table: "balances".to_string(),
+                    key: "alice".to_string(),
+                    before: None,
+                    after: Some(after),
+                },
+            };
+            crate::exports::to_arraybuffer_layout(serde_json::to_vec(&message).unwrap())
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect::<String>()
+        };
+        
+        // A transform that adds a `memo` field to its rows after block 1
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__height" (func $height (result i32)))
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "{}")
+                (data (i32.const 2048) "{}")
+                (func (export "process_block") (result i32)
+                    (if (result i32) (i32.eq (call $height) (i32.const 1))
+                        (then (call $push (i32.const 1024)))
+                        (else (call $push (i32.const 2048))))
+                )
+            )
+            "#,
+            message(serde_json::json!({ "amount": 1 })),
+            message(serde_json::json!({ "amount": 2, "memo": "hi" })),
+        ))
+        .unwrap();
+        
+        // Without enforcement the drift goes unnoticed
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        runtime.process_block(1, vec![1; 32]).unwrap();
+        runtime.process_block(2, vec![2; 32]).unwrap();
+        
+        // With enforcement, block 1 fixes the schema and block 2 fails
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        runtime.set_enforce_schemas(true);
+        runtime.process_block(1, vec![1; 32]).unwrap();
+        let error = runtime.process_block(2, vec![2; 32]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Table balances in <bytes> changed fields at block 2: expected {\"amount\"}, got {\"amount\", \"memo\"}"
+        );
+        assert!(runtime.cdc_messages.is_empty());
+        assert!(runtime.compute_inverse_messages(2).is_err());
+        
+        // A registered schema applies from the first block
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        runtime.set_enforce_schemas(true);
+        runtime.register_table_schema("balances", &["amount", "memo"]);
+        assert!(runtime.process_block(1, vec![1; 32]).is_err());
+        runtime.process_block(2, vec![2; 32]).unwrap();
+    }
+    
+    #[test]
+    fn test_out_of_range_pointers_fail_cleanly() {
+        let push_from = |ptr: u32| {
+            let wasm_bytes = wat::parse_str(format!(
+                r#"
+                (module
+                    (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "\ff\ff\ff\ff")
+                    (func (export "process_block") (result i32)
+                        (call $push (i32.const {}))
+                    )
+                )
+                "#,
+                ptr
+            ))
+            .unwrap();
+            WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+        };
+        
+        // Pointers past the end of memory, one too close to the end to hold a
+        // length, and an in-range pointer with an implausible length
+        for (ptr, expected) in [
+            (0x7fff_fff0, "is outside the module's 65536 bytes of memory"),
+            (65534, "is outside the module's 65536 bytes of memory"),
+            (0, "Length 4294967295 at pointer 0x0 runs past the module's 65536 bytes of memory"),
+        ] {
+            let mut runtime = push_from(ptr);
+            let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+            assert!(format!("{:?}", error).contains(expected), "{:?}", error);
+            assert!(runtime.cdc_messages.is_empty());
+        }
+    }
+    
+    #[test]
+    fn test_unregistered_view_fails_transform() {
+        let mut runtime = view_calling_runtime();
+        
+        assert!(runtime.process_block(1, vec![0; 32]).is_err());
+        assert!(runtime.get_state().is_empty());
+        
+        // A failing view function fails the transform the same way
+        runtime.register_view("balance", |_| Err(anyhow!("metashrew unavailable")));
+        assert!(runtime.process_block(1, vec![0; 32]).is_err());
+        assert!(runtime.get_state().is_empty());
+    }
+    
+    #[test]
+    fn test_view_failures_are_classified_apart_from_transform_errors() {
+        let mut runtime = view_calling_runtime();
+        
+        // The transform gives up because the view failed
+        runtime.register_view("balance", |_| Err(anyhow!("metashrew unavailable")));
+        let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+        match error.downcast_ref::<Error>() {
+            Some(Error::ViewAccess(message)) => assert!(message.contains("view balance failed"), "{}", message),
+            other => panic!("Expected a view access error, got {:?}", other),
+        }
+        
+        // The same code without a view failure is the transform's own error
+        let mut runtime = view_calling_runtime();
+        let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::Transform(_))));
+        
+        // A view failure is forgotten once the view recovers
+        runtime.register_view("balance", |_| Ok(b"10".to_vec()));
+        runtime.process_block(1, vec![0; 32]).unwrap();
+    }
+    
+    #[test]
+    fn test_traps_and_panics_fail_only_the_block() {
+        // A transform that panics, compiled to `unreachable`, at block 1
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__height" (func $height (result i32)))
+                (memory (export "memory") 1)
+                (func (export "process_block") (result i32)
+                    (if (i32.eq (call $height) (i32.const 1))
+                        (then unreachable))
+                    (i32.const 0)
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        let mut runtime = WasmRuntime::from_bytes_named(&wasm_bytes, "panicking").unwrap();
+        
+        let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+        match error.downcast_ref::<Error>() {
+            Some(Error::Wasm(message)) => {
+                assert!(message.contains("process_block function of panicking trapped"), "{}", message);
+                assert!(message.contains("unreachable"), "{}", message);
+            }
+            other => panic!("Expected a WASM error, got {:?}", other),
+        }
+        
+        // The runtime is still usable for the next block
+        runtime.process_block(2, vec![0; 32]).unwrap();
+        
+        // A panicking view function fails the view instead of the process
+        let mut runtime = view_calling_runtime();
+        runtime.register_view("balance", |_| panic!("view glue bug"));
+        let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::ViewAccess(_))));
+        
+        // So does a panicking view fetcher
+        let mut runtime = view_calling_runtime();
+        runtime.set_view_fetcher(Some(Arc::new(|_, _, _, _| panic!("view fetcher bug"))));
+        let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+        match error.downcast_ref::<Error>() {
+            Some(Error::ViewAccess(message)) => assert!(message.contains("view balance failed"), "{}", message),
+            other => panic!("Expected a view access error, got {:?}", other),
+        }
+        
+        runtime.register_view("balance", |_| Ok(b"10".to_vec()));
+        runtime.process_block(1, vec![0; 32]).unwrap();
+    }
+    
+    /// A transform that pushes one message, and a second one if the `balance`
+    /// view returns more than five bytes
+    fn view_dependent_runtime() -> WasmRuntime {
+        let message = |key: &str| {
+            let message = CdcMessage {
+                header: CdcHeader {
+                    source: "test".to_string(),
+                    timestamp: Utc::now(),
+                    block_height: 1,
+                    block_hash: "00".to_string(),
+                    transaction_id: None,
+                    event_id: None,
+                },
+                payload: CdcPayload {
+                    operation: CdcOperation::Create,
+                    table: "balances".to_string(),
+                    key: key.to_string(),
+                    before: None,
+                    after: Some(serde_json::json!({ "amount": 1 })),
+                },
+            };
+            crate::exports::to_arraybuffer_layout(serde_json::to_vec(&message).unwrap())
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect::<String>()
+        };
+        
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__view" (func $view (param i32 i32) (result i32)))
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\07\00\00\00balance")
+                (data (i32.const 16) "\05\00\00\00alice")
+                (data (i32.const 1024) "{}")
+                (data (i32.const 2048) "{}")
+                (func (export "process_block") (result i32)
+                    (drop (call $push (i32.const 1024)))
+                    (if (i32.gt_s (call $view (i32.const 0) (i32.const 16)) (i32.const 5))
+                        (then (drop (call $push (i32.const 2048)))))
+                    (i32.const 0)
+                )
+            )
+            "#,
+            message("alice"),
+            message("bob"),
+        ))
+        .unwrap();
+        
+        WasmRuntime::from_bytes_named(&wasm_bytes, "view-dependent").unwrap()
+    }
+    
+    #[test]
+    fn test_noop_updates_are_suppressed() {
+        // A transform pushing an update that changes nothing, with its keys
+        // in a different order, then one that changes the amount
+        let message = |key: &str, before: serde_json::Value, after: serde_json::Value| {
+            let message = CdcMessage {
+                header: CdcHeader {
+                    source: "test".to_string(),
+                    timestamp: Utc::now(),
+                    block_height: 1,
+                    block_hash: "00".to_string(),
+                    transaction_id: None,
+                    event_id: None,
+                },
+                payload: CdcPayload {
+                    operation: CdcOperation::Update,
+                    table: "balances".to_string(),
+                    key: key.to_string(),
+                    before: Some(before),
+                    after: Some(after),
+                },
+            };
+            crate::exports::to_arraybuffer_layout(serde_json::to_vec(&message).unwrap())
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect::<String>()
+        };
+        
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "{}")
+                (data (i32.const 2048) "{}")
+                (func (export "process_block") (result i32)
+                    (drop (call $push (i32.const 1024)))
+                    (drop (call $push (i32.const 2048)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+            message(
+                "alice",
+                serde_json::json!({ "amount": 1, "owner": "alice" }),
+                serde_json::json!({ "owner": "alice", "amount": 1 }),
+            ),
+            message("bob", serde_json::json!({ "amount": 1 }), serde_json::json!({ "amount": 2 })),
+        ))
+        .unwrap();
+        
+        let keys = |suppress: bool| {
+            let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+            runtime.set_suppress_noop_updates(suppress);
+            let result = runtime.process_block(1, vec![0; 32]).unwrap();
+            result.cdc_messages.into_iter().map(|message| message.payload.key).collect::<Vec<_>>()
+        };
+        
+        assert_eq!(keys(false), vec!["alice", "bob"]);
+        assert_eq!(keys(true), vec!["bob"]);
+    }
+    
+    #[test]
+    fn test_deterministic_transform_passes_double_run() {
+        let mut runtime = view_dependent_runtime();
+        runtime.register_view("balance", |input| Ok(input.to_vec()));
+        
+        let result = runtime.process_block_twice(1, vec![0; 32]).unwrap();
+        assert_eq!(result.cdc_messages.len(), 1);
+    }
+    
+    #[test]
+    fn test_event_ids_are_stable_across_runs() {
+        let run = || {
+            let mut runtime = view_dependent_runtime();
+            runtime.register_view("balance", |_| Ok(b"enough".to_vec()));
+            runtime.process_block(1, vec![7; 32]).unwrap().cdc_messages
+        };
+        
+        let first: Vec<Option<String>> = run().into_iter().map(|m| m.header.event_id).collect();
+        let second: Vec<Option<String>> = run().into_iter().map(|m| m.header.event_id).collect();
+        
+        // Re-emitting the block yields the same IDs, which differ within the block
+        assert_eq!(first.len(), 2);
+        assert!(first.iter().all(Option::is_some));
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+    }
+    
+    #[test]
+    fn test_nondeterministic_transform_fails_double_run() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        // The view answers differently on every call, and the cache is off
+        // so the second run sees a different answer
+        let mut runtime = view_dependent_runtime();
+        runtime.set_view_cache_capacity(0);
+        let calls = AtomicUsize::new(0);
+        runtime.register_view("balance", move |input| {
+            Ok(input.repeat(calls.fetch_add(1, Ordering::SeqCst) + 1))
+        });
+        
+        let error = runtime.process_block_twice(1, vec![0; 32]).unwrap_err().to_string();
+        assert!(error.contains("view-dependent is nondeterministic at block 1"), "{}", error);
+        assert!(error.contains("first run produced 1 messages, second run produced 2"), "{}", error);
+        assert!(error.contains("message 1:"), "{}", error);
+        assert!(error.contains("first:  <none>"), "{}", error);
+        assert!(error.contains(r#""key":"bob""#), "{}", error);
+        assert!(!error.contains("message 0:"), "{}", error);
+        assert!(runtime.compute_inverse_messages(1).is_err());
+    }
+    
+    #[test]
+    fn test_source_prefix_is_applied_before_inversion() {
+        let mut runtime = view_dependent_runtime();
+        runtime.register_view("balance", |input| Ok(input.to_vec()));
+        runtime.set_source_prefix(true, true);
+        
+        let result = runtime.process_block(1, vec![0; 32]).unwrap();
+        assert_eq!(result.cdc_messages.len(), 1);
+        assert_eq!(result.cdc_messages[0].payload.key, "test:alice");
+        assert_eq!(result.cdc_messages[0].payload.table, "test_balances");
+        
+        // The inverse of the block deletes the prefixed row
+        let inverse = runtime.compute_inverse_messages(1).unwrap();
+        assert_eq!(inverse[0].payload.operation, CdcOperation::Delete);
+        assert_eq!(inverse[0].payload.key, "test:alice");
+        assert_eq!(inverse[0].payload.table, "test_balances");
+        
+        // Keys only
+        runtime.set_source_prefix(true, false);
+        let result = runtime.process_block(2, vec![0; 32]).unwrap();
+        assert_eq!(result.cdc_messages[0].payload.key, "test:alice");
+        assert_eq!(result.cdc_messages[0].payload.table, "balances");
+    }
+    
+    #[test]
+    fn test_table_sources_override_the_transform_source() {
+        let message = |table: &str| {
+            let message = CdcMessage {
+                header: CdcHeader {
+                    source: "multi".to_string(),
+                    timestamp: Utc::now(),
+                    block_height: 1,
+                    block_hash: "00".to_string(),
+                    transaction_id: None,
+                    event_id: None,
+                },
+                payload: CdcPayload {
+                    operation: CdcOperation::Create,
+                    table: table.to_string(),
+                    key: "alice".to_string(),
+                    before: None,
+                    after: Some(serde_json::json!({ "amount": 1 })),
+                },
+            };
+            crate::exports::to_arraybuffer_layout(serde_json::to_vec(&message).unwrap())
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect::<String>()
+        };
+        
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (data (i32.const 1024) "{}")
+                (func (export "process_block") (result i32)
+                    (drop (call $push (i32.const 0)))
+                    (drop (call $push (i32.const 1024)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+            message("brc20_balances"),
+            message("rune_balances"),
+        ))
+        .unwrap();
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        runtime.set_table_sources(HashMap::from([("brc20_balances".to_string(), "brc20".to_string())]));
+        
+        let result = runtime.process_block(1, vec![0; 32]).unwrap();
+        assert_eq!(result.cdc_messages[0].header.source, "brc20");
+        assert_eq!(result.cdc_messages[1].header.source, "multi");
+        
+        // The overridden source is the one tables are prefixed with
+        runtime.set_source_prefix(false, true);
+        let result = runtime.process_block(2, vec![0; 32]).unwrap();
+        assert_eq!(result.cdc_messages[0].payload.table, "brc20_brc20_balances");
+        assert_eq!(result.cdc_messages[1].payload.table, "multi_rune_balances");
+    }
+    
+    #[test]
+    fn test_errors_name_the_module() {
+        let error = WasmRuntime::from_bytes_named(b"not wasm", "token-balances").unwrap_err();
+        assert!(error.to_string().contains("token-balances"), "{}", error);
+        
+        // Failures while running a transform name it too
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (func (export "process_block") (result i32)
+                    i32.const -1
+                )
+                (func (export "rollback") (result i32)
+                    i32.const -1
+                )
+                (memory (export "memory") 1)
+            )
+            "#,
+        )
+        .unwrap();
+        let mut runtime = WasmRuntime::from_bytes_named(&wasm_bytes, "token-balances").unwrap();
+        assert_eq!(runtime.name(), "token-balances");
+        assert!(!runtime.uses_auto_rollback());
+        
+        let error = runtime.process_block(1, vec![0; 32]).unwrap_err();
+        assert!(error.to_string().contains("token-balances"), "{}", error);
+        let error = runtime.rollback(0, vec![0; 32]).unwrap_err();
+        assert!(error.to_string().contains("token-balances"), "{}", error);
+    }
+    
+    #[test]
+    fn test_auto_rollback_inverts_cached_messages() {
+        // view_dependent_runtime has no rollback export
+        let mut runtime = view_dependent_runtime();
+        runtime.register_view("balance", |input| Ok(input.to_vec()));
+        assert!(runtime.uses_auto_rollback());
+        
+        for height in 1..=3 {
+            runtime.process_block(height, vec![height as u8; 32]).unwrap();
+        }
+        
+        // A reorg back to block 1 undoes blocks 3 and 2, newest first
+        let result = runtime.rollback(1, vec![1; 32]).unwrap();
+        let undone: Vec<(u32, CdcOperation, &str)> = result.cdc_messages.iter()
+            .map(|m| (m.header.block_height, m.payload.operation, m.payload.key.as_str()))
+            .collect();
+        assert_eq!(undone, vec![
+            (2, CdcOperation::Delete, "alice"),
+            (1, CdcOperation::Delete, "alice"),
+        ]);
+        
+        // Nothing above the tip means nothing to undo
+        assert!(runtime.rollback(3, vec![3; 32]).unwrap().cdc_messages.is_empty());
+        
+        // Manual rollback needs a rollback export
+        assert!(runtime.set_rollback_mode(RollbackMode::Manual).is_err());
+        assert!(runtime.uses_auto_rollback());
+    }
+    
+    #[test]
+    fn test_prev_block_hash_is_delivered() {
+        // A transform storing the previous block hash under the `prev` key
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__prev_block_hash_status" (func $prev_block_hash_status (param i32) (result i32)))
+                (import "env" "__load" (func $load (param i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\04\00\00\00prev")
+                (func (export "process_block") (result i32)
+                    (if (i32.ne (call $prev_block_hash_status (i32.const 56)) (i32.const 0))
+                        (then (return (i32.const -1))))
+                    (i32.store (i32.const 64) (i32.load (i32.const 56)))
+                    (call $load (i32.const 68))
+                    (drop (call $set_state (i32.const 0) (i32.const 64)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        
+        let result = runtime.process_block(0, vec![0; 32]).unwrap();
+        assert_eq!(result.state_snapshot.get(b"prev"), Some(&Vec::new()));
+        
+        runtime.set_prev_block_hash(vec![0; 32]);
+        let result = runtime.process_block(1, vec![1; 32]).unwrap();
+        assert_eq!(result.state_snapshot.get(b"prev"), Some(&vec![0; 32]));
+    }
+    
+    #[test]
+    fn test_raw_block_is_fetched_lazily_once_per_block() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        // A transform reading the raw block twice and storing it under `raw`
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__get_block_raw" (func $get_block_raw (result i32)))
+                (import "env" "__load" (func $load (param i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\03\00\00\00raw")
+                (func (export "process_block") (result i32)
+                    (drop (call $get_block_raw))
+                    (i32.store (i32.const 64) (call $get_block_raw))
+                    (call $load (i32.const 68))
+                    (drop (call $set_state (i32.const 0) (i32.const 64)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        runtime.set_block_fetcher(Some(Arc::new(move |height| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            match height {
+                1 | 2 => Ok(vec![height as u8; 3]),
+                _ => Err(anyhow!("block {} not found", height)),
+            }
+        })));
+        
+        // Both reads of a block share one fetch
+        let result = runtime.process_block(1, vec![1; 32]).unwrap();
+        assert_eq!(result.state_snapshot.get(b"raw"), Some(&vec![1; 3]));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        
+        let result = runtime.process_block(2, vec![2; 32]).unwrap();
+        assert_eq!(result.state_snapshot.get(b"raw"), Some(&vec![2; 3]));
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        
+        // A failed fetch fails the block as retryable
+        let error = runtime.process_block(3, vec![3; 32]).unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::ViewAccess(_))));
+        
+        // A block provided up front is never fetched
+        runtime.set_block_raw(4, vec![4; 2]);
+        let result = runtime.process_block(4, vec![4; 32]).unwrap();
+        assert_eq!(result.state_snapshot.get(b"raw"), Some(&vec![4; 2]));
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+    
+    #[test]
+    fn test_reset_clears_per_run_caches() {
+        let mut runtime = view_dependent_runtime();
+        runtime.register_view("balance", |input| Ok(input.to_vec()));
+        runtime.process_block(1, vec![1; 32]).unwrap();
+        assert!(!runtime.cdc_messages.is_empty());
+        assert_eq!(runtime.compute_inverse_messages(1).unwrap().len(), 1);
+        assert_eq!(runtime.view_cache_stats().entries, 1);
+        
+        runtime.reset();
+        assert!(runtime.cdc_cache.is_empty());
+        assert!(runtime.cdc_messages.is_empty());
+        assert!(runtime.compute_inverse_messages(1).is_err());
+        assert_eq!(runtime.view_cache_stats().entries, 0);
+        assert_eq!(runtime.current_height, 0);
+        assert!(runtime.current_hash.is_empty());
+        
+        // The runtime still works after a reset
+        let result = runtime.process_block(1, vec![1; 32]).unwrap();
+        assert_eq!(result.cdc_messages.len(), 1);
+    }
+    
+    #[test]
+    fn test_view_results_are_cached_by_height() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        
+        let mut runtime = view_calling_runtime();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        runtime.register_view("balance", move |input| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(input.to_vec())
+        });
+        
+        // Repeated calls at the same height are answered from the cache
+        runtime.process_block(5, vec![0; 32]).unwrap();
+        runtime.process_block(5, vec![0; 32]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        runtime.process_block(6, vec![1; 32]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(runtime.view_cache_stats(), ViewCacheStats { hits: 1, misses: 2, entries: 2 });
+        
+        // A reorg back to height 5 drops the result for height 6 only
+        runtime.rollback(5, vec![0; 32]).unwrap();
+        runtime.process_block(6, vec![2; 32]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        runtime.process_block(5, vec![0; 32]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        
+        // Without a cache every call reaches the view function
+        runtime.set_view_cache_capacity(0);
+        runtime.process_block(5, vec![0; 32]).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
 }
 
 // Stub implementation for when the host feature is not enabled
@@ -507,4 +2714,8 @@ impl WasmRuntime {
     pub fn from_bytes(_wasm_bytes: &[u8]) -> crate::error::Result<Self> {
         Ok(Self)
     }
+    
+    pub fn from_bytes_named(_wasm_bytes: &[u8], _name: &str) -> crate::error::Result<Self> {
+        Ok(Self)
+    }
 }
\ No newline at end of file
@@ -13,5 +13,5 @@ pub mod utils;
 
 /// Re-export common types and functions for convenience
 pub use error::{Error, Result};
-pub use serialization::{deserialize, serialize, serialize_to_json};
+pub use serialization::{deserialize, serialize, serialize_to_json, serialize_to_json_pretty, serialize_to_json_with, JsonOptions};
 pub use types::*;
\ No newline at end of file
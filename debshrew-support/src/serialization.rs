@@ -4,7 +4,21 @@
 //! in various formats, including JSON, bincode, and hex.
 
 use crate::error::{Error, Result};
-use serde::{de::DeserializeOwned, Serialize};
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Options controlling how values are written as JSON
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonOptions {
+    /// Indent the output over multiple lines
+    #[serde(default)]
+    pub pretty: bool,
+    
+    /// Write the keys of every object in sorted order, so equal values always
+    /// produce identical output
+    #[serde(default)]
+    pub sort_keys: bool,
+}
 
 /// Serialize a value to JSON
 ///
@@ -44,6 +58,104 @@ pub fn serialize_to_json<T: Serialize>(value: &T) -> Result<String> {
     serde_json::to_string(value).map_err(Error::from)
 }
 
+/// Serialize a value to indented, multi-line JSON
+///
+/// # Arguments
+///
+/// * `value` - The value to serialize
+///
+/// # Returns
+///
+/// The serialized value as a pretty-printed JSON string
+///
+/// # Errors
+///
+/// Returns an error if serialization fails
+pub fn serialize_to_json_pretty<T: Serialize>(value: &T) -> Result<String> {
+    serde_json::to_string_pretty(value).map_err(Error::from)
+}
+
+/// Serialize a value to JSON with the given options
+///
+/// # Arguments
+///
+/// * `value` - The value to serialize
+/// * `options` - Whether to pretty-print and sort object keys
+///
+/// # Returns
+///
+/// The serialized value as a JSON string
+///
+/// # Errors
+///
+/// Returns an error if serialization fails
+///
+/// # Examples
+///
+/// ```
+/// use debshrew_support::serialization::{serialize_to_json_with, JsonOptions};
+/// use serde::Serialize;
+///
+/// #[derive(Serialize)]
+/// struct Person {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// let person = Person {
+///     name: "Alice".to_string(),
+///     age: 30,
+/// };
+///
+/// let options = JsonOptions { pretty: false, sort_keys: true };
+/// let json = serialize_to_json_with(&person, options).unwrap();
+/// assert_eq!(json, r#"{"age":30,"name":"Alice"}"#);
+/// ```
+pub fn serialize_to_json_with<T: Serialize>(value: &T, options: JsonOptions) -> Result<String> {
+    if options.sort_keys {
+        let sorted = sort_json_keys(serialize_to_json_value(value)?);
+        return serialize_json_text(&sorted, options.pretty);
+    }
+    
+    serialize_json_text(value, options.pretty)
+}
+
+/// Sort the keys of every object in a JSON value
+///
+/// # Arguments
+///
+/// * `value` - The value to sort
+///
+/// # Returns
+///
+/// The value with all object keys in sorted order
+pub fn sort_json_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries.into_iter()
+                    .map(|(key, value)| (key, sort_json_keys(value)))
+                    .collect()
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_json_keys).collect())
+        }
+        other => other,
+    }
+}
+
+/// Write a value as compact or pretty JSON text
+fn serialize_json_text<T: Serialize>(value: &T, pretty: bool) -> Result<String> {
+    if pretty {
+        serialize_to_json_pretty(value)
+    } else {
+        serialize_to_json(value)
+    }
+}
+
 /// Serialize a value to a JSON value
 ///
 /// # Arguments
@@ -115,6 +227,11 @@ pub fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>> {
 
 /// Deserialize a bincode byte vector to a value
 ///
+/// The data is decoded with the same options as `bincode::deserialize`,
+/// but no more bytes may be read than the input holds. A corrupt length
+/// field claiming more data than that fails with a size limit error instead
+/// of attempting a huge allocation.
+///
 /// # Arguments
 ///
 /// * `data` - The byte vector to deserialize
@@ -125,9 +242,14 @@ pub fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>> {
 ///
 /// # Errors
 ///
-/// Returns an error if deserialization fails
+/// Returns an error if deserialization fails or a length exceeds the input
 pub fn deserialize<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
-    bincode::deserialize(data).map_err(Error::from)
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(data.len() as u64)
+        .deserialize_from(data)
+        .map_err(Error::from)
 }
 
 /// Encode bytes as a hex string
@@ -185,6 +307,33 @@ mod tests {
         assert_eq!(deserialized, test);
     }
 
+    #[test]
+    fn test_json_options() {
+        let value = serde_json::json!({
+            "name": "test",
+            "nested": { "z": 1, "a": [{ "y": true, "b": null }] },
+        });
+        let test = TestStruct {
+            name: "test".to_string(),
+            value: 42,
+        };
+        
+        assert_eq!(serialize_to_json_pretty(&test).unwrap(), "{\n  \"name\": \"test\",\n  \"value\": 42\n}");
+        
+        // Field order follows the struct unless keys are sorted
+        let options = JsonOptions { pretty: false, sort_keys: true };
+        assert_eq!(serialize_to_json_with(&test, JsonOptions::default()).unwrap(), serialize_to_json(&test).unwrap());
+        assert_eq!(
+            serialize_to_json_with(&value, options).unwrap(),
+            r#"{"name":"test","nested":{"a":[{"b":null,"y":true}],"z":1}}"#
+        );
+        
+        let options = JsonOptions { pretty: true, sort_keys: true };
+        let pretty = serialize_to_json_with(&value, options).unwrap();
+        assert!(pretty.contains('\n'));
+        assert_eq!(deserialize_from_json::<serde_json::Value>(&pretty).unwrap(), value);
+    }
+
     #[test]
     fn test_bincode_serialization() {
         let test = TestStruct {
@@ -197,6 +346,24 @@ mod tests {
         assert_eq!(deserialized, test);
     }
 
+    #[test]
+    fn test_oversized_bincode_length_is_rejected() {
+        // A string claiming 2^62 bytes is rejected before allocating
+        let mut blob = 42u32.to_le_bytes().to_vec();
+        blob.splice(0..0, (1u64 << 62).to_le_bytes());
+        blob.extend([0u8; 16]);
+        
+        match deserialize::<TestStruct>(&blob) {
+            Err(Error::Bincode(e)) => assert!(matches!(*e, bincode::ErrorKind::SizeLimit), "{}", e),
+            other => panic!("Expected a size limit error, got {:?}", other),
+        }
+        
+        // A list claiming 2^62 entries fails once the input runs out
+        let mut blob = (1u64 << 62).to_le_bytes().to_vec();
+        blob.extend(serialize(&(vec![1u8], vec![2u8])).unwrap());
+        assert!(matches!(deserialize::<Vec<(Vec<u8>, Vec<u8>)>>(&blob), Err(Error::Bincode(_))));
+    }
+
     #[test]
     fn test_hex_encoding() {
         let bytes = vec![0x01, 0x02, 0x03, 0x04];
@@ -3,9 +3,13 @@
 //! This module defines the core data types used throughout the debshrew project,
 //! including CDC message types, state types, and other shared data structures.
 
+use crate::error::{Error, Result};
+use crate::serialization::sort_json_keys;
+use crate::utils::datetime_to_timestamp_ms;
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
 
 /// CDC message header
 ///
@@ -28,6 +32,10 @@ pub struct CdcHeader {
     /// Transaction ID where the change occurred (if applicable)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub transaction_id: Option<String>,
+    
+    /// Content-addressed event ID for downstream dedup (set by the runtime)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
 }
 
 /// CDC message payload
@@ -66,6 +74,155 @@ pub struct CdcMessage {
     pub payload: CdcPayload,
 }
 
+impl CdcMessage {
+    /// Get a canonical JSON representation of the message
+    ///
+    /// Object keys are sorted recursively and the volatile header timestamp is
+    /// omitted, so logically identical messages always produce the same string.
+    /// The event ID is omitted too, since it is derived from this string.
+    /// This makes the result suitable for hashing and content-based dedup.
+    ///
+    /// # Returns
+    ///
+    /// The canonical JSON string
+    pub fn canonical_json(&self) -> String {
+        // Serializing a CdcMessage cannot fail: all map keys are strings
+        let mut value = serde_json::to_value(self).expect("CdcMessage is always serializable");
+        if let Some(header) = value.get_mut("header").and_then(|header| header.as_object_mut()) {
+            header.remove("timestamp");
+            header.remove("event_id");
+        }
+        
+        sort_json_keys(value).to_string()
+    }
+    
+    /// Compute the content-addressed event ID of the message
+    ///
+    /// The ID is the hex SHA-256 of the canonical JSON, the block hash and the
+    /// message's position among the messages of its block. Re-emitting the
+    /// same content at the same position of the same block, for example after
+    /// a restart, yields the same ID, so consumers can use it to drop
+    /// duplicates. Identical messages within a block still get distinct IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The position of the message within its block
+    ///
+    /// # Returns
+    ///
+    /// The event ID
+    pub fn compute_event_id(&self, sequence: u32) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.canonical_json().as_bytes());
+        hasher.update(self.header.block_hash.as_bytes());
+        hasher.update(sequence.to_be_bytes());
+        
+        hex::encode(hasher.finalize())
+    }
+    
+    /// Stamp the content-addressed event ID onto the message header
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The position of the message within its block
+    pub fn stamp_event_id(&mut self, sequence: u32) {
+        self.header.event_id = Some(self.compute_event_id(sequence));
+    }
+    
+    /// Check if the message is an update that changes nothing
+    ///
+    /// The `before` and `after` values are compared as JSON values, so
+    /// objects are equal regardless of key order, as in `canonical_json`.
+    ///
+    /// # Returns
+    ///
+    /// true if the message is an `Update` whose `before` equals its `after`
+    pub fn is_noop_update(&self) -> bool {
+        self.payload.operation == CdcOperation::Update && self.payload.before == self.payload.after
+    }
+    
+    /// Get a view of the message that serializes its header timestamp in
+    /// the given format
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp_format` - How to write the header timestamp
+    ///
+    /// # Returns
+    ///
+    /// A serializable view of the message
+    pub fn with_timestamp_format(&self, timestamp_format: TimestampFormat) -> FormattedCdcMessage<'_> {
+        FormattedCdcMessage { message: self, timestamp_format }
+    }
+}
+
+/// Format of the CDC header timestamp
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// An RFC 3339 string in UTC, e.g. `2023-01-01T00:00:00Z`
+    #[default]
+    Rfc3339,
+    
+    /// Milliseconds since the Unix epoch, like Debezium's `ts_ms`
+    EpochMillis,
+}
+
+/// A CDC message serialized with a chosen header timestamp format
+///
+/// Created by `CdcMessage::with_timestamp_format`.
+#[derive(Debug, Clone, Copy)]
+pub struct FormattedCdcMessage<'a> {
+    /// The message
+    message: &'a CdcMessage,
+    
+    /// How the header timestamp is written
+    timestamp_format: TimestampFormat,
+}
+
+impl Serialize for FormattedCdcMessage<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        /// `CdcHeader` with the timestamp as epoch milliseconds; the fields
+        /// must match those of `CdcHeader`
+        #[derive(Serialize)]
+        struct EpochMillisHeader<'a> {
+            source: &'a str,
+            timestamp: u64,
+            block_height: u32,
+            block_hash: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            transaction_id: Option<&'a String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            event_id: Option<&'a String>,
+        }
+        
+        #[derive(Serialize)]
+        struct EpochMillisMessage<'a> {
+            header: EpochMillisHeader<'a>,
+            payload: &'a CdcPayload,
+        }
+        
+        match self.timestamp_format {
+            TimestampFormat::Rfc3339 => self.message.serialize(serializer),
+            TimestampFormat::EpochMillis => {
+                let header = &self.message.header;
+                EpochMillisMessage {
+                    header: EpochMillisHeader {
+                        source: &header.source,
+                        timestamp: datetime_to_timestamp_ms(&header.timestamp),
+                        block_height: header.block_height,
+                        block_hash: &header.block_hash,
+                        transaction_id: header.transaction_id.as_ref(),
+                        event_id: header.event_id.as_ref(),
+                    },
+                    payload: &self.message.payload,
+                }
+                .serialize(serializer)
+            }
+        }
+    }
+}
+
 /// CDC operation type
 ///
 /// Represents the type of operation that generated the CDC message.
@@ -80,6 +237,83 @@ pub enum CdcOperation {
     
     /// Delete operation (removed record)
     Delete,
+    
+    /// Snapshot boundary marker
+    ///
+    /// A control message carrying no row data, emitted once the transform has
+    /// finished the initial snapshot of the table. Consumers can switch from
+    /// reading a snapshot to streaming changes when they see it.
+    #[serde(rename = "snapshot_complete")]
+    SnapshotComplete,
+    
+    /// Liveness marker
+    ///
+    /// A control message carrying no row data, emitted when no CDC messages
+    /// have been produced for a while, so consumers can tell a quiet stream
+    /// from a stalled one. The header carries the current block height.
+    Heartbeat,
+    
+    /// Reorg start marker
+    ///
+    /// A control message sent before the inverse messages of a reorg. Its
+    /// `after` holds the `from_height` being rolled back and the `to_height`
+    /// of the common ancestor.
+    #[serde(rename = "reorg_begin")]
+    ReorgBegin,
+    
+    /// Reorg end marker
+    ///
+    /// A control message sent after the inverse messages of a reorg, before
+    /// the blocks of the new chain. Carries the same heights as the matching
+    /// `reorg_begin`.
+    #[serde(rename = "reorg_end")]
+    ReorgEnd,
+}
+
+impl CdcOperation {
+    /// Check if the operation is a control marker rather than a row change
+    ///
+    /// # Returns
+    ///
+    /// true if the operation carries no row data, false otherwise
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            CdcOperation::SnapshotComplete | CdcOperation::Heartbeat | CdcOperation::ReorgBegin | CdcOperation::ReorgEnd
+        )
+    }
+    
+    /// Get the name of the operation as it appears in serialized messages
+    ///
+    /// # Returns
+    ///
+    /// The lowercase operation name, e.g. `create` or `snapshot_complete`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CdcOperation::Create => "create",
+            CdcOperation::Update => "update",
+            CdcOperation::Delete => "delete",
+            CdcOperation::SnapshotComplete => "snapshot_complete",
+            CdcOperation::Heartbeat => "heartbeat",
+            CdcOperation::ReorgBegin => "reorg_begin",
+            CdcOperation::ReorgEnd => "reorg_end",
+        }
+    }
+}
+
+/// Conflict policy for merging transform states
+///
+/// A conflict is a key present in both states with different values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Keep this state's value on conflict
+    PreferSelf,
+    
+    /// Take the other state's value on conflict
+    PreferOther,
+    
+    /// Fail the merge on conflict, leaving this state unchanged
+    Error,
 }
 
 /// Transform state
@@ -93,6 +327,9 @@ pub struct TransformState {
     
     /// Whether the state has been modified
     dirty: bool,
+    
+    /// Keys set or deleted since the state was last marked clean
+    touched: BTreeSet<Vec<u8>>,
 }
 
 impl TransformState {
@@ -101,6 +338,7 @@ impl TransformState {
         Self {
             inner: HashMap::new(),
             dirty: false,
+            touched: BTreeSet::new(),
         }
     }
     
@@ -124,6 +362,7 @@ impl TransformState {
     /// * `key` - The key to set
     /// * `value` - The value to set
     pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.touched.insert(key.clone());
         self.inner.insert(key, value);
         self.dirty = true;
     }
@@ -140,6 +379,7 @@ impl TransformState {
     pub fn delete(&mut self, key: &[u8]) -> bool {
         let result = self.inner.remove(key).is_some();
         if result {
+            self.touched.insert(key.to_vec());
             self.dirty = true;
         }
         result
@@ -155,8 +395,44 @@ impl TransformState {
     }
     
     /// Mark the state as clean
+    ///
+    /// Also forgets the keys changed so far, so `changed_since` starts afresh.
     pub fn mark_clean(&mut self) {
         self.dirty = false;
+        self.touched.clear();
+    }
+    
+    /// Get the changes made since the state was last marked clean
+    ///
+    /// Each key set or deleted since then is listed once, with its current
+    /// value, or None if it is now absent. Applying the changes with
+    /// `apply_changes` to the state as it was when marked clean reproduces
+    /// this state, so a sequence of these diffs can stand in for full
+    /// snapshots.
+    ///
+    /// # Returns
+    ///
+    /// The changed keys in key order, with their new values or None for deletes
+    pub fn changed_since(&self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        self.touched.iter()
+            .map(|key| (key.clone(), self.inner.get(key).cloned()))
+            .collect()
+    }
+    
+    /// Apply changes produced by `changed_since`
+    ///
+    /// # Arguments
+    ///
+    /// * `changes` - The keys to set to a value, or to delete when the value is None
+    pub fn apply_changes(&mut self, changes: &[(Vec<u8>, Option<Vec<u8>>)]) {
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.set(key.clone(), value.clone()),
+                None => {
+                    self.delete(key);
+                }
+            }
+        }
     }
     
     /// Get all keys in the state
@@ -208,13 +484,83 @@ impl TransformState {
         self.inner.is_empty()
     }
     
+    /// Compute a digest of the state's contents
+    ///
+    /// The digest is the hex SHA-256 of the length-prefixed keys and values
+    /// in key order, so equal contents give equal digests however the state
+    /// was built. Whether the state is dirty is not part of the digest.
+    ///
+    /// # Returns
+    ///
+    /// The digest
+    pub fn digest(&self) -> String {
+        let mut entries: Vec<_> = self.inner.iter().collect();
+        entries.sort();
+        
+        let mut hasher = Sha256::new();
+        for (key, value) in entries {
+            hasher.update((key.len() as u64).to_be_bytes());
+            hasher.update(key);
+            hasher.update((value.len() as u64).to_be_bytes());
+            hasher.update(value);
+        }
+        
+        hex::encode(hasher.finalize())
+    }
+    
     /// Clear the state
     pub fn clear(&mut self) {
         if !self.inner.is_empty() {
-            self.inner.clear();
+            self.touched.extend(self.inner.drain().map(|(key, _)| key));
             self.dirty = true;
         }
     }
+    
+    /// Merge another state into this one
+    ///
+    /// Keys only present in `other` are added. Keys present in both states
+    /// with different values are resolved according to `policy`. The state
+    /// is only marked dirty if the merge actually changed it.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The state to merge in
+    /// * `policy` - How to resolve conflicting keys
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the states were merged
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `policy` is `MergePolicy::Error` and the states
+    /// have a conflicting key, in which case this state is left unchanged
+    pub fn merge(&mut self, other: &TransformState, policy: MergePolicy) -> Result<()> {
+        // Check for conflicts up front so a failed merge has no effect
+        if policy == MergePolicy::Error {
+            if let Some(key) = other.inner.iter()
+                .find(|(k, v)| self.inner.get(*k).is_some_and(|existing| existing != *v))
+                .map(|(k, _)| k)
+            {
+                return Err(Error::State(format!("Conflicting value for key {} while merging states", hex::encode(key))));
+            }
+        }
+        
+        for (key, value) in &other.inner {
+            let changed = match self.inner.get(key) {
+                None => true,
+                Some(existing) => existing != value && policy == MergePolicy::PreferOther,
+            };
+            
+            if changed {
+                self.touched.insert(key.clone());
+                self.inner.insert(key.clone(), value.clone());
+                self.dirty = true;
+            }
+        }
+        
+        Ok(())
+    }
 }
 
 /// Block metadata
@@ -262,6 +608,7 @@ mod tests {
                 block_height: 123456,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: Some("tx123".to_string()),
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -281,6 +628,88 @@ mod tests {
         assert_eq!(deserialized, message);
     }
     
+    #[test]
+    fn test_timestamp_formats() {
+        let message = CdcMessage {
+            header: CdcHeader {
+                source: "test_source".to_string(),
+                timestamp: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+                block_height: 123456,
+                block_hash: "00".to_string(),
+                transaction_id: None,
+                event_id: None,
+            },
+            payload: CdcPayload {
+                operation: CdcOperation::Delete,
+                table: "test_table".to_string(),
+                key: "test_key".to_string(),
+                before: Some(serde_json::json!({ "field1": "value1" })),
+                after: None,
+            },
+        };
+        
+        // RFC 3339 matches the derived serialization
+        let rfc3339 = serde_json::to_string(&message.with_timestamp_format(TimestampFormat::Rfc3339)).unwrap();
+        assert_eq!(rfc3339, serde_json::to_string(&message).unwrap());
+        assert!(rfc3339.contains(r#""timestamp":"2023-01-01T00:00:00Z""#));
+        
+        // Epoch milliseconds changes only the timestamp
+        let epoch_millis = serde_json::to_value(message.with_timestamp_format(TimestampFormat::EpochMillis)).unwrap();
+        let mut expected = serde_json::to_value(&message).unwrap();
+        expected["header"]["timestamp"] = serde_json::json!(1672531200000u64);
+        assert_eq!(epoch_millis, expected);
+        
+        let format: TimestampFormat = serde_json::from_str(r#""epoch_millis""#).unwrap();
+        assert_eq!(format, TimestampFormat::EpochMillis);
+    }
+    
+    #[test]
+    fn test_canonical_json_is_stable() {
+        let message = |timestamp, after: &str| CdcMessage {
+            header: CdcHeader {
+                source: "test_source".to_string(),
+                timestamp,
+                block_height: 123456,
+                block_hash: "00".to_string(),
+                transaction_id: None,
+                event_id: None,
+            },
+            payload: CdcPayload {
+                operation: CdcOperation::Update,
+                table: "test_table".to_string(),
+                key: "test_key".to_string(),
+                before: None,
+                after: Some(serde_json::from_str(after).unwrap()),
+            },
+        };
+        
+        let a = message(
+            Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            r#"{"field1": "value1", "nested": {"x": 1, "y": [{"b": 2, "a": 1}]}}"#,
+        );
+        let b = message(
+            Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap(),
+            r#"{"nested": {"y": [{"a": 1, "b": 2}], "x": 1}, "field1": "value1"}"#,
+        );
+        
+        assert_eq!(a.canonical_json(), b.canonical_json());
+        assert!(!a.canonical_json().contains("timestamp"));
+        assert!(a.canonical_json().contains(r#"{"a":1,"b":2}"#));
+        
+        // Content changes are still visible
+        let c = message(a.header.timestamp, r#"{"field1": "value2"}"#);
+        assert_ne!(a.canonical_json(), c.canonical_json());
+        
+        // The event ID ignores the timestamp and itself, but not the position
+        let mut stamped = a.clone();
+        stamped.stamp_event_id(0);
+        assert_eq!(stamped.header.event_id, Some(b.compute_event_id(0)));
+        assert_eq!(stamped.compute_event_id(0), a.compute_event_id(0));
+        assert_ne!(a.compute_event_id(0), a.compute_event_id(1));
+        assert_ne!(a.compute_event_id(0), c.compute_event_id(0));
+        assert!(serde_json::to_string(&stamped).unwrap().contains(r#""event_id":""#));
+    }
+    
     #[test]
     fn test_transform_state() {
         let mut state = TransformState::new();
@@ -325,4 +754,147 @@ mod tests {
         assert!(state.is_empty());
         assert!(state.is_dirty());
     }
+    
+    #[test]
+    fn test_changed_since_replays_to_state() {
+        let mut state = TransformState::new();
+        state.set(b"alice".to_vec(), b"10".to_vec());
+        state.set(b"bob".to_vec(), b"20".to_vec());
+        state.set(b"carol".to_vec(), b"30".to_vec());
+        state.mark_clean();
+        assert!(state.changed_since().is_empty());
+        let base = state.clone();
+        
+        // A block sets, overwrites, deletes, and re-creates a deleted key
+        state.set(b"alice".to_vec(), b"15".to_vec());
+        state.set(b"dave".to_vec(), b"40".to_vec());
+        assert!(state.delete(b"bob"));
+        assert!(!state.delete(b"missing"));
+        assert!(state.delete(b"carol"));
+        state.set(b"carol".to_vec(), b"35".to_vec());
+        
+        let changes = state.changed_since();
+        assert_eq!(changes, vec![
+            (b"alice".to_vec(), Some(b"15".to_vec())),
+            (b"bob".to_vec(), None),
+            (b"carol".to_vec(), Some(b"35".to_vec())),
+            (b"dave".to_vec(), Some(b"40".to_vec())),
+        ]);
+        
+        // Replaying the diff onto the previous snapshot gives the new state
+        let mut replayed = base.clone();
+        replayed.apply_changes(&changes);
+        let mut expected: Vec<_> = state.iter().collect();
+        let mut actual: Vec<_> = replayed.iter().collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+        
+        // Clearing the state lists every key as deleted
+        state.mark_clean();
+        state.clear();
+        let mut replayed_clear = replayed.clone();
+        replayed_clear.apply_changes(&state.changed_since());
+        assert!(replayed_clear.is_empty());
+        assert_eq!(state.changed_since().len(), 3);
+    }
+    
+    #[test]
+    fn test_state_digest() {
+        let mut state = TransformState::new();
+        state.set(b"alice".to_vec(), b"10".to_vec());
+        state.set(b"bob".to_vec(), b"20".to_vec());
+        
+        // Insertion order and dirtiness do not matter
+        let mut other = TransformState::new();
+        other.set(b"bob".to_vec(), b"20".to_vec());
+        other.set(b"alice".to_vec(), b"10".to_vec());
+        other.mark_clean();
+        assert_eq!(state.digest(), other.digest());
+        
+        // Contents do, including where keys end and values begin
+        other.set(b"bob".to_vec(), b"21".to_vec());
+        assert_ne!(state.digest(), other.digest());
+        let mut shifted = TransformState::new();
+        shifted.set(b"alice1".to_vec(), b"0".to_vec());
+        shifted.set(b"bob".to_vec(), b"20".to_vec());
+        assert_ne!(state.digest(), shifted.digest());
+        assert_ne!(TransformState::new().digest(), state.digest());
+    }
+    
+    fn merge_fixture() -> (TransformState, TransformState) {
+        let mut left = TransformState::new();
+        left.set(b"shared".to_vec(), b"left".to_vec());
+        left.set(b"same".to_vec(), b"value".to_vec());
+        left.set(b"left_only".to_vec(), b"1".to_vec());
+        left.mark_clean();
+        
+        let mut right = TransformState::new();
+        right.set(b"shared".to_vec(), b"right".to_vec());
+        right.set(b"same".to_vec(), b"value".to_vec());
+        right.set(b"right_only".to_vec(), b"2".to_vec());
+        
+        (left, right)
+    }
+    
+    #[test]
+    fn test_merge_prefer_self() {
+        let (mut left, right) = merge_fixture();
+        
+        left.merge(&right, MergePolicy::PreferSelf).unwrap();
+        
+        assert_eq!(left.len(), 4);
+        assert_eq!(left.get(b"shared"), Some(&b"left".to_vec()));
+        assert_eq!(left.get(b"right_only"), Some(&b"2".to_vec()));
+        assert!(left.is_dirty());
+    }
+    
+    #[test]
+    fn test_merge_prefer_other() {
+        let (mut left, right) = merge_fixture();
+        
+        left.merge(&right, MergePolicy::PreferOther).unwrap();
+        
+        assert_eq!(left.len(), 4);
+        assert_eq!(left.get(b"shared"), Some(&b"right".to_vec()));
+        assert_eq!(left.get(b"left_only"), Some(&b"1".to_vec()));
+        assert_eq!(left.get(b"right_only"), Some(&b"2".to_vec()));
+        assert!(left.is_dirty());
+    }
+    
+    #[test]
+    fn test_merge_error_policy() {
+        // Overlapping keys with different values fail and leave the state untouched
+        let (mut left, right) = merge_fixture();
+        
+        assert!(left.merge(&right, MergePolicy::Error).is_err());
+        assert_eq!(left.len(), 3);
+        assert_eq!(left.get(b"shared"), Some(&b"left".to_vec()));
+        assert_eq!(left.get(b"right_only"), None);
+        assert!(!left.is_dirty());
+        
+        // Disjoint keys and identical values merge cleanly
+        let mut other = TransformState::new();
+        other.set(b"same".to_vec(), b"value".to_vec());
+        other.set(b"disjoint".to_vec(), b"3".to_vec());
+        
+        left.merge(&other, MergePolicy::Error).unwrap();
+        assert_eq!(left.len(), 4);
+        assert_eq!(left.get(b"disjoint"), Some(&b"3".to_vec()));
+        assert!(left.is_dirty());
+    }
+    
+    #[test]
+    fn test_merge_without_changes_stays_clean() {
+        let (mut left, _) = merge_fixture();
+        let subset = left.clone();
+        
+        for policy in [MergePolicy::PreferSelf, MergePolicy::PreferOther, MergePolicy::Error] {
+            left.merge(&subset, policy).unwrap();
+            assert!(!left.is_dirty());
+        }
+        
+        left.merge(&TransformState::new(), MergePolicy::Error).unwrap();
+        assert!(!left.is_dirty());
+    }
 }
\ No newline at end of file
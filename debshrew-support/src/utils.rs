@@ -7,6 +7,10 @@ use chrono::{DateTime, TimeZone, Utc};
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// The namespace used for UUIDv5 CDC message identifiers
+pub const CDC_UUID_NAMESPACE: Uuid = Uuid::from_u128(0x6d1f_4c2a_9b3e_4f7d_8a05_2e6c_b4d9_e1a7);
 
 /// Get the current timestamp in milliseconds since the Unix epoch
 ///
@@ -171,6 +175,49 @@ pub fn generate_cdc_message_id(source: &str, table: &str, key: &str, block_heigh
     format!("{}:{}:{}:{}", source, table, key, block_height)
 }
 
+/// Generate a deterministic UUID for a CDC message
+///
+/// The UUID is a version 5 UUID in the debshrew namespace, so the same inputs
+/// always produce the same ID. This lets downstream consumers deduplicate
+/// messages that are redelivered after a restart or a reprocess.
+///
+/// # Arguments
+///
+/// * `source` - The source of the CDC message
+/// * `table` - The table name
+/// * `key` - The record key
+/// * `block_height` - The block height
+/// * `seq` - The position of the message within the block
+///
+/// # Returns
+///
+/// A version 5 UUID identifying the CDC message
+///
+/// # Examples
+///
+/// ```
+/// use debshrew_support::utils::generate_cdc_uuid;
+///
+/// let id = generate_cdc_uuid("test_source", "test_table", "test_key", 123456, 0);
+/// assert_eq!(id, generate_cdc_uuid("test_source", "test_table", "test_key", 123456, 0));
+/// assert_eq!(id.get_version_num(), 5);
+/// ```
+pub fn generate_cdc_uuid(source: &str, table: &str, key: &str, block_height: u32, seq: u32) -> Uuid {
+    // Length-prefix the string fields so that no two distinct inputs share a name
+    let name = format!(
+        "{}:{}|{}:{}|{}:{}|{}|{}",
+        source.len(),
+        source,
+        table.len(),
+        table,
+        key.len(),
+        key,
+        block_height,
+        seq
+    );
+    Uuid::new_v5(&CDC_UUID_NAMESPACE, name.as_bytes())
+}
+
 /// Truncate a string to a maximum length with ellipsis
 ///
 /// # Arguments
@@ -283,6 +330,25 @@ mod tests {
         assert_eq!(id, "test_source:test_table:test_key:123456");
     }
     
+    #[test]
+    fn test_generate_cdc_uuid() {
+        let id = generate_cdc_uuid("test_source", "test_table", "test_key", 123456, 0);
+        assert_eq!(id, generate_cdc_uuid("test_source", "test_table", "test_key", 123456, 0));
+        assert_eq!(id.get_version_num(), 5);
+        
+        assert_ne!(id, generate_cdc_uuid("other_source", "test_table", "test_key", 123456, 0));
+        assert_ne!(id, generate_cdc_uuid("test_source", "other_table", "test_key", 123456, 0));
+        assert_ne!(id, generate_cdc_uuid("test_source", "test_table", "other_key", 123456, 0));
+        assert_ne!(id, generate_cdc_uuid("test_source", "test_table", "test_key", 123457, 0));
+        assert_ne!(id, generate_cdc_uuid("test_source", "test_table", "test_key", 123456, 1));
+        
+        // Shifting characters between fields must not collide
+        assert_ne!(
+            generate_cdc_uuid("ab", "c", "k", 1, 0),
+            generate_cdc_uuid("a", "bc", "k", 1, 0)
+        );
+    }
+    
     #[test]
     fn test_truncate_string() {
         assert_eq!(truncate_string("Hello, world!", 5), "Hello...");
@@ -0,0 +1,373 @@
+//! Pull-based CDC API
+//!
+//! This module provides an optional HTTP endpoint for consumers that poll
+//! debshrew for CDC messages instead of reading them from a sink. `GET
+//! /cdc?since=H` returns the messages of the cached blocks above height `H`
+//! as a JSON array, in block order. Only blocks still in the block cache can
+//! be served, so a consumer should poll more often than `cache_size` blocks
+//! are produced.
+//!
+//! The cache only holds the current chain, so a consumer that has seen blocks
+//! later rolled back by a reorg gets `409 Conflict` naming the height to
+//! fetch again from, instead of messages that silently skip the rollback.
+//! Passing the hash of block `H` as `hash` lets the check tell the two
+//! chains apart once the new chain has grown past `H`.
+
+use crate::block::BlockCache;
+use crate::error::{Error, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use log::{error, info};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Start serving the CDC API
+///
+/// Must be called from within a Tokio runtime. The server runs on a spawned
+/// task until the task is aborted or the runtime shuts down.
+///
+/// # Arguments
+///
+/// * `address` - The socket address to listen on, such as `127.0.0.1:8080`
+/// * `cache` - The block cache to serve messages from
+///
+/// # Returns
+///
+/// The address the server is listening on, and the handle of the server task
+///
+/// # Errors
+///
+/// Returns an error if the address is invalid or cannot be bound
+pub fn serve_cdc_api(address: &str, cache: Arc<Mutex<BlockCache>>) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let address: SocketAddr = address.parse()
+        .map_err(|e| Error::Configuration(format!("Invalid CDC API address {}: {}", address, e)))?;
+    
+    let make_service = make_service_fn(move |_| {
+        let cache = cache.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle_request(request, cache.clone())))
+        }
+    });
+    
+    let server = Server::try_bind(&address)
+        .map_err(|e| Error::Generic(format!("Failed to bind CDC API to {}: {}", address, e)))?
+        .serve(make_service);
+    let local_address = server.local_addr();
+    info!("Serving CDC API on http://{}/cdc", local_address);
+    
+    let handle = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("CDC API server failed: {}", e);
+        }
+    });
+    
+    Ok((local_address, handle))
+}
+
+/// Handle a request to the CDC API
+///
+/// # Arguments
+///
+/// * `request` - The HTTP request
+/// * `cache` - The block cache to serve messages from
+///
+/// # Returns
+///
+/// The JSON-encoded messages, or an error response for unknown paths,
+/// invalid queries and consumers behind a rollback
+async fn handle_request(request: Request<Body>, cache: Arc<Mutex<BlockCache>>) -> std::result::Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/cdc" {
+        return Ok(text_response(StatusCode::NOT_FOUND, "Not found".to_string()));
+    }
+    
+    let since = match parse_since(request.uri().query()) {
+        Ok(since) => since,
+        Err(message) => return Ok(text_response(StatusCode::BAD_REQUEST, message)),
+    };
+    let hash = query_param(request.uri().query(), "hash");
+    
+    let cache = cache.lock().await;
+    if let Some(rewind_to) = rolled_back_since(&cache, since, hash.as_deref()) {
+        return Ok(text_response(
+            StatusCode::CONFLICT,
+            format!("Blocks above {} were rolled back by a reorg; fetch again since {}", rewind_to, rewind_to),
+        ));
+    }
+    
+    let messages = cache.get_cdc_messages_since(since);
+    drop(cache);
+    let response = match serde_json::to_vec(&messages) {
+        Ok(body) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("valid response"),
+        Err(e) => text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize CDC messages: {}", e)),
+    };
+    
+    Ok(response)
+}
+
+/// Parse the `since` parameter of a query string
+///
+/// # Arguments
+///
+/// * `query` - The query string of the request, if any
+///
+/// # Returns
+///
+/// The height to return messages above, 0 if the parameter is missing, or
+/// an error message if it is not a block height
+fn parse_since(query: Option<&str>) -> std::result::Result<u32, String> {
+    match query_param(query, "since") {
+        Some(value) => value.parse()
+            .map_err(|_| format!("Invalid since height: {}", value)),
+        None => Ok(0),
+    }
+}
+
+/// Get a parameter of a query string
+///
+/// # Arguments
+///
+/// * `query` - The query string of the request, if any
+/// * `name` - The name of the parameter
+///
+/// # Returns
+///
+/// The decoded value of the first parameter with the name, if any
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(query.unwrap_or("").as_bytes())
+        .find(|(param, _)| param == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Check whether a consumer has seen blocks that a reorg rolled back
+///
+/// A consumer is behind a rollback when it has seen a block above the
+/// cached chain, when the hash it saw for block `since` is not the cached
+/// one, or, without a hash, when `since` is in the range the most recent
+/// rollback removed, since the blocks it saw there may be gone.
+///
+/// # Arguments
+///
+/// * `cache` - The block cache
+/// * `since` - The last height the consumer has seen
+/// * `hash` - The hash the consumer saw for block `since`, if given
+///
+/// # Returns
+///
+/// The height the consumer should fetch again from, or None if its view
+/// matches the cached chain
+fn rolled_back_since(cache: &BlockCache, since: u32, hash: Option<&str>) -> Option<u32> {
+    let highest = cache.highest_height()?;
+    
+    // Back to the rollback if the consumer may have seen what it removed,
+    // otherwise to the start of the cache
+    let rewind_to = match cache.last_rollback() {
+        Some((ancestor, _)) if ancestor < since => ancestor,
+        _ => cache.lowest_height()?.saturating_sub(1),
+    };
+    
+    let rolled_back = match (hash, cache.get_block_hash(since)) {
+        _ if since > highest => true,
+        (Some(hash), Some(cached)) => hash != cached,
+        (Some(_), None) => false,
+        (None, _) => cache.last_rollback()
+            .is_some_and(|(ancestor, previous_highest)| since > ancestor && since <= previous_highest),
+    };
+    
+    rolled_back.then_some(rewind_to)
+}
+
+/// Build a plain text response
+fn text_response(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from(message))
+        .expect("valid response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MockMetashrewClient;
+    use crate::sink::NullSink;
+    use crate::synchronizer::BlockSynchronizer;
+    use chrono::Utc;
+    use debshrew_runtime::{TransformResult, WasmRuntime};
+    use debshrew_support::{BlockMetadata, CdcHeader, CdcMessage, CdcOperation, CdcPayload, TransformState};
+    
+    fn cached_block(height: u32) -> (BlockMetadata, TransformResult) {
+        let message = CdcMessage {
+            header: CdcHeader {
+                source: "test".to_string(),
+                timestamp: Utc::now(),
+                block_height: height,
+                block_hash: format!("hash{}", height),
+                transaction_id: None,
+                event_id: None,
+            },
+            payload: CdcPayload {
+                operation: CdcOperation::Create,
+                table: "test_table".to_string(),
+                key: format!("key_{}", height),
+                before: None,
+                after: Some(serde_json::json!({ "height": height })),
+            },
+        };
+        
+        let metadata = BlockMetadata {
+            height,
+            hash: format!("hash{}", height),
+            timestamp: Utc::now(),
+        };
+        (metadata, TransformResult::new(vec![message], TransformState::new()))
+    }
+    
+    /// A WASM runtime whose transform pushes `message` for every block
+    fn runtime_pushing(message: &CdcMessage) -> WasmRuntime {
+        let data: String = debshrew_runtime::exports::to_arraybuffer_layout(serde_json::to_vec(message).unwrap())
+            .iter()
+            .map(|b| format!("\\{:02x}", b))
+            .collect();
+        
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "process_block") (result i32)
+                    (call $push (i32.const 0))
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+            data
+        ))
+        .unwrap();
+        
+        WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_cdc_endpoint_serves_synchronized_blocks() {
+        let mut client = MockMetashrewClient::new();
+        client.set_height(3);
+        for height in 0..=3 {
+            client.set_block_hash(height, vec![height as u8]);
+        }
+        
+        // Blocks 1 to 3 go through the transform into the synchronizer's cache
+        let (_, result) = cached_block(0);
+        let mut synchronizer = BlockSynchronizer::new(
+            client,
+            runtime_pushing(&result.cdc_messages[0]),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        synchronizer.catch_up().await.unwrap();
+        
+        let (address, handle) = serve_cdc_api("127.0.0.1:0", synchronizer.get_cache().await).unwrap();
+        let client = reqwest::Client::new();
+        let get = |query: &str| client.get(format!("http://{}/cdc?{}", address, query)).send();
+        
+        let messages: Vec<CdcMessage> = get("since=1").await.unwrap().json().await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|message| message.payload.key == "key_0"));
+        
+        // Block 3 was processed with hash 03, so a consumer at another hash
+        // is sent back to the common ancestor
+        let response = get("since=3&hash=04").await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+        let messages: Vec<CdcMessage> = get("since=3&hash=03").await.unwrap().json().await.unwrap();
+        assert!(messages.is_empty());
+        
+        handle.abort();
+    }
+    
+    #[tokio::test]
+    async fn test_cdc_endpoint_serves_messages_since_height() {
+        let mut cache = BlockCache::new(6).unwrap();
+        for height in 1..=4 {
+            let (metadata, result) = cached_block(height);
+            cache.add_block(metadata, result).unwrap();
+        }
+        
+        let (address, handle) = serve_cdc_api("127.0.0.1:0", Arc::new(Mutex::new(cache))).unwrap();
+        let client = reqwest::Client::new();
+        
+        let messages: Vec<CdcMessage> = client.get(format!("http://{}/cdc?since=2", address))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let keys: Vec<&str> = messages.iter().map(|message| message.payload.key.as_str()).collect();
+        assert_eq!(keys, vec!["key_3", "key_4"]);
+        
+        // Without `since`, everything cached is returned
+        let messages: Vec<CdcMessage> = client.get(format!("http://{}/cdc", address))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 4);
+        
+        let response = client.get(format!("http://{}/cdc?since=abc", address)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        
+        let response = client.get(format!("http://{}/other", address)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        
+        handle.abort();
+    }
+    
+    #[tokio::test]
+    async fn test_cdc_endpoint_reports_rollbacks() {
+        // Blocks 3 and 4 are replaced by a fork up to block 5
+        let mut cache = BlockCache::new(6).unwrap();
+        for height in 1..=4 {
+            let (metadata, result) = cached_block(height);
+            cache.add_block(metadata, result).unwrap();
+        }
+        cache.rollback(2).unwrap();
+        for height in 3..=5 {
+            let (mut metadata, result) = cached_block(height);
+            metadata.hash = format!("fork{}", height);
+            cache.add_block(metadata, result).unwrap();
+        }
+        
+        let (address, handle) = serve_cdc_api("127.0.0.1:0", Arc::new(Mutex::new(cache))).unwrap();
+        let client = reqwest::Client::new();
+        let get = |query: &str| client.get(format!("http://{}/cdc?{}", address, query)).send();
+        
+        // A consumer that may have seen the rolled back blocks is sent back to the common ancestor
+        for query in ["since=4", "since=4&hash=hash4", "since=7"] {
+            let response = get(query).await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::CONFLICT, "{}", query);
+            assert!(response.text().await.unwrap().contains("fetch again since 2"), "{}", query);
+        }
+        
+        // A consumer already on the new chain, or from before the rollback, is served
+        let keys = |messages: Vec<CdcMessage>| -> Vec<String> {
+            messages.into_iter().map(|message| message.payload.key).collect()
+        };
+        let messages = get("since=4&hash=fork4").await.unwrap().json().await.unwrap();
+        assert_eq!(keys(messages), vec!["key_5"]);
+        let messages = get("since=2").await.unwrap().json().await.unwrap();
+        assert_eq!(keys(messages), vec!["key_3", "key_4", "key_5"]);
+        
+        handle.abort();
+    }
+}
\ No newline at end of file
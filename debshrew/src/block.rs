@@ -7,6 +7,7 @@ use crate::error::{Error, Result};
 use debshrew_runtime::TransformResult;
 use debshrew_support::{BlockMetadata, CdcMessage, TransformState};
 use std::collections::VecDeque;
+use std::ops::RangeInclusive;
 
 /// Block cache
 ///
@@ -19,6 +20,10 @@ pub struct BlockCache {
     
     /// The cached blocks
     blocks: VecDeque<CachedBlock>,
+    
+    /// The height rolled back to and the previous highest height of the
+    /// most recent rollback
+    last_rollback: Option<(u32, u32)>,
 }
 
 /// Cached block
@@ -58,6 +63,7 @@ impl BlockCache {
         Ok(Self {
             max_size,
             blocks: VecDeque::with_capacity(max_size as usize),
+            last_rollback: None,
         })
     }
     
@@ -174,6 +180,10 @@ impl BlockCache {
         
         // Find the highest common ancestor
         for (height, hash) in sorted_hashes {
+            if !self.contains_height(height) {
+                continue;
+            }
+            
             if let Some(cached_hash) = self.get_block_hash(height) {
                 if cached_hash == hash {
                     return Some(height);
@@ -203,12 +213,25 @@ impl BlockCache {
             .ok_or_else(|| Error::ReorgHandling(format!("Block at height {} not found in cache", height)))?;
         
         // Remove all blocks after the specified height
+        if let Some(highest) = self.highest_height().filter(|highest| *highest > height) {
+            self.last_rollback = Some((height, highest));
+        }
         self.blocks.truncate(index + 1);
         
         // Return the state snapshot at the specified height
         Ok(self.blocks[index].state_snapshot.clone())
     }
     
+    /// Get the most recent rollback that removed blocks
+    ///
+    /// # Returns
+    ///
+    /// The height rolled back to and the highest height before the rollback,
+    /// or None if no blocks have been rolled back
+    pub fn last_rollback(&self) -> Option<(u32, u32)> {
+        self.last_rollback
+    }
+    
     /// Get all CDC messages in the cache
     ///
     /// # Returns
@@ -260,6 +283,25 @@ impl BlockCache {
         messages
     }
     
+    /// Get the CDC messages for all cached blocks above a height
+    ///
+    /// Lets a consumer that has seen everything up to `height` fetch only
+    /// what came after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The last height the consumer has seen (exclusive)
+    ///
+    /// # Returns
+    ///
+    /// The CDC messages of the cached blocks above `height`, in block order
+    pub fn get_cdc_messages_since(&self, height: u32) -> Vec<CdcMessage> {
+        self.blocks.iter()
+            .filter(|block| block.metadata.height > height)
+            .flat_map(|block| block.cdc_messages.iter().cloned())
+            .collect()
+    }
+    
     /// Clear the cache
     pub fn clear(&mut self) {
         self.blocks.clear();
@@ -309,6 +351,31 @@ impl BlockCache {
     pub fn highest_height(&self) -> Option<u32> {
         self.blocks.back().map(|block| block.metadata.height)
     }
+    
+    /// Get the range of block heights in the cache
+    ///
+    /// # Returns
+    ///
+    /// The lowest through the highest cached height, or None if the cache is empty
+    pub fn height_range(&self) -> Option<RangeInclusive<u32>> {
+        Some(self.lowest_height()?..=self.highest_height()?)
+    }
+    
+    /// Check if a block height is in the cache
+    ///
+    /// Heights outside the cached range are rejected without scanning the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height
+    ///
+    /// # Returns
+    ///
+    /// true if a block at the height is cached, false otherwise
+    pub fn contains_height(&self, height: u32) -> bool {
+        self.height_range().is_some_and(|range| range.contains(&height))
+            && self.get_block_at_height(height).is_some()
+    }
 }
 
 #[cfg(test)]
@@ -331,6 +398,7 @@ mod tests {
                 block_height: height,
                 block_hash: hash.to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -344,10 +412,7 @@ mod tests {
             },
         };
         
-        let transform_result = TransformResult {
-            cdc_messages: vec![cdc_message],
-            state_snapshot: TransformState::new(),
-        };
+        let transform_result = TransformResult::new(vec![cdc_message], TransformState::new());
         
         (metadata, transform_result)
     }
@@ -432,7 +497,9 @@ mod tests {
         assert!(cache.get_block_at_height(1).is_none());
         
         // Test rollback
+        assert_eq!(cache.last_rollback(), None);
         let _state = cache.rollback(2).unwrap();
+        assert_eq!(cache.last_rollback(), Some((2, 4)));
         
         assert_eq!(cache.len(), 1);
         assert_eq!(cache.lowest_height(), Some(2));
@@ -447,6 +514,24 @@ mod tests {
         assert!(cache.is_empty());
     }
 
+    #[test]
+    fn test_get_cdc_messages_since() {
+        let mut cache = BlockCache::new(5).unwrap();
+        for height in 1..=5 {
+            let (metadata, result) = create_test_block(height, &format!("hash{}", height));
+            cache.add_block(metadata, result).unwrap();
+        }
+        
+        let keys = |messages: Vec<CdcMessage>| -> Vec<String> {
+            messages.into_iter().map(|message| message.payload.key).collect()
+        };
+        
+        assert_eq!(keys(cache.get_cdc_messages_since(2)), vec!["key_3", "key_4", "key_5"]);
+        assert_eq!(keys(cache.get_cdc_messages_since(0)).len(), 5);
+        assert!(cache.get_cdc_messages_since(5).is_empty());
+        assert!(cache.get_cdc_messages_since(10).is_empty());
+    }
+
     #[test]
     fn test_find_common_ancestor() {
         // Create a block cache
@@ -496,4 +581,27 @@ mod tests {
         let ancestor = cache.find_common_ancestor(&new_hashes);
         assert!(ancestor.is_none());
     }
+    
+    #[test]
+    fn test_height_range() {
+        let mut cache = BlockCache::new(3).unwrap();
+        assert_eq!(cache.height_range(), None);
+        assert!(!cache.contains_height(0));
+        
+        for height in 4..=7 {
+            let (metadata, result) = create_test_block(height, &format!("hash{}", height));
+            cache.add_block(metadata, result).unwrap();
+        }
+        
+        // Block 4 was evicted
+        assert_eq!(cache.height_range(), Some(5..=7));
+        assert!(!cache.contains_height(4));
+        assert!(cache.contains_height(5));
+        assert!(cache.contains_height(7));
+        assert!(!cache.contains_height(8));
+        
+        cache.rollback(5).unwrap();
+        assert_eq!(cache.height_range(), Some(5..=5));
+        assert!(!cache.contains_height(6));
+    }
 }
\ No newline at end of file
@@ -3,11 +3,23 @@
 //! This module provides the client implementation for communicating with metashrew.
 
 use crate::error::{Error, Result};
-use crate::config::MetashrewConfig;
+use crate::config::{MetashrewConfig, RpcMethods};
 use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
 use reqwest::{Client, ClientBuilder};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(any(test, feature = "testing"))]
+use std::collections::BTreeMap;
+#[cfg(any(test, feature = "testing"))]
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
 use url::Url;
 
 /// Metashrew client trait
@@ -57,6 +69,116 @@ pub trait MetashrewClient: Send + Sync {
     ///
     /// Returns an error if the request fails
     async fn call_view(&self, view_name: &str, params: &[u8], height: Option<u32>) -> Result<Vec<u8>>;
+    
+    /// Get the raw serialized block at a given height
+    ///
+    /// Only needed by transforms that parse raw block data. The default
+    /// implementation reports that raw blocks are unsupported.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height
+    ///
+    /// # Returns
+    ///
+    /// The raw block bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or raw blocks are unsupported
+    async fn get_block(&self, height: u32) -> Result<Vec<u8>> {
+        Err(Error::MetashrewClient(format!("Raw block not available for height {}", height)))
+    }
+    
+    /// Get how far metashrew has indexed the chain
+    ///
+    /// The default implementation treats the height reported by `get_height`
+    /// as both the indexed and the chain height, so metashrew is always
+    /// considered synced.
+    ///
+    /// # Returns
+    ///
+    /// The indexed and chain heights
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails
+    async fn sync_info(&self) -> Result<SyncInfo> {
+        let height = self.get_height().await?;
+        Ok(SyncInfo { indexed_height: height, chain_height: height })
+    }
+    
+    /// Check if metashrew has indexed the whole chain
+    ///
+    /// # Returns
+    ///
+    /// true if metashrew is synced, false if it is still indexing
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails
+    async fn is_synced(&self) -> Result<bool> {
+        Ok(self.sync_info().await?.is_synced())
+    }
+}
+
+/// Typed helpers for metashrew clients
+///
+/// Implemented for every `MetashrewClient`. View parameters and results are
+/// encoded as JSON, the same codec transforms use with `serialize_params`
+/// and `deserialize_result`.
+#[async_trait]
+pub trait MetashrewClientExt: MetashrewClient {
+    /// Call a view function with typed parameters and result
+    ///
+    /// # Arguments
+    ///
+    /// * `view_name` - The name of the view function
+    /// * `params` - The view parameters
+    /// * `height` - The block height to query at (optional)
+    ///
+    /// # Returns
+    ///
+    /// The decoded view result
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameters cannot be encoded, the request
+    /// fails, or the result cannot be decoded
+    async fn call_view_typed<P, R>(&self, view_name: &str, params: &P, height: Option<u32>) -> Result<R>
+    where
+        P: Serialize + Sync + ?Sized,
+        R: DeserializeOwned,
+    {
+        let params = serde_json::to_vec(params)?;
+        let result = self.call_view(view_name, &params, height).await?;
+        
+        serde_json::from_slice(&result)
+            .map_err(|e| Error::MetashrewClient(format!("Failed to decode result of view {}: {}", view_name, e)))
+    }
+}
+
+impl<T: MetashrewClient + ?Sized> MetashrewClientExt for T {}
+
+/// Metashrew sync status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncInfo {
+    /// The highest block metashrew has indexed
+    pub indexed_height: u32,
+    
+    /// The height of the chain metashrew is indexing
+    pub chain_height: u32,
+}
+
+impl SyncInfo {
+    /// Check if metashrew has indexed the whole chain
+    ///
+    /// # Returns
+    ///
+    /// true if the indexed height has reached the chain height, false otherwise
+    pub fn is_synced(&self) -> bool {
+        self.indexed_height >= self.chain_height
+    }
 }
 
 /// JSON-RPC request
@@ -107,17 +229,180 @@ struct JsonRpcError {
     data: Option<serde_json::Value>,
 }
 
+/// Responses awaited on a WebSocket connection, by request ID
+type PendingResponses = Arc<std::sync::Mutex<HashMap<u32, oneshot::Sender<Result<String>>>>>;
+
+/// How JSON-RPC requests reach metashrew
+#[derive(Debug, Clone)]
+enum Transport {
+    /// One HTTP POST per request
+    Http(Client),
+    
+    /// Requests multiplexed over a persistent WebSocket connection
+    WebSocket(Arc<WebSocketConnection>),
+}
+
+/// A persistent WebSocket connection to metashrew
+///
+/// Requests are written as they are made and matched to their responses by
+/// JSON-RPC ID, so concurrent view calls share one connection instead of
+/// each paying for an HTTP round trip. The connection is opened on first use
+/// and reopened on the next request after it drops.
+#[derive(Debug)]
+struct WebSocketConnection {
+    /// The metashrew URL
+    url: Url,
+    
+    /// The maximum time to wait for the connection to open
+    connect_timeout: Duration,
+    
+    /// The maximum time to wait for a response
+    request_timeout: Duration,
+    
+    /// Queue of requests to write, `None` until connected
+    outgoing: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    
+    /// Requests awaiting a response
+    pending: PendingResponses,
+}
+
+impl WebSocketConnection {
+    /// Create a connection that opens on first use
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The `ws://` or `wss://` metashrew URL
+    /// * `connect_timeout` - The maximum time to wait for the connection to open
+    /// * `request_timeout` - The maximum time to wait for a response
+    ///
+    /// # Returns
+    ///
+    /// A new, not yet connected, WebSocket connection
+    fn new(url: Url, connect_timeout: Duration, request_timeout: Duration) -> Self {
+        Self {
+            url,
+            connect_timeout,
+            request_timeout,
+            outgoing: Mutex::new(None),
+            pending: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+    
+    /// Get the request queue of the open connection, connecting if needed
+    ///
+    /// # Returns
+    ///
+    /// A sender for the request queue
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection cannot be opened
+    async fn outgoing(&self) -> Result<mpsc::UnboundedSender<String>> {
+        let mut outgoing = self.outgoing.lock().await;
+        if let Some(sender) = outgoing.as_ref().filter(|sender| !sender.is_closed()) {
+            return Ok(sender.clone());
+        }
+        
+        let (mut stream, _) = time::timeout(self.connect_timeout, tokio_tungstenite::connect_async(self.url.as_str()))
+            .await
+            .map_err(|_| Error::MetashrewClient(format!("Timed out connecting to {}", self.url)))?
+            .map_err(|e| Error::MetashrewClient(format!("Failed to connect to {}: {}", self.url, e)))?;
+        
+        // A single task writes queued requests and routes responses to their
+        // callers until the connection drops
+        let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+        let pending = self.pending.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    request = receiver.recv() => match request {
+                        Some(request) => {
+                            if stream.send(Message::Text(request)).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                    message = stream.next() => match message {
+                        Some(Ok(Message::Text(response))) => {
+                            let id = serde_json::from_str::<serde_json::Value>(&response)
+                                .ok()
+                                .and_then(|response| response["id"].as_u64());
+                            let caller = id.and_then(|id| pending.lock().unwrap().remove(&(id as u32)));
+                            if let Some(caller) = caller {
+                                let _ = caller.send(Ok(response));
+                            }
+                        }
+                        Some(Ok(_)) => {}
+                        Some(Err(_)) | None => break,
+                    },
+                }
+            }
+            
+            // Closing the queue makes the next request reconnect
+            drop(receiver);
+            for (_, caller) in pending.lock().unwrap().drain() {
+                let _ = caller.send(Err(Error::MetashrewClient("WebSocket connection closed".to_string())));
+            }
+        });
+        
+        *outgoing = Some(sender.clone());
+        Ok(sender)
+    }
+    
+    /// Send a request and wait for the response with the same ID
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The JSON-RPC request ID
+    /// * `request` - The serialized request
+    ///
+    /// # Returns
+    ///
+    /// The serialized response
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection fails or no response arrives in time
+    async fn request(&self, id: u32, request: String) -> Result<String> {
+        let outgoing = self.outgoing().await?;
+        let (caller, response) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, caller);
+        
+        if outgoing.send(request).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(Error::MetashrewClient("WebSocket connection closed".to_string()));
+        }
+        
+        match time::timeout(self.request_timeout, response).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(Error::MetashrewClient("WebSocket connection closed".to_string())),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(Error::MetashrewClient(format!("Timed out waiting for response {}", id)))
+            }
+        }
+    }
+}
+
 /// JSON-RPC client for metashrew
+///
+/// Requests are sent over a persistent WebSocket connection when the URL has
+/// a `ws://` or `wss://` scheme, and as HTTP POST requests otherwise.
 #[derive(Debug, Clone)]
 pub struct JsonRpcClient {
-    /// The HTTP client
-    client: Client,
+    /// How requests reach metashrew
+    transport: Transport,
     
     /// The metashrew URL
     url: Url,
     
-    /// The request ID counter
-    request_id: u32,
+    /// The JSON-RPC method names
+    methods: RpcMethods,
+    
+    /// The request ID counter, shared by all clones of the client so IDs
+    /// stay unique across concurrent requests
+    request_id: Arc<AtomicU32>,
 }
 
 impl JsonRpcClient {
@@ -138,12 +423,21 @@ impl JsonRpcClient {
         let url = Url::parse(url)
             .map_err(|e| Error::MetashrewClient(format!("Invalid URL: {}", e)))?;
         
-        let client = Client::new();
+        let transport = if is_websocket(&url) {
+            Transport::WebSocket(Arc::new(WebSocketConnection::new(
+                url.clone(),
+                Duration::from_secs(10),
+                Duration::from_secs(30),
+            )))
+        } else {
+            Transport::Http(Client::new())
+        };
         
         Ok(Self {
-            client,
+            transport,
             url,
-            request_id: 0,
+            methods: RpcMethods::default(),
+            request_id: Arc::new(AtomicU32::new(0)),
         })
     }
     
@@ -161,12 +455,29 @@ impl JsonRpcClient {
     ///
     /// Returns an error if the configuration is invalid
     pub fn from_config(config: &MetashrewConfig) -> Result<Self> {
-        let url = Url::parse(&config.url)
-            .map_err(|e| Error::MetashrewClient(format!("Invalid URL: {}", e)))?;
+        let url = config.rpc_url()?;
+        
+        // Connecting should be quick, but large view results can take a
+        // while to read; large results are also often compressed
+        let request_timeout = config.request_timeout.unwrap_or(config.timeout);
+        if is_websocket(&url) {
+            return Ok(Self {
+                transport: Transport::WebSocket(Arc::new(WebSocketConnection::new(
+                    url.clone(),
+                    Duration::from_secs(config.connect_timeout),
+                    Duration::from_secs(request_timeout),
+                ))),
+                url,
+                methods: config.rpc_methods.clone(),
+                request_id: Arc::new(AtomicU32::new(0)),
+            });
+        }
         
         let client_builder = ClientBuilder::new()
-            .timeout(Duration::from_secs(config.timeout))
-            .connect_timeout(Duration::from_secs(config.timeout));
+            .timeout(Duration::from_secs(request_timeout))
+            .connect_timeout(Duration::from_secs(config.connect_timeout))
+            .gzip(true)
+            .deflate(true);
         
         // Add authentication if provided
         if let (Some(_username), Some(_password)) = (&config.username, &config.password) {
@@ -175,9 +486,10 @@ impl JsonRpcClient {
                 .map_err(|e| Error::MetashrewClient(format!("Failed to build HTTP client: {}", e)))?;
             
             return Ok(Self {
-                client,
+                transport: Transport::Http(client),
                 url,
-                request_id: 0,
+                methods: config.rpc_methods.clone(),
+                request_id: Arc::new(AtomicU32::new(0)),
             });
         }
         
@@ -185,9 +497,10 @@ impl JsonRpcClient {
             .map_err(|e| Error::MetashrewClient(format!("Failed to build HTTP client: {}", e)))?;
         
         Ok(Self {
-            client,
+            transport: Transport::Http(client),
             url,
-            request_id: 0,
+            methods: config.rpc_methods.clone(),
+            request_id: Arc::new(AtomicU32::new(0)),
         })
     }
     
@@ -196,10 +509,9 @@ impl JsonRpcClient {
     /// # Returns
     ///
     /// The next request ID
-    fn next_request_id(&mut self) -> u32 {
-        let id = self.request_id;
-        self.request_id = self.request_id.wrapping_add(1);
-        id
+    fn next_request_id(&self) -> u32 {
+        // fetch_add wraps on overflow
+        self.request_id.fetch_add(1, Ordering::Relaxed)
     }
     
     /// Send a JSON-RPC request
@@ -216,7 +528,7 @@ impl JsonRpcClient {
     /// # Errors
     ///
     /// Returns an error if the request fails
-    async fn send_request<T, R>(&mut self, method: &str, params: T) -> Result<R>
+    async fn send_request<T, R>(&self, method: &str, params: T) -> Result<R>
     where
         T: Serialize,
         R: for<'de> Deserialize<'de>,
@@ -228,20 +540,32 @@ impl JsonRpcClient {
             id: self.next_request_id(),
         };
         
-        let response = self.client.post(self.url.clone())
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| Error::MetashrewClient(format!("Failed to send request: {}", e)))?;
-        
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Error::MetashrewClient(format!("HTTP error: {}", status)));
-        }
-        
-        let json_response: JsonRpcResponse<R> = response.json()
-            .await
-            .map_err(|e| Error::MetashrewClient(format!("Failed to parse response: {}", e)))?;
+        let json_response: JsonRpcResponse<R> = match &self.transport {
+            Transport::Http(client) => {
+                let response = client.post(self.url.clone())
+                    .json(&request)
+                    .send()
+                    .await
+                    .map_err(|e| Error::MetashrewClient(format!("Failed to send request: {}", e)))?;
+                
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(Error::MetashrewClient(format!("HTTP error: {}", status)));
+                }
+                
+                response.json()
+                    .await
+                    .map_err(|e| Error::MetashrewClient(format!("Failed to parse response: {}", e)))?
+            }
+            Transport::WebSocket(connection) => {
+                let body = serde_json::to_string(&request)
+                    .map_err(|e| Error::MetashrewClient(format!("Failed to serialize request: {}", e)))?;
+                let response = connection.request(request.id, body).await?;
+                
+                serde_json::from_str(&response)
+                    .map_err(|e| Error::MetashrewClient(format!("Failed to parse response: {}", e)))?
+            }
+        };
         
         if let Some(error) = json_response.error {
             return Err(Error::MetashrewClient(format!("JSON-RPC error: {} (code: {})", error.message, error.code)));
@@ -252,17 +576,20 @@ impl JsonRpcClient {
     }
 }
 
+/// Check whether a URL should be reached over WebSocket
+fn is_websocket(url: &Url) -> bool {
+    matches!(url.scheme(), "ws" | "wss")
+}
+
 #[async_trait]
 impl MetashrewClient for JsonRpcClient {
     async fn get_height(&self) -> Result<u32> {
-        let mut client = self.clone();
-        let height: u32 = client.send_request("metashrew_height", ()).await?;
+        let height: u32 = self.send_request(&self.methods.height, ()).await?;
         Ok(height)
     }
     
     async fn get_block_hash(&self, height: u32) -> Result<Vec<u8>> {
-        let mut client = self.clone();
-        let hash: String = client.send_request("metashrew_blockHash", vec![height]).await?;
+        let hash: String = self.send_request(&self.methods.block_hash, vec![height]).await?;
         
         // Convert hex string to bytes
         let hash_bytes = hex::decode(hash)
@@ -272,8 +599,6 @@ impl MetashrewClient for JsonRpcClient {
     }
     
     async fn call_view(&self, view_name: &str, params: &[u8], height: Option<u32>) -> Result<Vec<u8>> {
-        let mut client = self.clone();
-        
         // Convert params to hex string
         let params_hex = hex::encode(params);
         
@@ -284,7 +609,7 @@ impl MetashrewClient for JsonRpcClient {
         };
         
         // Call the view function
-        let result: String = client.send_request("metashrew_view", view_params).await?;
+        let result: String = self.send_request(&self.methods.view, view_params).await?;
         
         // Convert hex string to bytes
         let result_bytes = hex::decode(result)
@@ -292,6 +617,25 @@ impl MetashrewClient for JsonRpcClient {
         
         Ok(result_bytes)
     }
+    
+    async fn get_block(&self, height: u32) -> Result<Vec<u8>> {
+        let block: String = self.send_request(&self.methods.block, vec![height]).await?;
+        
+        // Convert hex string to bytes
+        let block_bytes = hex::decode(block)
+            .map_err(|e| Error::MetashrewClient(format!("Failed to decode block: {}", e)))?;
+        
+        Ok(block_bytes)
+    }
+    
+    async fn sync_info(&self) -> Result<SyncInfo> {
+        let Some(method) = &self.methods.sync_status else {
+            let height = self.get_height().await?;
+            return Ok(SyncInfo { indexed_height: height, chain_height: height });
+        };
+        
+        self.send_request(method, ()).await
+    }
 }
 
 /// Mock metashrew client for testing
@@ -305,6 +649,12 @@ pub struct MockMetashrewClient {
     
     /// The view function results
     pub view_results: Vec<(String, Vec<u8>, Option<u32>, Vec<u8>)>,
+    
+    /// The raw blocks by height
+    pub blocks: HashMap<u32, Vec<u8>>,
+    
+    /// The chain height, if metashrew is still indexing towards it
+    pub chain_height: Option<u32>,
 }
 
 impl MockMetashrewClient {
@@ -318,6 +668,8 @@ impl MockMetashrewClient {
             height: 0,
             block_hashes: Vec::new(),
             view_results: Vec::new(),
+            blocks: HashMap::new(),
+            chain_height: None,
         }
     }
     
@@ -356,6 +708,28 @@ impl MockMetashrewClient {
     pub fn set_view_result(&mut self, view_name: &str, params: &[u8], height: Option<u32>, result: Vec<u8>) {
         self.view_results.push((view_name.to_string(), params.to_vec(), height, result));
     }
+    
+    /// Set the raw block for a given height
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height
+    /// * `block` - The raw block bytes
+    pub fn set_block(&mut self, height: u32, block: Vec<u8>) {
+        self.blocks.insert(height, block);
+    }
+    
+    /// Set the chain height metashrew is indexing towards
+    ///
+    /// While the current block height is below it, the client reports that
+    /// metashrew is not synced.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The chain height, or None to report metashrew as synced
+    pub fn set_chain_height(&mut self, height: Option<u32>) {
+        self.chain_height = height;
+    }
 }
 
 #[async_trait]
@@ -386,6 +760,19 @@ impl MetashrewClient for MockMetashrewClient {
         
         Err(Error::MetashrewClient(format!("View result not found for {}", view_name)))
     }
+    
+    async fn get_block(&self, height: u32) -> Result<Vec<u8>> {
+        self.blocks.get(&height)
+            .cloned()
+            .ok_or_else(|| Error::MetashrewClient(format!("Block not found for height {}", height)))
+    }
+    
+    async fn sync_info(&self) -> Result<SyncInfo> {
+        Ok(SyncInfo {
+            indexed_height: self.height,
+            chain_height: self.chain_height.unwrap_or(self.height),
+        })
+    }
 }
 
 impl Default for MockMetashrewClient {
@@ -394,14 +781,194 @@ impl Default for MockMetashrewClient {
     }
 }
 
+/// A chain state played by `ScriptedMetashrewClient`
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Default)]
+pub struct ChainState {
+    /// The block hashes by height; the highest height is the tip
+    pub block_hashes: BTreeMap<u32, Vec<u8>>,
+    
+    /// The view function results by view name and parameters
+    pub view_results: HashMap<(String, Vec<u8>), Vec<u8>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl ChainState {
+    /// Create an empty chain state
+    ///
+    /// # Returns
+    ///
+    /// A chain state with no blocks
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Add blocks to the chain state
+    ///
+    /// # Arguments
+    ///
+    /// * `heights` - The heights of the blocks
+    /// * `hash` - The hash of the block at a height
+    ///
+    /// # Returns
+    ///
+    /// The chain state with the blocks added
+    pub fn with_blocks<F: Fn(u32) -> Vec<u8>>(mut self, heights: RangeInclusive<u32>, hash: F) -> Self {
+        for height in heights {
+            self.block_hashes.insert(height, hash(height));
+        }
+        self
+    }
+    
+    /// Add a view function result to the chain state
+    ///
+    /// # Arguments
+    ///
+    /// * `view_name` - The name of the view function
+    /// * `params` - The parameters to match
+    /// * `result` - The result to return
+    ///
+    /// # Returns
+    ///
+    /// The chain state with the view result added
+    pub fn with_view_result(mut self, view_name: &str, params: &[u8], result: Vec<u8>) -> Self {
+        self.view_results.insert((view_name.to_string(), params.to_vec()), result);
+        self
+    }
+    
+    /// Get the height of the chain tip
+    ///
+    /// # Returns
+    ///
+    /// The highest block height, or 0 for an empty chain
+    pub fn tip(&self) -> u32 {
+        self.block_hashes.keys().next_back().copied().unwrap_or(0)
+    }
+}
+
+/// Metashrew client playing a scripted timeline of chain states
+///
+/// Each call to `get_height` (including through the default `sync_info`)
+/// moves on to the next chain state, staying on the last one once the
+/// script is played out. Block hashes and view results are answered from
+/// the chain state shown by the latest `get_height`, so a script such as
+/// "tip 10, reorg at 7 with new hashes, extend to 12" drives the
+/// synchronizer through the reorg step by step.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug)]
+pub struct ScriptedMetashrewClient {
+    /// The chain states, in the order they are played
+    states: Vec<ChainState>,
+    
+    /// The index of the current chain state, or None before the first `get_height`
+    position: std::sync::Mutex<Option<usize>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl ScriptedMetashrewClient {
+    /// Create a new scripted metashrew client
+    ///
+    /// # Arguments
+    ///
+    /// * `states` - The chain states, in the order they are played
+    ///
+    /// # Returns
+    ///
+    /// A new scripted metashrew client
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the script has no chain states
+    pub fn new(states: Vec<ChainState>) -> Result<Self> {
+        if states.is_empty() {
+            return Err(Error::MetashrewClient("A scripted chain needs at least one state".to_string()));
+        }
+        
+        Ok(Self {
+            states,
+            position: std::sync::Mutex::new(None),
+        })
+    }
+    
+    /// Check whether the last chain state has been reached
+    ///
+    /// # Returns
+    ///
+    /// true once `get_height` has shown the last chain state, false otherwise
+    pub fn is_finished(&self) -> bool {
+        *self.position.lock().unwrap() == Some(self.states.len() - 1)
+    }
+    
+    /// Get the chain state shown by the latest `get_height`
+    ///
+    /// # Returns
+    ///
+    /// The current chain state, or the first one before any `get_height`
+    fn current(&self) -> &ChainState {
+        let position = self.position.lock().unwrap().unwrap_or(0);
+        &self.states[position]
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[async_trait]
+impl MetashrewClient for ScriptedMetashrewClient {
+    async fn get_height(&self) -> Result<u32> {
+        let mut position = self.position.lock().unwrap();
+        let next = position.map_or(0, |current| (current + 1).min(self.states.len() - 1));
+        *position = Some(next);
+        
+        Ok(self.states[next].tip())
+    }
+    
+    async fn get_block_hash(&self, height: u32) -> Result<Vec<u8>> {
+        self.current().block_hashes.get(&height)
+            .cloned()
+            .ok_or_else(|| Error::MetashrewClient(format!("Block hash not found for height {}", height)))
+    }
+    
+    async fn call_view(&self, view_name: &str, params: &[u8], _height: Option<u32>) -> Result<Vec<u8>> {
+        self.current().view_results.get(&(view_name.to_string(), params.to_vec()))
+            .cloned()
+            .ok_or_else(|| Error::MetashrewClient(format!("View result not found for {}", view_name)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use tokio::runtime::Runtime;
     use wiremock::{Mock, MockServer, ResponseTemplate};
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{body_partial_json, method, path};
     use serde_json::json;
 
+    #[test]
+    fn test_scripted_metashrew_client() {
+        assert!(ScriptedMetashrewClient::new(Vec::new()).is_err());
+        
+        let client = ScriptedMetashrewClient::new(vec![
+            ChainState::new().with_blocks(0..=2, |height| vec![height as u8]),
+            ChainState::new()
+                .with_blocks(0..=1, |height| vec![height as u8, 1])
+                .with_view_result("balance", b"alice", vec![7]),
+        ]).unwrap();
+        let rt = Runtime::new().unwrap();
+        
+        // Each height poll moves on to the next state, then stays on the last
+        assert_eq!(rt.block_on(client.get_height()).unwrap(), 2);
+        assert_eq!(rt.block_on(client.get_block_hash(1)).unwrap(), vec![1]);
+        assert!(rt.block_on(client.call_view("balance", b"alice", None)).is_err());
+        assert!(!client.is_finished());
+        
+        for _ in 0..2 {
+            assert_eq!(rt.block_on(client.get_height()).unwrap(), 1);
+            assert_eq!(rt.block_on(client.get_block_hash(1)).unwrap(), vec![1, 1]);
+            assert!(rt.block_on(client.get_block_hash(2)).is_err());
+            assert_eq!(rt.block_on(client.call_view("balance", b"alice", None)).unwrap(), vec![7]);
+            assert!(client.is_finished());
+        }
+    }
+    
     #[test]
     fn test_mock_metashrew_client() {
         let mut client = MockMetashrewClient::new();
@@ -429,6 +996,39 @@ mod tests {
         // Test error cases
         assert!(rt.block_on(client.get_block_hash(456)).is_err());
         assert!(rt.block_on(client.call_view("nonexistent", &[], None)).is_err());
+        
+        // Test get_block
+        client.set_block(123, vec![10, 11]);
+        assert_eq!(rt.block_on(client.get_block(123)).unwrap(), vec![10, 11]);
+        assert!(rt.block_on(client.get_block(456)).is_err());
+    }
+    
+    #[test]
+    fn test_call_view_typed() {
+        #[derive(Serialize)]
+        struct BalanceQuery {
+            address: String,
+            token: String,
+        }
+        
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Balance {
+            amount: u64,
+        }
+        
+        let query = BalanceQuery { address: "bc1q".to_string(), token: "ordi".to_string() };
+        let mut client = MockMetashrewClient::new();
+        client.set_view_result("balance", &serde_json::to_vec(&query).unwrap(), Some(5), br#"{"amount":42}"#.to_vec());
+        client.set_view_result("broken", &serde_json::to_vec(&query).unwrap(), None, b"not json".to_vec());
+        
+        let rt = Runtime::new().unwrap();
+        let balance: Balance = rt.block_on(client.call_view_typed("balance", &query, Some(5))).unwrap();
+        assert_eq!(balance, Balance { amount: 42 });
+        
+        // Works through a trait object too
+        let client: Box<dyn MetashrewClient> = Box::new(client);
+        let result: Result<Balance> = rt.block_on(client.call_view_typed("broken", &query, None));
+        assert!(matches!(result, Err(Error::MetashrewClient(_))));
     }
 
     #[tokio::test]
@@ -455,4 +1055,340 @@ mod tests {
         let height = client.get_height().await.unwrap();
         assert_eq!(height, 123);
     }
+    
+    #[tokio::test]
+    async fn test_websocket_client() {
+        // A metashrew stand-in answering out of order, so responses must be
+        // matched to requests by ID
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicU32::new(0));
+        tokio::spawn({
+            let connections = connections.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    let mut requests = Vec::new();
+                    while let Some(Ok(Message::Text(request))) = socket.next().await {
+                        requests.push(serde_json::from_str::<serde_json::Value>(&request).unwrap());
+                        if requests.len() < 2 {
+                            continue;
+                        }
+                        
+                        for request in requests.drain(..).rev() {
+                            let result = match request["method"].as_str().unwrap() {
+                                "metashrew_height" => json!(123),
+                                "metashrew_view" => json!(format!("{}ff", request["params"][1].as_str().unwrap())),
+                                method => panic!("unexpected method {}", method),
+                            };
+                            let response = json!({ "jsonrpc": "2.0", "result": result, "id": request["id"] });
+                            socket.send(Message::Text(response.to_string())).await.unwrap();
+                        }
+                    }
+                }
+            }
+        });
+        
+        let client = JsonRpcClient::new(&format!("ws://{}", address)).unwrap();
+        let (height, view) = tokio::join!(
+            client.get_height(),
+            client.call_view("balance", &[1, 2], Some(5)),
+        );
+        assert_eq!(height.unwrap(), 123);
+        assert_eq!(view.unwrap(), vec![1, 2, 0xff]);
+        
+        // Later requests reuse the connection
+        let (height, view) = tokio::join!(
+            client.get_height(),
+            client.call_view("balance", &[3], None),
+        );
+        assert_eq!(height.unwrap(), 123);
+        assert_eq!(view.unwrap(), vec![3, 0xff]);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_concurrent_requests_have_unique_ids() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": 123,
+                    "id": 0
+                })))
+            .mount(&mock_server)
+            .await;
+        
+        // Clones share the counter with the client they came from
+        let client = JsonRpcClient::new(&mock_server.uri()).unwrap();
+        let other = client.clone();
+        let requests = (0..8).map(|i| {
+            let client = if i % 2 == 0 { client.clone() } else { other.clone() };
+            tokio::spawn(async move { client.get_height().await })
+        });
+        for result in futures::future::join_all(requests).await {
+            assert_eq!(result.unwrap().unwrap(), 123);
+        }
+        
+        let mut ids: Vec<u64> = mock_server.received_requests().await.unwrap()
+            .iter()
+            .map(|request| request.body_json::<serde_json::Value>().unwrap()["id"].as_u64().unwrap())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..8).collect::<Vec<u64>>());
+    }
+    
+    #[tokio::test]
+    async fn test_requests_do_not_clone_the_client() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "metashrew_height" })))
+            .respond_with(ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(200))
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": 123,
+                    "id": 0
+                })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "metashrew_view" })))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": "0a0b",
+                    "id": 1
+                })))
+            .mount(&mock_server)
+            .await;
+        
+        let client = JsonRpcClient::new(&mock_server.uri()).unwrap();
+        
+        // A clone would hold a second reference to the id counter while the
+        // request is in flight
+        let (height, references) = tokio::join!(client.get_height(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Arc::strong_count(&client.request_id)
+        });
+        assert_eq!(height.unwrap(), 123);
+        assert_eq!(references, 1);
+        
+        // Requests still carry consecutive ids and decode as before
+        assert_eq!(client.call_view("balance", &[1, 2], Some(5)).await.unwrap(), vec![0x0a, 0x0b]);
+        let ids: Vec<u64> = mock_server.received_requests().await.unwrap()
+            .iter()
+            .map(|request| request.body_json::<serde_json::Value>().unwrap()["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, vec![0, 1]);
+    }
+    
+    #[tokio::test]
+    async fn test_compressed_responses_are_decoded() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::io::Write;
+        
+        let body = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "result": "ab".repeat(4096),
+            "id": 0
+        })).unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&body).unwrap();
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(&body).unwrap();
+        
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "params": ["gzip"] })))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_raw(gzip.finish().unwrap(), "application/json"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "params": ["deflate"] })))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "deflate")
+                .set_body_raw(deflate.finish().unwrap(), "application/json"))
+            .mount(&mock_server)
+            .await;
+        
+        let config: MetashrewConfig = serde_json::from_value(json!({ "url": mock_server.uri() })).unwrap();
+        let client = JsonRpcClient::from_config(&config).unwrap();
+        for encoding in ["gzip", "deflate"] {
+            let result: String = client.send_request("metashrew_view", vec![encoding]).await.unwrap();
+            assert_eq!(result, "ab".repeat(4096));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_json_rpc_client_custom_methods() {
+        // Start a mock server
+        let mock_server = MockServer::start().await;
+        
+        // Mock the overridden height and block hash methods
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "getblockcount" })))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": 456,
+                    "id": 0
+                })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "getblockhash", "params": [456] })))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": "0102",
+                    "id": 1
+                })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        
+        // Create a client with overridden method names
+        let config = MetashrewConfig {
+            url: mock_server.uri(),
+            rpc_path: None,
+            username: None,
+            password: None,
+            timeout: 30,
+            connect_timeout: 5,
+            request_timeout: None,
+            max_retries: 3,
+            retry_delay: 1000,
+            rpc_methods: RpcMethods {
+                height: "getblockcount".to_string(),
+                block_hash: "getblockhash".to_string(),
+                ..RpcMethods::default()
+            },
+        };
+        let client = JsonRpcClient::from_config(&config).unwrap();
+        
+        // The client should call the overridden methods
+        assert_eq!(client.get_height().await.unwrap(), 456);
+        assert_eq!(client.get_block_hash(456).await.unwrap(), vec![1, 2]);
+    }
+    
+    #[tokio::test]
+    async fn test_requests_are_posted_to_the_rpc_path() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/metashrew/rpc"))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": 123,
+                    "id": 0
+                })))
+            .mount(&mock_server)
+            .await;
+        
+        // A path in the URL is kept, and the RPC path is appended to it
+        let config: MetashrewConfig = serde_json::from_value(json!({
+            "url": format!("{}/metashrew/", mock_server.uri()),
+            "rpc_path": "/rpc"
+        })).unwrap();
+        assert_eq!(config.rpc_url().unwrap().path(), "/metashrew/rpc");
+        assert_eq!(JsonRpcClient::from_config(&config).unwrap().get_height().await.unwrap(), 123);
+        
+        let config: MetashrewConfig = serde_json::from_value(json!({
+            "url": format!("{}/metashrew/rpc", mock_server.uri())
+        })).unwrap();
+        assert_eq!(JsonRpcClient::from_config(&config).unwrap().get_height().await.unwrap(), 123);
+        
+        // Without the path, requests miss the endpoint
+        let config: MetashrewConfig = serde_json::from_value(json!({ "url": mock_server.uri() })).unwrap();
+        assert!(JsonRpcClient::from_config(&config).unwrap().get_height().await.is_err());
+        
+        let paths: Vec<String> = mock_server.received_requests().await.unwrap()
+            .iter()
+            .map(|request| request.url.path().to_string())
+            .collect();
+        assert_eq!(paths[..2], ["/metashrew/rpc", "/metashrew/rpc"]);
+    }
+    
+    #[tokio::test]
+    async fn test_connect_and_request_timeouts_are_independent() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200)
+                .set_delay(Duration::from_millis(1500))
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": 123,
+                    "id": 0
+                })))
+            .mount(&mock_server)
+            .await;
+        
+        let mut config: MetashrewConfig = serde_json::from_value(json!({
+            "url": mock_server.uri(),
+            "timeout": 1,
+            "connect_timeout": 1,
+            "request_timeout": 3
+        })).unwrap();
+        assert_eq!(config.connect_timeout, 1);
+        assert_eq!(config.request_timeout, Some(3));
+        
+        // A slow response is within the request timeout even though it takes
+        // longer than the connect timeout and the fallback timeout
+        let client = JsonRpcClient::from_config(&config).unwrap();
+        assert_eq!(client.get_height().await.unwrap(), 123);
+        
+        // Without a request timeout, the fallback timeout applies
+        config.request_timeout = None;
+        let client = JsonRpcClient::from_config(&config).unwrap();
+        assert!(client.get_height().await.is_err());
+        
+        // The connect timeout defaults to a few seconds
+        let config: MetashrewConfig = serde_json::from_value(json!({ "url": mock_server.uri() })).unwrap();
+        assert_eq!((config.timeout, config.connect_timeout, config.request_timeout), (30, 5, None));
+    }
+    
+    #[tokio::test]
+    async fn test_json_rpc_client_sync_info() {
+        let mock_server = MockServer::start().await;
+        
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": "metashrew_syncStatus" })))
+            .respond_with(ResponseTemplate::new(200)
+                .set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "result": { "indexed_height": 100, "chain_height": 120 },
+                    "id": 0
+                })))
+            .mount(&mock_server)
+            .await;
+        
+        let config = MetashrewConfig {
+            url: mock_server.uri(),
+            rpc_path: None,
+            username: None,
+            password: None,
+            timeout: 30,
+            connect_timeout: 5,
+            request_timeout: None,
+            max_retries: 3,
+            retry_delay: 1000,
+            rpc_methods: RpcMethods {
+                sync_status: Some("metashrew_syncStatus".to_string()),
+                ..RpcMethods::default()
+            },
+        };
+        let client = JsonRpcClient::from_config(&config).unwrap();
+        
+        let info = client.sync_info().await.unwrap();
+        assert_eq!(info, SyncInfo { indexed_height: 100, chain_height: 120 });
+        assert!(!client.is_synced().await.unwrap());
+    }
 }
\ No newline at end of file
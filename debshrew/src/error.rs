@@ -54,13 +54,33 @@ pub enum Error {
     #[error("URL parse error: {0}")]
     UrlParse(#[from] url::ParseError),
 
-    /// Error occurred during Kafka operations
+    /// Error occurred during Kafka operations that may succeed if retried
     #[error("Kafka error: {0}")]
     Kafka(String),
+    
+    /// Error occurred during Kafka operations that recurs if retried, such
+    /// as a message that is too large or a topic the producer may not write
+    #[error("Kafka error: {0}")]
+    KafkaPermanent(String),
 
-    /// Error occurred during PostgreSQL operations
+    /// Error occurred during PostgreSQL operations that may succeed if retried
     #[error("PostgreSQL error: {0}")]
     Postgres(String),
+    
+    /// Error occurred during PostgreSQL operations that recurs if retried,
+    /// such as a constraint violation
+    #[error("PostgreSQL error: {0}")]
+    PostgresPermanent(String),
+
+    /// Error occurred during Elasticsearch operations that may succeed if
+    /// retried, such as a rejection while the cluster is overloaded
+    #[error("Elasticsearch error: {0}")]
+    Elasticsearch(String),
+    
+    /// Error occurred during Elasticsearch operations that recurs if retried,
+    /// such as a document that does not match the index mapping
+    #[error("Elasticsearch error: {0}")]
+    ElasticsearchPermanent(String),
 
     /// Error occurred during file operations
     #[error("File error: {0}")]
@@ -75,6 +95,100 @@ pub enum Error {
     Anyhow(#[from] anyhow::Error),
 }
 
+impl Error {
+    /// Create an error from a PostgreSQL error, classified by its SQLSTATE
+    ///
+    /// Errors without a SQLSTATE come from the connection rather than the
+    /// server and are transient. Server errors are transient if their class
+    /// means the server or connection was unavailable or the transaction lost
+    /// a race, and permanent otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - What was being done when the error occurred
+    /// * `e` - The PostgreSQL error
+    ///
+    /// # Returns
+    ///
+    /// `Error::Postgres` for a transient error, `Error::PostgresPermanent` otherwise
+    pub fn postgres(context: &str, e: postgres::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e.code() {
+            Some(code) if !is_transient_sql_state(code) => Error::PostgresPermanent(message),
+            _ => Error::Postgres(message),
+        }
+    }
+    
+    /// Create an error from a Kafka error, classified by its error code
+    ///
+    /// Only a full producer queue, timeouts, transport failures and
+    /// unavailable brokers or partition leaders are transient. Anything else,
+    /// including errors without an error code such as an invalid producer
+    /// configuration, is permanent.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - What was being done when the error occurred
+    /// * `e` - The Kafka error
+    ///
+    /// # Returns
+    ///
+    /// `Error::Kafka` for a transient error, `Error::KafkaPermanent` otherwise
+    pub fn kafka(context: &str, e: &rdkafka::error::KafkaError) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e.rdkafka_error_code() {
+            Some(code) if is_transient_kafka_code(code) => Error::Kafka(message),
+            _ => Error::KafkaPermanent(message),
+        }
+    }
+    
+    /// Create an error from a runtime error, keeping its category
+    ///
+    /// # Arguments
+    ///
+    /// * `e` - The error returned by the WASM runtime
+    ///
+    /// # Returns
+    ///
+    /// `Error::Runtime` if the error carries a runtime error category,
+    /// `Error::Anyhow` otherwise
+    pub fn runtime(e: anyhow::Error) -> Self {
+        match e.downcast::<debshrew_runtime::error::Error>() {
+            Ok(e) => Error::Runtime(e),
+            Err(e) => Error::Anyhow(e),
+        }
+    }
+    
+    /// Check if the error is transient
+    ///
+    /// Transient errors come from the network or from timeouts, so the same
+    /// operation may succeed if it is retried. Anything else, such as a
+    /// message that cannot be serialized, fails the same way every time.
+    ///
+    /// # Returns
+    ///
+    /// true if retrying the failed operation may succeed, false otherwise
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+            ),
+            Error::Http(e) => e.is_timeout() || e.is_connect(),
+            Error::Kafka(_) | Error::Postgres(_) | Error::Elasticsearch(_) => true,
+            Error::Runtime(debshrew_runtime::error::Error::ViewAccess(_)) => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<&str> for Error {
     fn from(s: &str) -> Self {
         Error::Generic(s.to_string())
@@ -89,16 +203,61 @@ impl From<String> for Error {
 
 impl From<rdkafka::error::KafkaError> for Error {
     fn from(e: rdkafka::error::KafkaError) -> Self {
-        Error::Kafka(format!("Kafka error: {}", e))
+        Error::kafka("Kafka error", &e)
     }
 }
 
 impl From<postgres::Error> for Error {
     fn from(e: postgres::Error) -> Self {
-        Error::Postgres(format!("PostgreSQL error: {}", e))
+        Error::postgres("PostgreSQL error", e)
     }
 }
 
+/// Check if a Kafka error code means the failed operation may succeed if retried
+///
+/// # Arguments
+///
+/// * `code` - The librdkafka error code
+///
+/// # Returns
+///
+/// true for a full producer queue, timeouts, transport failures and
+/// unavailable brokers or partition leaders
+pub fn is_transient_kafka_code(code: rdkafka::types::RDKafkaErrorCode) -> bool {
+    use rdkafka::types::RDKafkaErrorCode;
+    
+    matches!(
+        code,
+        RDKafkaErrorCode::QueueFull
+            | RDKafkaErrorCode::MessageTimedOut
+            | RDKafkaErrorCode::RequestTimedOut
+            | RDKafkaErrorCode::OperationTimedOut
+            | RDKafkaErrorCode::BrokerTransportFailure
+            | RDKafkaErrorCode::NetworkException
+            | RDKafkaErrorCode::AllBrokersDown
+            | RDKafkaErrorCode::BrokerNotAvailable
+            | RDKafkaErrorCode::LeaderNotAvailable
+            | RDKafkaErrorCode::NotLeaderForPartition
+            | RDKafkaErrorCode::NotEnoughReplicas
+            | RDKafkaErrorCode::NotEnoughReplicasAfterAppend
+    )
+}
+
+/// Check if a SQLSTATE means the failed operation may succeed if retried
+///
+/// # Arguments
+///
+/// * `code` - The SQLSTATE of a PostgreSQL server error
+///
+/// # Returns
+///
+/// true for connection exceptions (08), transaction rollbacks such as
+/// serialization failures and deadlocks (40), insufficient resources (53),
+/// operator intervention such as a shutdown (57) and system errors (58)
+pub fn is_transient_sql_state(code: &postgres::error::SqlState) -> bool {
+    matches!(&code.code()[..2], "08" | "40" | "53" | "57" | "58")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,4 +287,64 @@ mod tests {
         assert!(matches!(error, Error::Io(_)));
         assert!(error.to_string().contains("file not found"));
     }
+
+    #[test]
+    fn test_error_is_transient() {
+        assert!(Error::Kafka("broker down".to_string()).is_transient());
+        assert!(Error::Postgres("connection reset".to_string()).is_transient());
+        assert!(Error::from(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out")).is_transient());
+
+        assert!(!Error::from(std::io::Error::new(std::io::ErrorKind::NotFound, "missing")).is_transient());
+        assert!(!Error::Sink("invalid key".to_string()).is_transient());
+        assert!(!Error::Configuration("bad".to_string()).is_transient());
+        
+        // A view failure inside the transform may clear up, a transform bug won't
+        use debshrew_runtime::error::Error as RuntimeError;
+        assert!(Error::runtime(RuntimeError::ViewAccess("view balance failed".to_string()).into()).is_transient());
+        assert!(!Error::runtime(RuntimeError::Transform("failed with code -1".to_string()).into()).is_transient());
+        assert!(!Error::runtime(anyhow::anyhow!("trap")).is_transient());
+    }
+
+    #[test]
+    fn test_postgres_errors_are_classified() {
+        use postgres::error::SqlState;
+
+        assert!(!is_transient_sql_state(&SqlState::UNIQUE_VIOLATION));
+        assert!(!is_transient_sql_state(&SqlState::UNDEFINED_TABLE));
+        assert!(is_transient_sql_state(&SqlState::CONNECTION_FAILURE));
+        assert!(is_transient_sql_state(&SqlState::T_R_SERIALIZATION_FAILURE));
+        assert!(is_transient_sql_state(&SqlState::ADMIN_SHUTDOWN));
+        assert!(!Error::PostgresPermanent("duplicate key".to_string()).is_transient());
+
+        // Nothing listens on port 1, so connecting fails without a SQLSTATE
+        let e = postgres::Client::connect("host=127.0.0.1 port=1 user=debshrew connect_timeout=1", postgres::NoTls)
+            .err()
+            .unwrap();
+        let error = Error::postgres("Failed to connect to PostgreSQL", e);
+        assert!(matches!(error, Error::Postgres(_)));
+        assert!(error.is_transient());
+    }
+
+    #[test]
+    fn test_kafka_errors_are_classified() {
+        use rdkafka::error::KafkaError;
+        use rdkafka::types::RDKafkaErrorCode;
+
+        let transient = Error::kafka("Failed to send message", &KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull));
+        assert!(matches!(transient, Error::Kafka(_)));
+        assert!(transient.is_transient());
+        assert!(Error::from(KafkaError::Flush(RDKafkaErrorCode::OperationTimedOut)).is_transient());
+
+        // Resending the same message to the same topic fails the same way
+        for code in [
+            RDKafkaErrorCode::MessageSizeTooLarge,
+            RDKafkaErrorCode::TopicAuthorizationFailed,
+            RDKafkaErrorCode::InvalidTopic,
+        ] {
+            let error = Error::kafka("Failed to send message", &KafkaError::MessageProduction(code));
+            assert!(matches!(error, Error::KafkaPermanent(_)));
+            assert!(!error.is_transient());
+        }
+        assert!(!Error::kafka("Failed to create Kafka producer", &KafkaError::ClientCreation("bad".to_string())).is_transient());
+    }
 }
\ No newline at end of file
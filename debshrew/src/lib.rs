@@ -15,8 +15,10 @@
 //! # Example
 //!
 //! ```no_run
-//! use debshrew::{BlockSynchronizer, MetashrewClient, JsonRpcClient, create_sink, SinkConfig};
+//! use debshrew::{Acks, BlockSynchronizer, CompressionType, MetashrewClient, JsonRpcClient, KeyFormat, MessageFormat, create_sink, SerializationErrorPolicy, SinkConfig};
 //! use debshrew_runtime::WasmRuntime;
+//! use debshrew_support::TimestampFormat;
+//! use std::collections::HashMap;
 //! use std::path::Path;
 //!
 //! #[tokio::main]
@@ -34,6 +36,20 @@
 //!         client_id: None,
 //!         batch_size: 100,
 //!         flush_interval: 1000,
+//!         on_serialization_error: SerializationErrorPolicy::Fail,
+//!         table_keys: HashMap::new(),
+//!         compression_type: CompressionType::None,
+//!         acks: Acks::All,
+//!         retries: None,
+//!         enable_idempotence: false,
+//!         concurrent_sends: false,
+//!         key_format: KeyFormat::String,
+//!         message_format: MessageFormat::Envelope,
+//!         emit_tombstones: false,
+//!         route_by_operation: false,
+//!         sort_keys: false,
+//!         timestamp_format: TimestampFormat::Rfc3339,
+//!         retry: None,
 //!     };
 //!     let sink = create_sink(&sink_config)?;
 //!
@@ -50,12 +66,15 @@
 #![warn(missing_docs)]
 #![warn(rustdoc::missing_doc_code_examples)]
 
+pub mod api;
 pub mod block;
 pub mod client;
 pub mod config;
 pub mod error;
 pub mod sink;
+pub mod state;
 pub mod synchronizer;
+pub mod wal;
 
 /// Re-export common types and functions for convenience
 pub use block::BlockCache;
@@ -64,5 +83,6 @@ pub use config::*;
 pub use debshrew_runtime::WasmRuntime;
 pub use debshrew_support;
 pub use error::{Error, Result};
-pub use sink::{CdcSink, create_sink, ConsoleSink, FileSink, KafkaSink, NullSink, PostgresSink};
-pub use synchronizer::{BlockSynchronizer, Synchronizer};
\ No newline at end of file
+pub use sink::{CdcSink, create_sink, register_sink_factory, ConsoleSink, ElasticsearchSink, FileSink, KafkaProducerOptions, KafkaSink, NullSink, PostgresSink, RetryingSink, TableKeys};
+pub use synchronizer::{BlockSynchronizer, StepOutcome, StopHandle, SyncStats, Synchronizer};
+pub use wal::WriteAheadLog;
\ No newline at end of file
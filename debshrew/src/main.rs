@@ -3,19 +3,24 @@
 //! This is the main binary for the debshrew service, which provides a CLI
 //! interface for running the service.
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use debshrew::{
+    api::serve_cdc_api,
     client::JsonRpcClient,
     config::{Config, SinkConfig},
     create_sink,
     error::Result,
+    state,
     BlockSynchronizer,
+    WriteAheadLog,
 };
 use debshrew_runtime::WasmRuntime;
+use debshrew_support::TimestampFormat;
 use env_logger::Env;
-use log::{error, info};
+use log::{error, info, warn};
 use std::path::PathBuf;
 use tokio::signal;
+use tokio::sync::oneshot;
 
 /// Debshrew CLI
 #[derive(Parser)]
@@ -31,38 +36,373 @@ struct Cli {
 enum Commands {
     /// Run the debshrew service
     Run {
-        /// Path to the configuration file
-        #[clap(short, long)]
-        config: Option<PathBuf>,
+        /// Configuration options
+        #[clap(flatten)]
+        args: ConfigArgs,
         
-        /// Metashrew URL
-        #[clap(short, long)]
-        metashrew_url: Option<String>,
+        /// Print the resolved configuration (with secrets redacted) and exit
+        #[clap(long)]
+        print_config: bool,
         
-        /// Path to the transform WASM module
-        #[clap(short, long)]
-        transform: Option<PathBuf>,
+        /// Catch up to the chain tip once and exit instead of polling
+        #[clap(long)]
+        once: bool,
         
-        /// Sink type (kafka, postgres, file, console)
-        #[clap(short, long)]
-        sink_type: Option<String>,
+        /// Process each block twice and fail if the CDC output differs
+        #[clap(long)]
+        validate_determinism: bool,
+    },
+    
+    /// Check the configuration and the transform module without running
+    Validate {
+        /// Configuration options
+        #[clap(flatten)]
+        args: ConfigArgs,
+    },
+    
+    /// Inspect the debshrew configuration
+    Config {
+        /// Subcommand
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+    
+    /// Inspect the persisted transform state
+    State {
+        /// Subcommand
+        #[clap(subcommand)]
+        command: StateCommands,
+    },
+}
+
+/// Configuration subcommands
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the resolved configuration with secrets redacted
+    Show {
+        /// Configuration options
+        #[clap(flatten)]
+        args: ConfigArgs,
+    },
+}
+
+/// State subcommands
+#[derive(Subcommand)]
+enum StateCommands {
+    /// Print the value of a single key
+    Get {
+        /// The key, as UTF-8 text or 0x-prefixed hex
+        key: String,
         
-        /// Path to the sink configuration file
-        #[clap(short, long)]
-        sink_config: Option<PathBuf>,
+        /// Path to the persisted state file
+        #[clap(long)]
+        state_file: PathBuf,
+    },
+    
+    /// List keys and values
+    List {
+        /// Only list keys starting with this prefix (UTF-8 text or 0x-prefixed hex)
+        #[clap(long)]
+        prefix: Option<String>,
         
-        /// Block cache size
-        #[clap(short, long, default_value = "6")]
-        cache_size: u32,
+        /// Path to the persisted state file
+        #[clap(long)]
+        state_file: PathBuf,
+    },
+}
+
+/// Options used to build the service configuration
+#[derive(Args)]
+struct ConfigArgs {
+    /// Path to the configuration file
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    
+    /// Metashrew URL
+    #[clap(short, long)]
+    metashrew_url: Option<String>,
+    
+    /// Path to the transform WASM module
+    #[clap(short, long)]
+    transform: Option<PathBuf>,
+    
+    /// Sink type (kafka, postgres, elasticsearch, file, console)
+    #[clap(short, long)]
+    sink_type: Option<String>,
+    
+    /// Path to the sink configuration file
+    #[clap(short, long)]
+    sink_config: Option<PathBuf>,
+    
+    /// Block cache size
+    #[clap(short, long, default_value = "6")]
+    cache_size: u32,
+    
+    /// Starting block height
+    #[clap(short, long)]
+    start_height: Option<u32>,
+    
+    /// Log level
+    #[clap(short, long, default_value = "info")]
+    log_level: String,
+}
+
+/// Load the configuration from a file, command line arguments or the environment
+///
+/// # Arguments
+///
+/// * `args` - The configuration options
+///
+/// # Returns
+///
+/// The resolved configuration
+///
+/// # Errors
+///
+/// Returns an error if the configuration cannot be loaded
+fn load_config(args: ConfigArgs) -> Result<Config> {
+    let ConfigArgs {
+        config,
+        metashrew_url,
+        transform,
+        sink_type,
+        sink_config,
+        cache_size,
+        start_height,
+        log_level,
+    } = args;
+    
+    let config = if let Some(config_path) = config {
+        info!("Loading configuration from {}", config_path.display());
+        Config::from_file(config_path)?
+    } else if metashrew_url.is_none() && transform.is_none() && sink_type.is_none() && sink_config.is_none() {
+        // Without a file or flags, configure from the environment
+        info!("Loading configuration from environment variables");
+        Config::from_env()?
+    } else {
+        // Create configuration from command line arguments
+        let metashrew_url = metashrew_url.ok_or_else(|| {
+            error!("Metashrew URL is required when not using a configuration file");
+            "Metashrew URL is required when not using a configuration file"
+        })?;
         
-        /// Starting block height
-        #[clap(short, long)]
-        start_height: Option<u32>,
+        let transform_path = transform.ok_or_else(|| {
+            error!("Transform path is required when not using a configuration file");
+            "Transform path is required when not using a configuration file"
+        })?;
         
-        /// Log level
-        #[clap(short, long, default_value = "info")]
-        log_level: String,
-    },
+        let sink_config = if let Some(sink_type) = sink_type {
+            match sink_type.as_str() {
+                "kafka" => {
+                    let sink_config_path = sink_config.ok_or_else(|| {
+                        error!("Sink configuration is required for Kafka sink");
+                        "Sink configuration is required for Kafka sink"
+                    })?;
+                    
+                    let sink_config_str = std::fs::read_to_string(sink_config_path)?;
+                    let kafka_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
+                    
+                    SinkConfig::Kafka {
+                        bootstrap_servers: kafka_config["bootstrap_servers"].as_str().unwrap_or("localhost:9092").to_string(),
+                        topic: kafka_config["topic"].as_str().unwrap_or("cdc-events").to_string(),
+                        client_id: kafka_config["client_id"].as_str().map(|s| s.to_string()),
+                        batch_size: kafka_config["batch_size"].as_u64().unwrap_or(100) as usize,
+                        flush_interval: kafka_config["flush_interval"].as_u64().unwrap_or(1000),
+                        on_serialization_error: serde_json::from_value(kafka_config["on_serialization_error"].clone()).unwrap_or_default(),
+                        table_keys: serde_json::from_value(kafka_config["table_keys"].clone()).unwrap_or_default(),
+                        compression_type: serde_json::from_value(kafka_config["compression_type"].clone()).unwrap_or_default(),
+                        acks: serde_json::from_value(kafka_config["acks"].clone()).unwrap_or_default(),
+                        retries: kafka_config["retries"].as_u64().map(|retries| retries as u32),
+                        enable_idempotence: kafka_config["enable_idempotence"].as_bool().unwrap_or(false),
+                        concurrent_sends: kafka_config["concurrent_sends"].as_bool().unwrap_or(false),
+                        key_format: serde_json::from_value(kafka_config["key_format"].clone()).unwrap_or_default(),
+                        message_format: serde_json::from_value(kafka_config["message_format"].clone()).unwrap_or_default(),
+                        emit_tombstones: kafka_config["emit_tombstones"].as_bool().unwrap_or(false),
+                        route_by_operation: kafka_config["route_by_operation"].as_bool().unwrap_or(false),
+                        sort_keys: kafka_config["sort_keys"].as_bool().unwrap_or(false),
+                        timestamp_format: serde_json::from_value(kafka_config["timestamp_format"].clone()).unwrap_or_default(),
+                        retry: serde_json::from_value(kafka_config["retry"].clone()).unwrap_or_default(),
+                    }
+                }
+                "postgres" => {
+                    let sink_config_path = sink_config.ok_or_else(|| {
+                        error!("Sink configuration is required for PostgreSQL sink");
+                        "Sink configuration is required for PostgreSQL sink"
+                    })?;
+                    
+                    let sink_config_str = std::fs::read_to_string(sink_config_path)?;
+                    let postgres_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
+                    
+                    SinkConfig::Postgres {
+                        connection_string: postgres_config["connection_string"].as_str().unwrap_or("").to_string(),
+                        schema: postgres_config["schema"].as_str().unwrap_or("public").to_string(),
+                        batch_size: postgres_config["batch_size"].as_u64().unwrap_or(100) as usize,
+                        flush_interval: postgres_config["flush_interval"].as_u64().unwrap_or(1000),
+                        table_keys: serde_json::from_value(postgres_config["table_keys"].clone()).unwrap_or_default(),
+                        column_types: serde_json::from_value(postgres_config["column_types"].clone()).unwrap_or_default(),
+                        route_by_operation: postgres_config["route_by_operation"].as_bool().unwrap_or(false),
+                        max_batch_age_ms: postgres_config["max_batch_age_ms"].as_u64(),
+                        retry: serde_json::from_value(postgres_config["retry"].clone()).unwrap_or_default(),
+                    }
+                }
+                "elasticsearch" => {
+                    let sink_config_path = sink_config.ok_or_else(|| {
+                        error!("Sink configuration is required for Elasticsearch sink");
+                        "Sink configuration is required for Elasticsearch sink"
+                    })?;
+                    
+                    let sink_config_str = std::fs::read_to_string(sink_config_path)?;
+                    let elasticsearch_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
+                    
+                    SinkConfig::Elasticsearch {
+                        url: elasticsearch_config["url"].as_str().unwrap_or("http://localhost:9200").to_string(),
+                        index: elasticsearch_config["index"].as_str().unwrap_or("cdc-events").to_string(),
+                        batch_size: elasticsearch_config["batch_size"].as_u64().unwrap_or(100) as usize,
+                        retry: serde_json::from_value(elasticsearch_config["retry"].clone()).unwrap_or_default(),
+                    }
+                }
+                "file" => {
+                    let sink_config_path = sink_config.ok_or_else(|| {
+                        error!("Sink configuration is required for file sink");
+                        "Sink configuration is required for file sink"
+                    })?;
+                    
+                    let sink_config_str = std::fs::read_to_string(sink_config_path)?;
+                    let file_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
+                    
+                    SinkConfig::File {
+                        path: file_config["path"].as_str().unwrap_or("cdc-events.json").to_string(),
+                        append: file_config["append"].as_bool().unwrap_or(true),
+                        flush_interval: file_config["flush_interval"].as_u64().unwrap_or(1000),
+                        on_serialization_error: serde_json::from_value(file_config["on_serialization_error"].clone()).unwrap_or_default(),
+                        route_by_operation: file_config["route_by_operation"].as_bool().unwrap_or(false),
+                        sort_keys: file_config["sort_keys"].as_bool().unwrap_or(false),
+                        timestamp_format: serde_json::from_value(file_config["timestamp_format"].clone()).unwrap_or_default(),
+                        framing: serde_json::from_value(file_config["framing"].clone()).unwrap_or_default(),
+                        retry: serde_json::from_value(file_config["retry"].clone()).unwrap_or_default(),
+                    }
+                }
+                "console" => {
+                    let (pretty_print, sort_keys, timestamp_format) = if let Some(sink_config_path) = sink_config {
+                        let sink_config_str = std::fs::read_to_string(sink_config_path)?;
+                        let console_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
+                        (
+                            console_config["pretty_print"].as_bool().unwrap_or(false),
+                            console_config["sort_keys"].as_bool().unwrap_or(false),
+                            serde_json::from_value(console_config["timestamp_format"].clone()).unwrap_or_default(),
+                        )
+                    } else {
+                        (false, false, TimestampFormat::default())
+                    };
+                    
+                    SinkConfig::Console { pretty_print, sort_keys, timestamp_format }
+                }
+                _ => {
+                    error!("Invalid sink type: {}", sink_type);
+                    return Err(format!("Invalid sink type: {}", sink_type).into());
+                }
+            }
+        } else {
+            // Default to console sink
+            SinkConfig::Console { pretty_print: false, sort_keys: false, timestamp_format: TimestampFormat::default() }
+        };
+        
+        Config {
+            metashrew: debshrew::config::MetashrewConfig {
+                url: metashrew_url,
+                rpc_path: None,
+                username: None,
+                password: None,
+                timeout: 30,
+                connect_timeout: 5,
+                request_timeout: None,
+                max_retries: 3,
+                retry_delay: 1000,
+                rpc_methods: debshrew::config::RpcMethods::default(),
+            },
+            transform: debshrew::config::TransformConfig {
+                path: Some(transform_path.to_string_lossy().to_string()),
+                url: None,
+                bytes_base64: None,
+                prefix_keys_with_source: false,
+                prefix_tables_with_source: false,
+                enforce_schemas: false,
+                view_timeout: None,
+                view_budget: None,
+                suppress_noop_updates: false,
+                catch_panics: true,
+                table_sources: std::collections::HashMap::new(),
+                rollback_mode: debshrew_runtime::RollbackMode::default(),
+            },
+            sink: sink_config,
+            cache_size,
+            confirmation_depth: None,
+            start_height,
+            log_level,
+            blocks_per_flush: None,
+            max_inflight_blocks: None,
+            max_blocks_per_iteration: None,
+            ordering: debshrew::config::Ordering::default(),
+            delivery: debshrew::config::Delivery::default(),
+            adaptive_polling: None,
+            reorg_timeout: None,
+            reorg_settle_ms: None,
+            max_block_retries: None,
+            wal_path: None,
+            state_path: None,
+            initial_state_path: None,
+            heartbeat_interval: None,
+            heartbeat_table: None,
+            cdc_api_address: None,
+            verify_state_snapshots: false,
+            reorg_markers: false,
+        }
+    };
+    
+    Ok(config)
+}
+
+/// Listen for the signals that request a graceful shutdown
+///
+/// SIGINT (Ctrl+C) is handled on every platform. On unix, SIGTERM, which
+/// Kubernetes and systemd send to stop a service, is handled the same way,
+/// so buffered output is flushed before the process exits.
+///
+/// # Returns
+///
+/// A channel receiving the name of the first shutdown signal
+///
+/// # Errors
+///
+/// Returns an error if a signal handler cannot be installed
+fn listen_for_shutdown() -> Result<oneshot::Receiver<&'static str>> {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    
+    // Install the SIGTERM handler before returning, so the signal is never
+    // delivered with its default action of killing the process
+    #[cfg(unix)]
+    let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let name = tokio::select! {
+            result = signal::ctrl_c() => {
+                result.expect("Failed to listen for Ctrl+C");
+                "SIGINT"
+            }
+            _ = sigterm.recv() => "SIGTERM",
+        };
+        
+        #[cfg(not(unix))]
+        let name = {
+            signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+            "SIGINT"
+        };
+        
+        let _ = shutdown_tx.send(name);
+    });
+    
+    Ok(shutdown_rx)
 }
 
 /// Main function
@@ -73,124 +413,18 @@ async fn main() -> Result<()> {
     
     // Run the appropriate command
     match cli.command {
-        Commands::Run {
-            config,
-            metashrew_url,
-            transform,
-            sink_type,
-            sink_config,
-            cache_size,
-            start_height,
-            log_level,
-        } => {
+        Commands::Run { args, print_config, once, validate_determinism } => {
             // Initialize logger
-            env_logger::Builder::from_env(Env::default().default_filter_or(&log_level)).init();
+            env_logger::Builder::from_env(Env::default().default_filter_or(&args.log_level)).init();
             
             // Load configuration
-            let config = if let Some(config_path) = config {
-                info!("Loading configuration from {}", config_path.display());
-                Config::from_file(config_path)?
-            } else {
-                // Create configuration from command line arguments
-                let metashrew_url = metashrew_url.ok_or_else(|| {
-                    error!("Metashrew URL is required when not using a configuration file");
-                    "Metashrew URL is required when not using a configuration file"
-                })?;
-                
-                let transform_path = transform.ok_or_else(|| {
-                    error!("Transform path is required when not using a configuration file");
-                    "Transform path is required when not using a configuration file"
-                })?;
-                
-                let sink_config = if let Some(sink_type) = sink_type {
-                    match sink_type.as_str() {
-                        "kafka" => {
-                            let sink_config_path = sink_config.ok_or_else(|| {
-                                error!("Sink configuration is required for Kafka sink");
-                                "Sink configuration is required for Kafka sink"
-                            })?;
-                            
-                            let sink_config_str = std::fs::read_to_string(sink_config_path)?;
-                            let kafka_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
-                            
-                            SinkConfig::Kafka {
-                                bootstrap_servers: kafka_config["bootstrap_servers"].as_str().unwrap_or("localhost:9092").to_string(),
-                                topic: kafka_config["topic"].as_str().unwrap_or("cdc-events").to_string(),
-                                client_id: kafka_config["client_id"].as_str().map(|s| s.to_string()),
-                                batch_size: kafka_config["batch_size"].as_u64().unwrap_or(100) as usize,
-                                flush_interval: kafka_config["flush_interval"].as_u64().unwrap_or(1000),
-                            }
-                        }
-                        "postgres" => {
-                            let sink_config_path = sink_config.ok_or_else(|| {
-                                error!("Sink configuration is required for PostgreSQL sink");
-                                "Sink configuration is required for PostgreSQL sink"
-                            })?;
-                            
-                            let sink_config_str = std::fs::read_to_string(sink_config_path)?;
-                            let postgres_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
-                            
-                            SinkConfig::Postgres {
-                                connection_string: postgres_config["connection_string"].as_str().unwrap_or("").to_string(),
-                                schema: postgres_config["schema"].as_str().unwrap_or("public").to_string(),
-                                batch_size: postgres_config["batch_size"].as_u64().unwrap_or(100) as usize,
-                                flush_interval: postgres_config["flush_interval"].as_u64().unwrap_or(1000),
-                            }
-                        }
-                        "file" => {
-                            let sink_config_path = sink_config.ok_or_else(|| {
-                                error!("Sink configuration is required for file sink");
-                                "Sink configuration is required for file sink"
-                            })?;
-                            
-                            let sink_config_str = std::fs::read_to_string(sink_config_path)?;
-                            let file_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
-                            
-                            SinkConfig::File {
-                                path: file_config["path"].as_str().unwrap_or("cdc-events.json").to_string(),
-                                append: file_config["append"].as_bool().unwrap_or(true),
-                                flush_interval: file_config["flush_interval"].as_u64().unwrap_or(1000),
-                            }
-                        }
-                        "console" => {
-                            let pretty_print = if let Some(sink_config_path) = sink_config {
-                                let sink_config_str = std::fs::read_to_string(sink_config_path)?;
-                                let console_config: serde_json::Value = serde_json::from_str(&sink_config_str)?;
-                                console_config["pretty_print"].as_bool().unwrap_or(false)
-                            } else {
-                                false
-                            };
-                            
-                            SinkConfig::Console { pretty_print }
-                        }
-                        _ => {
-                            error!("Invalid sink type: {}", sink_type);
-                            return Err(format!("Invalid sink type: {}", sink_type).into());
-                        }
-                    }
-                } else {
-                    // Default to console sink
-                    SinkConfig::Console { pretty_print: false }
-                };
-                
-                Config {
-                    metashrew: debshrew::config::MetashrewConfig {
-                        url: metashrew_url,
-                        username: None,
-                        password: None,
-                        timeout: 30,
-                        max_retries: 3,
-                        retry_delay: 1000,
-                    },
-                    transform: debshrew::config::TransformConfig {
-                        path: transform_path.to_string_lossy().to_string(),
-                    },
-                    sink: sink_config,
-                    cache_size,
-                    start_height,
-                    log_level,
-                }
-            };
+            let config = load_config(args)?;
+            
+            // Print the configuration and exit if requested
+            if print_config {
+                println!("{}", config.to_redacted_json()?);
+                return Ok(());
+            }
             
             // Validate configuration
             config.validate()?;
@@ -200,52 +434,204 @@ async fn main() -> Result<()> {
             let client = JsonRpcClient::from_config(&config.metashrew)?;
             
             // Load transform module
-            info!("Loading transform module from {}", config.transform.path);
-            let runtime = WasmRuntime::new(&config.transform.path)?;
+            info!("Loading transform module from {}", config.transform.location());
+            let mut runtime = WasmRuntime::from_source(config.transform.source().await?)?;
+            runtime.set_source_prefix(
+                config.transform.prefix_keys_with_source,
+                config.transform.prefix_tables_with_source,
+            );
+            runtime.set_enforce_schemas(config.transform.enforce_schemas);
+            runtime.set_view_timeout(config.transform.view_timeout.map(std::time::Duration::from_millis));
+            runtime.set_view_budget(config.transform.view_budget.map(std::time::Duration::from_millis));
+            runtime.set_suppress_noop_updates(config.transform.suppress_noop_updates);
+            runtime.set_catch_panics(config.transform.catch_panics);
+            runtime.set_table_sources(config.transform.table_sources.clone());
+            
+            // Seed the transform state if configured
+            if let Some(path) = &config.initial_state_path {
+                info!("Seeding transform state from {}", path);
+                runtime.set_state(state::load_initial_state(path)?);
+            }
             
             // Create CDC sink
             info!("Creating CDC sink");
             let sink = create_sink(&config.sink)?;
+            sink.health_check().await?;
             
             // Create block synchronizer
             info!("Creating block synchronizer with cache size {}", config.cache_size);
             let mut synchronizer = BlockSynchronizer::new(client, runtime, sink, config.cache_size)?;
             
+            // Batch sink writes by block count if configured
+            synchronizer.set_blocks_per_flush(config.blocks_per_flush);
+            
+            // Bound the blocks held by the sink if configured
+            synchronizer.set_max_inflight_blocks(config.max_inflight_blocks);
+            
+            // Process a large jump in chunks if configured
+            synchronizer.set_max_blocks_per_iteration(config.max_blocks_per_iteration);
+            
+            // Send the blocks of a batch concurrently if configured
+            synchronizer.set_ordering(config.ordering);
+            
+            // Checkpoint before sending if at-most-once delivery is configured
+            synchronizer.set_delivery(config.delivery);
+            
+            // Adapt the polling interval to the chain tip if configured
+            synchronizer.set_adaptive_polling(config.adaptive_polling);
+            
+            // Bound reorg handling if configured
+            synchronizer.set_reorg_timeout(config.reorg_timeout);
+            
+            // Let a flapping chain settle before handling a reorg if configured
+            synchronizer.set_reorg_settle(config.reorg_settle_ms);
+            
+            // Give up on a block after a number of retries if configured
+            synchronizer.set_max_block_retries(config.max_block_retries);
+            
+            // Record CDC messages in a write-ahead log if configured
+            if let Some(wal_path) = &config.wal_path {
+                synchronizer.set_wal(WriteAheadLog::open(wal_path)?);
+            }
+            
+            // Check the transform is deterministic if requested
+            if validate_determinism {
+                info!("Processing each block twice to validate determinism");
+                synchronizer.set_validate_determinism(true);
+            }
+            
+            // Check the cached state snapshots if configured
+            synchronizer.set_verify_state_snapshots(config.verify_state_snapshots);
+            synchronizer.set_rollback_mode(config.transform.rollback_mode).await?;
+            synchronizer.set_reorg_markers(config.reorg_markers);
+            
+            // Persist the transform state if configured
+            synchronizer.set_state_path(config.state_path.as_ref().map(PathBuf::from));
+            
+            // Emit heartbeats on a quiet stream if configured
+            synchronizer.set_heartbeat_interval(config.heartbeat_interval);
+            if let Some(table) = &config.heartbeat_table {
+                synchronizer.set_heartbeat_table(table);
+            }
+            
             // Set starting height if provided
             if let Some(height) = config.start_height {
                 info!("Setting starting height to {}", height);
                 synchronizer.set_starting_height(height);
             }
             
+            // Catch up once and exit if requested
+            if once {
+                info!("Catching up to the chain tip");
+                synchronizer.catch_up().await?;
+                synchronizer.shutdown().await?;
+                
+                info!("Debshrew caught up to block {}", synchronizer.get_current_height());
+                return Ok(());
+            }
+            
+            // Serve the pull-based CDC API if configured
+            if let Some(address) = &config.cdc_api_address {
+                serve_cdc_api(address, synchronizer.get_cache().await)?;
+            }
+            
             // Run the synchronizer
             info!("Starting block synchronization");
             
-            // Handle Ctrl+C
-            let (shutdown_tx, mut shutdown_rx) = tokio::sync::oneshot::channel();
-            
-            tokio::spawn(async move {
-                signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
-                info!("Received Ctrl+C, shutting down...");
-                let _ = shutdown_tx.send(());
-            });
-            
-            // Run the synchronizer until shutdown signal
-            tokio::select! {
-                result = synchronizer.run() => {
-                    if let Err(e) = result {
-                        error!("Synchronizer error: {}", e);
-                        return Err(e);
-                    }
-                }
-                _ = &mut shutdown_rx => {
-                    info!("Shutting down synchronizer");
-                    synchronizer.stop();
-                }
+            // Handle Ctrl+C, and SIGTERM on unix
+            let shutdown_rx = listen_for_shutdown()?;
+            
+            // Run the synchronizer until shutdown signal, letting the block
+            // in progress finish
+            let result = synchronizer.run_until_signal(async move {
+                let signal = shutdown_rx.await;
+                info!("Received {}, shutting down synchronizer", signal.unwrap_or("shutdown signal"));
+            }).await;
+            if let Err(e) = result {
+                error!("Synchronizer error: {}", e);
+                return Err(e);
             }
             
+            // Flush, close the sink, and print the summary once run has returned
+            synchronizer.shutdown().await?;
+            
             info!("Debshrew service stopped");
         }
+        Commands::Validate { args } => {
+            // Initialize logger
+            env_logger::Builder::from_env(Env::default().default_filter_or(&args.log_level)).init();
+            
+            // Load and validate configuration
+            let config = load_config(args)?;
+            config.validate()?;
+            
+            // Inspect the transform module
+            info!("Loading transform module from {}", config.transform.location());
+            let runtime = WasmRuntime::from_source(config.transform.source().await?)?;
+            info!("Exported functions: {}", runtime.exported_functions().join(", "));
+            for (module, name) in runtime.imported_functions() {
+                info!("Imported function: {}::{}", module, name);
+            }
+            
+            // Warn on imports the host does not provide
+            let unexpected = runtime.unexpected_imports();
+            for (module, name) in &unexpected {
+                warn!("Transform imports {}::{}, which the host does not provide", module, name);
+            }
+            
+            if unexpected.is_empty() {
+                info!("Configuration and transform module are valid");
+            } else {
+                warn!("Transform was built against an incompatible host ABI and will fail to run");
+            }
+        }
+        Commands::Config { command } => match command {
+            ConfigCommands::Show { args } => {
+                let config = load_config(args)?;
+                println!("{}", config.to_redacted_json()?);
+            }
+        },
+        Commands::State { command } => match command {
+            StateCommands::Get { key, state_file } => {
+                let transform_state = state::load_state(state_file)?;
+                match state::get_entry(&transform_state, &state::parse_key(&key)?) {
+                    Some(value) => println!("{}", value),
+                    None => return Err(format!("Key not found: {}", key).into()),
+                }
+            }
+            StateCommands::List { prefix, state_file } => {
+                let transform_state = state::load_state(state_file)?;
+                let prefix = prefix.as_deref().map(state::parse_key).transpose()?.unwrap_or_default();
+                for line in state::list_entries(&transform_state, &prefix) {
+                    println!("{}", line);
+                }
+            }
+        },
     }
     
     Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Duration;
+    
+    #[tokio::test]
+    async fn test_sigterm_triggers_shutdown() {
+        let shutdown_rx = listen_for_shutdown().unwrap();
+        
+        let status = Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        
+        let signal = tokio::time::timeout(Duration::from_secs(5), shutdown_rx)
+            .await
+            .expect("shutdown was not triggered")
+            .unwrap();
+        assert_eq!(signal, "SIGTERM");
+    }
 }
\ No newline at end of file
@@ -3,19 +3,26 @@
 //! This module provides the CDC sink interfaces and implementations for
 //! outputting CDC messages to various destinations.
 
-use crate::config::SinkConfig;
+use crate::config::{Acks, ColumnType, CompressionType, FileFraming, KeyFormat, MessageFormat, RetryConfig, SerializationErrorPolicy, SinkConfig};
 use crate::error::{Error, Result};
 use async_trait::async_trait;
-use debshrew_support::CdcMessage;
+use futures::future::try_join_all;
+use debshrew_support::{serialize_to_json_with, CdcMessage, CdcOperation, JsonOptions, TimestampFormat};
+use debshrew_support::utils::datetime_to_timestamp_ms;
 use rdkafka::config::ClientConfig;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
 use tokio::sync::Mutex as TokioMutex;
 use postgres::types::ToSql;
+use rand::Rng;
+use log::warn;
 
 /// CDC sink trait
 ///
@@ -59,6 +66,120 @@ pub trait CdcSink: Send + Sync {
     ///
     /// Returns an error if the sink cannot be closed
     async fn close(&self) -> Result<()>;
+    
+    /// Check that the sink's destination is reachable and writable
+    ///
+    /// Called once before synchronization starts, so a misconfigured sink
+    /// fails at startup rather than on the first block with CDC messages.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the sink is ready to accept messages
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination cannot be reached or written to
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Get the block height the sink has durably committed through
+    ///
+    /// Sinks that buffer or acknowledge messages asynchronously report the
+    /// block height that `mark_sent` last reported once every message sent
+    /// up to it is committed, so the synchronizer only advances its
+    /// checkpoint that far. Sinks that do not track durability return None,
+    /// and the checkpoint advances once `send` (and `flush`, when called)
+    /// succeed.
+    ///
+    /// # Returns
+    ///
+    /// The committed block height, or None if the sink does not track it
+    fn committed_height(&self) -> Option<u32> {
+        None
+    }
+    
+    /// Record that the messages of every block up to a height have been sent
+    ///
+    /// The synchronizer calls this after each successful `send`, including
+    /// for blocks without messages, so a sink tracking its committed height
+    /// follows the delivered blocks rather than the heights of the messages.
+    /// A sink with nothing outstanding reports the height as committed
+    /// straight away.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height sent through
+    fn mark_sent(&self, _height: u32) {}
+    
+    /// Get when the sink's buffered messages are due to be flushed
+    ///
+    /// Sinks that hold messages back until a batch fills up can bound how
+    /// long a message waits, reporting when their oldest buffered message
+    /// reaches that age. The synchronizer flushes the sink at that time,
+    /// even while it is idle waiting for new blocks.
+    ///
+    /// # Returns
+    ///
+    /// When to flush, or None if nothing is waiting on a deadline
+    fn flush_deadline(&self) -> Option<tokio::time::Instant> {
+        None
+    }
+}
+
+/// A function building a custom sink from its `params`
+pub type SinkFactory = dyn Fn(&serde_json::Value) -> Result<Box<dyn CdcSink>> + Send + Sync;
+
+/// Custom sink factories, by type name
+static SINK_FACTORIES: OnceLock<Mutex<HashMap<String, Arc<SinkFactory>>>> = OnceLock::new();
+
+/// Register a factory for a custom sink type
+///
+/// A `SinkConfig::Custom` whose `type_name` matches is built by calling the
+/// factory with the configured `params`. Registering a name again replaces
+/// the previous factory.
+///
+/// # Arguments
+///
+/// * `type_name` - The name used as `type_name` in the sink configuration
+/// * `factory` - Builds the sink from its parameters
+pub fn register_sink_factory<F>(type_name: &str, factory: F)
+where
+    F: Fn(&serde_json::Value) -> Result<Box<dyn CdcSink>> + Send + Sync + 'static,
+{
+    SINK_FACTORIES.get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(type_name.to_string(), Arc::new(factory));
+}
+
+/// Build a custom sink with its registered factory
+///
+/// # Arguments
+///
+/// * `type_name` - The custom sink type
+/// * `params` - The sink parameters
+///
+/// # Returns
+///
+/// The custom sink
+///
+/// # Errors
+///
+/// Returns an error if no factory is registered for the type, or the
+/// factory fails
+fn create_custom_sink(type_name: &str, params: &serde_json::Value) -> Result<Box<dyn CdcSink>> {
+    // Clone the factory out so it runs without holding the registry lock
+    let factory = SINK_FACTORIES.get()
+        .and_then(|factories| {
+            factories.lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get(type_name)
+                .cloned()
+        })
+        .ok_or_else(|| Error::Configuration(format!("No sink factory registered for type {}", type_name)))?;
+    
+    factory(params)
 }
 
 /// Create a CDC sink from a configuration
@@ -75,385 +196,2140 @@ pub trait CdcSink: Send + Sync {
 ///
 /// Returns an error if the sink cannot be created
 pub fn create_sink(config: &SinkConfig) -> Result<Box<dyn CdcSink>> {
-    match config {
-        SinkConfig::Kafka { bootstrap_servers, topic, client_id, batch_size, flush_interval } => {
-            let sink = KafkaSink::new(
+    let sink: Box<dyn CdcSink> = match config {
+        SinkConfig::Kafka {
+            bootstrap_servers,
+            topic,
+            client_id,
+            batch_size,
+            flush_interval,
+            on_serialization_error,
+            table_keys,
+            compression_type,
+            acks,
+            retries,
+            enable_idempotence,
+            concurrent_sends,
+            key_format,
+            message_format,
+            emit_tombstones,
+            route_by_operation,
+            sort_keys,
+            timestamp_format,
+            ..
+        } => {
+            let options = KafkaProducerOptions {
+                compression_type: *compression_type,
+                acks: *acks,
+                retries: *retries,
+                enable_idempotence: *enable_idempotence,
+            };
+            let mut sink = KafkaSink::new(
                 bootstrap_servers,
                 topic,
                 client_id.as_deref(),
                 *batch_size,
                 *flush_interval,
+                options,
             )?;
-            Ok(Box::new(sink))
+            sink.set_serialization_error_policy(*on_serialization_error);
+            sink.set_table_keys(TableKeys::new(table_keys.clone()));
+            sink.set_key_format(*key_format);
+            sink.set_message_format(*message_format);
+            sink.set_emit_tombstones(*emit_tombstones);
+            sink.set_route_by_operation(*route_by_operation);
+            sink.set_json_options(JsonOptions { pretty: false, sort_keys: *sort_keys });
+            sink.set_timestamp_format(*timestamp_format);
+            sink.set_concurrent_sends(*concurrent_sends);
+            Box::new(sink)
         }
-        SinkConfig::Postgres { connection_string, schema, batch_size, flush_interval } => {
-            let sink = PostgresSink::new(
+        SinkConfig::Postgres { connection_string, schema, batch_size, flush_interval, table_keys, column_types, route_by_operation, max_batch_age_ms, .. } => {
+            let mut sink = PostgresSink::new(
                 connection_string,
                 schema,
                 *batch_size,
                 *flush_interval,
             )?;
-            Ok(Box::new(sink))
+            sink.set_table_keys(TableKeys::new(table_keys.clone()));
+            sink.set_column_types(column_types.clone());
+            sink.set_route_by_operation(*route_by_operation);
+            sink.set_max_batch_age(max_batch_age_ms.map(Duration::from_millis));
+            Box::new(sink)
+        }
+        SinkConfig::Elasticsearch { url, index, batch_size, .. } => {
+            Box::new(ElasticsearchSink::new(url, index, *batch_size)?)
         }
-        SinkConfig::File { path, append, flush_interval } => {
-            let sink = FileSink::new(path, *append, *flush_interval)?;
-            Ok(Box::new(sink))
+        SinkConfig::File {
+            path,
+            append,
+            flush_interval,
+            on_serialization_error,
+            route_by_operation,
+            sort_keys,
+            timestamp_format,
+            framing,
+            ..
+        } => {
+            let mut sink = FileSink::new(path, *append, *flush_interval)?;
+            sink.set_serialization_error_policy(*on_serialization_error);
+            sink.set_route_by_operation(*route_by_operation);
+            sink.set_json_options(JsonOptions { pretty: false, sort_keys: *sort_keys });
+            sink.set_timestamp_format(*timestamp_format);
+            sink.set_framing(*framing);
+            Box::new(sink)
         }
-        SinkConfig::Console { pretty_print } => {
-            let sink = ConsoleSink::new(*pretty_print);
-            Ok(Box::new(sink))
+        SinkConfig::Console { pretty_print, sort_keys, timestamp_format } => {
+            let mut sink = ConsoleSink::with_json_options(JsonOptions { pretty: *pretty_print, sort_keys: *sort_keys });
+            sink.set_timestamp_format(*timestamp_format);
+            Box::new(sink)
         }
+        SinkConfig::Custom { type_name, params, .. } => create_custom_sink(type_name, params)?,
+    };
+    
+    // Wrap the sink so transient send failures are retried
+    match config.retry() {
+        Some(retry) => Ok(Box::new(RetryingSink::new(sink, retry))),
+        None => Ok(sink),
     }
 }
 
-/// Kafka CDC sink
+/// Get the destination a message is routed to
 ///
-/// This sink sends CDC messages to a Kafka topic.
-pub struct KafkaSink {
-    /// The Kafka producer
-    producer: FutureProducer,
-    
-    /// The Kafka topic
-    topic: String,
-    
-    /// The batch size
-    batch_size: usize,
+/// When routing by operation, row changes go to the destination suffixed
+/// with their operation (`balances` becomes `balances_delete`). Control
+/// messages always stay on the base destination.
+///
+/// # Arguments
+///
+/// * `destination` - The base destination name (topic or table)
+/// * `message` - The CDC message
+/// * `route_by_operation` - Whether to route by operation
+///
+/// # Returns
+///
+/// The destination name for the message
+fn routed_destination(destination: &str, message: &CdcMessage, route_by_operation: bool) -> String {
+    let operation = message.payload.operation;
+    if route_by_operation && !operation.is_control() {
+        format!("{}_{}", destination, operation.as_str())
+    } else {
+        destination.to_string()
+    }
+}
+
+/// Open/closed state of a sink
+///
+/// Lets `close` run its final flush until it succeeds, and makes `send`
+/// after `close` fail instead of writing to a sink that has been shut down.
+#[derive(Debug, Default)]
+struct CloseState {
+    /// Whether a close has started, after which sends are refused
+    closing: AtomicBool,
     
-    /// The flush interval in milliseconds
-    flush_interval: u64,
+    /// Whether a close has completed its final flush
+    closed: AtomicBool,
 }
 
-impl KafkaSink {
-    /// Create a new Kafka sink
-    ///
-    /// # Arguments
-    ///
-    /// * `bootstrap_servers` - The Kafka bootstrap servers
-    /// * `topic` - The Kafka topic
-    /// * `client_id` - The Kafka client ID (optional)
-    /// * `batch_size` - The batch size
-    /// * `flush_interval` - The flush interval in milliseconds
+impl CloseState {
+    /// Check that the sink is still open
     ///
     /// # Returns
     ///
-    /// A new Kafka sink
+    /// Ok(()) if the sink has not been closed
     ///
     /// # Errors
     ///
-    /// Returns an error if the Kafka producer cannot be created
-    pub fn new(
-        bootstrap_servers: &str,
-        topic: &str,
-        client_id: Option<&str>,
-        batch_size: usize,
-        flush_interval: u64,
-    ) -> Result<Self> {
-        let mut client_config = ClientConfig::new();
-        client_config.set("bootstrap.servers", bootstrap_servers);
-        
-        if let Some(id) = client_id {
-            client_config.set("client.id", id);
+    /// Returns `Error::Sink` if the sink has been closed
+    fn ensure_open(&self) -> Result<()> {
+        if self.closing.load(Ordering::SeqCst) {
+            return Err(Error::Sink("sink closed".to_string()));
         }
         
-        // Set additional Kafka configuration
-        client_config.set("message.timeout.ms", "5000");
-        client_config.set("socket.keepalive.enable", "true");
-        
-        let producer: FutureProducer = client_config.create()
-            .map_err(|e| Error::Kafka(format!("Failed to create Kafka producer: {}", e)))?;
-        
-        Ok(Self {
-            producer,
-            topic: topic.to_string(),
-            batch_size,
-            flush_interval,
-        })
+        Ok(())
+    }
+    
+    /// Start closing the sink, refusing sends from here on
+    ///
+    /// # Returns
+    ///
+    /// true if the final flush still has to run, false if an earlier close
+    /// completed it
+    fn begin_close(&self) -> bool {
+        self.closing.store(true, Ordering::SeqCst);
+        !self.closed.load(Ordering::SeqCst)
+    }
+    
+    /// Record that the final flush succeeded, so later closes do nothing
+    fn finish_close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
     }
 }
 
-#[async_trait]
-impl CdcSink for KafkaSink {
-    async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
-        // Process messages in batches
-        for chunk in messages.chunks(self.batch_size) {
-            // Process each message in the chunk
-            for message in chunk {
-                // Use the message key as the Kafka key
-                let key = message.payload.key.clone();
-                
-                // Serialize the message to JSON
-                let value = serde_json::to_string(message)
-                    .map_err(|e| Error::Kafka(format!("Failed to serialize message: {}", e)))?;
-                
-                // Send the message to Kafka and wait for the result
-                self.producer.send(
-                    FutureRecord::to(&self.topic)
-                        .key(&key)
-                        .payload(&value),
-                    Duration::from_millis(5000),
-                )
-                .await
-                .map_err(|(e, _)| Error::Kafka(format!("Failed to send message: {}", e)))?;
-            }
+/// Split items into consecutive chunks whose total size stays under a limit
+///
+/// Items keep their order. An item larger than the limit gets a chunk of
+/// its own.
+///
+/// # Arguments
+///
+/// * `items` - The items to split
+/// * `max_bytes` - The most bytes in a chunk
+/// * `size` - Gets the size of an item in bytes
+///
+/// # Returns
+///
+/// The chunks of items
+fn split_by_size<T, F>(items: Vec<T>, max_bytes: usize, size: F) -> Vec<Vec<T>>
+where
+    F: Fn(&T) -> usize,
+{
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+    
+    for item in items {
+        let item_bytes = size(&item);
+        if !chunk.is_empty() && chunk_bytes + item_bytes > max_bytes {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_bytes = 0;
         }
         
-        Ok(())
+        chunk_bytes += item_bytes;
+        chunk.push(item);
     }
     
-    async fn flush(&self) -> Result<()> {
-        self.producer.flush(Duration::from_millis(self.flush_interval))
-            .map_err(|e| Error::Kafka(format!("Failed to flush Kafka producer: {}", e)))?;
-        
-        Ok(())
+    if !chunk.is_empty() {
+        chunks.push(chunk);
     }
     
-    async fn close(&self) -> Result<()> {
-        // Flush the producer before closing
-        self.flush().await?;
-        
-        Ok(())
-    }
+    chunks
 }
 
-/// PostgreSQL CDC sink
+/// Split items into groups that share a key
 ///
-/// This sink sends CDC messages to a PostgreSQL database.
-pub struct PostgresSink {
-    /// The PostgreSQL connection string
-    connection_string: String,
+/// Groups are ordered by the first appearance of their key, and items keep
+/// their relative order within a group, so sending each group serially
+/// preserves the order of items with the same key.
+///
+/// # Arguments
+///
+/// * `items` - The items to group
+/// * `key` - Extracts the key of an item
+///
+/// # Returns
+///
+/// The groups of items
+fn group_by_key<T, K, F>(items: Vec<T>, key: F) -> Vec<Vec<T>>
+where
+    K: Eq + std::hash::Hash,
+    F: Fn(&T) -> K,
+{
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    let mut group_indexes: HashMap<K, usize> = HashMap::new();
     
-    /// The PostgreSQL schema
-    schema: String,
+    for item in items {
+        let index = *group_indexes.entry(key(&item)).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(item);
+    }
     
-    /// The batch size
-    batch_size: usize,
+    groups
+}
+
+/// Serialize CDC messages to JSON according to a serialization error policy
+///
+/// # Arguments
+///
+/// * `messages` - The CDC messages to serialize
+/// * `policy` - What to do with a message that fails to serialize
+/// * `serialize` - The serialization function
+///
+/// # Returns
+///
+/// Each successfully serialized message paired with its serialized form
+///
+/// # Errors
+///
+/// Returns the first serialization error if the policy is `Fail`
+fn serialize_messages<T, F, E>(
+    messages: &[CdcMessage],
+    policy: SerializationErrorPolicy,
+    serialize: F,
+) -> std::result::Result<Vec<(&CdcMessage, T)>, E>
+where
+    F: Fn(&CdcMessage) -> std::result::Result<T, E>,
+    E: std::fmt::Display,
+{
+    let mut serialized = Vec::with_capacity(messages.len());
     
-    /// The flush interval in milliseconds
-    flush_interval: u64,
+    for message in messages {
+        match serialize(message) {
+            Ok(json) => serialized.push((message, json)),
+            Err(e) => match policy {
+                SerializationErrorPolicy::Fail => return Err(e),
+                SerializationErrorPolicy::Skip => warn!(
+                    "Skipping CDC message for {}/{} at height {} that failed to serialize: {}",
+                    message.payload.table,
+                    message.payload.key,
+                    message.header.block_height,
+                    e
+                ),
+            },
+        }
+    }
     
-    /// The message buffer
-    buffer: Arc<TokioMutex<Vec<CdcMessage>>>,
+    Ok(serialized)
 }
 
-impl PostgresSink {
-    /// Create a new PostgreSQL sink
+/// Key column used for tables without a key definition
+const DEFAULT_KEY_COLUMN: &str = "id";
+
+/// Separator between the segments of a composite CDC key
+const KEY_SEPARATOR: char = ':';
+
+/// Per-table primary-key definitions
+///
+/// Maps each table to its key columns. The CDC `key` of a message is split on
+/// `:` into one segment per key column, with the last segment taking the rest
+/// of the key. Tables without a definition use a single `id` column.
+#[derive(Debug, Clone)]
+pub struct TableKeys {
+    /// The key columns per table
+    tables: HashMap<String, Vec<String>>,
+    
+    /// The key columns for tables without a definition
+    default_columns: Vec<String>,
+}
+
+impl TableKeys {
+    /// Create table key definitions
     ///
     /// # Arguments
     ///
-    /// * `connection_string` - The PostgreSQL connection string
-    /// * `schema` - The PostgreSQL schema
-    /// * `batch_size` - The batch size
-    /// * `flush_interval` - The flush interval in milliseconds
+    /// * `tables` - The key columns per table
     ///
     /// # Returns
     ///
-    /// A new PostgreSQL sink
+    /// The table key definitions
+    pub fn new(tables: HashMap<String, Vec<String>>) -> Self {
+        Self {
+            tables,
+            default_columns: vec![DEFAULT_KEY_COLUMN.to_string()],
+        }
+    }
+    
+    /// Get the key columns for a table
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The table name
+    ///
+    /// # Returns
+    ///
+    /// The table's key columns, or the default key column if it has no definition
+    pub fn columns(&self, table: &str) -> &[String] {
+        self.tables.get(table).unwrap_or(&self.default_columns)
+    }
+    
+    /// Split a CDC key into one segment per key column of a table
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The table name
+    /// * `key` - The CDC key
+    ///
+    /// # Returns
+    ///
+    /// The key segments, in key column order
     ///
     /// # Errors
     ///
-    /// Returns an error if the PostgreSQL connection cannot be created
-    pub fn new(
-        connection_string: &str,
-        schema: &str,
-        batch_size: usize,
-        flush_interval: u64,
-    ) -> Result<Self> {
-        // Validate the connection string by attempting to connect
-        let _client = postgres::Client::connect(connection_string, postgres::NoTls)
-            .map_err(|e| Error::Postgres(format!("Failed to connect to PostgreSQL: {}", e)))?;
+    /// Returns an error if the key has fewer segments than the table has key columns
+    pub fn split_key<'a>(&self, table: &str, key: &'a str) -> Result<Vec<&'a str>> {
+        let columns = self.columns(table);
+        let segments: Vec<&str> = key.splitn(columns.len(), KEY_SEPARATOR).collect();
         
-        Ok(Self {
-            connection_string: connection_string.to_string(),
-            schema: schema.to_string(),
-            batch_size,
-            flush_interval,
-            buffer: Arc::new(TokioMutex::new(Vec::new())),
-        })
+        if segments.len() != columns.len() {
+            return Err(Error::Sink(format!(
+                "Key {} for table {} does not have a segment for each key column ({})",
+                key,
+                table,
+                columns.join(", ")
+            )));
+        }
+        
+        Ok(segments)
     }
     
-    /// Apply CDC messages to a PostgreSQL database
+    /// Build a JSON object for a CDC key with one field per key column
     ///
     /// # Arguments
     ///
-    /// * `messages` - The CDC messages to apply
+    /// * `table` - The table name
+    /// * `key` - The CDC key
     ///
     /// # Returns
     ///
-    /// Ok(()) if the messages were applied successfully
+    /// The key object, e.g. `{"address": "bc1q...", "token": "ordi"}`
     ///
     /// # Errors
     ///
-    /// Returns an error if the messages cannot be applied
-    async fn apply_messages(&self, messages: &[CdcMessage]) -> Result<()> {
-        // Connect to PostgreSQL
-        let mut client = postgres::Client::connect(&self.connection_string, postgres::NoTls)
-            .map_err(|e| Error::Postgres(format!("Failed to connect to PostgreSQL: {}", e)))?;
-        
-        // Start a transaction
-        client.batch_execute("BEGIN")
-            .map_err(|e| Error::Postgres(format!("Failed to start transaction: {}", e)))?;
+    /// Returns an error if the key has fewer segments than the table has key columns
+    pub fn key_object(&self, table: &str, key: &str) -> Result<serde_json::Value> {
+        let segments = self.split_key(table, key)?;
         
-        // Process each message
-        for message in messages {
-            let table = format!("{}.{}", self.schema, message.payload.table);
-            
-            match message.payload.operation {
-                debshrew_support::CdcOperation::Create => {
-                    // Extract fields from the after state
-                    if let Some(after) = &message.payload.after {
-                        let fields: Vec<String> = after.as_object()
-                            .ok_or_else(|| Error::Postgres("Invalid after state".to_string()))?
-                            .keys()
-                            .map(|k| k.to_string())
-                            .collect();
-                        
-                        // Convert JSON values to strings for PostgreSQL
-                        let values: Vec<String> = fields.iter()
-                            .map(|f| after[f].to_string())
-                            .collect();
-                        
-                        // Build the INSERT statement
-                        let placeholders: Vec<String> = (1..=fields.len())
-                            .map(|i| format!("${}", i))
-                            .collect();
-                        
-                        let query = format!(
-                            "INSERT INTO {} ({}) VALUES ({})",
-                            table,
-                            fields.join(", "),
-                            placeholders.join(", ")
-                        );
-                        
-                        // Execute the INSERT statement
-                        let params: Vec<&(dyn ToSql + Sync)> = values.iter()
-                            .map(|v| v as &(dyn ToSql + Sync))
+        Ok(serde_json::Value::Object(
+            self.columns(table).iter()
+                .zip(segments)
+                .map(|(column, segment)| (column.clone(), serde_json::Value::from(segment)))
+                .collect()
+        ))
+    }
+}
+
+/// Serialize a CDC message with its key in the given format
+///
+/// # Arguments
+///
+/// * `message` - The CDC message
+/// * `key_format` - How to serialize the key
+/// * `table_keys` - The key columns per table, used for JSON keys
+/// * `json_options` - How the JSON is written
+/// * `timestamp_format` - How the header timestamp is written
+///
+/// # Returns
+///
+/// The serialized message
+///
+/// # Errors
+///
+/// Returns an error if the message cannot be serialized or its key does not
+/// match the table's key columns
+fn serialize_with_key_format(
+    message: &CdcMessage,
+    key_format: KeyFormat,
+    table_keys: &TableKeys,
+    json_options: JsonOptions,
+    timestamp_format: TimestampFormat,
+) -> debshrew_support::Result<String> {
+    let formatted = message.with_timestamp_format(timestamp_format);
+    match key_format {
+        KeyFormat::Json if !message.payload.operation.is_control() => {
+            let key = table_keys.key_object(&message.payload.table, &message.payload.key)
+                .map_err(|e| debshrew_support::Error::CdcMessage(e.to_string()))?;
+            let mut value = serde_json::to_value(formatted)?;
+            value["payload"]["key"] = key;
+            serialize_to_json_with(&value, json_options)
+        }
+        _ => serialize_to_json_with(&formatted, json_options),
+    }
+}
+
+/// Kafka record headers, as name and value pairs
+type KafkaHeaders = Vec<(&'static str, String)>;
+
+/// Serialize a CDC message as a flattened Kafka record
+///
+/// Row changes become only their `after` row, or no value for a delete, with
+/// the operation metadata in record headers named after those Debezium's
+/// `ExtractNewRecordState` transform adds. Control messages have no row, so
+/// they keep the envelope.
+///
+/// # Arguments
+///
+/// * `message` - The CDC message
+/// * `key_format` - How to serialize the key
+/// * `table_keys` - The key columns per table, used for JSON keys
+/// * `json_options` - How the JSON is written
+/// * `timestamp_format` - How the header timestamp of an envelope is written
+///
+/// # Returns
+///
+/// The record value, `None` for a tombstone, and the record headers
+///
+/// # Errors
+///
+/// Returns an error if the message cannot be serialized
+fn serialize_flattened(
+    message: &CdcMessage,
+    key_format: KeyFormat,
+    table_keys: &TableKeys,
+    json_options: JsonOptions,
+    timestamp_format: TimestampFormat,
+) -> debshrew_support::Result<(Option<String>, KafkaHeaders)> {
+    let operation = match message.payload.operation {
+        CdcOperation::Create => "c",
+        CdcOperation::Update => "u",
+        CdcOperation::Delete => "d",
+        CdcOperation::SnapshotComplete
+        | CdcOperation::Heartbeat
+        | CdcOperation::ReorgBegin
+        | CdcOperation::ReorgEnd => {
+            let value = serialize_with_key_format(message, key_format, table_keys, json_options, timestamp_format)?;
+            return Ok((Some(value), Vec::new()));
+        }
+    };
+    
+    let value = match message.payload.operation {
+        CdcOperation::Delete => None,
+        _ => Some(serialize_to_json_with(&message.payload.after, json_options)?),
+    };
+    let headers = vec![
+        ("__op", operation.to_string()),
+        ("__table", message.payload.table.clone()),
+        ("__block_height", message.header.block_height.to_string()),
+        ("__block_hash", message.header.block_hash.clone()),
+        ("__ts_ms", datetime_to_timestamp_ms(&message.header.timestamp).to_string()),
+    ];
+    
+    Ok((value, headers))
+}
+
+impl Default for TableKeys {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+/// Kafka producer tuning options
+///
+/// Compression and delivery guarantees passed through to the producer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KafkaProducerOptions {
+    /// The compression codec
+    pub compression_type: CompressionType,
+    
+    /// The acknowledgement level
+    pub acks: Acks,
+    
+    /// The number of times to retry a failed produce request (librdkafka default if None)
+    pub retries: Option<u32>,
+    
+    /// Whether the idempotent producer is enabled
+    pub enable_idempotence: bool,
+}
+
+/// A Kafka record built from a CDC message
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KafkaRecord {
+    /// The destination topic
+    topic: String,
+    
+    /// The record key
+    key: String,
+    
+    /// The record value, `None` for a tombstone
+    value: Option<String>,
+    
+    /// The record headers
+    headers: KafkaHeaders,
+}
+
+/// Kafka CDC sink
+///
+/// This sink sends CDC messages to a Kafka topic.
+pub struct KafkaSink {
+    /// The Kafka producer
+    producer: FutureProducer,
+    
+    /// The Kafka topic
+    topic: String,
+    
+    /// The batch size
+    batch_size: usize,
+    
+    /// The flush interval in milliseconds
+    flush_interval: u64,
+    
+    /// What to do with a message that fails to serialize
+    on_serialization_error: SerializationErrorPolicy,
+    
+    /// The key columns per table, used to build record keys
+    table_keys: TableKeys,
+    
+    /// How the CDC key is serialized
+    key_format: KeyFormat,
+    
+    /// The shape of record values
+    message_format: MessageFormat,
+    
+    /// Whether each delete is followed by a tombstone for its key
+    emit_tombstones: bool,
+    
+    /// Whether messages go to a topic suffixed with their operation
+    route_by_operation: bool,
+    
+    /// How message values are written as JSON
+    json_options: JsonOptions,
+    
+    /// How the header timestamp is written
+    timestamp_format: TimestampFormat,
+    
+    /// Whether messages with different keys are sent concurrently
+    concurrent_sends: bool,
+    
+    /// Whether a flush means every broker replica has the messages (acks=all)
+    durable_flush: bool,
+    
+    /// The block height sent through, as reported by `mark_sent`
+    sent_height: AtomicU32,
+    
+    /// Whether messages have been sent since the last successful flush
+    unflushed: AtomicBool,
+    
+    /// The block height sent through before a successful flush
+    committed_height: AtomicU32,
+    
+    /// Whether the sink has been closed
+    close_state: CloseState,
+}
+
+impl KafkaSink {
+    /// Create a new Kafka sink
+    ///
+    /// # Arguments
+    ///
+    /// * `bootstrap_servers` - The Kafka bootstrap servers
+    /// * `topic` - The Kafka topic
+    /// * `client_id` - The Kafka client ID (optional)
+    /// * `batch_size` - The batch size
+    /// * `flush_interval` - The flush interval in milliseconds
+    /// * `options` - The producer tuning options
+    ///
+    /// # Returns
+    ///
+    /// A new Kafka sink
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Kafka producer cannot be created
+    pub fn new(
+        bootstrap_servers: &str,
+        topic: &str,
+        client_id: Option<&str>,
+        batch_size: usize,
+        flush_interval: u64,
+        options: KafkaProducerOptions,
+    ) -> Result<Self> {
+        let client_config = Self::producer_config(bootstrap_servers, client_id, &options);
+        
+        let producer: FutureProducer = client_config.create()
+            .map_err(|e| Error::kafka("Failed to create Kafka producer", &e))?;
+        
+        Ok(Self {
+            producer,
+            topic: topic.to_string(),
+            batch_size,
+            flush_interval,
+            on_serialization_error: SerializationErrorPolicy::default(),
+            table_keys: TableKeys::default(),
+            key_format: KeyFormat::default(),
+            message_format: MessageFormat::default(),
+            emit_tombstones: false,
+            route_by_operation: false,
+            json_options: JsonOptions::default(),
+            timestamp_format: TimestampFormat::default(),
+            concurrent_sends: false,
+            durable_flush: options.acks == Acks::All,
+            sent_height: AtomicU32::new(0),
+            unflushed: AtomicBool::new(false),
+            committed_height: AtomicU32::new(0),
+            close_state: CloseState::default(),
+        })
+    }
+    
+    /// Build the Kafka producer configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `bootstrap_servers` - The Kafka bootstrap servers
+    /// * `client_id` - The Kafka client ID (optional)
+    /// * `options` - The producer tuning options
+    ///
+    /// # Returns
+    ///
+    /// The producer configuration
+    fn producer_config(
+        bootstrap_servers: &str,
+        client_id: Option<&str>,
+        options: &KafkaProducerOptions,
+    ) -> ClientConfig {
+        let mut client_config = ClientConfig::new();
+        client_config.set("bootstrap.servers", bootstrap_servers);
+        
+        if let Some(id) = client_id {
+            client_config.set("client.id", id);
+        }
+        
+        // Set additional Kafka configuration
+        client_config.set("message.timeout.ms", "5000");
+        client_config.set("socket.keepalive.enable", "true");
+        client_config.set("compression.type", options.compression_type.as_str());
+        client_config.set("acks", options.acks.as_str());
+        client_config.set("enable.idempotence", options.enable_idempotence.to_string());
+        
+        if let Some(retries) = options.retries {
+            client_config.set("retries", retries.to_string());
+        }
+        
+        client_config
+    }
+    
+    /// Set the policy for messages that fail to serialize
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The serialization error policy
+    pub fn set_serialization_error_policy(&mut self, policy: SerializationErrorPolicy) {
+        self.on_serialization_error = policy;
+    }
+    
+    /// Set the key columns per table
+    ///
+    /// # Arguments
+    ///
+    /// * `table_keys` - The table key definitions
+    pub fn set_table_keys(&mut self, table_keys: TableKeys) {
+        self.table_keys = table_keys;
+    }
+    
+    /// Set how the CDC key is serialized
+    ///
+    /// With `KeyFormat::Json`, both the record key and the `key` field of the
+    /// message are JSON objects built from the table's key columns, so Kafka
+    /// Connect can map them to a key schema.
+    ///
+    /// # Arguments
+    ///
+    /// * `key_format` - The key format
+    pub fn set_key_format(&mut self, key_format: KeyFormat) {
+        self.key_format = key_format;
+    }
+    
+    /// Set the shape of record values
+    ///
+    /// With `MessageFormat::Flattened`, records carry only the `after` row
+    /// and deletes are tombstones, so compacted topics drop deleted keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `message_format` - The message format
+    pub fn set_message_format(&mut self, message_format: MessageFormat) {
+        self.message_format = message_format;
+    }
+    
+    /// Set whether each delete is followed by a tombstone
+    ///
+    /// A tombstone is a record with the key of the deleted row and a null
+    /// value, which log compaction uses to drop the key. Deletes in the
+    /// flattened format are already tombstones, so they are not followed by
+    /// another.
+    ///
+    /// # Arguments
+    ///
+    /// * `emit_tombstones` - Whether to emit tombstones
+    pub fn set_emit_tombstones(&mut self, emit_tombstones: bool) {
+        self.emit_tombstones = emit_tombstones;
+    }
+    
+    /// Set whether messages go to a topic per operation
+    ///
+    /// When enabled, each message is produced to the topic suffixed with its
+    /// operation, e.g. deletes go to `cdc-events_delete`. Control messages
+    /// stay on the base topic.
+    ///
+    /// # Arguments
+    ///
+    /// * `route_by_operation` - Whether to route by operation
+    pub fn set_route_by_operation(&mut self, route_by_operation: bool) {
+        self.route_by_operation = route_by_operation;
+    }
+    
+    /// Set how message values are written as JSON
+    ///
+    /// # Arguments
+    ///
+    /// * `json_options` - The JSON options
+    pub fn set_json_options(&mut self, json_options: JsonOptions) {
+        self.json_options = json_options;
+    }
+    
+    /// Set how the header timestamp is written
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp_format` - The timestamp format
+    pub fn set_timestamp_format(&mut self, timestamp_format: TimestampFormat) {
+        self.timestamp_format = timestamp_format;
+    }
+    
+    /// Set whether messages with different keys are sent concurrently
+    ///
+    /// When enabled, each batch is split into one group per record key. The
+    /// groups are sent concurrently, but the messages within a group are sent
+    /// one after another, so messages for the same key are never reordered.
+    ///
+    /// # Arguments
+    ///
+    /// * `concurrent_sends` - Whether to send concurrently
+    pub fn set_concurrent_sends(&mut self, concurrent_sends: bool) {
+        self.concurrent_sends = concurrent_sends;
+    }
+    
+    /// Send records to Kafka one after another
+    ///
+    /// # Arguments
+    ///
+    /// * `records` - The records to send
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if every record was delivered
+    ///
+    /// # Errors
+    ///
+    /// Returns an error at the first record that cannot be delivered
+    async fn send_records(&self, records: Vec<KafkaRecord>) -> Result<()> {
+        for record in records {
+            let headers = record.headers.iter().fold(OwnedHeaders::new(), |headers, (key, value)| {
+                headers.insert(Header { key, value: Some(value) })
+            });
+            let mut future_record = FutureRecord::to(&record.topic)
+                .key(&record.key)
+                .headers(headers);
+            if let Some(value) = &record.value {
+                future_record = future_record.payload(value);
+            }
+            
+            // Send the message to Kafka and wait for the result
+            self.producer.send(future_record, Duration::from_millis(5000))
+                .await
+                .map_err(|(e, _)| Error::kafka("Failed to send message", &e))?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Build the Kafka records for a batch of CDC messages
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages
+    ///
+    /// # Returns
+    ///
+    /// One record per message, without the messages skipped by the
+    /// serialization error policy
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a message cannot be serialized under the `Fail`
+    /// policy or its key does not match the table's key columns
+    fn records(&self, messages: &[CdcMessage]) -> Result<Vec<KafkaRecord>> {
+        let serialized = serialize_messages(messages, self.on_serialization_error, |message| {
+            match self.message_format {
+                MessageFormat::Envelope => serialize_with_key_format(
+                    message,
+                    self.key_format,
+                    &self.table_keys,
+                    self.json_options,
+                    self.timestamp_format,
+                )
+                .map(|value| (Some(value), Vec::new())),
+                MessageFormat::Flattened => serialize_flattened(
+                    message,
+                    self.key_format,
+                    &self.table_keys,
+                    self.json_options,
+                    self.timestamp_format,
+                ),
+            }
+        })
+        .map_err(|e| Error::Sink(format!("Failed to serialize message: {}", e)))?;
+        
+        let mut records = Vec::with_capacity(serialized.len());
+        for (message, (value, headers)) in serialized {
+            // Use the table's primary key as the Kafka key
+            let record = KafkaRecord {
+                topic: routed_destination(&self.topic, message, self.route_by_operation),
+                key: self.record_key(message)?,
+                value,
+                headers,
+            };
+            
+            // Follow a delete with a tombstone, unless it is one already
+            let tombstone = (self.emit_tombstones
+                && message.payload.operation == CdcOperation::Delete
+                && record.value.is_some())
+                .then(|| KafkaRecord { value: None, headers: Vec::new(), ..record.clone() });
+            
+            records.push(record);
+            records.extend(tombstone);
+        }
+        
+        Ok(records)
+    }
+    
+    /// Build the Kafka record key for a CDC message
+    ///
+    /// The key is made of the segments for the table's key columns, so
+    /// records with the same primary key land on the same partition.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The CDC message
+    ///
+    /// # Returns
+    ///
+    /// The record key
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CDC key does not match the table's key columns
+    fn record_key(&self, message: &CdcMessage) -> Result<String> {
+        let (table, key) = (&message.payload.table, &message.payload.key);
+        
+        // Control markers have no row key; key them by table so they land
+        // on the same partition as the table's rows
+        if message.payload.operation.is_control() {
+            return Ok(table.clone());
+        }
+        
+        let record_key = match self.key_format {
+            KeyFormat::String => self.table_keys.split_key(table, key)
+                .map(|segments| segments.join(&KEY_SEPARATOR.to_string())),
+            KeyFormat::Json => self.table_keys.key_object(table, key)
+                .map(|object| object.to_string()),
+        };
+        
+        record_key
+    }
+}
+
+/// The most bytes of Kafka records sent together, librdkafka's default
+/// `message.max.bytes`
+const MAX_KAFKA_CHUNK_BYTES: usize = 1_000_000;
+
+/// How long a health check waits for a destination to respond
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_millis(5000);
+
+#[async_trait]
+impl CdcSink for KafkaSink {
+    async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+        self.close_state.ensure_open()?;
+        
+        if !messages.is_empty() {
+            self.unflushed.store(true, Ordering::SeqCst);
+        }
+        
+        // Process messages in batches
+        for chunk in messages.chunks(self.batch_size) {
+            // Build a record for each message in the chunk
+            let records = self.records(chunk)?;
+            
+            // Keep the records in flight under the request size, however
+            // large the messages of the batch are
+            let size = |record: &KafkaRecord| record.key.len() + record.value.as_ref().map_or(0, String::len);
+            for records in split_by_size(records, MAX_KAFKA_CHUNK_BYTES, size) {
+                if self.concurrent_sends {
+                    let groups = group_by_key(records, |record| record.key.clone());
+                    try_join_all(groups.into_iter().map(|group| self.send_records(group))).await?;
+                } else {
+                    self.send_records(records).await?;
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    async fn flush(&self) -> Result<()> {
+        // Read before flushing, so a concurrent send is not counted as committed
+        let sent_height = self.sent_height.load(Ordering::SeqCst);
+        self.unflushed.store(false, Ordering::SeqCst);
+        if let Err(e) = self.producer.flush(Duration::from_millis(self.flush_interval)) {
+            self.unflushed.store(true, Ordering::SeqCst);
+            return Err(Error::kafka("Failed to flush Kafka producer", &e));
+        }
+        self.committed_height.store(sent_height, Ordering::SeqCst);
+        
+        Ok(())
+    }
+    
+    async fn close(&self) -> Result<()> {
+        // Sends are refused from here on; a close whose flush failed flushes
+        // again when retried
+        if !self.close_state.begin_close() {
+            return Ok(());
+        }
+        
+        // Flush the producer before closing
+        self.flush().await?;
+        self.close_state.finish_close();
+        
+        Ok(())
+    }
+    
+    async fn health_check(&self) -> Result<()> {
+        // Creating the producer does not contact the brokers, so ask them
+        // for cluster metadata; the call blocks until they answer
+        let producer = self.producer.clone();
+        tokio::task::spawn_blocking(move || {
+            producer.client().fetch_metadata(None, HEALTH_CHECK_TIMEOUT)
+        })
+        .await
+        .map_err(|e| Error::Kafka(format!("Kafka health check panicked: {}", e)))?
+        .map_err(|e| Error::kafka("Failed to fetch Kafka metadata", &e))?;
+        
+        Ok(())
+    }
+    
+    fn committed_height(&self) -> Option<u32> {
+        // With fewer acknowledgements a flushed message can still be lost
+        self.durable_flush.then(|| self.committed_height.load(Ordering::SeqCst))
+    }
+    
+    fn mark_sent(&self, height: u32) {
+        self.sent_height.store(height, Ordering::SeqCst);
+        if !self.unflushed.load(Ordering::SeqCst) {
+            self.committed_height.store(height, Ordering::SeqCst);
+        }
+    }
+}
+
+/// PostgreSQL CDC sink
+///
+/// This sink sends CDC messages to a PostgreSQL database.
+pub struct PostgresSink {
+    /// The PostgreSQL connection string
+    connection_string: String,
+    
+    /// The PostgreSQL schema
+    schema: String,
+    
+    /// The batch size
+    batch_size: usize,
+    
+    /// The flush interval in milliseconds
+    flush_interval: u64,
+    
+    /// The message buffer
+    buffer: Arc<TokioMutex<Vec<CdcMessage>>>,
+    
+    /// The primary-key columns per table
+    table_keys: TableKeys,
+    
+    /// The SQL types column values are coerced to, per table
+    column_types: HashMap<String, HashMap<String, ColumnType>>,
+    
+    /// Whether messages are written to a table suffixed with their operation
+    route_by_operation: bool,
+    
+    /// The longest a buffered message waits before the buffer is flushed
+    max_batch_age: Option<Duration>,
+    
+    /// When the oldest message in the buffer was added
+    buffered_since: Mutex<Option<tokio::time::Instant>>,
+    
+    /// The block height sent through, as reported by `mark_sent`
+    sent_height: AtomicU32,
+    
+    /// The block height sent through when the buffer was last applied
+    committed_height: AtomicU32,
+    
+    /// Whether the sink has been closed
+    close_state: CloseState,
+}
+
+impl PostgresSink {
+    /// Create a new PostgreSQL sink
+    ///
+    /// # Arguments
+    ///
+    /// * `connection_string` - The PostgreSQL connection string
+    /// * `schema` - The PostgreSQL schema
+    /// * `batch_size` - The batch size
+    /// * `flush_interval` - The flush interval in milliseconds
+    ///
+    /// # Returns
+    ///
+    /// A new PostgreSQL sink
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the PostgreSQL connection cannot be created
+    pub fn new(
+        connection_string: &str,
+        schema: &str,
+        batch_size: usize,
+        flush_interval: u64,
+    ) -> Result<Self> {
+        // Validate the connection string by attempting to connect
+        let _client = postgres::Client::connect(connection_string, postgres::NoTls)
+            .map_err(|e| Error::postgres("Failed to connect to PostgreSQL", e))?;
+        
+        Ok(Self {
+            connection_string: connection_string.to_string(),
+            schema: schema.to_string(),
+            batch_size,
+            flush_interval,
+            buffer: Arc::new(TokioMutex::new(Vec::new())),
+            table_keys: TableKeys::default(),
+            column_types: HashMap::new(),
+            route_by_operation: false,
+            max_batch_age: None,
+            buffered_since: Mutex::new(None),
+            sent_height: AtomicU32::new(0),
+            committed_height: AtomicU32::new(0),
+            close_state: CloseState::default(),
+        })
+    }
+    
+    /// Set the longest a buffered message waits before the buffer is flushed
+    ///
+    /// Without it, messages are only applied once `batch_size` of them are
+    /// buffered or the sink is flushed, which can hold back a quiet stream
+    /// for a long time.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_batch_age` - The maximum age of a buffered message, or None for no limit
+    pub fn set_max_batch_age(&mut self, max_batch_age: Option<Duration>) {
+        self.max_batch_age = max_batch_age;
+    }
+    
+    /// Lock the time the oldest buffered message was added
+    fn buffered_since(&self) -> std::sync::MutexGuard<'_, Option<tokio::time::Instant>> {
+        self.buffered_since.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
+    /// Set the primary-key columns per table
+    ///
+    /// # Arguments
+    ///
+    /// * `table_keys` - The table key definitions
+    pub fn set_table_keys(&mut self, table_keys: TableKeys) {
+        self.table_keys = table_keys;
+    }
+    
+    /// Set the SQL types column values are coerced to
+    ///
+    /// # Arguments
+    ///
+    /// * `column_types` - The column types, by table and then column
+    pub fn set_column_types(&mut self, column_types: HashMap<String, HashMap<String, ColumnType>>) {
+        self.column_types = column_types;
+    }
+    
+    /// Convert a column value to a statement parameter
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The table of the CDC message
+    /// * `column` - The column name
+    /// * `value` - The JSON value of the column
+    ///
+    /// # Returns
+    ///
+    /// The value coerced to the column's declared type, or its JSON text if
+    /// the column has no declared type
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value cannot be coerced to the declared type
+    fn column_value(&self, table: &str, column: &str, value: &serde_json::Value) -> Result<SqlValue> {
+        match self.column_types.get(table).and_then(|columns| columns.get(column)) {
+            Some(column_type) => coerce_value(column, value, *column_type),
+            None => Ok(SqlValue::Text(Some(value.to_string()))),
+        }
+    }
+    
+    /// Convert a CDC key segment to a statement parameter
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The table of the CDC message
+    /// * `column` - The key column name
+    /// * `segment` - The key segment for the column
+    ///
+    /// # Returns
+    ///
+    /// The segment coerced to the column's declared type, or as text if the
+    /// column has no declared type
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the segment cannot be coerced to the declared type
+    fn key_value(&self, table: &str, column: &str, segment: &str) -> Result<SqlValue> {
+        match self.column_types.get(table).and_then(|columns| columns.get(column)) {
+            Some(column_type) => coerce_value(column, &serde_json::Value::String(segment.to_string()), *column_type),
+            None => Ok(SqlValue::Text(Some(segment.to_string()))),
+        }
+    }
+    
+    /// Set whether messages are written to a table per operation
+    ///
+    /// When enabled, each message is recorded in its table suffixed with the
+    /// operation, e.g. deletes from `balances` go to `balances_delete`. The
+    /// suffixed tables are append-only event tables: every message, whatever
+    /// its operation, inserts a row with its `after` state (or `before` state
+    /// for a delete) and key columns, since an update or delete could never
+    /// find the row created in another table.
+    ///
+    /// # Arguments
+    ///
+    /// * `route_by_operation` - Whether to route by operation
+    pub fn set_route_by_operation(&mut self, route_by_operation: bool) {
+        self.route_by_operation = route_by_operation;
+    }
+    
+    /// Apply CDC messages to a PostgreSQL database
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages to apply
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the messages were applied successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the messages cannot be applied
+    async fn apply_messages(&self, messages: &[CdcMessage]) -> Result<()> {
+        self.apply_messages_blocking(messages)
+    }
+    
+    /// Build the SQL statements that apply CDC messages
+    ///
+    /// Consecutive creates for the same table with the same columns and
+    /// distinct keys are written with multi-row INSERTs, each kept under
+    /// PostgreSQL's limit of parameters per statement, so a block of any
+    /// size can be applied regardless of the configured `batch_size`.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages to apply
+    ///
+    /// # Returns
+    ///
+    /// The statements, in the order they must be executed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a message has an invalid key or after state
+    fn statements(&self, messages: &[CdcMessage]) -> Result<Vec<Statement>> {
+        let mut statements = Vec::new();
+        let mut pending: Option<PendingInsert> = None;
+        
+        for message in messages {
+            // Control markers carry no row to write
+            if message.payload.operation.is_control() {
+                continue;
+            }
+            
+            let table = format!(
+                "{}.{}",
+                self.schema,
+                routed_destination(&message.payload.table, message, self.route_by_operation)
+            );
+            let source_table = &message.payload.table;
+            let key_columns = self.table_keys.columns(source_table);
+            let key_values = key_columns.iter()
+                .zip(self.table_keys.split_key(source_table, &message.payload.key)?)
+                .map(|(column, segment)| self.key_value(source_table, column, segment))
+                .collect::<Result<Vec<SqlValue>>>()?;
+            
+            // Only creates are batched, so anything else ends the pending INSERT
+            if message.payload.operation != debshrew_support::CdcOperation::Create || self.route_by_operation {
+                statements.extend(pending.take().map(PendingInsert::into_statements).unwrap_or_default());
+            }
+            
+            // Operation tables record every message as an event row
+            if self.route_by_operation {
+                let state = message.payload.after.as_ref().or(message.payload.before.as_ref());
+                let (mut fields, mut values) = match state {
+                    Some(state) => {
+                        let fields: Vec<String> = state.as_object()
+                            .ok_or_else(|| Error::Sink("Invalid event state".to_string()))?
+                            .keys()
+                            .map(|k| k.to_string())
                             .collect();
-                        
-                        client.execute(&query, &params)
-                            .map_err(|e| Error::Postgres(format!("Failed to execute INSERT: {}", e)))?;
+                        let values = fields.iter()
+                            .map(|f| self.column_value(source_table, f, &state[f]))
+                            .collect::<Result<Vec<SqlValue>>>()?;
+                        (fields, values)
+                    }
+                    None => (Vec::new(), Vec::new()),
+                };
+                
+                // Take key columns missing from the state from the key
+                for (column, value) in key_columns.iter().zip(key_values) {
+                    if !fields.contains(column) {
+                        fields.push(column.clone());
+                        values.push(value);
                     }
                 }
-                debshrew_support::CdcOperation::Update => {
+                
+                statements.push(Statement {
+                    query: event_insert_query(&table, &fields),
+                    values,
+                    action: "INSERT",
+                });
+                continue;
+            }
+            
+            match message.payload.operation {
+                debshrew_support::CdcOperation::Create => {
                     // Extract fields from the after state
                     if let Some(after) = &message.payload.after {
-                        let fields: Vec<String> = after.as_object()
-                            .ok_or_else(|| Error::Postgres("Invalid after state".to_string()))?
+                        let mut fields: Vec<String> = after.as_object()
+                            .ok_or_else(|| Error::Sink("Invalid after state".to_string()))?
                             .keys()
                             .map(|k| k.to_string())
                             .collect();
                         
-                        // Convert JSON values to strings for PostgreSQL
-                        let values: Vec<String> = fields.iter()
-                            .map(|f| after[f].to_string())
-                            .collect();
-                        
-                        // Build the UPDATE statement
-                        let set_clauses: Vec<String> = fields.iter()
-                            .enumerate()
-                            .map(|(i, f)| format!("{} = ${}", f, i + 1))
-                            .collect();
+                        // Convert JSON values to the column types for PostgreSQL
+                        let mut values = fields.iter()
+                            .map(|f| self.column_value(source_table, f, &after[f]))
+                            .collect::<Result<Vec<SqlValue>>>()?;
                         
-                        let query = format!(
-                            "UPDATE {} SET {} WHERE id = ${}",
-                            table,
-                            set_clauses.join(", "),
-                            fields.len() + 1
-                        );
+                        // Take key columns missing from the after state from the key
+                        for (column, value) in key_columns.iter().zip(&key_values) {
+                            if !fields.contains(column) {
+                                fields.push(column.clone());
+                                values.push(value.clone());
+                            }
+                        }
                         
-                        // Execute the UPDATE statement
-                        let mut params: Vec<&(dyn ToSql + Sync)> = values.iter()
-                            .map(|v| v as &(dyn ToSql + Sync))
+                        // Add the row to the pending INSERT, unless it needs a new one
+                        let key = &message.payload.key;
+                        if !pending.as_ref().is_some_and(|insert| insert.accepts(&table, &fields, key)) {
+                            statements.extend(pending.take().map(PendingInsert::into_statements).unwrap_or_default());
+                            pending = Some(PendingInsert::new(table, fields, key_columns.to_vec()));
+                        }
+                        if let Some(insert) = &mut pending {
+                            insert.push(key, values);
+                        }
+                    }
+                }
+                debshrew_support::CdcOperation::Update => {
+                    // Extract fields from the after state
+                    if let Some(after) = &message.payload.after {
+                        let fields: Vec<String> = after.as_object()
+                            .ok_or_else(|| Error::Sink("Invalid after state".to_string()))?
+                            .keys()
+                            .map(|k| k.to_string())
                             .collect();
                         
-                        let key = &message.payload.key;
-                        params.push(key as &(dyn ToSql + Sync));
+                        // Convert JSON values to the column types for PostgreSQL,
+                        // followed by the key values
+                        let mut values = fields.iter()
+                            .map(|f| self.column_value(source_table, f, &after[f]))
+                            .collect::<Result<Vec<SqlValue>>>()?;
+                        values.extend(key_values);
                         
-                        client.execute(&query, &params)
-                            .map_err(|e| Error::Postgres(format!("Failed to execute UPDATE: {}", e)))?;
+                        statements.push(Statement {
+                            query: update_query(&table, &fields, key_columns),
+                            values,
+                            action: "UPDATE",
+                        });
                     }
                 }
-                debshrew_support::CdcOperation::Delete => {
-                    // Build the DELETE statement
-                    let query = format!("DELETE FROM {} WHERE id = $1", table);
-                    
-                    // Execute the DELETE statement
-                    client.execute(&query, &[&message.payload.key])
-                        .map_err(|e| Error::Postgres(format!("Failed to execute DELETE: {}", e)))?;
+                debshrew_support::CdcOperation::Delete => {
+                    statements.push(Statement {
+                        query: delete_query(&table, key_columns),
+                        values: key_values,
+                        action: "DELETE",
+                    });
+                }
+                debshrew_support::CdcOperation::SnapshotComplete
+                | debshrew_support::CdcOperation::Heartbeat
+                | debshrew_support::CdcOperation::ReorgBegin
+                | debshrew_support::CdcOperation::ReorgEnd => {}
+            }
+        }
+        
+        statements.extend(pending.map(PendingInsert::into_statements).unwrap_or_default());
+        
+        Ok(statements)
+    }
+    
+    /// Apply CDC messages to a PostgreSQL database without an async context
+    ///
+    /// The synchronous `postgres` client drives its own runtime, so this
+    /// must not be called from a thread that is already inside a Tokio
+    /// runtime unless it is allowed to block.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages to apply
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the messages were applied successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the messages cannot be applied
+    fn apply_messages_blocking(&self, messages: &[CdcMessage]) -> Result<()> {
+        let statements = self.statements(messages)?;
+        
+        // Connect to PostgreSQL
+        let mut client = postgres::Client::connect(&self.connection_string, postgres::NoTls)
+            .map_err(|e| Error::postgres("Failed to connect to PostgreSQL", e))?;
+        
+        // Start a transaction
+        client.batch_execute("BEGIN")
+            .map_err(|e| Error::postgres("Failed to start transaction", e))?;
+        
+        // Execute each statement
+        for statement in &statements {
+            let params: Vec<&(dyn ToSql + Sync)> = statement.values.iter()
+                .map(SqlValue::as_param)
+                .collect();
+            
+            client.execute(&statement.query, &params)
+                .map_err(|e| Error::postgres(&format!("Failed to execute {}", statement.action), e))?;
+        }
+        
+        // Commit the transaction
+        client.batch_execute("COMMIT")
+            .map_err(|e| Error::postgres("Failed to commit transaction", e))?;
+        
+        Ok(())
+    }
+}
+
+/// The most parameters PostgreSQL accepts in a single statement
+const MAX_STATEMENT_PARAMS: usize = 65535;
+
+/// A SQL statement with its parameters
+#[derive(Debug, Clone, PartialEq)]
+struct Statement {
+    /// The statement text
+    query: String,
+    
+    /// The statement parameters
+    values: Vec<SqlValue>,
+    
+    /// The kind of statement, for error messages
+    action: &'static str,
+}
+
+/// Rows to write with multi-row INSERTs into one table
+#[derive(Debug)]
+struct PendingInsert {
+    /// The qualified table name
+    table: String,
+    
+    /// The columns to insert
+    fields: Vec<String>,
+    
+    /// The key columns
+    key_columns: Vec<String>,
+    
+    /// The CDC keys of the rows, since a row may appear only once per INSERT
+    keys: HashSet<String>,
+    
+    /// The values of each row
+    rows: Vec<Vec<SqlValue>>,
+}
+
+impl PendingInsert {
+    /// Start an INSERT with no rows
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The qualified table name
+    /// * `fields` - The columns to insert
+    /// * `key_columns` - The key columns
+    ///
+    /// # Returns
+    ///
+    /// The pending INSERT
+    fn new(table: String, fields: Vec<String>, key_columns: Vec<String>) -> Self {
+        Self { table, fields, key_columns, keys: HashSet::new(), rows: Vec::new() }
+    }
+    
+    /// Check whether a row can join the INSERT
+    ///
+    /// An upsert cannot touch the same row twice, so a repeated key needs a
+    /// new INSERT.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The qualified table name of the row
+    /// * `fields` - The columns of the row
+    /// * `key` - The CDC key of the row
+    ///
+    /// # Returns
+    ///
+    /// true if the row has the same table and columns and a new key
+    fn accepts(&self, table: &str, fields: &[String], key: &str) -> bool {
+        self.table == table && self.fields == fields && !self.keys.contains(key)
+    }
+    
+    /// Add a row to the INSERT
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The CDC key of the row
+    /// * `values` - The values of the row, in column order
+    fn push(&mut self, key: &str, values: Vec<SqlValue>) {
+        self.keys.insert(key.to_string());
+        self.rows.push(values);
+    }
+    
+    /// Build the INSERT statements, each under the parameter limit
+    ///
+    /// # Returns
+    ///
+    /// One statement per group of rows that fits the limit
+    fn into_statements(self) -> Vec<Statement> {
+        let rows_per_statement = (MAX_STATEMENT_PARAMS / self.fields.len().max(1)).max(1);
+        
+        self.rows.chunks(rows_per_statement)
+            .map(|rows| Statement {
+                query: insert_query(&self.table, &self.fields, &self.key_columns, rows.len()),
+                values: rows.concat(),
+                action: "INSERT",
+            })
+            .collect()
+    }
+}
+
+/// A statement parameter, typed so it binds to its column's SQL type
+#[derive(Debug, Clone, PartialEq)]
+enum SqlValue {
+    /// A `text` value
+    Text(Option<String>),
+    
+    /// A `bigint` value
+    BigInt(Option<i64>),
+    
+    /// A `double precision` value
+    Double(Option<f64>),
+    
+    /// A `boolean` value
+    Boolean(Option<bool>),
+    
+    /// A `bytea` value
+    Bytea(Option<Vec<u8>>),
+}
+
+impl SqlValue {
+    /// Get the value as a statement parameter
+    ///
+    /// # Returns
+    ///
+    /// The value, bound as SQL NULL if it is None
+    fn as_param(&self) -> &(dyn ToSql + Sync) {
+        match self {
+            SqlValue::Text(value) => value,
+            SqlValue::BigInt(value) => value,
+            SqlValue::Double(value) => value,
+            SqlValue::Boolean(value) => value,
+            SqlValue::Bytea(value) => value,
+        }
+    }
+}
+
+/// Coerce a JSON value to the SQL type declared for its column
+///
+/// JSON null becomes SQL NULL of the declared type.
+///
+/// # Arguments
+///
+/// * `column` - The column name, for error messages
+/// * `value` - The JSON value
+/// * `column_type` - The declared column type
+///
+/// # Returns
+///
+/// The coerced value
+///
+/// # Errors
+///
+/// Returns `Error::Sink` if the value cannot be represented as the declared type
+fn coerce_value(column: &str, value: &serde_json::Value, column_type: ColumnType) -> Result<SqlValue> {
+    use serde_json::Value;
+    
+    let failed = || Error::Sink(format!(
+        "Cannot coerce value {} of column {} to {}",
+        value,
+        column,
+        column_type.as_str()
+    ));
+    
+    let coerced = match (column_type, value) {
+        (ColumnType::Text, Value::Null) => SqlValue::Text(None),
+        (ColumnType::Text, Value::String(s)) => SqlValue::Text(Some(s.clone())),
+        (ColumnType::Text, other) => SqlValue::Text(Some(other.to_string())),
+        (ColumnType::BigInt, Value::Null) => SqlValue::BigInt(None),
+        (ColumnType::BigInt, Value::Number(n)) => SqlValue::BigInt(Some(n.as_i64().ok_or_else(failed)?)),
+        (ColumnType::BigInt, Value::String(s)) => SqlValue::BigInt(Some(s.trim().parse().map_err(|_| failed())?)),
+        (ColumnType::Double, Value::Null) => SqlValue::Double(None),
+        (ColumnType::Double, Value::Number(n)) => SqlValue::Double(Some(n.as_f64().ok_or_else(failed)?)),
+        (ColumnType::Double, Value::String(s)) => SqlValue::Double(Some(s.trim().parse().map_err(|_| failed())?)),
+        (ColumnType::Boolean, Value::Null) => SqlValue::Boolean(None),
+        (ColumnType::Boolean, Value::Bool(b)) => SqlValue::Boolean(Some(*b)),
+        (ColumnType::Boolean, Value::String(s)) => SqlValue::Boolean(Some(s.trim().parse().map_err(|_| failed())?)),
+        (ColumnType::Bytea, Value::Null) => SqlValue::Bytea(None),
+        (ColumnType::Bytea, Value::String(s)) => {
+            SqlValue::Bytea(Some(hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(|_| failed())?))
+        }
+        _ => return Err(failed()),
+    };
+    
+    Ok(coerced)
+}
+
+/// Build a WHERE condition matching a row by its key columns
+///
+/// # Arguments
+///
+/// * `key_columns` - The key columns
+/// * `first_param` - The number of the first query parameter to use
+///
+/// # Returns
+///
+/// The condition, e.g. `a = $3 AND b = $4`
+fn key_condition(key_columns: &[String], first_param: usize) -> String {
+    key_columns.iter()
+        .enumerate()
+        .map(|(i, column)| format!("{} = ${}", column, first_param + i))
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+/// Build an INSERT statement that upserts on the table's key columns
+///
+/// The values of row `r` (from 0) are parameters `$(r * n + 1)..$(r * n + n)`
+/// for `n` fields.
+///
+/// # Arguments
+///
+/// * `table` - The qualified table name
+/// * `fields` - The columns to insert
+/// * `key_columns` - The key columns
+/// * `rows` - The number of rows to insert
+///
+/// # Returns
+///
+/// The INSERT statement
+fn insert_query(table: &str, fields: &[String], key_columns: &[String], rows: usize) -> String {
+    let placeholders: Vec<String> = (0..rows)
+        .map(|row| {
+            let params: Vec<String> = (1..=fields.len())
+                .map(|i| format!("${}", row * fields.len() + i))
+                .collect();
+            format!("({})", params.join(", "))
+        })
+        .collect();
+    
+    let updates: Vec<String> = fields.iter()
+        .filter(|field| !key_columns.contains(field))
+        .map(|field| format!("{} = EXCLUDED.{}", field, field))
+        .collect();
+    
+    let on_conflict = if updates.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!("DO UPDATE SET {}", updates.join(", "))
+    };
+    
+    format!(
+        "INSERT INTO {} ({}) VALUES {} ON CONFLICT ({}) {}",
+        table,
+        fields.join(", "),
+        placeholders.join(", "),
+        key_columns.join(", "),
+        on_conflict
+    )
+}
+
+/// Build an INSERT statement that appends a row to an event table
+///
+/// # Arguments
+///
+/// * `table` - The qualified table name
+/// * `fields` - The columns to insert
+///
+/// # Returns
+///
+/// The INSERT statement
+fn event_insert_query(table: &str, fields: &[String]) -> String {
+    let params: Vec<String> = (1..=fields.len()).map(|i| format!("${}", i)).collect();
+    
+    format!("INSERT INTO {} ({}) VALUES ({})", table, fields.join(", "), params.join(", "))
+}
+
+/// Build an UPDATE statement for a row identified by its key columns
+///
+/// The field values are parameters `$1..$n`, followed by the key values.
+///
+/// # Arguments
+///
+/// * `table` - The qualified table name
+/// * `fields` - The columns to update
+/// * `key_columns` - The key columns
+///
+/// # Returns
+///
+/// The UPDATE statement
+fn update_query(table: &str, fields: &[String], key_columns: &[String]) -> String {
+    let set_clauses: Vec<String> = fields.iter()
+        .enumerate()
+        .map(|(i, f)| format!("{} = ${}", f, i + 1))
+        .collect();
+    
+    format!(
+        "UPDATE {} SET {} WHERE {}",
+        table,
+        set_clauses.join(", "),
+        key_condition(key_columns, fields.len() + 1)
+    )
+}
+
+/// Build a DELETE statement for a row identified by its key columns
+///
+/// # Arguments
+///
+/// * `table` - The qualified table name
+/// * `key_columns` - The key columns
+///
+/// # Returns
+///
+/// The DELETE statement
+fn delete_query(table: &str, key_columns: &[String]) -> String {
+    format!("DELETE FROM {} WHERE {}", table, key_condition(key_columns, 1))
+}
+
+#[async_trait]
+impl CdcSink for PostgresSink {
+    async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+        self.close_state.ensure_open()?;
+        
+        // Add messages to the buffer
+        let new_messages = messages.len();
+        let mut buffer = self.buffer.lock().await;
+        if buffer.is_empty() && !messages.is_empty() {
+            *self.buffered_since() = Some(tokio::time::Instant::now());
+        }
+        buffer.extend(messages);
+        
+        // Flush the buffer if it exceeds the batch size, or its oldest
+        // message has waited too long. Only blocks marked as sent before
+        // this call are complete in the buffer, so only they are committed
+        let too_old = self.flush_deadline().is_some_and(|deadline| deadline <= tokio::time::Instant::now());
+        if buffer.len() >= self.batch_size || too_old {
+            let sent_height = self.sent_height.load(Ordering::SeqCst);
+            let messages_to_send = buffer.clone();
+            buffer.clear();
+            let buffered_since = self.buffered_since().take();
+            
+            // Release the lock before applying messages
+            drop(buffer);
+            
+            // Apply the messages
+            if let Err(e) = self.apply_messages(&messages_to_send).await {
+                // Put back the messages buffered by earlier calls, so a retry
+                // of this call delivers them along with its own messages
+                let earlier = messages_to_send.len() - new_messages;
+                if earlier > 0 {
+                    *self.buffered_since() = buffered_since;
+                }
+                self.buffer.lock().await.splice(0..0, messages_to_send.into_iter().take(earlier));
+                return Err(e);
+            }
+            self.committed_height.store(sent_height, Ordering::SeqCst);
+        }
+        
+        Ok(())
+    }
+    
+    async fn flush(&self) -> Result<()> {
+        // Read before taking the buffer, so a concurrent send is not counted as committed
+        let sent_height = self.sent_height.load(Ordering::SeqCst);
+        
+        // Get the buffered messages
+        let messages_to_send = {
+            let mut buffer = self.buffer.lock().await;
+            let messages = buffer.clone();
+            buffer.clear();
+            *self.buffered_since() = None;
+            messages
+        };
+        
+        // Apply the messages if there are any
+        if !messages_to_send.is_empty() {
+            // Use a timeout based on flush_interval
+            let timeout = Duration::from_millis(self.flush_interval);
+            let apply_future = self.apply_messages(&messages_to_send);
+            
+            match tokio::time::timeout(timeout, apply_future).await {
+                Ok(result) => result?,
+                Err(_) => return Err(Error::Postgres(format!("Flush operation timed out after {} ms", self.flush_interval))),
+            }
+        }
+        self.committed_height.store(sent_height, Ordering::SeqCst);
+        
+        Ok(())
+    }
+    
+    async fn close(&self) -> Result<()> {
+        // Sends are refused from here on, so once a close has flushed the
+        // buffer it stays empty and the drop flush has nothing to do. A close
+        // whose flush failed flushes again when retried.
+        if !self.close_state.begin_close() {
+            return Ok(());
+        }
+        
+        // Flush the buffer before closing
+        self.flush().await?;
+        self.close_state.finish_close();
+        
+        Ok(())
+    }
+    
+    async fn health_check(&self) -> Result<()> {
+        let connection_string = self.connection_string.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut client = postgres::Client::connect(&connection_string, postgres::NoTls)
+                .map_err(|e| Error::postgres("Failed to connect to PostgreSQL", e))?;
+            client.batch_execute("SELECT 1")
+                .map_err(|e| Error::postgres("PostgreSQL health check failed", e))
+        })
+        .await
+        .map_err(|e| Error::Postgres(format!("PostgreSQL health check panicked: {}", e)))?
+    }
+    
+    fn committed_height(&self) -> Option<u32> {
+        Some(self.committed_height.load(Ordering::SeqCst))
+    }
+    
+    fn mark_sent(&self, height: u32) {
+        self.sent_height.store(height, Ordering::SeqCst);
+        
+        // Messages are buffered exactly while a batch age is being tracked
+        if self.buffered_since().is_none() {
+            self.committed_height.store(height, Ordering::SeqCst);
+        }
+    }
+    
+    fn flush_deadline(&self) -> Option<tokio::time::Instant> {
+        let buffered_since = (*self.buffered_since())?;
+        Some(buffered_since + self.max_batch_age?)
+    }
+}
+
+impl Drop for PostgresSink {
+    fn drop(&mut self) {
+        // Take whatever is still buffered; if the buffer is locked, someone
+        // is mid-flush and we cannot safely wait for them here
+        let messages = match self.buffer.try_lock() {
+            Ok(mut buffer) => std::mem::take(&mut *buffer),
+            Err(_) => {
+                warn!("PostgreSQL sink dropped while its buffer was locked; buffered messages may be lost");
+                return;
+            }
+        };
+        
+        if messages.is_empty() {
+            return;
+        }
+        
+        // Apply the messages on a separate thread so the blocking client
+        // never runs inside the caller's Tokio runtime
+        let result = std::thread::scope(|scope| {
+            scope.spawn(|| self.apply_messages_blocking(&messages)).join()
+        });
+        
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!(
+                "Failed to flush {} buffered messages while dropping PostgreSQL sink: {}",
+                messages.len(),
+                e
+            ),
+            Err(_) => warn!(
+                "Panicked while flushing {} buffered messages during PostgreSQL sink drop",
+                messages.len()
+            ),
+        }
+    }
+}
+
+/// Elasticsearch CDC sink
+///
+/// This sink indexes the `after` document of each CDC message through the
+/// `_bulk` API of Elasticsearch or OpenSearch, using the message key as the
+/// document ID, so a later update replaces the document. Deletes remove the
+/// document.
+pub struct ElasticsearchSink {
+    /// The HTTP client
+    client: reqwest::Client,
+    
+    /// The base URL of the cluster
+    url: url::Url,
+    
+    /// The index name, with `{table}` standing for the message table
+    index: String,
+    
+    /// The maximum number of actions per bulk request
+    batch_size: usize,
+    
+    /// Whether the sink has been closed
+    close_state: CloseState,
+}
+
+impl ElasticsearchSink {
+    /// Create a new Elasticsearch sink
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The base URL of the cluster
+    /// * `index` - The index name; `{table}` is replaced with the message table
+    /// * `batch_size` - The maximum number of actions per bulk request
+    ///
+    /// # Returns
+    ///
+    /// A new Elasticsearch sink
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid
+    pub fn new(url: &str, index: &str, batch_size: usize) -> Result<Self> {
+        // Keep any path prefix when joining endpoint names onto the URL
+        let mut url = url::Url::parse(url)?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url,
+            index: index.to_string(),
+            batch_size: batch_size.max(1),
+            close_state: CloseState::default(),
+        })
+    }
+    
+    /// Build the newline-delimited body of a bulk request
+    ///
+    /// Control messages have no document and are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages
+    ///
+    /// # Returns
+    ///
+    /// The request body, or None if no message produces an action
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a document cannot be serialized
+    fn bulk_body(&self, messages: &[CdcMessage]) -> Result<Option<String>> {
+        let mut body = String::new();
+        
+        for message in messages {
+            let action = match message.payload.operation {
+                CdcOperation::Create | CdcOperation::Update => "index",
+                CdcOperation::Delete => "delete",
+                CdcOperation::SnapshotComplete
+                | CdcOperation::Heartbeat
+                | CdcOperation::ReorgBegin
+                | CdcOperation::ReorgEnd => continue,
+            };
+            
+            let metadata = serde_json::json!({
+                (action): {
+                    "_index": self.index.replace("{table}", &message.payload.table),
+                    "_id": message.payload.key,
                 }
+            });
+            body.push_str(&metadata.to_string());
+            body.push('\n');
+            
+            if message.payload.operation != CdcOperation::Delete {
+                body.push_str(&serde_json::to_string(&message.payload.after)?);
+                body.push('\n');
             }
         }
         
-        // Commit the transaction
-        client.batch_execute("COMMIT")
-            .map_err(|e| Error::Postgres(format!("Failed to commit transaction: {}", e)))?;
+        Ok((!body.is_empty()).then_some(body))
+    }
+    
+    /// Send one bulk request and check the result of every action
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The newline-delimited request body
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if every action succeeded
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or any action failed. Rejections
+    /// under load (429) and server errors are transient, anything else is
+    /// permanent.
+    async fn send_bulk(&self, body: String) -> Result<()> {
+        let response = self.client.post(self.url.join("_bulk")?)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+            .body(body)
+            .send()
+            .await?;
         
-        Ok(())
+        let status = response.status().as_u16();
+        if !response.status().is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(elasticsearch_error(status, format!("Bulk request failed with status {}: {}", status, text)));
+        }
+        
+        let result: serde_json::Value = response.json().await?;
+        if result["errors"].as_bool() != Some(true) {
+            return Ok(());
+        }
+        
+        // Deleting a document that does not exist leaves the index as wanted
+        let failures: Vec<(u16, &serde_json::Value)> = result["items"].as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item.as_object()?.values().next())
+            .filter_map(|item| Some((item["status"].as_u64()? as u16, item)))
+            .filter(|(status, item)| *status >= 300 && !(*status == 404 && item["result"] == "not_found"))
+            .collect();
+        
+        let Some((_, first)) = failures.first() else {
+            return Ok(());
+        };
+        let message = format!(
+            "{} bulk actions failed; first failure on document {}: {}: {}",
+            failures.len(),
+            first["_id"].as_str().unwrap_or_default(),
+            first["error"]["type"].as_str().unwrap_or("unknown"),
+            first["error"]["reason"].as_str().unwrap_or("no reason given"),
+        );
+        
+        // Retrying re-indexes the whole request, which only helps if every
+        // failure was transient
+        if failures.iter().all(|(status, _)| *status == 429 || *status >= 500) {
+            Err(Error::Elasticsearch(message))
+        } else {
+            Err(Error::ElasticsearchPermanent(message))
+        }
+    }
+}
+
+/// Classify an Elasticsearch error by HTTP status
+///
+/// # Arguments
+///
+/// * `status` - The HTTP status of the response
+/// * `message` - The error message
+///
+/// # Returns
+///
+/// `Error::Elasticsearch` for 429 and server errors,
+/// `Error::ElasticsearchPermanent` otherwise
+fn elasticsearch_error(status: u16, message: String) -> Error {
+    if status == 429 || status >= 500 {
+        Error::Elasticsearch(message)
+    } else {
+        Error::ElasticsearchPermanent(message)
     }
 }
 
 #[async_trait]
-impl CdcSink for PostgresSink {
+impl CdcSink for ElasticsearchSink {
     async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
-        // Add messages to the buffer
-        let mut buffer = self.buffer.lock().await;
-        buffer.extend(messages);
+        self.close_state.ensure_open()?;
         
-        // Flush the buffer if it exceeds the batch size
-        if buffer.len() >= self.batch_size {
-            let messages_to_send = buffer.clone();
-            buffer.clear();
-            
-            // Release the lock before applying messages
-            drop(buffer);
-            
-            // Apply the messages
-            self.apply_messages(&messages_to_send).await?;
+        for chunk in messages.chunks(self.batch_size) {
+            if let Some(body) = self.bulk_body(chunk)? {
+                self.send_bulk(body).await?;
+            }
         }
         
         Ok(())
     }
     
     async fn flush(&self) -> Result<()> {
-        // Get the buffered messages
-        let messages_to_send = {
-            let mut buffer = self.buffer.lock().await;
-            let messages = buffer.clone();
-            buffer.clear();
-            messages
-        };
-        
-        // Apply the messages if there are any
-        if !messages_to_send.is_empty() {
-            // Use a timeout based on flush_interval
-            let timeout = Duration::from_millis(self.flush_interval);
-            let apply_future = self.apply_messages(&messages_to_send);
-            
-            match tokio::time::timeout(timeout, apply_future).await {
-                Ok(result) => result?,
-                Err(_) => return Err(Error::Postgres(format!("Flush operation timed out after {} ms", self.flush_interval))),
-            }
-        }
-        
+        // Every send completes its bulk requests, so nothing is buffered
         Ok(())
     }
     
     async fn close(&self) -> Result<()> {
-        // Flush the buffer before closing
-        self.flush().await?;
+        // Every send completes its bulk requests, so there is nothing to flush
+        self.close_state.begin_close();
+        self.close_state.finish_close();
+        Ok(())
+    }
+    
+    async fn health_check(&self) -> Result<()> {
+        let response = self.client.get(self.url.clone()).send().await?;
+        if !response.status().is_success() {
+            return Err(elasticsearch_error(
+                response.status().as_u16(),
+                format!("Elasticsearch health check failed with status {}", response.status()),
+            ));
+        }
         
         Ok(())
     }
 }
 
+/// The open output file of a file sink
+struct FileOutput {
+    /// The buffered file writer
+    writer: BufWriter<File>,
+    
+    /// Whether the opening bracket of the JSON array has been written
+    array_open: bool,
+    
+    /// Whether the JSON array holds at least one message
+    has_records: bool,
+    
+    /// Whether the closing bracket of the JSON array has been written
+    array_closed: bool,
+}
+
 /// File CDC sink
 ///
 /// This sink writes CDC messages to a file.
 pub struct FileSink {
-    /// The file
-    file: Arc<Mutex<File>>,
+    /// The path to the file
+    path: PathBuf,
+    
+    /// The output file
+    file: Arc<Mutex<FileOutput>>,
+    
+    /// How messages are framed in the file
+    framing: FileFraming,
     
     /// The flush interval in milliseconds
     flush_interval: u64,
+    
+    /// What to do with a message that fails to serialize
+    on_serialization_error: SerializationErrorPolicy,
+    
+    /// Whether written messages name a table suffixed with their operation
+    route_by_operation: bool,
+    
+    /// How messages are written as JSON; output is always one line per message
+    json_options: JsonOptions,
+    
+    /// How the header timestamp is written
+    timestamp_format: TimestampFormat,
+    
+    /// Whether the sink has been closed
+    close_state: CloseState,
 }
 
 impl FileSink {
@@ -491,26 +2367,206 @@ impl FileSink {
             .map_err(|e| Error::File(format!("Failed to open file: {}", e)))?;
         
         Ok(Self {
-            file: Arc::new(Mutex::new(file)),
+            path: PathBuf::from(path),
+            file: Arc::new(Mutex::new(FileOutput {
+                writer: BufWriter::new(file),
+                array_open: false,
+                has_records: false,
+                array_closed: false,
+            })),
+            framing: FileFraming::default(),
             flush_interval,
+            on_serialization_error: SerializationErrorPolicy::default(),
+            route_by_operation: false,
+            json_options: JsonOptions::default(),
+            timestamp_format: TimestampFormat::default(),
+            close_state: CloseState::default(),
         })
     }
+    
+    /// Set the policy for messages that fail to serialize
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The serialization error policy
+    pub fn set_serialization_error_policy(&mut self, policy: SerializationErrorPolicy) {
+        self.on_serialization_error = policy;
+    }
+    
+    /// Set whether written messages name a table per operation
+    ///
+    /// When enabled, the table of each written message is suffixed with the
+    /// operation, e.g. deletes from `balances` are written as `balances_delete`.
+    ///
+    /// # Arguments
+    ///
+    /// * `route_by_operation` - Whether to route by operation
+    pub fn set_route_by_operation(&mut self, route_by_operation: bool) {
+        self.route_by_operation = route_by_operation;
+    }
+    
+    /// Set how messages are written as JSON
+    ///
+    /// Pretty-printing is ignored, since the file holds one message per line.
+    ///
+    /// # Arguments
+    ///
+    /// * `json_options` - The JSON options
+    pub fn set_json_options(&mut self, json_options: JsonOptions) {
+        self.json_options = JsonOptions { pretty: false, ..json_options };
+    }
+    
+    /// Set how the header timestamp is written
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp_format` - The timestamp format
+    pub fn set_timestamp_format(&mut self, timestamp_format: TimestampFormat) {
+        self.timestamp_format = timestamp_format;
+    }
+    
+    /// Set how messages are framed in the file
+    ///
+    /// With `JsonArray` framing the array is only closed by `close`; a file
+    /// left open by a crash is continued the next time it is appended to.
+    ///
+    /// # Arguments
+    ///
+    /// * `framing` - The file framing
+    pub fn set_framing(&mut self, framing: FileFraming) {
+        self.framing = framing;
+    }
+    
+    /// Open the JSON array, continuing one already in the file
+    ///
+    /// A closed array has its closing bracket removed so new messages are
+    /// added to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output file
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the array is open for writing
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file holds something other than a JSON array
+    /// or cannot be read or written
+    fn open_array(&self, output: &mut FileOutput) -> Result<()> {
+        if output.array_open {
+            return Ok(());
+        }
+        
+        let (length, has_records) = existing_array(&self.path)?;
+        output.writer.get_ref()
+            .set_len(length)
+            .map_err(|e| Error::File(format!("Failed to truncate file: {}", e)))?;
+        if length == 0 {
+            output.writer.write_all(b"[")
+                .map_err(|e| Error::File(format!("Failed to write to file: {}", e)))?;
+        }
+        
+        output.array_open = true;
+        output.has_records = has_records;
+        
+        Ok(())
+    }
+}
+
+/// Inspect the JSON array already written to a file
+///
+/// Only the start and end of the file are read, so large files are cheap to
+/// reopen.
+///
+/// # Arguments
+///
+/// * `path` - The path to the file
+///
+/// # Returns
+///
+/// The length to truncate the file to before appending (just past the last
+/// message, or 0 for an empty file) and whether the array holds a message
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or does not hold a JSON array
+fn existing_array(path: &Path) -> Result<(u64, bool)> {
+    const PROBE_SIZE: u64 = 256;
+    
+    let read_error = |e: std::io::Error| Error::File(format!("Failed to read file: {}", e));
+    let mut file = File::open(path).map_err(read_error)?;
+    let length = file.metadata().map_err(read_error)?.len();
+    
+    let mut head = Vec::new();
+    (&mut file).take(PROBE_SIZE).read_to_end(&mut head).map_err(read_error)?;
+    let first = head.iter().find(|byte| !byte.is_ascii_whitespace());
+    match first {
+        None if length <= PROBE_SIZE => return Ok((0, false)),
+        Some(b'[') => {}
+        _ => return Err(Error::File(format!("File {} does not hold a JSON array", path.display()))),
+    }
+    
+    let tail_start = length.saturating_sub(PROBE_SIZE);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_start)).map_err(read_error)?;
+    file.read_to_end(&mut tail).map_err(read_error)?;
+    
+    // Drop trailing whitespace and the closing bracket, if the array was closed
+    let mut end = tail.len();
+    while end > 0 && tail[end - 1].is_ascii_whitespace() {
+        end -= 1;
+    }
+    if end > 0 && tail[end - 1] == b']' {
+        end -= 1;
+        while end > 0 && tail[end - 1].is_ascii_whitespace() {
+            end -= 1;
+        }
+    }
+    if end == 0 {
+        return Err(Error::File(format!("File {} does not hold a JSON array", path.display())));
+    }
+    
+    let has_records = tail[end - 1] != b'[';
+    Ok((tail_start + end as u64, has_records))
 }
 
 #[async_trait]
 impl CdcSink for FileSink {
-    async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
-        let mut file = self.file.lock()
+    async fn send(&self, mut messages: Vec<CdcMessage>) -> Result<()> {
+        self.close_state.ensure_open()?;
+        
+        if self.route_by_operation {
+            for message in &mut messages {
+                message.payload.table = routed_destination(&message.payload.table, message, true);
+            }
+        }
+        
+        // Serialize the messages to JSON
+        let serialized = serialize_messages(&messages, self.on_serialization_error, |message| {
+            serialize_to_json_with(&message.with_timestamp_format(self.timestamp_format), self.json_options)
+        })
+            .map_err(|e| Error::Sink(format!("Failed to serialize message: {}", e)))?;
+        
+        let mut output = self.file.lock()
             .map_err(|e| Error::File(format!("Failed to lock file: {}", e)))?;
         
-        for message in messages {
-            // Serialize the message to JSON
-            let json = serde_json::to_string(&message)
-                .map_err(|e| Error::File(format!("Failed to serialize message: {}", e)))?;
-            
+        if self.framing == FileFraming::JsonArray {
+            self.open_array(&mut output)?;
+        }
+        
+        for (_, json) in serialized {
             // Write the message to the file
-            writeln!(file, "{}", json)
-                .map_err(|e| Error::File(format!("Failed to write to file: {}", e)))?;
+            let result = match self.framing {
+                FileFraming::Ndjson => writeln!(output.writer, "{}", json),
+                FileFraming::JsonArray => {
+                    let separator = if output.has_records { "," } else { "" };
+                    output.has_records = true;
+                    write!(output.writer, "{}\n{}", separator, json)
+                }
+            };
+            result.map_err(|e| Error::File(format!("Failed to write to file: {}", e)))?;
         }
         
         Ok(())
@@ -521,10 +2577,10 @@ impl CdcSink for FileSink {
         let timeout = Duration::from_millis(self.flush_interval);
         
         let flush_future = async {
-            let mut file = self.file.lock()
+            let mut output = self.file.lock()
                 .map_err(|e| Error::File(format!("Failed to lock file: {}", e)))?;
             
-            file.flush()
+            output.writer.flush()
                 .map_err(|e| Error::File(format!("Failed to flush file: {}", e)))?;
             
             Ok(())
@@ -537,19 +2593,67 @@ impl CdcSink for FileSink {
     }
     
     async fn close(&self) -> Result<()> {
+        // Sends are refused from here on; a close whose flush failed flushes
+        // again when retried
+        if !self.close_state.begin_close() {
+            return Ok(());
+        }
+        
+        // Close the JSON array, writing an empty one if nothing was sent
+        if self.framing == FileFraming::JsonArray {
+            let mut output = self.file.lock()
+                .map_err(|e| Error::File(format!("Failed to lock file: {}", e)))?;
+            if !output.array_closed {
+                self.open_array(&mut output)?;
+                output.writer.write_all(b"\n]\n")
+                    .map_err(|e| Error::File(format!("Failed to write to file: {}", e)))?;
+                output.array_closed = true;
+            }
+        }
+        
         // Flush the file before closing
         self.flush().await?;
+        self.close_state.finish_close();
+        
+        Ok(())
+    }
+    
+    async fn health_check(&self) -> Result<()> {
+        // Reopen the path rather than trusting the open handle, which keeps
+        // working after the file is removed or made read-only
+        OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| Error::File(format!("File {} is not writable: {}", self.path.display(), e)))?;
         
         Ok(())
     }
 }
 
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        // Best-effort flush so buffered lines aren't silently discarded
+        // when the sink goes away without an explicit close
+        match self.file.lock() {
+            Ok(mut output) => {
+                if let Err(e) = output.writer.flush() {
+                    warn!("Failed to flush file sink on drop: {}", e);
+                }
+            }
+            Err(_) => warn!("File sink dropped with a poisoned lock; buffered messages may be lost"),
+        }
+    }
+}
+
 /// Console CDC sink
 ///
 /// This sink writes CDC messages to the console.
 pub struct ConsoleSink {
-    /// Whether to pretty-print the messages
-    pretty_print: bool,
+    /// How messages are written as JSON
+    json_options: JsonOptions,
+    
+    /// How the header timestamp is written
+    timestamp_format: TimestampFormat,
 }
 
 impl ConsoleSink {
@@ -563,7 +2667,29 @@ impl ConsoleSink {
     ///
     /// A new console sink
     pub fn new(pretty_print: bool) -> Self {
-        Self { pretty_print }
+        Self::with_json_options(JsonOptions { pretty: pretty_print, sort_keys: false })
+    }
+    
+    /// Create a new console sink with the given JSON options
+    ///
+    /// # Arguments
+    ///
+    /// * `json_options` - How messages are written as JSON
+    ///
+    /// # Returns
+    ///
+    /// A new console sink
+    pub fn with_json_options(json_options: JsonOptions) -> Self {
+        Self { json_options, timestamp_format: TimestampFormat::default() }
+    }
+    
+    /// Set how the header timestamp is written
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp_format` - The timestamp format
+    pub fn set_timestamp_format(&mut self, timestamp_format: TimestampFormat) {
+        self.timestamp_format = timestamp_format;
     }
 }
 
@@ -572,13 +2698,8 @@ impl CdcSink for ConsoleSink {
     async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
         for message in messages {
             // Serialize the message to JSON
-            let json = if self.pretty_print {
-                serde_json::to_string_pretty(&message)
-                    .map_err(|e| Error::Generic(format!("Failed to serialize message: {}", e)))?
-            } else {
-                serde_json::to_string(&message)
-                    .map_err(|e| Error::Generic(format!("Failed to serialize message: {}", e)))?
-            };
+            let json = serialize_to_json_with(&message.with_timestamp_format(self.timestamp_format), self.json_options)
+                .map_err(|e| Error::Generic(format!("Failed to serialize message: {}", e)))?;
             
             // Write the message to the console
             println!("{}", json);
@@ -608,33 +2729,148 @@ impl NullSink {
     ///
     /// # Returns
     ///
-    /// A new null sink
-    pub fn new() -> Self {
-        Self
+    /// A new null sink
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl CdcSink for NullSink {
+    async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+        // Discard all messages
+        Ok(())
+    }
+    
+    async fn flush(&self) -> Result<()> {
+        // No need to flush
+        Ok(())
+    }
+    
+    async fn close(&self) -> Result<()> {
+        // No need to close
+        Ok(())
+    }
+}
+
+impl Default for NullSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Retrying CDC sink
+///
+/// This sink wraps another sink and retries failed sends with exponential
+/// backoff, optionally with jitter. Only transient errors are retried; a
+/// permanent error, such as a message that cannot be serialized, is returned
+/// immediately.
+pub struct RetryingSink {
+    /// The wrapped sink
+    inner: Box<dyn CdcSink>,
+    
+    /// The maximum number of attempts, including the first
+    max_attempts: u32,
+    
+    /// The delay before the first retry
+    initial_backoff: Duration,
+    
+    /// The maximum delay between retries
+    max_backoff: Duration,
+    
+    /// Whether each delay is randomly shortened by up to half
+    jitter: bool,
+}
+
+impl RetryingSink {
+    /// Create a new retrying sink
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The sink to wrap
+    /// * `config` - The retry policy
+    ///
+    /// # Returns
+    ///
+    /// A new retrying sink
+    pub fn new(inner: Box<dyn CdcSink>, config: &RetryConfig) -> Self {
+        Self {
+            inner,
+            max_attempts: config.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            jitter: config.jitter,
+        }
+    }
+    
+    /// Get the delay before a retry
+    ///
+    /// # Arguments
+    ///
+    /// * `backoff` - The current backoff
+    ///
+    /// # Returns
+    ///
+    /// The backoff, or with jitter a random delay between half of it and all of it
+    fn delay(&self, backoff: Duration) -> Duration {
+        if !self.jitter {
+            return backoff;
+        }
+        
+        let half = backoff / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=backoff - half)
     }
 }
 
 #[async_trait]
-impl CdcSink for NullSink {
-    async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
-        // Discard all messages
-        Ok(())
+impl CdcSink for RetryingSink {
+    async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        
+        loop {
+            match self.inner.send(messages.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is_transient() && attempt < self.max_attempts => {
+                    let delay = self.delay(backoff);
+                    warn!(
+                        "Sink send failed (attempt {} of {}), retrying in {} ms: {}",
+                        attempt,
+                        self.max_attempts,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
     }
     
     async fn flush(&self) -> Result<()> {
-        // No need to flush
-        Ok(())
+        self.inner.flush().await
     }
     
     async fn close(&self) -> Result<()> {
-        // No need to close
-        Ok(())
+        self.inner.close().await
     }
-}
-
-impl Default for NullSink {
-    fn default() -> Self {
-        Self::new()
+    
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+    
+    fn committed_height(&self) -> Option<u32> {
+        self.inner.committed_height()
+    }
+    
+    fn mark_sent(&self, height: u32) {
+        self.inner.mark_sent(height);
+    }
+    
+    fn flush_deadline(&self) -> Option<tokio::time::Instant> {
+        self.inner.flush_deadline()
     }
 }
 
@@ -655,6 +2891,7 @@ mod tests {
                 block_height: 123,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -737,4 +2974,839 @@ mod tests {
         assert!(contents.contains("value1"));
         assert!(contents.contains("42"));
     }
+
+    #[tokio::test]
+    async fn test_elasticsearch_bulk_requests() {
+        use serde_json::{json, Value};
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+        
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/_bulk"))
+            .and(header("content-type", "application/x-ndjson"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"took": 1, "errors": false, "items": []})))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        
+        let sink = ElasticsearchSink::new(&server.uri(), "cdc-{table}", 2).unwrap();
+        let mut update = create_test_message();
+        update.payload.operation = CdcOperation::Update;
+        update.payload.after = Some(json!({"field1": "value2"}));
+        let mut delete = create_test_message();
+        delete.payload.operation = CdcOperation::Delete;
+        delete.payload.key = "other_key".to_string();
+        let mut heartbeat = create_test_message();
+        heartbeat.payload.operation = CdcOperation::Heartbeat;
+        sink.send(vec![create_test_message(), update, delete, heartbeat]).await.unwrap();
+        
+        // Two actions per request; the heartbeat has no document
+        let requests = server.received_requests().await.unwrap();
+        let bodies: Vec<Vec<Value>> = requests.iter()
+            .map(|request| {
+                std::str::from_utf8(&request.body).unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(bodies, vec![
+            vec![
+                json!({"index": {"_index": "cdc-test_table", "_id": "test_key"}}),
+                json!({"field1": "value1", "field2": 42}),
+                json!({"index": {"_index": "cdc-test_table", "_id": "test_key"}}),
+                json!({"field1": "value2"}),
+            ],
+            vec![json!({"delete": {"_index": "cdc-test_table", "_id": "other_key"}})],
+        ]);
+        
+        // Failed actions fail the send; a missing document on delete does not
+        let item = |action: &str, id: &str, status: u16, error: Option<&str>| {
+            let mut item = json!({"_index": "cdc-test_table", "_id": id, "status": status});
+            match error {
+                Some(error) => item["error"] = json!({"type": error, "reason": "failed"}),
+                None => item["result"] = json!("not_found"),
+            }
+            json!({ (action): item })
+        };
+        Mock::given(method("POST"))
+            .and(path("/_bulk"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"errors": true, "items": [
+                item("delete", "other_key", 404, None),
+                item("index", "test_key", 400, Some("mapper_parsing_exception")),
+            ]})))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/_bulk"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"errors": true, "items": [
+                item("delete", "other_key", 404, None),
+                item("index", "test_key", 429, Some("es_rejected_execution_exception")),
+            ]})))
+            .mount(&server)
+            .await;
+        
+        let error = sink.send(vec![create_test_message()]).await.unwrap_err();
+        assert!(matches!(&error, Error::ElasticsearchPermanent(message) if message.contains("1 bulk actions failed") && message.contains("mapper_parsing_exception")));
+        assert!(!error.is_transient());
+        
+        let error = sink.send(vec![create_test_message()]).await.unwrap_err();
+        assert!(matches!(error, Error::Elasticsearch(_)));
+        assert!(error.is_transient());
+    }
+    
+    #[test]
+    fn test_route_by_operation() {
+        let mut delete = create_test_message();
+        delete.payload.operation = CdcOperation::Delete;
+        let mut heartbeat = create_test_message();
+        heartbeat.payload.operation = CdcOperation::Heartbeat;
+        
+        assert_eq!(routed_destination("cdc-events", &delete, true), "cdc-events_delete");
+        assert_eq!(routed_destination("cdc-events", &delete, false), "cdc-events");
+        assert_eq!(routed_destination("cdc-events", &heartbeat, true), "cdc-events");
+        
+        // The file sink writes the delete under the delete-suffixed table
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("routed.json");
+        let mut sink = FileSink::new(file_path.to_str().unwrap(), false, 1000).unwrap();
+        sink.set_route_by_operation(true);
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(sink.send(vec![create_test_message(), delete])).unwrap();
+        rt.block_on(sink.close()).unwrap();
+        
+        let contents = std::fs::read_to_string(file_path).unwrap();
+        let tables: Vec<String> = contents.lines()
+            .map(|line| serde_json::from_str::<CdcMessage>(line).unwrap().payload.table)
+            .collect();
+        assert_eq!(tables, vec!["test_table_create".to_string(), "test_table_delete".to_string()]);
+    }
+    
+    /// Assert that a sink can be closed twice and refuses sends once closed
+    fn assert_close_is_final(sink: &dyn CdcSink) {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(sink.close()).unwrap();
+        rt.block_on(sink.close()).unwrap();
+        
+        match rt.block_on(sink.send(vec![create_test_message()])) {
+            Err(Error::Sink(message)) => assert_eq!(message, "sink closed"),
+            other => panic!("expected a sink closed error, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn test_custom_sink_from_config() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let factory_seen = seen.clone();
+        register_sink_factory("test-custom", move |params| {
+            factory_seen.lock().unwrap().push(params.clone());
+            Ok(Box::new(NullSink::new()) as Box<dyn CdcSink>)
+        });
+        
+        let config: SinkConfig = serde_json::from_value(serde_json::json!({
+            "type": "custom",
+            "type_name": "test-custom",
+            "params": { "endpoint": "https://example.com" },
+        }))
+        .unwrap();
+        config.validate().unwrap();
+        
+        let sink = create_sink(&config).unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(sink.send(vec![create_test_message()])).unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![serde_json::json!({ "endpoint": "https://example.com" })]);
+        
+        // Unregistered types are rejected when the sink is created
+        let config = SinkConfig::Custom {
+            type_name: "test-unregistered".to_string(),
+            params: serde_json::Value::Null,
+            retry: None,
+        };
+        let error = create_sink(&config).err().unwrap();
+        assert!(error.to_string().contains("No sink factory registered for type test-unregistered"));
+    }
+    
+    #[test]
+    fn test_file_sink_close_is_final() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("closed.json");
+        let sink = FileSink::new(file_path.to_str().unwrap(), false, 1000).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(sink.send(vec![create_test_message()])).unwrap();
+        assert_close_is_final(&sink);
+        drop(sink);
+        
+        // Only the message sent before closing was written
+        let contents = std::fs::read_to_string(file_path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+    }
+    
+    #[test]
+    fn test_file_sink_framing() {
+        let dir = tempdir().unwrap();
+        let rt = Runtime::new().unwrap();
+        let read_array = |path: &Path| -> Vec<serde_json::Value> {
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+        };
+        
+        // Newline-delimited output has one message per line
+        let ndjson_path = dir.path().join("events.ndjson");
+        let sink = FileSink::new(ndjson_path.to_str().unwrap(), false, 1000).unwrap();
+        rt.block_on(sink.send(vec![create_test_message(), create_test_message()])).unwrap();
+        rt.block_on(sink.close()).unwrap();
+        let contents = std::fs::read_to_string(&ndjson_path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        
+        // An array with nothing sent is still closed
+        let array_path = dir.path().join("events.json");
+        let mut sink = FileSink::new(array_path.to_str().unwrap(), false, 1000).unwrap();
+        sink.set_framing(FileFraming::JsonArray);
+        rt.block_on(sink.close()).unwrap();
+        assert!(read_array(&array_path).is_empty());
+        
+        // Appending reopens the closed array
+        for count in [2, 1] {
+            let mut sink = FileSink::new(array_path.to_str().unwrap(), true, 1000).unwrap();
+            sink.set_framing(FileFraming::JsonArray);
+            rt.block_on(sink.send(vec![create_test_message(); count])).unwrap();
+            rt.block_on(sink.close()).unwrap();
+        }
+        let records = read_array(&array_path);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["payload"]["table"], "test_table");
+        
+        // An array left open by a crash is continued
+        let mut sink = FileSink::new(array_path.to_str().unwrap(), true, 1000).unwrap();
+        sink.set_framing(FileFraming::JsonArray);
+        rt.block_on(sink.send(vec![create_test_message()])).unwrap();
+        drop(sink);
+        let mut sink = FileSink::new(array_path.to_str().unwrap(), true, 1000).unwrap();
+        sink.set_framing(FileFraming::JsonArray);
+        rt.block_on(sink.send(vec![create_test_message()])).unwrap();
+        rt.block_on(sink.close()).unwrap();
+        assert_eq!(read_array(&array_path).len(), 5);
+        
+        // Newline-delimited output is not mistaken for an array
+        let mut sink = FileSink::new(ndjson_path.to_str().unwrap(), true, 1000).unwrap();
+        sink.set_framing(FileFraming::JsonArray);
+        assert!(rt.block_on(sink.send(vec![create_test_message()])).is_err());
+    }
+    
+    #[test]
+    fn test_health_check_fails_for_unreachable_destinations() {
+        let rt = Runtime::new().unwrap();
+        
+        // A file whose directory has gone away can no longer be written
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("cdc").join("out.json");
+        let sink = FileSink::new(file_path.to_str().unwrap(), false, 1000).unwrap();
+        rt.block_on(sink.health_check()).unwrap();
+        std::fs::remove_dir_all(dir.path().join("cdc")).unwrap();
+        assert!(matches!(rt.block_on(sink.health_check()), Err(Error::File(_))));
+        
+        // Nothing listens on port 1, so the broker never answers
+        let sink = KafkaSink::new("127.0.0.1:1", "cdc-events", None, 100, 1000, KafkaProducerOptions::default()).unwrap();
+        assert!(matches!(rt.block_on(sink.health_check()), Err(Error::Kafka(_))));
+        
+        // Sinks without a remote destination are always healthy
+        rt.block_on(NullSink::new().health_check()).unwrap();
+        rt.block_on(ConsoleSink::new(false).health_check()).unwrap();
+    }
+    
+    #[test]
+    fn test_group_by_key_preserves_per_key_order() {
+        let records = vec![
+            ("alice", 1), ("bob", 1), ("alice", 2), ("carol", 1), ("bob", 2), ("alice", 3),
+        ];
+        
+        let groups = group_by_key(records, |(key, _)| *key);
+        assert_eq!(groups, vec![
+            vec![("alice", 1), ("alice", 2), ("alice", 3)],
+            vec![("bob", 1), ("bob", 2)],
+            vec![("carol", 1)],
+        ]);
+    }
+    
+    #[test]
+    fn test_split_by_size_keeps_chunks_under_limit() {
+        let records = vec!["aaaa", "bb", "cccc", "dddddddd", "e"];
+        
+        let chunks = split_by_size(records, 6, |record| record.len());
+        assert_eq!(chunks, vec![
+            vec!["aaaa", "bb"],
+            vec!["cccc"],
+            // An oversized record is sent on its own
+            vec!["dddddddd"],
+            vec!["e"],
+        ]);
+        assert!(split_by_size(Vec::<&str>::new(), 6, |record| record.len()).is_empty());
+    }
+    
+    #[test]
+    fn test_kafka_flattened_message_format() {
+        let mut sink = KafkaSink::new("localhost:9092", "cdc-events", None, 100, 1000, KafkaProducerOptions::default()).unwrap();
+        sink.set_message_format(MessageFormat::Flattened);
+        
+        let create = create_test_message();
+        let mut delete = create_test_message();
+        delete.payload.operation = CdcOperation::Delete;
+        delete.payload.before = delete.payload.after.take();
+        let mut heartbeat = create_test_message();
+        heartbeat.payload.operation = CdcOperation::Heartbeat;
+        heartbeat.payload.after = None;
+        
+        let records = sink.records(&[create.clone(), delete, heartbeat]).unwrap();
+        
+        // A row change carries only its after row, with metadata in headers
+        assert_eq!(records[0].key, "test_key");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(records[0].value.as_ref().unwrap()).unwrap(),
+            serde_json::json!({ "field1": "value1", "field2": 42 })
+        );
+        assert_eq!(records[0].headers, vec![
+            ("__op", "c".to_string()),
+            ("__table", "test_table".to_string()),
+            ("__block_height", "123".to_string()),
+            ("__block_hash", create.header.block_hash.clone()),
+            ("__ts_ms", datetime_to_timestamp_ms(&create.header.timestamp).to_string()),
+        ]);
+        
+        // A delete is a tombstone for its key
+        assert_eq!(records[1].key, "test_key");
+        assert_eq!(records[1].value, None);
+        assert_eq!(records[1].headers[0], ("__op", "d".to_string()));
+        
+        // Control messages keep the envelope
+        let heartbeat: serde_json::Value = serde_json::from_str(records[2].value.as_ref().unwrap()).unwrap();
+        assert_eq!(heartbeat["payload"]["operation"], "heartbeat");
+        assert!(records[2].headers.is_empty());
+        
+        // The envelope format is unchanged
+        sink.set_message_format(MessageFormat::Envelope);
+        let records = sink.records(&[create]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(records[0].value.as_ref().unwrap()).unwrap();
+        assert_eq!(value["payload"]["after"]["field2"], 42);
+        assert!(records[0].headers.is_empty());
+    }
+    
+    #[test]
+    fn test_kafka_tombstones_follow_deletes() {
+        let mut sink = KafkaSink::new("localhost:9092", "cdc-events", None, 100, 1000, KafkaProducerOptions::default()).unwrap();
+        sink.set_table_keys(TableKeys::new(HashMap::from([
+            ("test_table".to_string(), vec!["address".to_string(), "token".to_string()]),
+        ])));
+        
+        let mut create = create_test_message();
+        create.payload.key = "alice:ordi".to_string();
+        let mut delete = create.clone();
+        delete.payload.operation = CdcOperation::Delete;
+        delete.payload.before = delete.payload.after.take();
+        
+        // Deletes keep their envelope unless tombstones are enabled
+        let records = sink.records(&[create.clone(), delete.clone()]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.value.is_some()));
+        
+        sink.set_emit_tombstones(true);
+        let records = sink.records(&[create.clone(), delete.clone()]).unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records[1].value.as_ref().unwrap().contains("\"delete\""));
+        assert_eq!(records[2], KafkaRecord {
+            topic: "cdc-events".to_string(),
+            key: "alice:ordi".to_string(),
+            value: None,
+            headers: Vec::new(),
+        });
+        
+        // A flattened delete is already a tombstone
+        sink.set_message_format(MessageFormat::Flattened);
+        let records = sink.records(&[delete]).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, "alice:ordi");
+        assert_eq!(records[0].value, None);
+    }
+    
+    #[test]
+    fn test_kafka_sink_close_is_final() {
+        // Creating the producer does not contact the broker
+        let sink = KafkaSink::new("localhost:9092", "cdc-events", None, 100, 1000, KafkaProducerOptions::default()).unwrap();
+        assert_close_is_final(&sink);
+    }
+    
+    /// A PostgreSQL sink with default settings
+    ///
+    /// Built directly, since the constructor connects to the database.
+    fn test_postgres_sink() -> PostgresSink {
+        PostgresSink {
+            connection_string: "postgres://localhost/unused".to_string(),
+            schema: "public".to_string(),
+            batch_size: 100,
+            flush_interval: 1000,
+            buffer: Arc::new(TokioMutex::new(Vec::new())),
+            table_keys: TableKeys::default(),
+            column_types: HashMap::new(),
+            route_by_operation: false,
+            max_batch_age: None,
+            buffered_since: Mutex::new(None),
+            sent_height: AtomicU32::new(0),
+            committed_height: AtomicU32::new(0),
+            close_state: CloseState::default(),
+        }
+    }
+    
+    #[test]
+    fn test_postgres_sink_close_is_final() {
+        let sink = test_postgres_sink();
+        assert_close_is_final(&sink);
+        
+        // Nothing was buffered, so dropping the sink never connects
+        assert!(sink.buffer.try_lock().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_failed_close_flushes_again_when_retried() {
+        // Writes to /dev/full are buffered, then fail when flushed
+        let sink = FileSink::new("/dev/full", false, 1000).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(sink.send(vec![create_test_message()])).unwrap();
+        
+        // Each close reports the failed flush instead of claiming to be closed
+        for _ in 0..2 {
+            match rt.block_on(sink.close()) {
+                Err(Error::File(message)) => assert!(message.contains("Failed to flush file"), "{}", message),
+                other => panic!("expected a flush error, got {:?}", other),
+            }
+        }
+        
+        // Sends stay refused while the close is unfinished
+        match rt.block_on(sink.send(vec![create_test_message()])) {
+            Err(Error::Sink(message)) => assert_eq!(message, "sink closed"),
+            other => panic!("expected a sink closed error, got {:?}", other),
+        }
+        
+        // Once the buffered message is gone, a retried close completes
+        sink.file.lock().unwrap().writer = BufWriter::new(tempfile::tempfile().unwrap());
+        rt.block_on(sink.close()).unwrap();
+        rt.block_on(sink.close()).unwrap();
+    }
+    
+    #[test]
+    fn test_postgres_column_type_coercion() {
+        use serde_json::json;
+        
+        let mut sink = test_postgres_sink();
+        sink.set_column_types(HashMap::from([(
+            "balances".to_string(),
+            HashMap::from([
+                ("id".to_string(), ColumnType::BigInt),
+                ("amount".to_string(), ColumnType::BigInt),
+                ("price".to_string(), ColumnType::Double),
+                ("script".to_string(), ColumnType::Bytea),
+                ("address".to_string(), ColumnType::Text),
+            ]),
+        )]));
+        
+        // Hex strings become bytea, with or without a 0x prefix
+        assert_eq!(
+            sink.column_value("balances", "script", &json!("0x76a914")).unwrap(),
+            SqlValue::Bytea(Some(vec![0x76, 0xa9, 0x14]))
+        );
+        assert_eq!(
+            sink.column_value("balances", "script", &json!("76A914")).unwrap(),
+            SqlValue::Bytea(Some(vec![0x76, 0xa9, 0x14]))
+        );
+        
+        // Numeric strings and numbers become numeric columns
+        assert_eq!(sink.column_value("balances", "amount", &json!("1000")).unwrap(), SqlValue::BigInt(Some(1000)));
+        assert_eq!(sink.column_value("balances", "amount", &json!(-7)).unwrap(), SqlValue::BigInt(Some(-7)));
+        assert_eq!(sink.column_value("balances", "price", &json!("2.5")).unwrap(), SqlValue::Double(Some(2.5)));
+        assert_eq!(sink.column_value("balances", "amount", &json!(null)).unwrap(), SqlValue::BigInt(None));
+        assert_eq!(sink.key_value("balances", "id", "42").unwrap(), SqlValue::BigInt(Some(42)));
+        
+        // Text columns drop the JSON quotes, undeclared columns keep the JSON text
+        assert_eq!(sink.column_value("balances", "address", &json!("bc1q")).unwrap(), SqlValue::Text(Some("bc1q".to_string())));
+        assert_eq!(sink.column_value("balances", "memo", &json!("hi")).unwrap(), SqlValue::Text(Some("\"hi\"".to_string())));
+        assert_eq!(sink.column_value("other", "amount", &json!(5)).unwrap(), SqlValue::Text(Some("5".to_string())));
+        
+        // Failed coercions name the value, column and type
+        let error = sink.column_value("balances", "amount", &json!("12abc")).unwrap_err();
+        assert!(!error.is_transient());
+        assert_eq!(error.to_string(), "Sink error: Cannot coerce value \"12abc\" of column amount to bigint");
+        assert!(sink.column_value("balances", "amount", &json!(1.5)).is_err());
+        assert!(sink.column_value("balances", "script", &json!("0xzz")).is_err());
+        assert!(sink.key_value("balances", "id", "alice").is_err());
+    }
+    
+    #[test]
+    fn test_serialize_messages_policy() {
+        let mut bad = create_test_message();
+        bad.payload.key = "bad_key".to_string();
+        let messages = vec![create_test_message(), bad, create_test_message()];
+        
+        // Fail serialization for the message with the bad key
+        let serialize = |message: &CdcMessage| {
+            if message.payload.key == "bad_key" {
+                Err(serde::ser::Error::custom("unserializable payload"))
+            } else {
+                serde_json::to_string(message)
+            }
+        };
+        
+        // Skipping delivers the rest of the batch
+        let serialized = serialize_messages(&messages, SerializationErrorPolicy::Skip, serialize).unwrap();
+        assert_eq!(serialized.len(), 2);
+        assert!(serialized.iter().all(|(message, json)| message.payload.key == "test_key" && json.contains("test_key")));
+        
+        // Failing aborts the batch
+        assert!(serialize_messages(&messages, SerializationErrorPolicy::Fail, serialize).is_err());
+    }
+
+    #[test]
+    fn test_kafka_sink_skips_messages_that_fail_to_serialize() {
+        let mut sink = KafkaSink::new("localhost:9092", "cdc-events", None, 100, 1000, KafkaProducerOptions::default()).unwrap();
+        sink.set_key_format(KeyFormat::Json);
+        sink.set_table_keys(TableKeys::new(HashMap::from([
+            ("test_table".to_string(), vec!["address".to_string(), "token".to_string()]),
+        ])));
+        
+        // A key without a segment per key column cannot become a JSON key
+        let mut good = create_test_message();
+        good.payload.key = "alice:ordi".to_string();
+        let mut bad = create_test_message();
+        bad.payload.key = "bob".to_string();
+        let rt = Runtime::new().unwrap();
+        
+        // Failing aborts the send before anything reaches the broker
+        let error = rt.block_on(sink.send(vec![good.clone(), bad.clone()])).unwrap_err();
+        assert!(error.to_string().contains("Failed to serialize message"));
+        
+        // Skipping builds records for the rest of the batch
+        sink.set_serialization_error_policy(SerializationErrorPolicy::Skip);
+        let records = sink.records(&[good.clone(), bad.clone(), good]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|record| record.value.as_ref().unwrap().contains("alice")));
+        
+        // A batch of only skipped messages sends nothing
+        rt.block_on(sink.send(vec![bad])).unwrap();
+    }
+    
+    #[test]
+    fn test_file_sink_flushes_on_drop() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("dropped.json");
+        
+        let sink = FileSink::new(file_path.to_str().unwrap(), false, 1000).unwrap();
+        let rt = Runtime::new().unwrap();
+        
+        // Send without flushing or closing
+        rt.block_on(sink.send(vec![create_test_message(), create_test_message()])).unwrap();
+        drop(sink);
+        
+        let contents = std::fs::read_to_string(file_path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.contains("test_key"));
+    }
+    
+    fn test_table_keys() -> TableKeys {
+        let mut tables = HashMap::new();
+        tables.insert("balances".to_string(), vec!["address".to_string(), "token".to_string()]);
+        TableKeys::new(tables)
+    }
+    
+    #[test]
+    fn test_single_column_table_keys() {
+        let table_keys = test_table_keys();
+        let columns = table_keys.columns("test_table");
+        
+        // Unlisted tables use the whole key as the id column
+        assert_eq!(columns, ["id".to_string()]);
+        assert_eq!(table_keys.split_key("test_table", "a:b").unwrap(), vec!["a:b"]);
+        
+        assert_eq!(
+            insert_query("public.test_table", &["id".to_string(), "value".to_string()], columns, 1),
+            "INSERT INTO public.test_table (id, value) VALUES ($1, $2) ON CONFLICT (id) DO UPDATE SET value = EXCLUDED.value"
+        );
+        assert_eq!(
+            update_query("public.test_table", &["value".to_string()], columns),
+            "UPDATE public.test_table SET value = $1 WHERE id = $2"
+        );
+        assert_eq!(delete_query("public.test_table", columns), "DELETE FROM public.test_table WHERE id = $1");
+    }
+    
+    #[test]
+    fn test_composite_table_keys() {
+        let table_keys = test_table_keys();
+        let columns = table_keys.columns("balances");
+        
+        assert_eq!(table_keys.split_key("balances", "bc1q:ordi").unwrap(), vec!["bc1q", "ordi"]);
+        assert_eq!(table_keys.split_key("balances", "bc1q:ordi:x").unwrap(), vec!["bc1q", "ordi:x"]);
+        assert!(table_keys.split_key("balances", "bc1q").is_err());
+        
+        assert_eq!(
+            insert_query("public.balances", &["address".to_string(), "token".to_string()], columns, 1),
+            "INSERT INTO public.balances (address, token) VALUES ($1, $2) ON CONFLICT (address, token) DO NOTHING"
+        );
+        assert_eq!(
+            update_query("public.balances", &["amount".to_string(), "updated".to_string()], columns),
+            "UPDATE public.balances SET amount = $1, updated = $2 WHERE address = $3 AND token = $4"
+        );
+        assert_eq!(
+            delete_query("public.balances", columns),
+            "DELETE FROM public.balances WHERE address = $1 AND token = $2"
+        );
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_postgres_batch_age_sets_flush_deadline() {
+        let mut sink = test_postgres_sink();
+        
+        // Without a maximum age, buffered messages wait for a full batch
+        sink.send(vec![create_test_message()]).await.unwrap();
+        assert_eq!(sink.flush_deadline(), None);
+        sink.buffer.lock().await.clear();
+        *sink.buffered_since() = None;
+        
+        // The deadline follows the oldest buffered message, not the newest
+        sink.set_max_batch_age(Some(Duration::from_millis(50)));
+        let started = tokio::time::Instant::now();
+        sink.send(vec![create_test_message()]).await.unwrap();
+        tokio::time::advance(Duration::from_millis(20)).await;
+        sink.send(vec![create_test_message()]).await.unwrap();
+        
+        assert_eq!(sink.buffer.lock().await.len(), 2);
+        assert_eq!(sink.flush_deadline(), Some(started + Duration::from_millis(50)));
+        
+        // Blocks sent while messages are buffered are not committed yet
+        sink.mark_sent(7);
+        assert_eq!(sink.committed_height(), Some(0));
+        
+        // Empty the buffer so dropping the sink never connects
+        sink.buffer.lock().await.clear();
+        *sink.buffered_since() = None;
+        
+        // With nothing outstanding, empty blocks are committed as they are sent
+        sink.send(Vec::new()).await.unwrap();
+        sink.mark_sent(8);
+        assert_eq!(sink.committed_height(), Some(8));
+    }
+    
+    #[test]
+    fn test_postgres_large_block_is_split_under_parameter_limit() {
+        let sink = test_postgres_sink();
+        let row = |key: String, operation: CdcOperation| {
+            let mut message = create_test_message();
+            message.payload.key = key;
+            message.payload.operation = operation;
+            message
+        };
+        
+        // 40,000 rows of three columns need 120,000 parameters
+        let messages: Vec<CdcMessage> = (0..40_000)
+            .map(|i| row(format!("key{}", i), CdcOperation::Create))
+            .collect();
+        let statements = sink.statements(&messages).unwrap();
+        
+        assert_eq!(statements.len(), 2);
+        assert!(statements.iter().all(|statement| statement.values.len() <= MAX_STATEMENT_PARAMS));
+        assert_eq!(statements.iter().map(|statement| statement.values.len()).sum::<usize>(), 120_000);
+        assert!(statements[0].query.starts_with("INSERT INTO public.test_table (field1, field2, id) VALUES ($1, $2, $3), ($4, $5, $6)"));
+        assert!(statements[0].query.contains(&format!("(${}, ${}, ${}) ON CONFLICT", MAX_STATEMENT_PARAMS - 2, MAX_STATEMENT_PARAMS - 1, MAX_STATEMENT_PARAMS)));
+        
+        // Other operations and repeated keys start a new INSERT, keeping the order
+        let messages = vec![
+            row("a".to_string(), CdcOperation::Create),
+            row("b".to_string(), CdcOperation::Create),
+            row("a".to_string(), CdcOperation::Create),
+            row("b".to_string(), CdcOperation::Delete),
+            row("c".to_string(), CdcOperation::Create),
+        ];
+        let actions: Vec<(&str, usize)> = sink.statements(&messages).unwrap()
+            .iter()
+            .map(|statement| (statement.action, statement.values.len()))
+            .collect();
+        assert_eq!(actions, vec![("INSERT", 6), ("INSERT", 3), ("DELETE", 1), ("INSERT", 3)]);
+    }
+    
+    #[test]
+    fn test_postgres_route_by_operation_appends_events() {
+        let mut sink = test_postgres_sink();
+        sink.set_route_by_operation(true);
+        
+        let create = create_test_message();
+        let mut update = create_test_message();
+        update.payload.operation = CdcOperation::Update;
+        let mut delete = create_test_message();
+        delete.payload.operation = CdcOperation::Delete;
+        delete.payload.before = delete.payload.after.take();
+        let mut heartbeat = create_test_message();
+        heartbeat.payload.operation = CdcOperation::Heartbeat;
+        
+        // Every operation inserts an event row into its own table
+        let queries: Vec<String> = sink.statements(&[create, update, delete, heartbeat]).unwrap()
+            .into_iter()
+            .map(|statement| statement.query)
+            .collect();
+        assert_eq!(queries, vec![
+            "INSERT INTO public.test_table_create (field1, field2, id) VALUES ($1, $2, $3)".to_string(),
+            "INSERT INTO public.test_table_update (field1, field2, id) VALUES ($1, $2, $3)".to_string(),
+            "INSERT INTO public.test_table_delete (field1, field2, id) VALUES ($1, $2, $3)".to_string(),
+        ]);
+    }
+    
+    #[test]
+    fn test_kafka_compression_type() {
+        let options = KafkaProducerOptions {
+            compression_type: CompressionType::Zstd,
+            ..Default::default()
+        };
+        let config = KafkaSink::producer_config("localhost:9092", None, &options);
+        assert_eq!(config.get("compression.type"), Some("zstd"));
+        
+        let config = KafkaSink::producer_config("localhost:9092", Some("debshrew"), &KafkaProducerOptions::default());
+        assert_eq!(config.get("compression.type"), Some("none"));
+        assert_eq!(config.get("client.id"), Some("debshrew"));
+    }
+    
+    #[test]
+    fn test_kafka_delivery_options() {
+        let config = KafkaSink::producer_config("localhost:9092", None, &KafkaProducerOptions::default());
+        assert_eq!(config.get("acks"), Some("all"));
+        assert_eq!(config.get("enable.idempotence"), Some("false"));
+        assert_eq!(config.get("retries"), None);
+        
+        let options = KafkaProducerOptions {
+            acks: Acks::Leader,
+            retries: Some(5),
+            ..Default::default()
+        };
+        let config = KafkaSink::producer_config("localhost:9092", None, &options);
+        assert_eq!(config.get("acks"), Some("1"));
+        assert_eq!(config.get("retries"), Some("5"));
+    }
+    
+    #[test]
+    fn test_json_key_format() {
+        let table_keys = test_table_keys();
+        let mut message = create_test_message();
+        message.payload.table = "balances".to_string();
+        message.payload.key = "bc1q:ordi".to_string();
+        
+        let json = serialize_with_key_format(&message, KeyFormat::Json, &table_keys, JsonOptions::default(), TimestampFormat::default()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["payload"]["key"], serde_json::json!({"address": "bc1q", "token": "ordi"}));
+        
+        // The timestamp format survives rewriting the key
+        let json = serialize_with_key_format(&message, KeyFormat::Json, &table_keys, JsonOptions::default(), TimestampFormat::EpochMillis).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["header"]["timestamp"], serde_json::json!(message.header.timestamp.timestamp_millis()));
+        
+        // The string format leaves the message untouched
+        let json = serialize_with_key_format(&message, KeyFormat::String, &table_keys, JsonOptions::default(), TimestampFormat::default()).unwrap();
+        assert_eq!(json, serde_json::to_string(&message).unwrap());
+        
+        // Sorted keys put `header` fields in alphabetical order
+        let options = JsonOptions { pretty: false, sort_keys: true };
+        let json = serialize_with_key_format(&message, KeyFormat::String, &table_keys, options, TimestampFormat::default()).unwrap();
+        assert!(json.starts_with(r#"{"header":{"block_hash":"#), "{}", json);
+        
+        // A key without a segment per column can't be serialized as JSON
+        message.payload.key = "bc1q".to_string();
+        assert!(serialize_with_key_format(&message, KeyFormat::Json, &table_keys, JsonOptions::default(), TimestampFormat::default()).is_err());
+    }
+    
+    /// A sink that fails a fixed number of sends before succeeding
+    struct FlakySink {
+        failures: std::sync::atomic::AtomicU32,
+        attempts: Arc<std::sync::atomic::AtomicU32>,
+        error: fn() -> Error,
+    }
+    
+    #[async_trait]
+    impl CdcSink for FlakySink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            use std::sync::atomic::Ordering;
+            
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err((self.error)());
+            }
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    fn retrying_sink(failures: u32, error: fn() -> Error, jitter: bool) -> (RetryingSink, Arc<std::sync::atomic::AtomicU32>) {
+        let attempts = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let inner = FlakySink {
+            failures: std::sync::atomic::AtomicU32::new(failures),
+            attempts: attempts.clone(),
+            error,
+        };
+        let config = RetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 2,
+            jitter,
+        };
+        (RetryingSink::new(Box::new(inner), &config), attempts)
+    }
+    
+    #[tokio::test]
+    async fn test_retrying_sink_retries_transient_errors() {
+        use std::sync::atomic::Ordering;
+        
+        let (sink, attempts) = retrying_sink(2, || Error::Kafka("broker unavailable".to_string()), false);
+        assert!(sink.send(vec![create_test_message()]).await.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        
+        // Attempts are bounded by the retry policy
+        let (sink, attempts) = retrying_sink(5, || Error::Kafka("broker unavailable".to_string()), false);
+        assert!(matches!(sink.send(vec![create_test_message()]).await, Err(Error::Kafka(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_retrying_sink_jitters_backoff() {
+        use std::sync::atomic::Ordering;
+        
+        // A Kafka send failing twice, as during a leader election, is
+        // delivered on the third attempt after two jittered delays
+        let (sink, attempts) = retrying_sink(2, || Error::Kafka("Local: Not leader for partition".to_string()), true);
+        
+        let started = tokio::time::Instant::now();
+        assert!(sink.send(vec![create_test_message()]).await.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        
+        // Between half and all of 1 + 2 ms
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_micros(1500) && elapsed <= Duration::from_millis(3), "{:?}", elapsed);
+        
+        // Delays stay within half of the backoff and the backoff itself
+        for _ in 0..100 {
+            let delay = sink.delay(Duration::from_millis(100));
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_retrying_sink_fails_fast_on_permanent_errors() {
+        use std::sync::atomic::Ordering;
+        
+        let (sink, attempts) = retrying_sink(1, || Error::Sink("Failed to serialize message".to_string()), false);
+        assert!(matches!(sink.send(vec![create_test_message()]).await, Err(Error::Sink(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
 }
\ No newline at end of file
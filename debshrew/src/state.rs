@@ -0,0 +1,468 @@
+//! Transform state persistence
+//!
+//! This module defines the on-disk format of a persisted transform state and
+//! helpers for inspecting it. The state is stored as a JSON object mapping
+//! hex-encoded keys to hex-encoded values, sorted by key. A checkpoint wraps
+//! the state with the block height it was taken at, so a restart can resume
+//! from it. An initial state can also be seeded from a bincode-encoded list
+//! of key/value pairs.
+
+use crate::error::{Error, Result};
+use debshrew_support::TransformState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Write a transform state to a file
+///
+/// The state is written to a temporary file which then replaces the target,
+/// so readers never see a partially written state.
+///
+/// # Arguments
+///
+/// * `path` - The path of the state file
+/// * `state` - The transform state
+///
+/// # Returns
+///
+/// Ok(()) if the state was written successfully
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be written
+pub fn save_state<P: AsRef<Path>>(path: P, state: &TransformState) -> Result<()> {
+    write_state_file(path.as_ref(), &StateFile::Entries(hex_entries(state)))
+}
+
+/// Write a transform state to a file as a checkpoint at a block height
+///
+/// The state is written to a temporary file which then replaces the target,
+/// so the height and the state are always updated together.
+///
+/// # Arguments
+///
+/// * `path` - The path of the state file
+/// * `height` - The height of the last block the state includes
+/// * `state` - The transform state
+///
+/// # Returns
+///
+/// Ok(()) if the checkpoint was written successfully
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be written
+pub fn save_checkpoint<P: AsRef<Path>>(path: P, height: u32, state: &TransformState) -> Result<()> {
+    write_state_file(path.as_ref(), &StateFile::Checkpoint { height, state: hex_entries(state) })
+}
+
+/// Replace a state file with new contents
+///
+/// # Arguments
+///
+/// * `path` - The path of the state file
+/// * `file` - The contents to write
+///
+/// # Returns
+///
+/// Ok(()) if the file was written successfully
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be written
+fn write_state_file(path: &Path, file: &StateFile) -> Result<()> {
+    let json = serde_json::to_string_pretty(file)?;
+    
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, json)
+        .map_err(|e| Error::File(format!("Failed to write state file: {}", e)))?;
+    fs::rename(&temp_path, path)
+        .map_err(|e| Error::File(format!("Failed to replace state file: {}", e)))?;
+    
+    Ok(())
+}
+
+/// Read a transform state from a file
+///
+/// # Arguments
+///
+/// * `path` - The path of the state file
+///
+/// # Returns
+///
+/// The transform state
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or is not a valid state file
+pub fn load_state<P: AsRef<Path>>(path: P) -> Result<TransformState> {
+    Ok(load_checkpoint(path)?.state)
+}
+
+/// Read a checkpoint from a state file
+///
+/// # Arguments
+///
+/// * `path` - The path of the state file
+///
+/// # Returns
+///
+/// The checkpoint, without a height if the file holds only a state
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or is not a valid state file
+pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<Checkpoint> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| Error::File(format!("Failed to read state file: {}", e)))?;
+    
+    serde_json::from_str::<StateFile>(&json)?.into_checkpoint()
+}
+
+/// A transform state read from a state file
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// The height of the last block the state includes, if recorded
+    pub height: Option<u32>,
+    
+    /// The transform state
+    pub state: TransformState,
+}
+
+/// Contents of a state file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StateFile {
+    /// A state checkpointed at a block height
+    Checkpoint {
+        /// The height of the last block the state includes
+        height: u32,
+        
+        /// The hex-encoded state entries
+        state: BTreeMap<String, String>,
+    },
+    
+    /// The hex-encoded state entries alone
+    Entries(BTreeMap<String, String>),
+}
+
+impl StateFile {
+    /// Decode the state file into a checkpoint
+    ///
+    /// # Returns
+    ///
+    /// The checkpoint
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a key or value is not valid hex
+    fn into_checkpoint(self) -> Result<Checkpoint> {
+        match self {
+            StateFile::Checkpoint { height, state } => Ok(Checkpoint {
+                height: Some(height),
+                state: state_from_hex_entries(state)?,
+            }),
+            StateFile::Entries(entries) => Ok(Checkpoint {
+                height: None,
+                state: state_from_hex_entries(entries)?,
+            }),
+        }
+    }
+}
+
+/// Hex-encode the entries of a transform state
+///
+/// # Arguments
+///
+/// * `state` - The transform state
+///
+/// # Returns
+///
+/// The hex-encoded keys and values, sorted by key
+fn hex_entries(state: &TransformState) -> BTreeMap<String, String> {
+    state.iter()
+        .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+        .collect()
+}
+
+/// Serialized format of a transform state file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateFormat {
+    /// A JSON object mapping hex-encoded keys to hex-encoded values, as
+    /// written by `save_state`, or a checkpoint written by `save_checkpoint`
+    Json,
+    
+    /// A bincode-encoded list of key/value pairs, as produced by
+    /// `debshrew_support::serialize`
+    Bincode,
+}
+
+impl StateFormat {
+    /// Detect the format of a serialized transform state
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the state file
+    ///
+    /// # Returns
+    ///
+    /// `Json` if the data is a JSON object, `Bincode` otherwise
+    pub fn detect(data: &[u8]) -> Self {
+        match serde_json::from_slice::<serde_json::Value>(data) {
+            Ok(value) if value.is_object() => StateFormat::Json,
+            _ => StateFormat::Bincode,
+        }
+    }
+}
+
+/// Read a transform state to seed the runtime with, detecting its format
+///
+/// # Arguments
+///
+/// * `path` - The path of the state file
+///
+/// # Returns
+///
+/// The transform state
+///
+/// # Errors
+///
+/// Returns an error if the state file cannot be read or is not a valid
+/// state in either format
+pub fn load_initial_state<P: AsRef<Path>>(path: P) -> Result<TransformState> {
+    let data = fs::read(path)
+        .map_err(|e| Error::File(format!("Failed to read initial state file: {}", e)))?;
+    
+    match StateFormat::detect(&data) {
+        StateFormat::Json => Ok(serde_json::from_slice::<StateFile>(&data)?.into_checkpoint()?.state),
+        StateFormat::Bincode => {
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = debshrew_support::deserialize(&data)
+                .map_err(|e| Error::File(format!("Initial state file is neither JSON nor bincode: {}", e)))?;
+            
+            let mut state = TransformState::new();
+            for (key, value) in entries {
+                state.set(key, value);
+            }
+            state.mark_clean();
+            
+            Ok(state)
+        }
+    }
+}
+
+/// Build a transform state from hex-encoded entries
+///
+/// # Arguments
+///
+/// * `entries` - The hex-encoded keys and values
+///
+/// # Returns
+///
+/// The transform state
+///
+/// # Errors
+///
+/// Returns an error if a key or value is not valid hex
+fn state_from_hex_entries(entries: BTreeMap<String, String>) -> Result<TransformState> {
+    let mut state = TransformState::new();
+    for (key, value) in entries {
+        let key = hex::decode(&key)
+            .map_err(|e| Error::File(format!("Invalid state key {}: {}", key, e)))?;
+        let value = hex::decode(&value)
+            .map_err(|e| Error::File(format!("Invalid state value: {}", e)))?;
+        state.set(key, value);
+    }
+    state.mark_clean();
+    
+    Ok(state)
+}
+
+/// Parse a state key given on the command line
+///
+/// Keys prefixed with `0x` are decoded as hex; anything else is taken as UTF-8.
+///
+/// # Arguments
+///
+/// * `key` - The key as typed by the user
+///
+/// # Returns
+///
+/// The key bytes
+///
+/// # Errors
+///
+/// Returns an error if a `0x`-prefixed key is not valid hex
+pub fn parse_key(key: &str) -> Result<Vec<u8>> {
+    match key.strip_prefix("0x") {
+        Some(hex_key) => hex::decode(hex_key)
+            .map_err(|e| Error::Generic(format!("Invalid hex key {}: {}", key, e))),
+        None => Ok(key.as_bytes().to_vec()),
+    }
+}
+
+/// Render bytes for display
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes to render
+///
+/// # Returns
+///
+/// The hex encoding, followed by the UTF-8 text if the bytes are valid UTF-8
+pub fn render_bytes(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => format!("0x{} ({:?})", hex::encode(bytes), text),
+        Err(_) => format!("0x{}", hex::encode(bytes)),
+    }
+}
+
+/// Render the value of a single state key
+///
+/// # Arguments
+///
+/// * `state` - The transform state
+/// * `key` - The key bytes
+///
+/// # Returns
+///
+/// The rendered value, or None if the key is not in the state
+pub fn get_entry(state: &TransformState, key: &[u8]) -> Option<String> {
+    state.get(key).map(|value| render_bytes(value))
+}
+
+/// Render all state entries whose key starts with a prefix
+///
+/// # Arguments
+///
+/// * `state` - The transform state
+/// * `prefix` - The key prefix
+///
+/// # Returns
+///
+/// One `key = value` line per entry, sorted by key
+pub fn list_entries(state: &TransformState, prefix: &[u8]) -> Vec<String> {
+    let mut keys: Vec<&Vec<u8>> = state.keys_with_prefix(prefix).collect();
+    keys.sort();
+    
+    keys.into_iter()
+        .filter_map(|key| state.get(key).map(|value| (key, value)))
+        .map(|(key, value)| format!("{} = {}", render_bytes(key), render_bytes(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    
+    #[test]
+    fn test_state_file_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        
+        let mut state = TransformState::new();
+        state.set(b"balance:alice".to_vec(), b"100".to_vec());
+        state.set(b"balance:bob".to_vec(), vec![0xff, 0x00]);
+        state.set(b"supply".to_vec(), b"1000".to_vec());
+        save_state(&path, &state).unwrap();
+        
+        let loaded = load_state(&path).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert!(!loaded.is_dirty());
+        
+        // Single keys, by text or hex
+        let key = parse_key("balance:alice").unwrap();
+        assert_eq!(get_entry(&loaded, &key), Some("0x313030 (\"100\")".to_string()));
+        let key = parse_key("0x737570706c79").unwrap();
+        assert_eq!(get_entry(&loaded, &key), Some("0x31303030 (\"1000\")".to_string()));
+        assert_eq!(get_entry(&loaded, b"missing"), None);
+        
+        // Prefix listing, with non-UTF-8 values shown as hex only
+        let lines = list_entries(&loaded, b"balance:");
+        assert_eq!(lines, vec![
+            "0x62616c616e63653a616c696365 (\"balance:alice\") = 0x313030 (\"100\")".to_string(),
+            "0x62616c616e63653a626f62 (\"balance:bob\") = 0xff00".to_string(),
+        ]);
+        
+        // A plain state file has no checkpoint height
+        assert_eq!(load_checkpoint(&path).unwrap().height, None);
+    }
+    
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        
+        let mut state = TransformState::new();
+        state.set(b"balance:alice".to_vec(), b"100".to_vec());
+        save_checkpoint(&path, 42, &state).unwrap();
+        
+        let checkpoint = load_checkpoint(&path).unwrap();
+        assert_eq!(checkpoint.height, Some(42));
+        assert_eq!(checkpoint.state.get(b"balance:alice"), Some(&b"100".to_vec()));
+        
+        // The state commands and seeding read checkpoints too
+        assert_eq!(load_state(&path).unwrap().len(), 1);
+        assert_eq!(load_initial_state(&path).unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn test_initial_state_is_seen_by_first_block() {
+        use debshrew_runtime::WasmRuntime;
+        
+        // A transform that copies the `seed` key to `copy`
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__get_state" (func $get_state (param i32) (result i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (import "env" "__load" (func $load (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\04\00\00\00seed")
+                (data (i32.const 16) "\04\00\00\00copy")
+                (func (export "process_block") (result i32)
+                    (local $len i32)
+                    (local.set $len (call $get_state (i32.const 0)))
+                    (if (i32.le_s (local.get $len) (i32.const 0))
+                        (then (return (i32.const -1))))
+                    (i32.store (i32.const 64) (local.get $len))
+                    (call $load (i32.const 68))
+                    (drop (call $set_state (i32.const 16) (i32.const 64)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        let dir = tempdir().unwrap();
+        let json_path = dir.path().join("state.json");
+        let bincode_path = dir.path().join("state.bin");
+        
+        let mut seed = TransformState::new();
+        seed.set(b"seed".to_vec(), b"42".to_vec());
+        save_state(&json_path, &seed).unwrap();
+        let pairs = vec![(b"seed".to_vec(), b"42".to_vec())];
+        fs::write(&bincode_path, debshrew_support::serialize(&pairs).unwrap()).unwrap();
+        
+        assert_eq!(StateFormat::detect(&fs::read(&json_path).unwrap()), StateFormat::Json);
+        assert_eq!(StateFormat::detect(&fs::read(&bincode_path).unwrap()), StateFormat::Bincode);
+        
+        for path in [&json_path, &bincode_path] {
+            let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+            runtime.set_state(load_initial_state(path).unwrap());
+            
+            let result = runtime.process_block(1, vec![0; 32]).unwrap();
+            assert_eq!(result.state_snapshot.get(b"copy"), Some(&b"42".to_vec()));
+        }
+        
+        // Garbage is rejected rather than seeding an empty state
+        let garbage_path = dir.path().join("garbage");
+        fs::write(&garbage_path, b"not a state").unwrap();
+        assert!(load_initial_state(&garbage_path).is_err());
+    }
+}
\ No newline at end of file
@@ -5,17 +5,276 @@
 
 use crate::block::BlockCache;
 use crate::client::MetashrewClient;
+use crate::config::{AdaptivePollingConfig, Delivery, Ordering};
 use crate::error::{Error, Result};
 use crate::sink::CdcSink;
+use crate::state::{load_checkpoint, save_checkpoint};
+use crate::wal::WriteAheadLog;
 use async_trait::async_trait;
 use chrono::Utc;
-use debshrew_runtime::WasmRuntime;
-use debshrew_support::BlockMetadata;
-use log::{debug, info, warn};
+use debshrew_runtime::{BlockFetcher, RollbackMode, ViewCacheStats, ViewFetcher, WasmRuntime};
+use futures::future::try_join_all;
+use debshrew_support::{BlockMetadata, CdcHeader, CdcMessage, CdcOperation, CdcPayload};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicBool};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex, Notify};
 use tokio::time;
+use tokio_util::sync::CancellationToken;
+
+/// Upper bounds of the reorg depth histogram buckets
+///
+/// A reorg is counted in the first bucket its depth fits, and reorgs deeper
+/// than the last bound in a final overflow bucket.
+pub const REORG_DEPTH_BUCKETS: [u32; 5] = [1, 2, 3, 6, 12];
+
+/// Synchronization statistics
+///
+/// Running totals kept by the block synchronizer and printed as a summary on
+/// shutdown. Reorg depths and the view cache hit ratio are also published
+/// through the `metrics` facade, as `debshrew_reorg_depth` and
+/// `debshrew_view_cache_hit_ratio`.
+#[derive(Debug, Clone)]
+pub struct SyncStats {
+    /// Number of blocks processed, including blocks reprocessed after a reorg
+    pub blocks_processed: u64,
+    
+    /// Number of chain reorganizations handled
+    pub reorgs: u64,
+    
+    /// Number of reorgs by depth, bucketed by `REORG_DEPTH_BUCKETS`
+    pub reorg_depths: [u64; REORG_DEPTH_BUCKETS.len() + 1],
+    
+    /// View cache usage of the transform runtime
+    pub view_cache: ViewCacheStats,
+    
+    /// Number of CDC messages sent to the sink, by table and operation
+    pub messages: HashMap<(String, CdcOperation), u64>,
+    
+    /// Number of blocks whose cached state snapshot differed from the runtime state
+    pub state_divergences: u64,
+    
+    /// When the synchronizer started
+    started_at: Instant,
+}
+
+impl SyncStats {
+    /// Create empty statistics starting now
+    ///
+    /// # Returns
+    ///
+    /// New synchronization statistics
+    pub fn new() -> Self {
+        Self {
+            blocks_processed: 0,
+            reorgs: 0,
+            reorg_depths: [0; REORG_DEPTH_BUCKETS.len() + 1],
+            view_cache: ViewCacheStats::default(),
+            messages: HashMap::new(),
+            state_divergences: 0,
+            started_at: Instant::now(),
+        }
+    }
+    
+    /// Count CDC messages sent to the sink
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The CDC messages that were sent
+    pub fn record_messages(&mut self, messages: &[CdcMessage]) {
+        for message in messages {
+            *self.messages
+                .entry((message.payload.table.clone(), message.payload.operation))
+                .or_insert(0) += 1;
+        }
+    }
+    
+    /// Count a handled reorg
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - The number of blocks rolled back, from the local tip down
+    ///   to the common ancestor
+    pub fn record_reorg(&mut self, depth: u32) {
+        self.reorgs += 1;
+        
+        let bucket = REORG_DEPTH_BUCKETS.iter()
+            .position(|bound| depth <= *bound)
+            .unwrap_or(REORG_DEPTH_BUCKETS.len());
+        self.reorg_depths[bucket] += 1;
+        
+        metrics::histogram!("debshrew_reorg_depth", depth as f64);
+    }
+    
+    /// Record the view cache usage of the transform runtime
+    ///
+    /// # Arguments
+    ///
+    /// * `view_cache` - The runtime's view cache counters
+    pub fn record_view_cache(&mut self, view_cache: ViewCacheStats) {
+        self.view_cache = view_cache;
+        
+        if let Some(ratio) = self.view_cache_hit_ratio() {
+            metrics::gauge!("debshrew_view_cache_hit_ratio", ratio);
+        }
+    }
+    
+    /// Get the share of view calls answered from the view cache
+    ///
+    /// # Returns
+    ///
+    /// The hit ratio between 0 and 1, or None if no view was called
+    pub fn view_cache_hit_ratio(&self) -> Option<f64> {
+        let calls = self.view_cache.hits + self.view_cache.misses;
+        (calls > 0).then(|| self.view_cache.hits as f64 / calls as f64)
+    }
+    
+    /// Get the total number of CDC messages sent to the sink
+    ///
+    /// # Returns
+    ///
+    /// The total number of CDC messages
+    pub fn total_messages(&self) -> u64 {
+        self.messages.values().sum()
+    }
+    
+    /// Get the time elapsed since the synchronizer started
+    ///
+    /// # Returns
+    ///
+    /// The elapsed time
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+}
+
+impl Default for SyncStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for SyncStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Synchronization summary")?;
+        writeln!(f, "  Elapsed: {:.1}s", self.elapsed().as_secs_f64())?;
+        writeln!(f, "  Blocks processed: {}", self.blocks_processed)?;
+        writeln!(f, "  Reorgs: {}", self.reorgs)?;
+        
+        // Only buckets with reorgs, labelled by the depths they cover
+        let mut lower = 1;
+        for (index, count) in self.reorg_depths.iter().enumerate() {
+            let label = match REORG_DEPTH_BUCKETS.get(index) {
+                Some(&upper) if upper == lower => upper.to_string(),
+                Some(&upper) => format!("{}-{}", lower, upper),
+                None => format!("{}+", lower),
+            };
+            if *count > 0 {
+                writeln!(f, "    depth {}: {}", label, count)?;
+            }
+            lower = REORG_DEPTH_BUCKETS.get(index).map_or(lower, |upper| upper + 1);
+        }
+        
+        if let Some(ratio) = self.view_cache_hit_ratio() {
+            writeln!(
+                f,
+                "  View cache hit ratio: {:.1}% ({} hits, {} misses)",
+                ratio * 100.0,
+                self.view_cache.hits,
+                self.view_cache.misses
+            )?;
+        }
+        
+        if self.state_divergences > 0 {
+            writeln!(f, "  State snapshot divergences: {}", self.state_divergences)?;
+        }
+        
+        write!(f, "  CDC messages sent: {}", self.total_messages())?;
+        
+        // List counts by table and operation in a stable order
+        let mut counts: Vec<_> = self.messages.iter()
+            .map(|((table, operation), count)| (table.as_str(), operation.as_str(), count))
+            .collect();
+        counts.sort();
+        
+        for (table, operation, count) in counts {
+            write!(f, "\n    {} {}: {}", table, operation, count)?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// Handle for stopping a running synchronizer
+///
+/// Unlike `BlockSynchronizer::stop`, a handle does not need access to the
+/// synchronizer, so it can be used while `run` is in progress.
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    /// Whether the synchronizer is running
+    running: Arc<AtomicBool>,
+    
+    /// Wakes the synchronizer while it waits to retry a block
+    stop: Arc<Notify>,
+}
+
+impl StopHandle {
+    /// Stop the synchronizer once its current step completes
+    ///
+    /// A block waiting to be retried is abandoned rather than waited for.
+    pub fn stop(&self) {
+        self.running.store(false, atomic::Ordering::SeqCst);
+        self.stop.notify_waiters();
+    }
+}
+
+/// Outcome of a single synchronization step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// Metashrew had no new blocks
+    Idle,
+    
+    /// New blocks were processed
+    Processed {
+        /// The first block processed
+        from: u32,
+        
+        /// The last block processed
+        to: u32,
+    },
+    
+    /// A chain reorganization was handled
+    Reorg {
+        /// The block height after handling the reorg
+        height: u32,
+    },
+    
+    /// Metashrew is still indexing, so no blocks were processed
+    WaitingForSync {
+        /// The highest block metashrew has indexed
+        indexed_height: u32,
+        
+        /// The height of the chain metashrew is indexing
+        chain_height: u32,
+    },
+}
+
+/// Default table name of heartbeat messages
+pub const DEFAULT_HEARTBEAT_TABLE: &str = "debshrew_heartbeat";
+
+/// Table name of reorg marker messages
+pub const REORG_TABLE: &str = "debshrew_reorg";
+
+/// Longest wait before retrying a block, whatever delay the transform asks for
+pub const MAX_BLOCK_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Most times the chain tip is polled while waiting for it to settle
+pub const MAX_SETTLE_POLLS: u32 = 10;
 
 /// Block synchronizer
 ///
@@ -37,14 +296,95 @@ pub struct BlockSynchronizer<C: MetashrewClient> {
     /// The current block height
     current_height: u32,
     
-    /// Whether the synchronizer is running
-    running: bool,
+    /// Whether the synchronizer is running, shared with its stop handles
+    ///
+    /// Only cleared by a stop, so a block retried outside `run` is not
+    /// abandoned.
+    running: Arc<AtomicBool>,
+    
+    /// Notified when a stop handle stops the synchronizer
+    stop: Arc<Notify>,
+    
+    /// Cancels `run_with_token` between blocks and while sleeping
+    cancellation: CancellationToken,
+    
+    /// Set once `shutdown` has flushed and closed the sink
+    stopped: watch::Sender<bool>,
     
     /// The polling interval in milliseconds
     polling_interval: u64,
+    
+    /// Bounds for adapting the polling interval to the chain tip
+    adaptive_polling: Option<AdaptivePollingConfig>,
+    
+    /// The number of blocks to accumulate before sending to the sink
+    blocks_per_flush: Option<u32>,
+    
+    /// CDC messages of processed blocks not yet sent to the sink, by height
+    pending: Vec<(u32, Vec<CdcMessage>)>,
+    
+    /// The maximum number of blocks buffered or sent but not yet flushed
+    max_inflight_blocks: Option<u32>,
+    
+    /// The maximum number of blocks processed in one step
+    max_blocks_per_iteration: Option<u32>,
+    
+    /// Whether the last step stopped short of the metashrew tip
+    catching_up: bool,
+    
+    /// The number of blocks sent to the sink since it was last flushed
+    unflushed_blocks: u32,
+    
+    /// Whether the blocks of a batch are sent in order or concurrently
+    ordering: Ordering,
+    
+    /// Whether the checkpoint is written after or before the sink accepts a block
+    delivery: Delivery,
+    
+    /// Synchronization statistics
+    stats: SyncStats,
+    
+    /// The maximum time to spend handling a single reorg, in milliseconds
+    reorg_timeout: Option<u64>,
+    
+    /// When the reorg being handled must be done by
+    reorg_deadline: Option<time::Instant>,
+    
+    /// How long to let the chain tip settle before handling a reorg
+    reorg_settle: Option<Duration>,
+    
+    /// How many times a block may be retried before giving up on it
+    max_block_retries: Option<u32>,
+    
+    /// The write-ahead log that CDC messages are recorded in before sending
+    wal: Option<WriteAheadLog>,
+    
+    /// The file the transform state is persisted to after each block
+    state_path: Option<PathBuf>,
+    
+    /// How long without CDC messages before a heartbeat is emitted
+    heartbeat_interval: Option<Duration>,
+    
+    /// The table name of heartbeat messages
+    heartbeat_table: String,
+    
+    /// When CDC messages or a heartbeat were last sent to the sink
+    last_activity: time::Instant,
+    
+    /// Whether each block is processed twice to check the transform is deterministic
+    validate_determinism: bool,
+    
+    /// Whether the cached state snapshot is compared with the runtime state after each block
+    verify_state_snapshots: bool,
+    
+    /// Whether the inverse messages of a reorg are bracketed by markers
+    reorg_markers: bool,
+    
+    /// How the blocks rolled back by a reorg are undone
+    rollback_mode: RollbackMode,
 }
 
-impl<C: MetashrewClient> BlockSynchronizer<C> {
+impl<C: MetashrewClient + 'static> BlockSynchronizer<C> {
     /// Create a new block synchronizer
     ///
     /// # Arguments
@@ -70,8 +410,34 @@ impl<C: MetashrewClient> BlockSynchronizer<C> {
             sink: Arc::new(sink),
             cache: Arc::new(Mutex::new(cache)),
             current_height: 0,
-            running: false,
+            running: Arc::new(AtomicBool::new(true)),
+            stop: Arc::new(Notify::new()),
+            cancellation: CancellationToken::new(),
+            stopped: watch::channel(false).0,
             polling_interval: 1000,
+            adaptive_polling: None,
+            blocks_per_flush: None,
+            pending: Vec::new(),
+            max_inflight_blocks: None,
+            max_blocks_per_iteration: None,
+            catching_up: false,
+            unflushed_blocks: 0,
+            ordering: Ordering::Strict,
+            delivery: Delivery::AtLeastOnce,
+            stats: SyncStats::new(),
+            reorg_timeout: None,
+            reorg_deadline: None,
+            reorg_settle: None,
+            max_block_retries: None,
+            wal: None,
+            state_path: None,
+            heartbeat_interval: None,
+            heartbeat_table: DEFAULT_HEARTBEAT_TABLE.to_string(),
+            last_activity: time::Instant::now(),
+            validate_determinism: false,
+            verify_state_snapshots: false,
+            reorg_markers: false,
+            rollback_mode: RollbackMode::Auto,
         })
     }
     
@@ -84,458 +450,4094 @@ impl<C: MetashrewClient> BlockSynchronizer<C> {
         self.polling_interval = interval;
     }
     
-    /// Set the starting block height
+    /// Get the polling interval
     ///
-    /// # Arguments
+    /// # Returns
     ///
-    /// * `height` - The starting block height
-    pub fn set_starting_height(&mut self, height: u32) {
-        self.current_height = height;
+    /// The current polling interval in milliseconds
+    pub fn polling_interval(&self) -> u64 {
+        self.polling_interval
     }
     
-    /// Run the block synchronizer
-    ///
-    /// This method starts the block synchronizer and runs until stopped.
-    ///
-    /// # Returns
+    /// Set the bounds for adapting the polling interval to the chain tip
     ///
-    /// Ok(()) if the synchronizer ran successfully
+    /// With `Some`, the polling interval halves after each step that
+    /// processed blocks and doubles after each step that found none, staying
+    /// within the bounds. With `None`, the polling interval is fixed.
     ///
-    /// # Errors
+    /// # Arguments
     ///
-    /// Returns an error if the synchronizer encounters an error
-    pub async fn run(&mut self) -> Result<()> {
-        self.running = true;
+    /// * `adaptive_polling` - The polling interval bounds
+    pub fn set_adaptive_polling(&mut self, adaptive_polling: Option<AdaptivePollingConfig>) {
+        self.adaptive_polling = adaptive_polling;
         
-        // If the current height is 0, get the latest height from metashrew
-        if self.current_height == 0 {
-            self.current_height = self.client.get_height().await?;
-            info!("Starting at block height {}", self.current_height);
-        }
-        
-        // Main synchronization loop
-        while self.running {
-            // Poll metashrew for the latest height
-            let metashrew_height = self.client.get_height().await?;
-            
-            // Check if we need to process new blocks
-            if metashrew_height > self.current_height {
-                info!("Processing blocks {} to {}", self.current_height + 1, metashrew_height);
-                
-                // Process new blocks
-                for height in (self.current_height + 1)..=metashrew_height {
-                    self.process_block(height).await?;
-                    self.current_height = height;
-                }
-            } else if metashrew_height < self.current_height {
-                // Handle reorg
-                warn!("Chain reorganization detected: metashrew height {} < current height {}", metashrew_height, self.current_height);
-                self.handle_reorg(metashrew_height).await?;
-                self.current_height = metashrew_height;
-            }
-            
-            // Sleep for the polling interval
-            time::sleep(Duration::from_millis(self.polling_interval)).await;
+        if let Some(bounds) = adaptive_polling {
+            self.polling_interval = self.polling_interval.clamp(bounds.min_interval_ms, bounds.max_interval_ms);
         }
-        
-        Ok(())
     }
     
-    /// Stop the block synchronizer
-    pub fn stop(&mut self) {
-        self.running = false;
+    /// Set the number of blocks to accumulate before sending to the sink
+    ///
+    /// With `None`, the CDC messages of each block are sent as soon as the
+    /// block is processed. With `Some(n)`, messages are buffered across `n`
+    /// blocks and then sent and flushed together.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The number of blocks per flush
+    pub fn set_blocks_per_flush(&mut self, blocks: Option<u32>) {
+        self.blocks_per_flush = blocks;
     }
     
-    /// Process a block
+    /// Set the maximum number of blocks whose CDC messages may be in flight
     ///
-    /// # Arguments
+    /// A block is in flight from when it is processed until the sink has
+    /// flushed its messages. Once the limit is reached, block processing
+    /// waits for the sink to send and flush what it holds before continuing,
+    /// which bounds memory during a fast backfill with a slow sink.
     ///
-    /// * `height` - The block height
+    /// # Arguments
     ///
-    /// # Returns
+    /// * `blocks` - The maximum number of blocks in flight, or None for no limit
+    pub fn set_max_inflight_blocks(&mut self, blocks: Option<u32>) {
+        self.max_inflight_blocks = blocks;
+    }
+    
+    /// Set the order in which the blocks of a batch are sent to the sink
     ///
-    /// Ok(()) if the block was processed successfully
+    /// Under `Relaxed`, each block's messages are sent separately and the
+    /// sends are awaited concurrently.
     ///
-    /// # Errors
+    /// # Arguments
     ///
-    /// Returns an error if the block cannot be processed
-    async fn process_block(&self, height: u32) -> Result<()> {
-        // Get the block hash
-        let hash = self.client.get_block_hash(height).await?;
-        
-        // Create block metadata
-        let metadata = BlockMetadata {
-            height,
-            hash: hex::encode(&hash),
-            timestamp: Utc::now(),
-        };
-        
-        // Process the block with the transform module
-        let mut runtime = self.runtime.lock().await;
-        let transform_result = runtime.process_block(height, hash)?;
-        
-        // Add the block to the cache
-        let mut cache = self.cache.lock().await;
-        cache.add_block(metadata, transform_result.clone())?;
-        
-        // Send the CDC messages to the sink
-        self.sink.send(transform_result.cdc_messages).await?;
-        
-        debug!("Processed block {}", height);
-        
-        Ok(())
+    /// * `ordering` - The ordering
+    pub fn set_ordering(&mut self, ordering: Ordering) {
+        self.ordering = ordering;
     }
     
-    /// Handle a chain reorganization
+    /// Set the delivery guarantee
     ///
-    /// # Arguments
+    /// Decides whether the transform state is saved and the write-ahead log
+    /// truncated after the sink accepts a block's messages, or the state is
+    /// saved before they are sent. At-most-once delivery does not record
+    /// messages in the write-ahead log.
     ///
-    /// * `new_height` - The new block height
+    /// # Arguments
     ///
-    /// # Returns
+    /// * `delivery` - The delivery guarantee
+    pub fn set_delivery(&mut self, delivery: Delivery) {
+        self.delivery = delivery;
+    }
+    
+    /// Set the deadline for handling a single reorg
     ///
-    /// Ok(()) if the reorg was handled successfully
+    /// The deadline is checked between steps of the reorg (fetching hashes
+    /// and reprocessing blocks) and bounds each metashrew request made while
+    /// handling it. When it passes, reorg handling stops with an error,
+    /// leaving the synchronizer at the last fully processed block.
     ///
-    /// # Errors
+    /// # Arguments
     ///
-    /// Returns an error if the reorg cannot be handled
-    async fn handle_reorg(&self, new_height: u32) -> Result<()> {
-        // Get the block hashes for the new chain
-        let mut new_hashes = Vec::new();
-        for height in 0..=new_height {
-            let hash = self.client.get_block_hash(height).await?;
-            new_hashes.push((height, hex::encode(&hash)));
-        }
-        
-        // Find the common ancestor
-        let cache = self.cache.lock().await;
-        let common_ancestor = cache.find_common_ancestor(&new_hashes)
-            .ok_or_else(|| Error::ReorgHandling("No common ancestor found".to_string()))?;
-        
-        info!("Found common ancestor at height {}", common_ancestor);
-        
-        // Get the state snapshot at the common ancestor
-        let state_snapshot = cache.get_state_snapshot(common_ancestor)
-            .ok_or_else(|| Error::ReorgHandling(format!("State snapshot not found for height {}", common_ancestor)))?;
-        
-        // Release the cache lock
-        drop(cache);
-        
-        // Get the runtime lock
-        let mut runtime = self.runtime.lock().await;
-        
-        // Generate inverse CDC messages for the rolled back blocks
-        let mut inverse_messages = Vec::new();
-        
-        // Process blocks in reverse order from current_height down to common_ancestor + 1
-        for height in (common_ancestor + 1..=self.current_height).rev() {
-            info!("Generating inverse CDC messages for block {}", height);
-            
-            // Compute inverse messages for this block
-            let block_inverse = runtime.compute_inverse_messages(height)?;
-            inverse_messages.extend(block_inverse);
-        }
-        
-        // Reset the runtime state to the common ancestor
-        runtime.set_current_height(common_ancestor);
-        runtime.set_state(state_snapshot);
-        
-        // Send the inverse CDC messages to the sink
-        if !inverse_messages.is_empty() {
-            info!("Sending {} inverse CDC messages to sink", inverse_messages.len());
-            self.sink.send(inverse_messages).await?;
-        }
-        
-        // Roll back the cache
-        let mut cache = self.cache.lock().await;
-        cache.rollback(common_ancestor)?;
-        
-        // Release the runtime lock
-        drop(runtime);
-        
-        // Process the new chain
-        for height in (common_ancestor + 1)..=new_height {
-            // Release the cache lock
-            drop(cache);
-            
-            // Process the block
-            self.process_block(height).await?;
-            
-            // Reacquire the cache lock
-            cache = self.cache.lock().await;
-        }
-        
-        Ok(())
+    /// * `timeout` - The reorg timeout in milliseconds, or None for no limit
+    pub fn set_reorg_timeout(&mut self, timeout: Option<u64>) {
+        self.reorg_timeout = timeout;
     }
     
-    /// Get the current block height
+    /// Set how long to let the chain tip settle before handling a reorg
     ///
-    /// # Returns
+    /// While metashrew flaps between forks, acting on every change would
+    /// roll back and reprocess the same blocks over and over. With a settle
+    /// time, a detected reorg waits that long and checks the tip again,
+    /// repeating until the tip stops changing, so the flaps coalesce into a
+    /// single reorg to the settled tip. A tip that is still changing after
+    /// `MAX_SETTLE_POLLS` polls is handled as it is.
     ///
-    /// The current block height
-    pub fn get_current_height(&self) -> u32 {
-        self.current_height
+    /// # Arguments
+    ///
+    /// * `settle` - The settle time in milliseconds, or None to handle reorgs immediately
+    pub fn set_reorg_settle(&mut self, settle: Option<u64>) {
+        self.reorg_settle = settle.map(Duration::from_millis);
     }
     
-    /// Get the block cache
+    /// Set how many times a block may be retried
     ///
-    /// # Returns
+    /// A block is retried when a view fails inside the transform or the
+    /// transform asks for a retry. Once the limit is reached, processing the
+    /// block fails instead of holding up the synchronizer indefinitely.
     ///
-    /// The block cache
-    pub async fn get_cache(&self) -> Arc<Mutex<BlockCache>> {
-        self.cache.clone()
+    /// # Arguments
+    ///
+    /// * `max_retries` - The maximum number of retries, or None to keep retrying
+    pub fn set_max_block_retries(&mut self, max_retries: Option<u32>) {
+        self.max_block_retries = max_retries;
     }
     
-    /// Get the WASM runtime
+    /// Set the write-ahead log
     ///
-    /// # Returns
+    /// With a write-ahead log, CDC messages are recorded on disk before they
+    /// are sent to the sink and removed once the sink has accepted and flushed
+    /// them. Entries left over from a crash are replayed when `run` starts.
     ///
-    /// The WASM runtime
-    pub async fn get_runtime(&self) -> Arc<Mutex<WasmRuntime>> {
-        self.runtime.clone()
+    /// # Arguments
+    ///
+    /// * `wal` - The write-ahead log
+    pub fn set_wal(&mut self, wal: WriteAheadLog) {
+        self.wal = Some(wal);
     }
     
-    /// Get the CDC sink
+    /// Set whether to check that the transform is deterministic
     ///
-    /// # Returns
+    /// When enabled, each block is processed twice and synchronization fails
+    /// with a diff if the two runs produce different CDC messages.
     ///
-    /// The CDC sink
-    pub fn get_sink(&self) -> Arc<Box<dyn CdcSink>> {
-        self.sink.clone()
+    /// # Arguments
+    ///
+    /// * `validate` - Whether to process each block twice
+    pub fn set_validate_determinism(&mut self, validate: bool) {
+        self.validate_determinism = validate;
     }
     
-    /// Get the metashrew client
+    /// Set whether to check the cached state snapshots
     ///
-    /// # Returns
+    /// When enabled, a digest of the runtime state is compared with the
+    /// snapshot cached for each block after it is processed. A mismatch is
+    /// logged as an error and counted in the statistics, since a reorg would
+    /// roll back to the wrong state.
     ///
-    /// The metashrew client
-    pub fn get_client(&self) -> Arc<C> {
-        self.client.clone()
+    /// # Arguments
+    ///
+    /// * `verify` - Whether to compare the snapshots
+    pub fn set_verify_state_snapshots(&mut self, verify: bool) {
+        self.verify_state_snapshots = verify;
     }
-}
-
-/// Synchronizer trait
-///
-/// This trait defines the interface for block synchronizers.
-#[async_trait]
-pub trait Synchronizer: Send + Sync {
-    /// Run the synchronizer
+    
+    /// Set whether to bracket the output of a reorg with marker messages
+    ///
+    /// When enabled, a `reorg_begin` control message on the `debshrew_reorg`
+    /// table is sent before the inverse messages of a reorg, and a
+    /// `reorg_end` after them, so consumers can tell where a reorg starts
+    /// and ends in the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether to send reorg markers
+    pub fn set_reorg_markers(&mut self, enabled: bool) {
+        self.reorg_markers = enabled;
+    }
+    
+    /// Set how the blocks rolled back by a reorg are undone
+    ///
+    /// `Auto`, the default, sends the inverse of the CDC messages cached for
+    /// each rolled-back block that was sent. `Manual` calls the transform's
+    /// `rollback` export instead and sends the messages it pushes.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The rollback mode
     ///
     /// # Returns
     ///
-    /// Ok(()) if the synchronizer ran successfully
+    /// Ok(()) if the mode was set
     ///
     /// # Errors
     ///
-    /// Returns an error if the synchronizer encounters an error
-    async fn run(&mut self) -> Result<()>;
+    /// Returns an error if the mode is manual and the transform does not
+    /// export `rollback`
+    pub async fn set_rollback_mode(&mut self, mode: RollbackMode) -> Result<()> {
+        self.runtime.lock().await.set_rollback_mode(mode).map_err(Error::runtime)?;
+        self.rollback_mode = mode;
+        Ok(())
+    }
     
-    /// Stop the synchronizer
-    fn stop(&mut self);
+    /// Set the file to persist the transform state to
+    ///
+    /// The state is written after every processed block and after a reorg
+    /// rollback, so it can be inspected with `debshrew state`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The state file path, or None to disable persistence
+    pub fn set_state_path(&mut self, path: Option<PathBuf>) {
+        self.state_path = path;
+    }
     
-    /// Get the current block height
+    /// Set the heartbeat interval
     ///
-    /// # Returns
+    /// When set, a heartbeat control message carrying the current height is
+    /// sent to the sink whenever no CDC messages have been sent for the
+    /// interval. Heartbeats are held back while blocks are buffered for
+    /// `blocks_per_flush`.
     ///
-    /// The current block height
-    fn get_current_height(&self) -> u32;
-}
-
-#[async_trait]
-impl<C: MetashrewClient> Synchronizer for BlockSynchronizer<C> {
-    async fn run(&mut self) -> Result<()> {
-        self.run().await
+    /// # Arguments
+    ///
+    /// * `interval` - The heartbeat interval in milliseconds, or None to disable heartbeats
+    pub fn set_heartbeat_interval(&mut self, interval: Option<u64>) {
+        self.heartbeat_interval = interval.map(Duration::from_millis);
     }
     
-    fn stop(&mut self) {
-        self.stop();
+    /// Set the table name of heartbeat messages
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The heartbeat table name
+    pub fn set_heartbeat_table(&mut self, table: &str) {
+        self.heartbeat_table = table.to_string();
     }
     
-    fn get_current_height(&self) -> u32 {
-        self.get_current_height()
+    /// Set the maximum number of blocks processed in one step
+    ///
+    /// When metashrew is far ahead, a step processes only this many blocks
+    /// and `run` checks whether it was stopped before going straight on to
+    /// the next chunk.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The maximum number of blocks per step, or None for no limit
+    pub fn set_max_blocks_per_iteration(&mut self, blocks: Option<u32>) {
+        self.max_blocks_per_iteration = blocks;
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::client::MockMetashrewClient;
-    use crate::sink::{ConsoleSink, FileSink, NullSink};
-    use debshrew_runtime::MockTransform;
-    use debshrew_support::{CdcHeader, CdcMessage, CdcOperation, CdcPayload};
-    use std::sync::Arc;
-    use tempfile::tempdir;
-    use tokio::runtime::Runtime;
-
-    #[test]
-    fn test_block_synchronizer() {
-        // Create a mock metashrew client
-        let mut client = MockMetashrewClient::new();
-        client.set_height(10);
+    
+    /// Set the starting block height
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The starting block height
+    pub fn set_starting_height(&mut self, height: u32) {
+        self.current_height = height;
+    }
+    
+    /// Run the block synchronizer
+    ///
+    /// This method starts the block synchronizer and runs until stopped.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer ran successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the synchronizer encounters an error
+    pub async fn run(&mut self) -> Result<()> {
+        self.run_with_token(CancellationToken::new()).await
+    }
+    
+    /// Run the block synchronizer until stopped or cancelled
+    ///
+    /// Lets an embedding application coordinate shutdown with its own
+    /// cancellation token. The token is checked after each block and
+    /// interrupts the polling sleep, so cancelling returns promptly once the
+    /// block in progress is processed, after sending anything buffered.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The token that cancels the run
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer ran successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the synchronizer encounters an error
+    pub async fn run_with_token(&mut self, token: CancellationToken) -> Result<()> {
+        self.cancellation = token;
+        self.running.store(true, atomic::Ordering::SeqCst);
+        self.start().await?;
         
-        for i in 0..=10 {
-            client.set_block_hash(i, vec![i as u8]);
+        // Main synchronization loop
+        while self.running.load(atomic::Ordering::SeqCst) && !self.cancellation.is_cancelled() {
+            self.step().await?;
+            
+            // Go straight on to the next chunk of a large jump, otherwise
+            // sleep for the polling interval, waking early to flush the sink
+            // when its buffered messages are due
+            if !self.catching_up {
+                let flush_deadline = self.sink.flush_deadline();
+                tokio::select! {
+                    _ = time::sleep(Duration::from_millis(self.polling_interval)) => {}
+                    _ = time::sleep_until(flush_deadline.unwrap_or_else(time::Instant::now)), if flush_deadline.is_some() => {
+                        self.sink.flush().await?;
+                        self.unflushed_blocks = 0;
+                    }
+                    _ = self.stop.notified() => {}
+                    _ = self.cancellation.cancelled() => {}
+                }
+            }
         }
         
-        // Create a mock transform
-        let _transform = MockTransform::default();
-        
-        // Create a WASM runtime for testing
-        let runtime = WasmRuntime::for_testing().unwrap();
-        
-        // Create a null sink
-        let sink = Box::new(NullSink::new());
-        
-        // Create a block synchronizer
-        let mut synchronizer = BlockSynchronizer::new(client, runtime, sink, 6).unwrap();
-        
-        // Set the starting height
-        synchronizer.set_starting_height(5);
+        // Send anything still buffered
+        self.flush_pending().await?;
         
-        // Set a short polling interval
-        synchronizer.set_polling_interval(10);
+        Ok(())
+    }
+    
+    /// Run the block synchronizer until stopped or a deadline passes
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - When to stop, as with cancelling `run_with_token`
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer ran successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the synchronizer encounters an error
+    pub async fn run_until(&mut self, deadline: time::Instant) -> Result<()> {
+        let token = CancellationToken::new();
+        let timer = tokio::spawn({
+            let token = token.clone();
+            async move {
+                time::sleep_until(deadline).await;
+                token.cancel();
+            }
+        });
         
-        // Create a runtime for async tests
-        let rt = Runtime::new().unwrap();
+        let result = self.run_with_token(token).await;
+        timer.abort();
         
-        // Run the synchronizer for a short time
-        let handle = rt.spawn(async move {
-            let _ = synchronizer.run().await;
+        result
+    }
+    
+    /// Run the block synchronizer until stopped or a shutdown signal arrives
+    ///
+    /// The signal cancels the run as with `run_with_token`, so the block in
+    /// progress is finished rather than dropped part way through, and `run`
+    /// has returned before the caller goes on to `shutdown`.
+    ///
+    /// # Arguments
+    ///
+    /// * `signal` - A future that completes when shutdown is requested
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer ran successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the synchronizer encounters an error
+    pub async fn run_until_signal<F>(&mut self, signal: F) -> Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let listener = tokio::spawn({
+            let token = token.clone();
+            async move {
+                signal.await;
+                token.cancel();
+            }
         });
         
-        // Wait for a short time
-        std::thread::sleep(Duration::from_millis(100));
+        let result = self.run_with_token(token).await;
+        listener.abort();
         
-        // Abort the task
-        handle.abort();
+        result
     }
     
-    #[test]
-    fn test_block_synchronizer_with_different_sinks() {
-        // Create a mock metashrew client
-        let mut client = MockMetashrewClient::new();
-        client.set_height(10);
+    /// Catch up to the chain tip once
+    ///
+    /// Processes every block up to the current metashrew tip, handling any
+    /// reorg on the way, then sends anything still buffered and returns
+    /// instead of polling. Suited to running debshrew periodically.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer caught up successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the synchronizer encounters an error
+    pub async fn catch_up(&mut self) -> Result<()> {
+        self.start().await?;
         
-        for i in 0..=10 {
-            client.set_block_hash(i, vec![i as u8]);
+        // A reorg may leave new blocks to process, so step until idle
+        loop {
+            match self.step().await? {
+                StepOutcome::Idle => break,
+                StepOutcome::WaitingForSync { .. } => {
+                    time::sleep(Duration::from_millis(self.polling_interval)).await;
+                }
+                _ => {}
+            }
         }
         
-        // We don't need this runtime since we create a new one for each test
-        // let runtime = WasmRuntime::from_bytes(&[0]).unwrap();
+        // Send anything still buffered
+        self.flush_pending().await?;
         
-        // Create a tokio runtime for async tests
-        let rt = Runtime::new().unwrap();
+        Ok(())
+    }
+    
+    /// Prepare to synchronize
+    ///
+    /// Resets the statistics, resumes from the checkpoint in the state file,
+    /// replays the write-ahead log, and starts at the metashrew tip if no
+    /// starting height was set.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer is ready
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the checkpoint cannot be read, the write-ahead log
+    /// cannot be replayed, or metashrew cannot be reached
+    async fn start(&mut self) -> Result<()> {
+        self.stats = SyncStats::new();
         
-        // Create a new runtime for each test to avoid cloning
+        // Pick up where a previous run left off
+        self.restore_checkpoint().await?;
         
-        // Test with ConsoleSink
-        {
-            let console_sink = Box::new(ConsoleSink::new(false));
-            let test_runtime = WasmRuntime::for_testing().unwrap();
-            let synchronizer = BlockSynchronizer::new(client.clone(), test_runtime, console_sink, 6).unwrap();
-            
-            // Verify the sink type
-            let sink = synchronizer.get_sink();
-            rt.block_on(async {
-                // Send a test message to verify the sink works
-                let message = create_test_message();
-                let result = sink.send(vec![message]).await;
-                assert!(result.is_ok());
-                
-                // Flush and close the sink
-                assert!(sink.flush().await.is_ok());
-                assert!(sink.close().await.is_ok());
-            });
+        // Deliver anything a previous run recorded but did not confirm
+        self.replay_wal().await?;
+        
+        // If the current height is 0, get the latest height from metashrew
+        if self.current_height == 0 {
+            self.current_height = self.client.get_height().await?;
+            info!("Starting at block height {}", self.current_height);
         }
         
-        // Test with FileSink
-        {
-            // Create a temporary directory for the file
-            let dir = tempdir().unwrap();
-            let file_path = dir.path().join("test.json");
-            
-            let file_sink = Box::new(FileSink::new(file_path.to_str().unwrap(), false, 1000).unwrap());
-            let test_runtime = WasmRuntime::for_testing().unwrap();
+        self.last_activity = time::Instant::now();
+        
+        Ok(())
+    }
+    
+    /// Run a single synchronization step
+    ///
+    /// Polls metashrew once and processes any new blocks or handles a reorg.
+    /// Nothing is processed while metashrew is still indexing, so CDC messages
+    /// are never emitted from a partial index. `run` calls this in a loop;
+    /// calling it directly allows driving the synchronizer one poll at a time.
+    ///
+    /// # Returns
+    ///
+    /// What the step did
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if metashrew cannot be polled or a block or reorg
+    /// cannot be processed
+    pub async fn step(&mut self) -> Result<StepOutcome> {
+        // Poll metashrew for the latest height
+        let sync_info = self.client.sync_info().await?;
+        let metashrew_height = sync_info.indexed_height;
+        self.catching_up = false;
+        
+        // Check if we need to process new blocks
+        let outcome = if !sync_info.is_synced() {
+            info!(
+                "Waiting for metashrew to sync: indexed {} of {} blocks",
+                sync_info.indexed_height, sync_info.chain_height
+            );
+            
+            StepOutcome::WaitingForSync {
+                indexed_height: sync_info.indexed_height,
+                chain_height: sync_info.chain_height,
+            }
+        } else if metashrew_height > self.current_height {
+            let from = self.current_height + 1;
+            
+            // Process a large jump in chunks, so the caller regains control in between
+            let to = match self.max_blocks_per_iteration {
+                Some(max) => metashrew_height.min(from.saturating_add(max.max(1) - 1)),
+                None => metashrew_height,
+            };
+            self.catching_up = to < metashrew_height;
+            info!("Processing blocks {} to {}", from, to);
+            
+            // Process new blocks, stopping early if the run is cancelled
+            for height in from..=to {
+                if !self.process_block(height).await? {
+                    break;
+                }
+                self.current_height = height;
+                
+                if self.cancellation.is_cancelled() {
+                    break;
+                }
+            }
+            
+            StepOutcome::Processed { from, to: self.current_height }
+        } else if metashrew_height < self.current_height {
+            warn!("Chain reorganization detected: metashrew height {} < current height {}", metashrew_height, self.current_height);
+            
+            // Wait out a flapping chain, which may settle back on our tip
+            let settled_height = match self.reorg_settle {
+                Some(settle) => self.settle_tip(metashrew_height, settle).await?,
+                None => metashrew_height,
+            };
+            
+            if settled_height >= self.current_height && self.is_on_chain(self.current_height).await? {
+                info!("Chain settled at height {} without replacing block {}", settled_height, self.current_height);
+                StepOutcome::Idle
+            } else {
+                // Handle reorg, which advances the current height as it
+                // reprocesses the new chain
+                self.handle_reorg(settled_height).await?;
+                
+                StepOutcome::Reorg { height: settled_height }
+            }
+        } else {
+            StepOutcome::Idle
+        };
+        
+        self.send_heartbeat_if_quiet().await?;
+        self.adapt_polling_interval(&outcome);
+        
+        Ok(outcome)
+    }
+    
+    /// Wait for the chain tip to stop changing
+    ///
+    /// Sleeps for the settle time and polls the tip again, until two
+    /// consecutive polls see the same tip height and hash. Gives up after
+    /// `MAX_SETTLE_POLLS` polls so a chain that never settles cannot stall
+    /// synchronization.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The tip height that was last seen
+    /// * `settle` - How long to wait between polls
+    ///
+    /// # Returns
+    ///
+    /// The settled tip height, or the latest tip height if it did not settle
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if metashrew cannot be polled
+    async fn settle_tip(&self, height: u32, settle: Duration) -> Result<u32> {
+        let mut tip = (height, self.client.get_block_hash(height).await?);
+        
+        for _ in 0..MAX_SETTLE_POLLS {
+            time::sleep(settle).await;
+            
+            let height = self.client.sync_info().await?.indexed_height;
+            let latest = (height, self.client.get_block_hash(height).await?);
+            if latest == tip {
+                return Ok(height);
+            }
+            
+            debug!("Chain tip moved to height {} while settling", height);
+            tip = latest;
+        }
+        
+        warn!("Chain tip did not settle after {} polls; handling the reorg at height {}", MAX_SETTLE_POLLS, tip.0);
+        Ok(tip.0)
+    }
+    
+    /// Check whether a processed block is still on metashrew's chain
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The height of the block
+    ///
+    /// # Returns
+    ///
+    /// true if metashrew has the cached hash at the height, false otherwise
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block hash cannot be fetched
+    async fn is_on_chain(&self, height: u32) -> Result<bool> {
+        let hash = hex::encode(self.client.get_block_hash(height).await?);
+        Ok(self.cache.lock().await.get_block_hash(height) == Some(hash))
+    }
+    
+    /// Adapt the polling interval to the outcome of a step
+    ///
+    /// Poll sooner while catching up to the tip and back off while idle.
+    ///
+    /// # Arguments
+    ///
+    /// * `outcome` - The outcome of the step
+    fn adapt_polling_interval(&mut self, outcome: &StepOutcome) {
+        let Some(bounds) = self.adaptive_polling else {
+            return;
+        };
+        
+        let interval = match outcome {
+            StepOutcome::Processed { .. } | StepOutcome::Reorg { .. } => self.polling_interval / 2,
+            StepOutcome::Idle | StepOutcome::WaitingForSync { .. } => self.polling_interval.saturating_mul(2),
+        };
+        
+        self.polling_interval = interval.clamp(bounds.min_interval_ms, bounds.max_interval_ms);
+    }
+    
+    /// Send a heartbeat if no CDC messages were sent for the heartbeat interval
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if no heartbeat was due or it was sent successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sink fails to send the heartbeat
+    async fn send_heartbeat_if_quiet(&mut self) -> Result<()> {
+        let Some(interval) = self.heartbeat_interval else {
+            return Ok(());
+        };
+        
+        // Buffered blocks will be sent soon; a heartbeat now would overtake them
+        if !self.pending.is_empty() || self.last_activity.elapsed() < interval {
+            return Ok(());
+        }
+        
+        let block_hash = self.cache.lock().await
+            .get_block_hash(self.current_height)
+            .unwrap_or_default();
+        let heartbeat = CdcMessage {
+            header: CdcHeader {
+                source: "debshrew".to_string(),
+                timestamp: Utc::now(),
+                block_height: self.current_height,
+                block_hash,
+                transaction_id: None,
+                event_id: None,
+            },
+            payload: CdcPayload {
+                operation: CdcOperation::Heartbeat,
+                table: self.heartbeat_table.clone(),
+                key: String::new(),
+                before: None,
+                after: None,
+            },
+        };
+        
+        debug!("Sending heartbeat at block {}", self.current_height);
+        self.sink.send(vec![heartbeat]).await?;
+        self.last_activity = time::Instant::now();
+        
+        Ok(())
+    }
+    
+    /// Stop the block synchronizer
+    pub fn stop(&mut self) {
+        self.stop_handle().stop();
+    }
+    
+    /// Get a handle that stops the synchronizer from elsewhere
+    ///
+    /// `run` checks the handle after each step, so with
+    /// `max_blocks_per_iteration` set it also takes effect part way through
+    /// catching up.
+    ///
+    /// # Returns
+    ///
+    /// A stop handle for this synchronizer
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            running: self.running.clone(),
+            stop: self.stop.clone(),
+        }
+    }
+    
+    /// Shut down the block synchronizer
+    ///
+    /// Stops the synchronizer, sends any buffered CDC messages, closes the
+    /// sink, and logs a summary of the synchronization statistics. Futures
+    /// from `wait_stopped` complete once this is done.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer was shut down cleanly
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffered messages cannot be sent or the sink
+    /// cannot be closed
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.stop();
+        
+        // Send anything still buffered and close the sink
+        self.flush_pending().await?;
+        self.sink.close().await?;
+        
+        for line in self.stats.to_string().lines() {
+            info!("{}", line);
+        }
+        
+        self.stopped.send_replace(true);
+        
+        Ok(())
+    }
+    
+    /// Wait for the block synchronizer to shut down
+    ///
+    /// The returned future does not borrow the synchronizer, so it can be
+    /// taken before `run` and awaited elsewhere, such as in another task.
+    ///
+    /// # Returns
+    ///
+    /// A future that completes once `shutdown` has flushed the buffered CDC
+    /// messages and closed the sink, or the synchronizer is dropped
+    pub fn wait_stopped(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut stopped = self.stopped.subscribe();
+        
+        async move {
+            // An error means the synchronizer was dropped, which also stops it
+            let _ = stopped.wait_for(|stopped| *stopped).await;
+        }
+    }
+    
+    /// Resume from the checkpoint in the state file
+    ///
+    /// A checkpoint restores the transform state and the height of the last
+    /// block it includes, which takes precedence over the starting height.
+    /// Does nothing without a state file, or if the file holds a state
+    /// without a checkpoint height.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the checkpoint was restored or there was none
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file cannot be read
+    pub async fn restore_checkpoint(&mut self) -> Result<()> {
+        let Some(path) = self.state_path.as_ref().filter(|path| path.exists()) else {
+            return Ok(());
+        };
+        
+        let checkpoint = load_checkpoint(path)?;
+        let Some(height) = checkpoint.height else {
+            warn!("State file {} has no checkpoint height; not resuming from it", path.display());
+            return Ok(());
+        };
+        
+        info!("Resuming from the checkpoint at block {}", height);
+        let mut runtime = self.runtime.lock().await;
+        runtime.set_current_height(height);
+        runtime.set_state(checkpoint.state);
+        drop(runtime);
+        self.current_height = height;
+        
+        Ok(())
+    }
+    
+    /// Replay the write-ahead log to the sink
+    ///
+    /// Sends every entry left in the write-ahead log above the checkpoint,
+    /// which are the messages that were recorded but not confirmed by the sink
+    /// before a crash, and then truncates the log. The checkpoint is the
+    /// height restored by `restore_checkpoint`, or the starting height;
+    /// entries at or below it were already checkpointed before the crash and
+    /// are dropped without being sent again. Does nothing without a
+    /// write-ahead log.
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the log was replayed successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be read or truncated, or the sink
+    /// fails to accept the messages
+    pub async fn replay_wal(&mut self) -> Result<()> {
+        let entries = match &self.wal {
+            Some(wal) => wal.entries()?,
+            None => return Ok(()),
+        };
+        
+        let Some(last_height) = entries.iter().map(|entry| entry.height).max() else {
+            return Ok(());
+        };
+        
+        // A crash between the checkpoint and the truncation leaves entries
+        // the checkpoint already covers
+        let checkpoint_height = self.current_height;
+        let entries: Vec<_> = entries.into_iter()
+            .filter(|entry| entry.height > checkpoint_height)
+            .collect();
+        
+        if !entries.is_empty() {
+            info!("Replaying {} write-ahead log entries through block {}", entries.len(), last_height);
+            
+            let messages: Vec<CdcMessage> = entries.into_iter()
+                .flat_map(|entry| entry.messages)
+                .collect();
+            self.sink.send(messages).await?;
+            self.sink.flush().await?;
+        }
+        
+        if let Some(wal) = &mut self.wal {
+            wal.truncate_through(last_height)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Get the synchronization statistics
+    ///
+    /// # Returns
+    ///
+    /// The synchronization statistics
+    pub fn stats(&self) -> &SyncStats {
+        &self.stats
+    }
+    
+    /// Process a block
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height
+    ///
+    /// # Returns
+    ///
+    /// Ok(true) if the block was processed, or Ok(false) if the synchronizer
+    /// was stopped while waiting to retry it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block cannot be processed
+    async fn process_block(&mut self, height: u32) -> Result<bool> {
+        // Get the block hash
+        let hash = self.within_reorg_deadline(self.client.get_block_hash(height)).await?;
+        
+        // Create block metadata
+        let metadata = BlockMetadata {
+            height,
+            hash: hex::encode(&hash),
+            timestamp: Utc::now(),
+        };
+        
+        // A repeated notification for a block already processed is a no-op,
+        // while a different hash at that height means the chain reorganized
+        let cached_hash = self.cache.lock().await.get_block_hash(height);
+        if let Some(cached_hash) = cached_hash {
+            if cached_hash == metadata.hash {
+                debug!("Skipping block {} already processed with hash {}", height, cached_hash);
+                return Ok(true);
+            }
+            
+            warn!("Block {} changed hash from {} to {}, handling as a reorg", height, cached_hash, metadata.hash);
+            Box::pin(self.handle_reorg(self.current_height.max(height))).await?;
+            return Ok(true);
+        }
+        
+        // The parent hash comes from the cache, unless the parent has left it
+        let prev_hash = match height.checked_sub(1) {
+            None => Vec::new(),
+            Some(parent) => {
+                let cached = self.cache.lock().await.get_block_hash(parent);
+                match cached {
+                    Some(hash) => hex::decode(&hash)
+                        .map_err(|e| Error::BlockSynchronization(format!("Invalid cached hash of block {}: {}", parent, e)))?,
+                    None => self.within_reorg_deadline(self.client.get_block_hash(parent)).await?,
+                }
+            }
+        };
+        
+        // Process the block with the transform module, waiting and trying
+        // again while the transform asks for a retry
+        let mut retries = 0;
+        let transform_result = loop {
+            let mut runtime = self.runtime.clone().lock_owned().await;
+            runtime.set_prev_block_hash(prev_hash.clone());
+            
+            // The raw block is only fetched if the transform reads it, and
+            // views without a registered function are called on metashrew
+            runtime.set_block_fetcher(Some(self.block_fetcher()));
+            runtime.set_view_fetcher(Some(self.view_fetcher()));
+            
+            // Run the transform on the blocking pool, where the block fetcher
+            // may wait on the metashrew client. A transform still running at
+            // the reorg deadline is abandoned; it finishes in the background
+            // and its result is discarded
+            let validate_determinism = self.validate_determinism;
+            let block_hash = hash.clone();
+            let transform = tokio::task::spawn_blocking(move || {
+                let result = if validate_determinism {
+                    runtime.process_block_twice(height, block_hash)
+                } else {
+                    runtime.process_block(height, block_hash)
+                };
+                (runtime, result)
+            });
+            let (mut runtime, result) = self.within_reorg_deadline(async {
+                transform.await
+                    .map_err(|e| Error::BlockSynchronization(format!("Processing block {} panicked: {}", height, e)))
+            }).await?;
+            
+            // A view failing inside the transform is retried; any other
+            // failure is a bug in the transform and stops the synchronizer
+            let result = match result.map_err(Error::runtime) {
+                Ok(result) => result,
+                Err(e) if e.is_transient() => {
+                    drop(runtime);
+                    warn!("Block {} failed on a view, retrying in {} ms: {}", height, self.polling_interval, e);
+                    if !self.wait_to_retry(height, &mut retries, Duration::from_millis(self.polling_interval)).await? {
+                        return Ok(false);
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            
+            for warning in &result.warnings {
+                warn!("Transform warning at block {}: {}", height, warning);
+            }
+            self.stats.record_view_cache(runtime.view_cache_stats());
+            
+            let Some(delay) = result.retry_after else {
+                // At-most-once checkpoints before the messages are sent
+                if self.delivery == Delivery::AtMostOnce {
+                    if let Some(path) = &self.state_path {
+                        save_checkpoint(path, height, &runtime.get_state())?;
+                    }
+                }
+                
+                break result;
+            };
+            
+            // The views may answer differently once they recover
+            runtime.invalidate_view_cache_above(height.saturating_sub(1));
+            drop(runtime);
+            
+            let delay = delay.min(MAX_BLOCK_RETRY_DELAY);
+            info!("Transform asked to retry block {} in {} ms", height, delay.as_millis());
+            if !self.wait_to_retry(height, &mut retries, delay).await? {
+                return Ok(false);
+            }
+        };
+        
+        // Add the block to the cache
+        let mut cache = self.cache.lock().await;
+        cache.add_block(metadata, transform_result.clone())?;
+        drop(cache);
+        
+        if self.verify_state_snapshots {
+            self.verify_state_snapshot(height).await;
+        }
+        
+        self.stats.blocks_processed += 1;
+        
+        // Queue the CDC messages and send them once enough blocks are buffered
+        self.pending.push((height, transform_result.cdc_messages));
+        if self.pending.len() as u32 >= self.blocks_per_flush.unwrap_or(1) || self.inflight_limit_reached() {
+            self.flush_pending().await?;
+        }
+        
+        debug!("Processed block {}", height);
+        
+        Ok(true)
+    }
+    
+    /// Wait before retrying a block
+    ///
+    /// The wait ends early when the synchronizer is stopped or cancelled.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height
+    /// * `retries` - The number of times the block has been retried so far
+    /// * `delay` - How long to wait
+    ///
+    /// # Returns
+    ///
+    /// Ok(true) to retry the block, or Ok(false) if the synchronizer was
+    /// stopped or cancelled
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block has already been retried as many times
+    /// as allowed
+    async fn wait_to_retry(&self, height: u32, retries: &mut u32, delay: Duration) -> Result<bool> {
+        if self.max_block_retries.is_some_and(|max| *retries >= max) {
+            return Err(Error::BlockSynchronization(format!(
+                "Block {} was still not processed after {} retries", height, retries
+            )));
+        }
+        *retries += 1;
+        
+        // Listen for a stop before checking for one, so none is missed
+        let stop = self.stop.notified();
+        tokio::pin!(stop);
+        stop.as_mut().enable();
+        
+        if self.stopping() {
+            return Ok(false);
+        }
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = stop => {}
+            _ = self.cancellation.cancelled() => {}
+        }
+        
+        Ok(!self.stopping())
+    }
+    
+    /// Whether the synchronizer has been stopped or cancelled
+    fn stopping(&self) -> bool {
+        !self.running.load(atomic::Ordering::SeqCst) || self.cancellation.is_cancelled()
+    }
+    
+    /// Build the raw block source handed to the transform runtime
+    ///
+    /// The fetcher runs on the blocking pool during `process_block`, so it
+    /// waits on the metashrew client through the current runtime handle.
+    ///
+    /// # Returns
+    ///
+    /// A fetcher that gets raw blocks from the metashrew client
+    fn block_fetcher(&self) -> BlockFetcher {
+        let client = self.client.clone();
+        let handle = tokio::runtime::Handle::current();
+        
+        Arc::new(move |height| {
+            handle.block_on(client.get_block(height)).map_err(anyhow::Error::from)
+        })
+    }
+    
+    /// Build the view source handed to the transform runtime
+    ///
+    /// Like the block fetcher, it waits on the metashrew client through the
+    /// current runtime handle. A call that runs out of time is dropped, which
+    /// cancels the request.
+    ///
+    /// # Returns
+    ///
+    /// A fetcher that calls views at the block's height on the metashrew client
+    fn view_fetcher(&self) -> ViewFetcher {
+        let client = self.client.clone();
+        let handle = tokio::runtime::Handle::current();
+        
+        Arc::new(move |view_name, params, height, timeout| {
+            let call = client.call_view(view_name, params, Some(height));
+            let result = match timeout {
+                Some(timeout) => handle.block_on(time::timeout(timeout, call)).ok().transpose(),
+                None => handle.block_on(call).map(Some),
+            };
+            result.map_err(anyhow::Error::from)
+        })
+    }
+    
+    /// Compare the state snapshot cached for a block with the runtime state
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The height of the block just processed
+    ///
+    /// # Returns
+    ///
+    /// true if the digests match, false if they differ or the block is not cached
+    async fn verify_state_snapshot(&mut self, height: u32) -> bool {
+        let runtime_digest = self.runtime.lock().await.get_state().digest();
+        let snapshot_digest = self.cache.lock().await
+            .get_state_snapshot(height)
+            .map(|snapshot| snapshot.digest());
+        
+        if snapshot_digest.as_deref() == Some(runtime_digest.as_str()) {
+            return true;
+        }
+        
+        error!(
+            "State snapshot cached for block {} ({}) differs from the runtime state ({})",
+            height,
+            snapshot_digest.as_deref().unwrap_or("missing"),
+            runtime_digest
+        );
+        self.stats.state_divergences += 1;
+        
+        false
+    }
+    
+    /// Check whether the blocks in flight have reached the configured limit
+    ///
+    /// # Returns
+    ///
+    /// true if buffered and unflushed blocks together reach `max_inflight_blocks`
+    fn inflight_limit_reached(&self) -> bool {
+        self.max_inflight_blocks
+            .is_some_and(|max| self.pending.len() as u32 + self.unflushed_blocks >= max)
+    }
+    
+    /// Send the buffered CDC messages to the sink
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the messages were sent successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sink fails to send or flush the messages
+    async fn flush_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        
+        let blocks = std::mem::take(&mut self.pending);
+        self.deliver(blocks).await
+    }
+    
+    /// Send CDC messages for one or more blocks to the sink
+    ///
+    /// With at-least-once delivery, the messages are recorded in the
+    /// write-ahead log before sending, and the transform state is saved and
+    /// the log truncated once the sink has accepted them. A sink that reports
+    /// a committed height holds the checkpoint back to that height, so blocks
+    /// it has accepted but not yet committed are replayed after a crash. The sink is also
+    /// flushed when batching by block count is enabled, and when the blocks
+    /// it holds reach `max_inflight_blocks`.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The block heights and their CDC messages
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the messages were delivered successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the write-ahead log cannot be written or the sink
+    /// fails to send or flush the messages
+    async fn deliver(&mut self, blocks: Vec<(u32, Vec<CdcMessage>)>) -> Result<()> {
+        let last_height = blocks.iter().map(|(height, _)| *height).max();
+        let block_count = blocks.len() as u32;
+        let at_least_once = self.delivery == Delivery::AtLeastOnce;
+        
+        if let Some(wal) = self.wal.as_mut().filter(|_| at_least_once) {
+            for (height, messages) in &blocks {
+                wal.append(*height, messages)?;
+            }
+        }
+        
+        for (_, messages) in &blocks {
+            self.stats.record_messages(messages);
+        }
+        if blocks.iter().any(|(_, messages)| !messages.is_empty()) {
+            self.last_activity = time::Instant::now();
+        }
+        
+        match self.ordering {
+            Ordering::Strict => {
+                let messages: Vec<CdcMessage> = blocks.into_iter()
+                    .flat_map(|(_, messages)| messages)
+                    .collect();
+                self.sink.send(messages).await?;
+            }
+            Ordering::Relaxed => {
+                let sink = &self.sink;
+                try_join_all(blocks.into_iter().map(|(_, messages)| sink.send(messages))).await?;
+            }
+        }
+        if let Some(height) = last_height {
+            self.sink.mark_sent(height);
+        }
+        self.unflushed_blocks += block_count;
+        
+        if self.blocks_per_flush.is_some() || self.wal.is_some() || self.inflight_limit_reached() {
+            self.sink.flush().await?;
+            self.unflushed_blocks = 0;
+        }
+        
+        if at_least_once {
+            self.checkpoint(last_height).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Save the transform state and truncate the write-ahead log after a delivery
+    ///
+    /// The checkpoint is taken at the last delivered block, or at the sink's
+    /// committed height if that is lower. The state at a lower height is taken
+    /// from the block cache; if the block has left the cache, the previous
+    /// state file is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `last_height` - The highest block height delivered
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the checkpoint was written successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file or the write-ahead log cannot be written
+    async fn checkpoint(&mut self, last_height: Option<u32>) -> Result<()> {
+        let checkpoint_height = match (last_height, self.sink.committed_height()) {
+            (Some(height), Some(committed)) => Some(height.min(committed)),
+            (height, _) => height,
+        };
+        
+        if let (Some(path), Some(height)) = (&self.state_path, checkpoint_height) {
+            if checkpoint_height == last_height {
+                save_checkpoint(path, height, &self.runtime.lock().await.get_state())?;
+            } else {
+                debug!("Sink has committed through block {} of {:?}", height, last_height);
+                
+                if let Some(state) = self.cache.lock().await.get_state_snapshot(height) {
+                    save_checkpoint(path, height, &state)?;
+                }
+            }
+        }
+        
+        if let (Some(wal), Some(height)) = (&mut self.wal, checkpoint_height) {
+            wal.truncate_through(height)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Handle a chain reorganization
+    ///
+    /// # Arguments
+    ///
+    /// * `new_height` - The new block height
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the reorg was handled successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reorg cannot be handled
+    async fn handle_reorg(&mut self, new_height: u32) -> Result<()> {
+        // A reorg found while handling another one shares its deadline
+        let outer_deadline = self.reorg_deadline;
+        self.reorg_deadline = outer_deadline.or_else(|| {
+            self.reorg_timeout.map(|timeout| time::Instant::now() + Duration::from_millis(timeout))
+        });
+        
+        let result = self.roll_back_to_new_chain(new_height).await;
+        self.reorg_deadline = outer_deadline;
+        
+        result
+    }
+    
+    /// Roll back to the common ancestor and process the new chain
+    ///
+    /// # Arguments
+    ///
+    /// * `new_height` - The new block height
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the new chain was processed successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reorg cannot be handled or its deadline passes
+    async fn roll_back_to_new_chain(&mut self, new_height: u32) -> Result<()> {
+        // Only a cached block can be the common ancestor, so only the new
+        // chain's hashes at cached heights are needed
+        let cached_heights = self.cache.lock().await.height_range()
+            .ok_or_else(|| Error::ReorgHandling("No common ancestor found".to_string()))?;
+        
+        // Get the block hashes for the new chain
+        let mut new_hashes = Vec::new();
+        for height in *cached_heights.start()..=(*cached_heights.end()).min(new_height) {
+            self.check_reorg_deadline()?;
+            let hash = self.within_reorg_deadline(self.client.get_block_hash(height)).await?;
+            new_hashes.push((height, hex::encode(&hash)));
+        }
+        
+        // Find the common ancestor
+        let cache = self.cache.lock().await;
+        let common_ancestor = cache.find_common_ancestor(&new_hashes)
+            .ok_or_else(|| Error::ReorgHandling("No common ancestor found".to_string()))?;
+        
+        info!("Found common ancestor at height {}", common_ancestor);
+        let depth = cache.highest_height().unwrap_or(common_ancestor) - common_ancestor;
+        self.stats.record_reorg(depth);
+        
+        // Get the state snapshot at the common ancestor
+        let state_snapshot = cache.get_state_snapshot(common_ancestor)
+            .ok_or_else(|| Error::ReorgHandling(format!("State snapshot not found for height {}", common_ancestor)))?;
+        
+        // Release the cache lock
+        drop(cache);
+        
+        // Relaxed sends may have landed in any order, so have the sink flush
+        // all of them before the inverse messages follow
+        if self.ordering == Ordering::Relaxed && self.unflushed_blocks > 0 {
+            self.sink.flush().await?;
+            self.unflushed_blocks = 0;
+        }
+        
+        // A manual rollback undoes every block in the transform state, so
+        // buffered blocks must reach the sink before their undoing does
+        let manual_rollback = self.rollback_mode == RollbackMode::Manual;
+        if manual_rollback {
+            self.flush_pending().await?;
+        }
+        
+        // Blocks still buffered were never sent, so their messages are simply
+        // discarded; only blocks the sink has already seen need inverting
+        let last_sent_height = self.pending.first()
+            .map_or(self.current_height, |(height, _)| height.saturating_sub(1));
+        self.pending.retain(|(height, _)| *height <= common_ancestor);
+        
+        // Get the runtime lock
+        let runtime = self.runtime.clone();
+        let mut runtime = runtime.lock().await;
+        
+        // Generate inverse CDC messages for the rolled back blocks
+        let mut inverse_messages = Vec::new();
+        
+        if manual_rollback {
+            info!("Calling the transform's rollback to block {}", common_ancestor);
+            let hash = self.cache.lock().await.get_block_hash(common_ancestor).unwrap_or_default();
+            let hash = hex::decode(&hash).unwrap_or_default();
+            inverse_messages = runtime.rollback(common_ancestor, hash)?.cdc_messages;
+        } else {
+            // Process sent blocks in reverse order down to common_ancestor + 1
+            for height in (common_ancestor + 1..=last_sent_height).rev() {
+                info!("Generating inverse CDC messages for block {}", height);
+                
+                // Compute inverse messages for this block
+                let block_inverse = runtime.compute_inverse_messages(height)?;
+                inverse_messages.extend(block_inverse);
+            }
+        }
+        
+        // Reset the runtime state to the common ancestor
+        runtime.set_current_height(common_ancestor);
+        runtime.invalidate_view_cache_above(common_ancestor);
+        runtime.set_state(state_snapshot);
+        runtime.on_rollback_complete(common_ancestor)?;
+        
+        if let Some(path) = &self.state_path {
+            save_checkpoint(path, common_ancestor, &runtime.get_state())?;
+        }
+        
+        // Bracket the inverse messages with reorg markers if configured
+        if self.reorg_markers {
+            let cache = self.cache.lock().await;
+            let marker = |operation, height: u32| {
+                reorg_marker(operation, height, cache.get_block_hash(height).unwrap_or_default(), last_sent_height, common_ancestor)
+            };
+            inverse_messages.insert(0, marker(CdcOperation::ReorgBegin, last_sent_height));
+            inverse_messages.push(marker(CdcOperation::ReorgEnd, common_ancestor));
+        }
+        
+        // Send the inverse CDC messages to the sink
+        if !inverse_messages.is_empty() {
+            info!("Sending {} inverse CDC messages to sink", inverse_messages.len());
+            self.deliver(vec![(common_ancestor, inverse_messages)]).await?;
+        }
+        
+        // Roll back the cache
+        self.cache.lock().await.rollback(common_ancestor)?;
+        
+        // Release the runtime lock
+        drop(runtime);
+        
+        // The common ancestor is now a consistent checkpoint
+        self.current_height = common_ancestor;
+        
+        // Process the new chain, advancing the checkpoint block by block
+        for height in (common_ancestor + 1)..=new_height {
+            self.check_reorg_deadline()?;
+            if !self.process_block(height).await? {
+                break;
+            }
+            self.current_height = height;
+        }
+        
+        Ok(())
+    }
+    
+    /// Reprocess blocks with a new transform module
+    ///
+    /// For when the transform changed but the chain did not, e.g. after a
+    /// transform bug fix. The transform state is restored from the snapshot
+    /// cached at `from_height - 1`, the new module replaces the current one,
+    /// and the blocks from `from_height` up to the current height are
+    /// processed again, sending their corrected CDC messages. Optionally, the
+    /// inverse of the old module's messages for those blocks is sent first,
+    /// as on a reorg, so consumers can undo the old output.
+    ///
+    /// # Arguments
+    ///
+    /// * `from_height` - The first block to reprocess
+    /// * `runtime` - A runtime holding the new module; the current runtime's
+    ///   settings are kept
+    /// * `invert_old_output` - Whether to send the inverse of the old messages first
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the blocks were reprocessed successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `from_height` is not a processed block, no state
+    /// snapshot is cached for the block before it, or reprocessing fails
+    pub async fn reprocess(&mut self, from_height: u32, runtime: WasmRuntime, invert_old_output: bool) -> Result<()> {
+        if from_height == 0 || from_height > self.current_height {
+            return Err(Error::BlockSynchronization(format!(
+                "Cannot reprocess from block {}: blocks 1 to {} have been processed",
+                from_height,
+                self.current_height
+            )));
+        }
+        
+        let state_snapshot = self.cache.lock().await.get_state_snapshot(from_height - 1)
+            .ok_or_else(|| Error::BlockSynchronization(format!(
+                "Cannot reprocess from block {}: no state snapshot is cached for block {}",
+                from_height,
+                from_height - 1
+            )))?;
+        
+        // The old messages must reach the sink before their inverses
+        self.flush_pending().await?;
+        if self.ordering == Ordering::Relaxed && self.unflushed_blocks > 0 {
+            self.sink.flush().await?;
+            self.unflushed_blocks = 0;
+        }
+        
+        let last_height = self.current_height;
+        let mut inverse_messages = Vec::new();
+        {
+            let mut current_runtime = self.runtime.lock().await;
+            if invert_old_output {
+                for height in (from_height..=last_height).rev() {
+                    inverse_messages.extend(current_runtime.compute_inverse_messages(height)?);
+                }
+            }
+            
+            info!("Reprocessing blocks {} to {} with transform {}", from_height, last_height, runtime.name());
+            current_runtime.replace_module(runtime);
+            current_runtime.set_current_height(from_height - 1);
+            current_runtime.set_state(state_snapshot);
+            
+            if let Some(path) = &self.state_path {
+                save_checkpoint(path, from_height - 1, &current_runtime.get_state())?;
+            }
+        }
+        
+        if !inverse_messages.is_empty() {
+            info!("Sending {} inverse CDC messages to sink", inverse_messages.len());
+            self.deliver(vec![(from_height - 1, inverse_messages)]).await?;
+        }
+        
+        self.cache.lock().await.rollback(from_height - 1)?;
+        self.current_height = from_height - 1;
+        
+        for height in from_height..=last_height {
+            if !self.process_block(height).await? {
+                break;
+            }
+            self.current_height = height;
+        }
+        self.flush_pending().await?;
+        
+        Ok(())
+    }
+    
+    /// Fail reorg handling if its deadline has passed
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if no reorg is being handled or its deadline has not passed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the deadline has passed
+    fn check_reorg_deadline(&self) -> Result<()> {
+        match self.reorg_deadline {
+            Some(deadline) if time::Instant::now() >= deadline => Err(self.reorg_timeout_error()),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Wait for a metashrew request or transform call, giving up when the
+    /// reorg deadline passes
+    ///
+    /// Outside reorg handling the request is simply awaited.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request or call to wait for
+    ///
+    /// # Returns
+    ///
+    /// The result of the request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails or the reorg deadline passes first
+    async fn within_reorg_deadline<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.reorg_deadline {
+            Some(deadline) => time::timeout_at(deadline, request)
+                .await
+                .map_err(|_| self.reorg_timeout_error())?,
+            None => request.await,
+        }
+    }
+    
+    /// The error reorg handling stops with when its deadline passes
+    fn reorg_timeout_error(&self) -> Error {
+        Error::ReorgHandling(format!(
+            "Reorg handling exceeded timeout of {} ms; stopped at block {}",
+            self.reorg_timeout.unwrap_or_default(),
+            self.current_height
+        ))
+    }
+    
+    /// Get the current block height
+    ///
+    /// # Returns
+    ///
+    /// The current block height
+    pub fn get_current_height(&self) -> u32 {
+        self.current_height
+    }
+    
+    /// Get the block cache
+    ///
+    /// # Returns
+    ///
+    /// The block cache
+    pub async fn get_cache(&self) -> Arc<Mutex<BlockCache>> {
+        self.cache.clone()
+    }
+    
+    /// Get the WASM runtime
+    ///
+    /// # Returns
+    ///
+    /// The WASM runtime
+    pub async fn get_runtime(&self) -> Arc<Mutex<WasmRuntime>> {
+        self.runtime.clone()
+    }
+    
+    /// Get the CDC sink
+    ///
+    /// # Returns
+    ///
+    /// The CDC sink
+    pub fn get_sink(&self) -> Arc<Box<dyn CdcSink>> {
+        self.sink.clone()
+    }
+    
+    /// Get the metashrew client
+    ///
+    /// # Returns
+    ///
+    /// The metashrew client
+    pub fn get_client(&self) -> Arc<C> {
+        self.client.clone()
+    }
+}
+
+/// Synchronizer trait
+///
+/// This trait defines the interface for block synchronizers.
+#[async_trait]
+pub trait Synchronizer: Send + Sync {
+    /// Run the synchronizer
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the synchronizer ran successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the synchronizer encounters an error
+    async fn run(&mut self) -> Result<()>;
+    
+    /// Run a single synchronization step
+    ///
+    /// # Returns
+    ///
+    /// What the step did
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the step fails
+    async fn step(&mut self) -> Result<StepOutcome>;
+    
+    /// Stop the synchronizer
+    fn stop(&mut self);
+    
+    /// Get the current block height
+    ///
+    /// # Returns
+    ///
+    /// The current block height
+    fn get_current_height(&self) -> u32;
+}
+
+#[async_trait]
+impl<C: MetashrewClient + 'static> Synchronizer for BlockSynchronizer<C> {
+    async fn run(&mut self) -> Result<()> {
+        self.run().await
+    }
+    
+    async fn step(&mut self) -> Result<StepOutcome> {
+        self.step().await
+    }
+    
+    fn stop(&mut self) {
+        self.stop();
+    }
+    
+    fn get_current_height(&self) -> u32 {
+        self.get_current_height()
+    }
+}
+
+/// Build a reorg marker message
+///
+/// # Arguments
+///
+/// * `operation` - `ReorgBegin` or `ReorgEnd`
+/// * `block_height` - The block height of the header
+/// * `block_hash` - The block hash of the header
+/// * `from_height` - The highest block height being rolled back
+/// * `to_height` - The height of the common ancestor
+///
+/// # Returns
+///
+/// The marker message
+fn reorg_marker(operation: CdcOperation, block_height: u32, block_hash: String, from_height: u32, to_height: u32) -> CdcMessage {
+    CdcMessage {
+        header: CdcHeader {
+            source: "debshrew".to_string(),
+            timestamp: Utc::now(),
+            block_height,
+            block_hash,
+            transaction_id: None,
+            event_id: None,
+        },
+        payload: CdcPayload {
+            operation,
+            table: REORG_TABLE.to_string(),
+            key: String::new(),
+            before: None,
+            after: Some(serde_json::json!({
+                "from_height": from_height,
+                "to_height": to_height,
+            })),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::MockMetashrewClient;
+    use crate::sink::{ConsoleSink, FileSink, NullSink};
+    use debshrew_runtime::transform::MockTransform;
+    use debshrew_support::{CdcHeader, CdcMessage, CdcOperation, CdcPayload};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn test_block_synchronizer() {
+        // Create a mock metashrew client
+        let mut client = MockMetashrewClient::new();
+        client.set_height(10);
+        
+        for i in 0..=10 {
+            client.set_block_hash(i, vec![i as u8]);
+        }
+        
+        // Create a mock transform
+        let _transform = MockTransform::default();
+        
+        // Create a WASM runtime for testing
+        let runtime = WasmRuntime::for_testing().unwrap();
+        
+        // Create a null sink
+        let sink = Box::new(NullSink::new());
+        
+        // Create a block synchronizer
+        let mut synchronizer = BlockSynchronizer::new(client, runtime, sink, 6).unwrap();
+        
+        // Set the starting height
+        synchronizer.set_starting_height(5);
+        
+        // Set a short polling interval
+        synchronizer.set_polling_interval(10);
+        
+        // Create a runtime for async tests
+        let rt = Runtime::new().unwrap();
+        
+        // Run the synchronizer for a short time
+        let handle = rt.spawn(async move {
+            let _ = synchronizer.run().await;
+        });
+        
+        // Wait for a short time
+        std::thread::sleep(Duration::from_millis(100));
+        
+        // Abort the task
+        handle.abort();
+    }
+    
+    #[test]
+    fn test_block_synchronizer_with_different_sinks() {
+        // Create a mock metashrew client
+        let mut client = MockMetashrewClient::new();
+        client.set_height(10);
+        
+        for i in 0..=10 {
+            client.set_block_hash(i, vec![i as u8]);
+        }
+        
+        // We don't need this runtime since we create a new one for each test
+        // let runtime = WasmRuntime::from_bytes(&[0]).unwrap();
+        
+        // Create a tokio runtime for async tests
+        let rt = Runtime::new().unwrap();
+        
+        // Create a new runtime for each test to avoid cloning
+        
+        // Test with ConsoleSink
+        {
+            let console_sink = Box::new(ConsoleSink::new(false));
+            let test_runtime = WasmRuntime::for_testing().unwrap();
+            let synchronizer = BlockSynchronizer::new(client.clone(), test_runtime, console_sink, 6).unwrap();
+            
+            // Verify the sink type
+            let sink = synchronizer.get_sink();
+            rt.block_on(async {
+                // Send a test message to verify the sink works
+                let message = create_test_message();
+                let result = sink.send(vec![message]).await;
+                assert!(result.is_ok());
+                
+                // Flush and close the sink
+                assert!(sink.flush().await.is_ok());
+                assert!(sink.close().await.is_ok());
+            });
+        }
+        
+        // Test with FileSink
+        {
+            // Create a temporary directory for the file
+            let dir = tempdir().unwrap();
+            let file_path = dir.path().join("test.json");
+            
+            let file_sink = Box::new(FileSink::new(file_path.to_str().unwrap(), false, 1000).unwrap());
+            let test_runtime = WasmRuntime::for_testing().unwrap();
             let synchronizer = BlockSynchronizer::new(client.clone(), test_runtime, file_sink, 6).unwrap();
             
-            // Verify the sink type
-            let sink = synchronizer.get_sink();
-            rt.block_on(async {
-                // Send a test message to verify the sink works
-                let message = create_test_message();
-                let result = sink.send(vec![message]).await;
-                assert!(result.is_ok());
-                
-                // Flush and close the sink
-                assert!(sink.flush().await.is_ok());
-                assert!(sink.close().await.is_ok());
-            });
+            // Verify the sink type
+            let sink = synchronizer.get_sink();
+            rt.block_on(async {
+                // Send a test message to verify the sink works
+                let message = create_test_message();
+                let result = sink.send(vec![message]).await;
+                assert!(result.is_ok());
+                
+                // Flush and close the sink
+                assert!(sink.flush().await.is_ok());
+                assert!(sink.close().await.is_ok());
+            });
+        }
+        
+        // Test with a custom sink implementation
+        {
+            // Create a custom sink that counts messages
+            #[derive(Clone)]
+            struct CountingSink {
+                count: Arc<tokio::sync::Mutex<usize>>,
+            }
+            
+            impl CountingSink {
+                fn new() -> Self {
+                    Self {
+                        count: Arc::new(tokio::sync::Mutex::new(0)),
+                    }
+                }
+                
+                async fn get_count(&self) -> usize {
+                    let count = self.count.lock().await;
+                    *count
+                }
+            }
+            
+            #[async_trait]
+            impl CdcSink for CountingSink {
+                async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+                    let mut count = self.count.lock().await;
+                    *count += messages.len();
+                    Ok(())
+                }
+                
+                async fn flush(&self) -> Result<()> {
+                    Ok(())
+                }
+                
+                async fn close(&self) -> Result<()> {
+                    Ok(())
+                }
+            }
+            
+            let counting_sink = CountingSink::new();
+            let counting_sink_clone = counting_sink.clone();
+            let test_runtime = WasmRuntime::for_testing().unwrap();
+            let synchronizer = BlockSynchronizer::new(client.clone(), test_runtime, Box::new(counting_sink), 6).unwrap();
+            
+            // Verify the sink works
+            rt.block_on(async {
+                // Get the sink from the synchronizer
+                let sink = synchronizer.get_sink();
+                
+                // Send test messages
+                let messages = vec![
+                    create_test_message(),
+                    create_test_message(),
+                    create_test_message(),
+                ];
+                
+                let result = sink.send(messages).await;
+                assert!(result.is_ok());
+                
+                // Verify the count
+                assert_eq!(counting_sink_clone.get_count().await, 3);
+            });
+        }
+    }
+    
+    /// A sink that records every send and counts flushes
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sends: Arc<std::sync::Mutex<Vec<Vec<CdcMessage>>>>,
+        flushes: Arc<std::sync::Mutex<usize>>,
+    }
+    
+    impl RecordingSink {
+        fn sends(&self) -> Vec<Vec<CdcMessage>> {
+            self.sends.lock().unwrap().clone()
+        }
+        
+        fn flushes(&self) -> usize {
+            *self.flushes.lock().unwrap()
+        }
+    }
+    
+    #[async_trait]
+    impl CdcSink for RecordingSink {
+        async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+            self.sends.lock().unwrap().push(messages);
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            *self.flushes.lock().unwrap() += 1;
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    /// A WASM runtime whose transform pushes one create message per block
+    fn message_pushing_runtime() -> WasmRuntime {
+        runtime_pushing(&create_test_message())
+    }
+    
+    /// A WASM runtime pushing the test message for every block, whose
+    /// `rollback` export pushes `undo` instead
+    fn runtime_with_rollback(undo: &CdcMessage) -> WasmRuntime {
+        let layout = |message: &CdcMessage| -> String {
+            debshrew_runtime::exports::to_arraybuffer_layout(serde_json::to_vec(message).unwrap())
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect()
+        };
+        
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (data (i32.const 4096) "{}")
+                (func (export "process_block") (result i32)
+                    (call $push (i32.const 0))
+                )
+                (func (export "rollback") (result i32)
+                    (call $push (i32.const 4096))
+                )
+            )
+            "#,
+            layout(&create_test_message()),
+            layout(undo),
+        ))
+        .unwrap();
+        
+        WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+    }
+    
+    /// A WASM runtime whose transform pushes `message` for every block
+    fn runtime_pushing(message: &CdcMessage) -> WasmRuntime {
+        let message = serde_json::to_vec(message).unwrap();
+        let data: String = debshrew_runtime::exports::to_arraybuffer_layout(&message)
+            .iter()
+            .map(|b| format!("\\{:02x}", b))
+            .collect();
+        
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "process_block") (result i32)
+                    (call $push (i32.const 0))
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+            data
+        ))
+        .unwrap();
+        
+        WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+    }
+    
+    /// A mock client whose blocks in `fork` have hashes tagged with `tag`
+    fn client_with_hashes(height: u32, fork: &[u32], tag: u8) -> MockMetashrewClient {
+        let mut client = MockMetashrewClient::new();
+        client.set_height(height);
+        
+        for i in 0..=height {
+            let hash = if fork.contains(&i) { vec![i as u8, tag] } else { vec![i as u8] };
+            client.set_block_hash(i, hash);
+        }
+        
+        client
+    }
+    
+    fn count_operation(sends: &[Vec<CdcMessage>], operation: CdcOperation) -> usize {
+        sends.iter().flatten().filter(|m| m.payload.operation == operation).count()
+    }
+    
+    #[test]
+    fn test_blocks_per_flush() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(9, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_blocks_per_flush(Some(3));
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for height in 1..=8 {
+                synchronizer.process_block(height).await.unwrap();
+            }
+        });
+        
+        // Two full windows were sent, the third is still buffered
+        assert_eq!(sink.sends().len(), 2);
+        assert!(sink.sends().iter().all(|messages| messages.len() == 3));
+        assert_eq!(sink.flushes(), 2);
+        
+        rt.block_on(async {
+            synchronizer.process_block(9).await.unwrap();
+        });
+        
+        assert_eq!(sink.sends().len(), 3);
+        assert_eq!(sink.flushes(), 3);
+    }
+    
+    #[test]
+    fn test_duplicate_block_is_not_reprocessed() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for height in 0..=2 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+            
+            // The same height and hash again emits nothing
+            synchronizer.process_block(2).await.unwrap();
+        });
+        
+        assert_eq!(sink.sends().len(), 3);
+        assert_eq!(synchronizer.stats().blocks_processed, 3);
+        
+        // The same height with a new hash is a reorg: the old block is
+        // inverted and the new one processed
+        synchronizer.client = Arc::new(client_with_hashes(2, &[2], 1));
+        rt.block_on(synchronizer.process_block(2)).unwrap();
+        
+        let sends = sink.sends();
+        assert_eq!(count_operation(&sends, CdcOperation::Delete), 1);
+        assert_eq!(count_operation(&sends, CdcOperation::Create), 4);
+        assert_eq!(synchronizer.get_current_height(), 2);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_request_delays_block() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        let message = serde_json::to_vec(&create_test_message()).unwrap();
+        let data: String = debshrew_runtime::exports::to_arraybuffer_layout(&message)
+            .iter()
+            .map(|b| format!("\\{:02x}", b))
+            .collect();
+        
+        // Ask for a retry while the status view reports a problem, otherwise
+        // push one message
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__view" (func $view (param i32 i32) (result i32)))
+                (import "env" "__request_retry" (func $retry (param i32 i32) (result i32)))
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\06\00\00\00status")
+                (data (i32.const 16) "\00\00\00\00")
+                (data (i32.const 32) "\10\00\00\00status view down")
+                (data (i32.const 1024) "{}")
+                (func (export "process_block") (result i32)
+                    (if (i32.gt_s (call $view (i32.const 0) (i32.const 16)) (i32.const 0))
+                        (then (return (call $retry (i32.const 500) (i32.const 32)))))
+                    (call $push (i32.const 1024))
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+            data
+        ))
+        .unwrap();
+        
+        // The view is down on the first call only
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        let view_calls = calls.clone();
+        runtime.register_view("status", move |_| {
+            match view_calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(b"down".to_vec()),
+                _ => Ok(Vec::new()),
+            }
+        });
+        
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1, &[], 0),
+            runtime,
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        let start = time::Instant::now();
+        synchronizer.process_block(1).await.unwrap();
+        
+        // The block was held back for the requested delay, then processed once
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(sink.sends().into_iter().flatten().count(), 1);
+        assert_eq!(synchronizer.stats().blocks_processed, 1);
+    }
+    
+    /// A runtime whose transform asks for every block to be retried in 2000000 seconds
+    fn always_retrying_runtime() -> WasmRuntime {
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__request_retry" (func $retry (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\07\00\00\00not yet")
+                (func (export "process_block") (result i32)
+                    (call $retry (i32.const 2000000000) (i32.const 0))
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_block_retries_are_bounded() {
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1, &[], 0),
+            always_retrying_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_max_block_retries(Some(2));
+        
+        // Each wait is capped, and the block fails once its retries run out
+        let start = time::Instant::now();
+        match synchronizer.process_block(1).await {
+            Err(Error::BlockSynchronization(_)) => {}
+            other => panic!("Expected a block synchronization error, got {:?}", other),
+        }
+        assert!(start.elapsed() >= MAX_BLOCK_RETRY_DELAY * 2);
+        assert!(start.elapsed() < MAX_BLOCK_RETRY_DELAY * 3);
+        assert_eq!(synchronizer.stats().blocks_processed, 0);
+    }
+    
+    #[tokio::test]
+    async fn test_stop_interrupts_block_retries() {
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            always_retrying_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        synchronizer.set_polling_interval(10);
+        
+        let handle = synchronizer.stop_handle();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(100)).await;
+            handle.stop();
+        });
+        
+        // The run ends without waiting out the retry, leaving the block unprocessed
+        time::timeout(Duration::from_secs(10), synchronizer.run())
+            .await
+            .expect("stopping did not interrupt the retry")
+            .unwrap();
+        assert_eq!(synchronizer.get_current_height(), 1);
+        assert_eq!(synchronizer.stats().blocks_processed, 0);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_view_failure_is_retried() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        // Fail when the status view fails, otherwise push nothing
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__view" (func $view (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\06\00\00\00status")
+                (data (i32.const 16) "\00\00\00\00")
+                (func (export "process_block") (result i32)
+                    (if (i32.lt_s (call $view (i32.const 0) (i32.const 16)) (i32.const 0))
+                        (then (return (i32.const -1))))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        // Metashrew is unavailable for the first two view calls
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        let view_calls = calls.clone();
+        runtime.register_view("status", move |_| {
+            match view_calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(anyhow::anyhow!("metashrew unavailable")),
+                _ => Ok(Vec::new()),
+            }
+        });
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            runtime,
+            Box::new(RecordingSink::default()),
+            6,
+        ).unwrap();
+        synchronizer.set_polling_interval(200);
+        
+        let start = time::Instant::now();
+        synchronizer.process_block(1).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(synchronizer.stats().blocks_processed, 1);
+        
+        // A transform failing without a view failure is not retried
+        let failing_bytes = wat::parse_str(
+            r#"
+            (module
+                (memory (export "memory") 1)
+                (func (export "process_block") (result i32)
+                    (i32.const -1)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        synchronizer.runtime = Arc::new(Mutex::new(WasmRuntime::from_bytes(&failing_bytes).unwrap()));
+        match synchronizer.process_block(2).await {
+            Err(Error::Runtime(debshrew_runtime::error::Error::Transform(_))) => {}
+            other => panic!("Expected a transform error, got {:?}", other.err()),
+        }
+        assert_eq!(synchronizer.stats().blocks_processed, 1);
+    }
+    
+    /// A client whose views answer after a delay
+    struct SlowViewClient {
+        inner: MockMetashrewClient,
+        delay: Duration,
+    }
+    
+    #[async_trait]
+    impl MetashrewClient for SlowViewClient {
+        async fn get_height(&self) -> Result<u32> {
+            self.inner.get_height().await
+        }
+        
+        async fn get_block_hash(&self, height: u32) -> Result<Vec<u8>> {
+            self.inner.get_block_hash(height).await
+        }
+        
+        async fn call_view(&self, view_name: &str, params: &[u8], height: Option<u32>) -> Result<Vec<u8>> {
+            time::sleep(self.delay).await;
+            self.inner.call_view(view_name, params, height).await
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_view_timeout_reports_hung_view_to_transform() {
+        // Store the status of the `status` view under the `status` state key
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__view_status" (func $view_status (param i32 i32 i32) (result i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\06\00\00\00status")
+                (data (i32.const 16) "\00\00\00\00")
+                (data (i32.const 48) "\06\00\00\00status")
+                (func (export "process_block") (result i32)
+                    (i32.store (i32.const 96) (i32.const 4))
+                    (i32.store (i32.const 100) (call $view_status (i32.const 0) (i32.const 16) (i32.const 64)))
+                    (drop (call $set_state (i32.const 48) (i32.const 96)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        // Process block 1 against metashrew views answering after a delay
+        let status_after = |delay: Duration| {
+            let wasm_bytes = wasm_bytes.clone();
+            async move {
+                let mut runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+                runtime.set_view_timeout(Some(Duration::from_millis(100)));
+                let mut inner = client_with_hashes(1, &[], 0);
+                inner.set_view_result("status", &[], Some(1), vec![1]);
+                let client = SlowViewClient { inner, delay };
+                let mut synchronizer = BlockSynchronizer::new(client, runtime, Box::new(NullSink::new()), 6).unwrap();
+                
+                let start = time::Instant::now();
+                synchronizer.process_block(1).await.unwrap();
+                
+                let state = synchronizer.runtime.lock().await.get_state();
+                let status = i32::from_le_bytes(state.get(b"status").unwrap()[..].try_into().unwrap());
+                (status, start.elapsed())
+            }
+        };
+        
+        // A view answering in time reaches the transform
+        let (status, _) = status_after(Duration::ZERO).await;
+        assert_eq!(status, debshrew_runtime::VIEW_OK);
+        
+        // A hung view is cancelled and reported to the transform once the timeout passes
+        let (status, elapsed) = status_after(Duration::from_secs(60)).await;
+        assert_eq!(status, debshrew_runtime::VIEW_TIMED_OUT);
+        assert!(elapsed < Duration::from_secs(5));
+    }
+    
+    /// A sink that holds sent messages until a slow flush
+    #[derive(Clone, Default)]
+    struct SlowFlushSink {
+        /// Blocks' messages held since the last flush, and the most ever held
+        held: Arc<std::sync::Mutex<(usize, usize)>>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for SlowFlushSink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            let mut held = self.held.lock().unwrap();
+            held.0 += 1;
+            held.1 = held.1.max(held.0);
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            time::sleep(Duration::from_millis(100)).await;
+            self.held.lock().unwrap().0 = 0;
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    /// A sink with slow sends that logs its sends and closes
+    #[derive(Clone, Default)]
+    struct SlowSendSink {
+        events: Arc<std::sync::Mutex<Vec<&'static str>>>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for SlowSendSink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            time::sleep(Duration::from_millis(100)).await;
+            self.events.lock().unwrap().push("send");
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            self.events.lock().unwrap().push("close");
+            Ok(())
+        }
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_waits_for_buffered_blocks_and_close() {
+        let sink = SlowSendSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_blocks_per_flush(Some(2));
+        synchronizer.process_block(1).await.unwrap();
+        assert!(sink.events.lock().unwrap().is_empty());
+        
+        let events = sink.events.clone();
+        let stopped = tokio::spawn({
+            let wait = synchronizer.wait_stopped();
+            async move {
+                wait.await;
+                events.lock().unwrap().push("stopped");
+            }
+        });
+        
+        // Not stopped before shutdown
+        time::sleep(Duration::from_millis(500)).await;
+        assert!(!stopped.is_finished());
+        
+        // The buffered block is sent and the sink closed before shutdown returns
+        synchronizer.shutdown().await.unwrap();
+        assert_eq!(*sink.events.lock().unwrap(), vec!["send", "close"]);
+        
+        stopped.await.unwrap();
+        assert_eq!(*sink.events.lock().unwrap(), vec!["send", "close", "stopped"]);
+        
+        // Waiting after shutdown completes immediately
+        synchronizer.wait_stopped().await;
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_max_inflight_blocks_applies_backpressure() {
+        let sink = SlowFlushSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(6, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_max_inflight_blocks(Some(2));
+        
+        let start = time::Instant::now();
+        for height in 1..=6 {
+            synchronizer.process_block(height).await.unwrap();
+        }
+        
+        // Processing waited for a flush every two blocks instead of letting
+        // the sink hold all six
+        assert_eq!(sink.held.lock().unwrap().1, 2);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+    
+    /// A slow sink recording the order of sends and the most sends in flight at once
+    #[derive(Clone, Default)]
+    struct ConcurrencySink {
+        /// Sends in flight, and the most ever in flight
+        in_flight: Arc<std::sync::Mutex<(usize, usize)>>,
+        
+        /// Block heights of the messages, in the order sends completed
+        heights: Arc<std::sync::Mutex<Vec<u32>>>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for ConcurrencySink {
+        async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.lock().unwrap().0 -= 1;
+            self.heights.lock().unwrap().extend(messages.iter().map(|m| m.header.block_height));
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_relaxed_ordering_sends_blocks_concurrently() {
+        let batch = |height: u32| {
+            let mut message = create_test_message();
+            message.header.block_height = height;
+            (height, vec![message])
+        };
+        
+        for (ordering, max_in_flight) in [(Ordering::Strict, 1), (Ordering::Relaxed, 3)] {
+            let sink = ConcurrencySink::default();
+            let mut synchronizer = BlockSynchronizer::new(
+                client_with_hashes(3, &[], 0),
+                message_pushing_runtime(),
+                Box::new(sink.clone()),
+                6,
+            ).unwrap();
+            synchronizer.set_ordering(ordering);
+            
+            synchronizer.deliver(vec![batch(1), batch(2), batch(3)]).await.unwrap();
+            
+            assert_eq!(sink.in_flight.lock().unwrap().1, max_in_flight);
+            assert_eq!(synchronizer.stats().total_messages(), 3);
+            if ordering == Ordering::Strict {
+                assert_eq!(*sink.heights.lock().unwrap(), vec![1, 2, 3]);
+            } else {
+                let mut heights = sink.heights.lock().unwrap().clone();
+                heights.sort_unstable();
+                assert_eq!(heights, vec![1, 2, 3]);
+            }
+        }
+    }
+    
+    #[test]
+    fn test_snapshot_marker_reaches_sink() {
+        let message = serde_json::to_vec(&create_test_message()).unwrap();
+        let encode = |bytes: &[u8]| -> String {
+            debshrew_runtime::exports::to_arraybuffer_layout(bytes)
+                .iter()
+                .map(|b| format!("\\{:02x}", b))
+                .collect()
+        };
+        
+        // Push one row, then mark the table's snapshot complete
+        let wasm_bytes = wat::parse_str(format!(
+            r#"
+            (module
+                (import "env" "__push_cdc_message" (func $push (param i32) (result i32)))
+                (import "env" "__mark_snapshot_complete" (func $mark (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (data (i32.const 4096) "{}")
+                (func (export "process_block") (result i32)
+                    (drop (call $push (i32.const 0)))
+                    (call $mark (i32.const 4096))
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+            encode(&message),
+            encode(b"test_table")
+        ))
+        .unwrap();
+        
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1, &[], 0),
+            WasmRuntime::from_bytes(&wasm_bytes).unwrap(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(synchronizer.process_block(1)).unwrap();
+        
+        let messages: Vec<CdcMessage> = sink.sends().into_iter().flatten().collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload.operation, CdcOperation::Create);
+        
+        // The marker follows the snapshot rows and carries no row data
+        let marker = &messages[1];
+        assert_eq!(marker.payload.operation, CdcOperation::SnapshotComplete);
+        assert_eq!(marker.payload.table, "test_table");
+        assert_eq!(marker.header.block_height, 1);
+        assert!(marker.payload.before.is_none() && marker.payload.after.is_none());
+        assert_eq!(
+            serde_json::to_value(marker).unwrap()["payload"]["operation"],
+            "snapshot_complete"
+        );
+    }
+    
+    #[test]
+    fn test_step_outcomes() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(3, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        
+        // Forward: all new blocks are processed in one step
+        let outcome = rt.block_on(synchronizer.step()).unwrap();
+        assert_eq!(outcome, StepOutcome::Processed { from: 1, to: 3 });
+        assert_eq!(synchronizer.get_current_height(), 3);
+        assert_eq!(sink.sends().len(), 3);
+        
+        // Idle: metashrew has nothing new
+        let outcome = rt.block_on(synchronizer.step()).unwrap();
+        assert_eq!(outcome, StepOutcome::Idle);
+        assert_eq!(sink.sends().len(), 3);
+        
+        // Reorg: metashrew dropped back to a fork at block 2
+        synchronizer.client = Arc::new(client_with_hashes(2, &[2], 1));
+        let outcome = rt.block_on(synchronizer.step()).unwrap();
+        assert_eq!(outcome, StepOutcome::Reorg { height: 2 });
+        assert_eq!(synchronizer.get_current_height(), 2);
+        assert_eq!(count_operation(&sink.sends(), CdcOperation::Delete), 2);
+    }
+    
+    #[test]
+    fn test_step_waits_for_metashrew_sync() {
+        let sink = RecordingSink::default();
+        let mut client = client_with_hashes(3, &[], 0);
+        client.set_chain_height(Some(5));
+        let mut synchronizer = BlockSynchronizer::new(
+            client,
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        
+        // Nothing is processed while metashrew is behind the chain
+        for _ in 0..3 {
+            let outcome = rt.block_on(synchronizer.step()).unwrap();
+            assert_eq!(outcome, StepOutcome::WaitingForSync { indexed_height: 3, chain_height: 5 });
+        }
+        assert_eq!(synchronizer.get_current_height(), 0);
+        assert!(sink.sends().is_empty());
+        
+        // Once metashrew catches up, the indexed blocks are processed
+        synchronizer.client = Arc::new(client_with_hashes(3, &[], 0));
+        let outcome = rt.block_on(synchronizer.step()).unwrap();
+        assert_eq!(outcome, StepOutcome::Processed { from: 1, to: 3 });
+        assert_eq!(sink.sends().len(), 3);
+    }
+    
+    #[test]
+    fn test_catch_up_processes_to_tip() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(5, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        synchronizer.set_blocks_per_flush(Some(3));
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(synchronizer.catch_up()).unwrap();
+        
+        // Blocks 2-5 were processed and the trailing partial batch was sent
+        assert_eq!(synchronizer.get_current_height(), 5);
+        assert_eq!(synchronizer.stats().blocks_processed, 4);
+        let sends = sink.sends();
+        assert_eq!(sends.len(), 2);
+        assert_eq!(sends.iter().map(Vec::len).sum::<usize>(), 4);
+    }
+    
+    #[tokio::test]
+    async fn test_scripted_reorg_through_step() {
+        use crate::client::{ChainState, ScriptedMetashrewClient};
+        
+        // Tip 10, then a reorg replacing block 7 onwards, then extended to 12
+        let original = |height: u32| vec![height as u8];
+        let replaced = |height: u32| vec![height as u8, 1];
+        let client = ScriptedMetashrewClient::new(vec![
+            ChainState::new().with_blocks(0..=10, original),
+            ChainState::new().with_blocks(0..=6, original).with_blocks(7..=7, replaced),
+            ChainState::new().with_blocks(0..=6, original).with_blocks(7..=12, replaced),
+        ]).unwrap();
+        
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client,
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Processed { from: 1, to: 10 });
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Reorg { height: 7 });
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Processed { from: 8, to: 12 });
+        assert!(synchronizer.client.is_finished());
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Idle);
+        
+        // Blocks 7-10 were reverted, and the new chain's blocks processed
+        let sends = sink.sends();
+        assert_eq!(count_operation(&sends, CdcOperation::Delete), 4);
+        assert_eq!(count_operation(&sends, CdcOperation::Create), 10 + 6);
+        assert_eq!(synchronizer.get_current_height(), 12);
+        assert_eq!(synchronizer.cache.lock().await.get_block_hash(7), Some(hex::encode(replaced(7))));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_reorg_settle_coalesces_flapping_chain() {
+        use crate::client::{ChainState, ScriptedMetashrewClient};
+        
+        // Tip 10, then flapping between two forks and the original chain
+        // before settling on the second fork at 9
+        let original = |height: u32| vec![height as u8];
+        let fork_a = |height: u32| vec![height as u8, 1];
+        let fork_b = |height: u32| vec![height as u8, 2];
+        let settled = ChainState::new().with_blocks(0..=6, original).with_blocks(7..=9, fork_b);
+        let client = ScriptedMetashrewClient::new(vec![
+            ChainState::new().with_blocks(0..=10, original),
+            ChainState::new().with_blocks(0..=6, original).with_blocks(7..=8, fork_a),
+            ChainState::new().with_blocks(0..=10, original),
+            settled.clone(),
+            settled,
+        ]).unwrap();
+        
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client,
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_reorg_settle(Some(500));
+        
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Processed { from: 1, to: 10 });
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Reorg { height: 9 });
+        assert!(synchronizer.client.is_finished());
+        
+        // Only the settled fork was processed, in a single reorg
+        let sends = sink.sends();
+        assert_eq!(synchronizer.stats().reorgs, 1);
+        assert_eq!(count_operation(&sends, CdcOperation::Delete), 4);
+        assert_eq!(count_operation(&sends, CdcOperation::Create), 10 + 3);
+        assert_eq!(synchronizer.get_current_height(), 9);
+        assert_eq!(synchronizer.cache.lock().await.get_block_hash(7), Some(hex::encode(fork_b(7))));
+        
+        // A flap that settles back on the original chain is not a reorg
+        let client = ScriptedMetashrewClient::new(vec![
+            ChainState::new().with_blocks(0..=10, original),
+            ChainState::new().with_blocks(0..=6, original).with_blocks(7..=8, fork_a),
+            ChainState::new().with_blocks(0..=10, original),
+        ]).unwrap();
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client,
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_reorg_settle(Some(500));
+        
+        synchronizer.step().await.unwrap();
+        assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Idle);
+        assert_eq!(synchronizer.stats().reorgs, 0);
+        assert_eq!(count_operation(&sink.sends(), CdcOperation::Delete), 0);
+        assert_eq!(synchronizer.get_current_height(), 10);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_reorg_settle_is_bounded() {
+        use crate::client::{ChainState, ScriptedMetashrewClient};
+        
+        // Tip 10, then flapping between two forks for longer than the
+        // settle polls allow
+        let original = |height: u32| vec![height as u8];
+        let fork_a = |height: u32| vec![height as u8, 1];
+        let fork_b = |height: u32| vec![height as u8, 2];
+        let mut states = vec![ChainState::new().with_blocks(0..=10, original)];
+        for flap in 0..MAX_SETTLE_POLLS * 3 {
+            states.push(match flap % 2 {
+                0 => ChainState::new().with_blocks(0..=6, original).with_blocks(7..=8, fork_a),
+                _ => ChainState::new().with_blocks(0..=6, original).with_blocks(7..=9, fork_b),
+            });
+        }
+        let client = ScriptedMetashrewClient::new(states).unwrap();
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            client,
+            message_pushing_runtime(),
+            Box::new(RecordingSink::default()),
+            6,
+        ).unwrap();
+        synchronizer.set_reorg_settle(Some(500));
+        synchronizer.step().await.unwrap();
+        
+        // The reorg is handled at the latest tip once the polls run out,
+        // while the chain is still flapping
+        let start = time::Instant::now();
+        assert!(matches!(synchronizer.step().await.unwrap(), StepOutcome::Reorg { .. }));
+        assert_eq!(start.elapsed(), Duration::from_millis(500) * MAX_SETTLE_POLLS);
+        assert!(!synchronizer.client.is_finished());
+        assert_eq!(synchronizer.stats().reorgs, 1);
+    }
+    
+    #[tokio::test]
+    async fn test_reorg_markers_surround_inverse_messages() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(5, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_reorg_markers(true);
+        for height in 1..=5 {
+            synchronizer.process_block(height).await.unwrap();
+            synchronizer.current_height = height;
+        }
+        
+        // Blocks 4 and 5 are replaced
+        synchronizer.client = Arc::new(client_with_hashes(5, &[4, 5], 1));
+        synchronizer.handle_reorg(5).await.unwrap();
+        
+        let messages: Vec<CdcMessage> = sink.sends().into_iter().skip(5).flatten().collect();
+        let operations: Vec<CdcOperation> = messages.iter().map(|m| m.payload.operation).collect();
+        assert_eq!(operations, vec![
+            CdcOperation::ReorgBegin,
+            CdcOperation::Delete,
+            CdcOperation::Delete,
+            CdcOperation::ReorgEnd,
+            CdcOperation::Create,
+            CdcOperation::Create,
+        ]);
+        
+        let begin = &messages[0];
+        assert_eq!(begin.payload.table, REORG_TABLE);
+        assert_eq!(begin.header.block_height, 5);
+        assert_eq!(begin.payload.after, Some(serde_json::json!({"from_height": 5, "to_height": 3})));
+        let end = &messages[3];
+        assert_eq!(end.header.block_height, 3);
+        assert_eq!(end.header.block_hash, hex::encode([3u8]));
+        assert_eq!(end.payload.after, begin.payload.after);
+    }
+    
+    #[tokio::test]
+    async fn test_rollback_modes() {
+        let mut undo = create_test_message();
+        undo.payload.operation = CdcOperation::Delete;
+        undo.payload.key = "undone_by_transform".to_string();
+        
+        for mode in [RollbackMode::Auto, RollbackMode::Manual] {
+            let sink = RecordingSink::default();
+            let mut synchronizer = BlockSynchronizer::new(
+                client_with_hashes(5, &[], 0),
+                runtime_with_rollback(&undo),
+                Box::new(sink.clone()),
+                6,
+            ).unwrap();
+            synchronizer.set_rollback_mode(mode).await.unwrap();
+            for height in 1..=5 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+            
+            // Blocks 4 and 5 are replaced
+            synchronizer.client = Arc::new(client_with_hashes(5, &[4, 5], 1));
+            synchronizer.handle_reorg(5).await.unwrap();
+            
+            let undone: Vec<String> = sink.sends()[5].iter()
+                .map(|m| format!("{}:{}", m.payload.operation.as_str(), m.payload.key))
+                .collect();
+            match mode {
+                // One inverse message per rolled-back block
+                RollbackMode::Auto => assert_eq!(undone, vec!["delete:test_key", "delete:test_key"]),
+                // Whatever the transform's rollback pushes
+                RollbackMode::Manual => assert_eq!(undone, vec!["delete:undone_by_transform"]),
+            }
+            assert_eq!(count_operation(&sink.sends()[6..], CdcOperation::Create), 2);
+            assert_eq!(synchronizer.get_current_height(), 5);
+        }
+        
+        // Manual rollback is refused for a transform without a rollback export
+        let wasm_bytes = wat::parse_str(
+            r#"(module (memory (export "memory") 1) (func (export "process_block") (result i32) i32.const 0))"#,
+        )
+        .unwrap();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(5, &[], 0),
+            WasmRuntime::from_bytes(&wasm_bytes).unwrap(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        assert!(synchronizer.set_rollback_mode(RollbackMode::Manual).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_reprocess_with_new_module() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(5, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        for height in 1..=5 {
+            synchronizer.process_block(height).await.unwrap();
+            synchronizer.current_height = height;
+        }
+        
+        // The fixed transform writes its rows to another table
+        let mut corrected = create_test_message();
+        corrected.payload.table = "corrected".to_string();
+        synchronizer.reprocess(3, runtime_pushing(&corrected), true).await.unwrap();
+        
+        // The old output of blocks 3-5 is inverted, then replaced
+        let sends = sink.sends();
+        let messages: Vec<&CdcMessage> = sends[5..].iter().flatten().collect();
+        let summary: Vec<(CdcOperation, &str)> = messages.iter()
+            .map(|m| (m.payload.operation, m.payload.table.as_str()))
+            .collect();
+        assert_eq!(summary, vec![
+            (CdcOperation::Delete, "test_table"),
+            (CdcOperation::Delete, "test_table"),
+            (CdcOperation::Delete, "test_table"),
+            (CdcOperation::Create, "corrected"),
+            (CdcOperation::Create, "corrected"),
+            (CdcOperation::Create, "corrected"),
+        ]);
+        assert_eq!(synchronizer.get_current_height(), 5);
+        
+        // Later blocks use the new module, and a reorg inverts the corrected output
+        synchronizer.client = Arc::new(client_with_hashes(6, &[5, 6], 1));
+        synchronizer.handle_reorg(6).await.unwrap();
+        let sends = sink.sends();
+        let inverted: Vec<&str> = sends[8..].iter().flatten()
+            .filter(|m| m.payload.operation == CdcOperation::Delete)
+            .map(|m| m.payload.table.as_str())
+            .collect();
+        assert_eq!(inverted, vec!["corrected"]);
+        
+        // Only cached blocks can be reprocessed
+        assert!(synchronizer.reprocess(0, message_pushing_runtime(), false).await.is_err());
+        assert!(synchronizer.reprocess(7, message_pushing_runtime(), false).await.is_err());
+    }
+    
+    #[test]
+    fn test_reorg_discards_unflushed_blocks() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(4, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_blocks_per_flush(Some(3));
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for height in 1..=4 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+        });
+        
+        // Blocks 1-3 were sent, block 4 is buffered
+        assert_eq!(sink.sends().len(), 1);
+        
+        // Reorg of the buffered block only: nothing to invert
+        synchronizer.client = Arc::new(client_with_hashes(4, &[4], 1));
+        rt.block_on(async {
+            synchronizer.handle_reorg(4).await.unwrap();
+            synchronizer.flush_pending().await.unwrap();
+        });
+        
+        let sends = sink.sends();
+        assert_eq!(sends.len(), 2);
+        assert_eq!(sends[1].len(), 1);
+        assert_eq!(count_operation(&sends, CdcOperation::Delete), 0);
+        
+        // Buffer block 5, then reorg back past the last flush
+        synchronizer.client = Arc::new(client_with_hashes(5, &[4], 1));
+        rt.block_on(async {
+            synchronizer.process_block(5).await.unwrap();
+            synchronizer.current_height = 5;
+        });
+        
+        synchronizer.client = Arc::new(client_with_hashes(5, &[3, 4, 5], 2));
+        rt.block_on(async {
+            synchronizer.handle_reorg(5).await.unwrap();
+        });
+        
+        // Blocks 3 and 4 had been sent and are inverted; block 5 is dropped
+        // and the replacement blocks 3-5 are sent as one window
+        let sends = sink.sends();
+        assert_eq!(sends.len(), 4);
+        assert_eq!(count_operation(&sends[2..3], CdcOperation::Delete), 2);
+        assert_eq!(sends[3].len(), 3);
+        assert_eq!(count_operation(&sends, CdcOperation::Create), 3 + 1 + 3);
+    }
+    
+    #[test]
+    fn test_rollback_hook_sees_common_ancestor() {
+        // A transform whose hook stores the height it is called at under the
+        // `ancestor` state key
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__height" (func $height (result i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\08\00\00\00ancestor")
+                (func (export "process_block") (result i32)
+                    i32.const 0
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+                (func (export "on_rollback_complete") (result i32)
+                    (i32.store (i32.const 64) (i32.const 4))
+                    (i32.store (i32.const 68) (call $height))
+                    (drop (call $set_state (i32.const 0) (i32.const 64)))
+                    (i32.const 0)
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(4, &[], 0),
+            WasmRuntime::from_bytes(&wasm_bytes).unwrap(),
+            Box::new(RecordingSink::default()),
+            6,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for height in 1..=4 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+            assert_eq!(synchronizer.runtime.lock().await.get_state().get(b"ancestor"), None);
+            
+            // Blocks 3 and 4 are replaced, so the common ancestor is block 2;
+            // the hook's write survives replaying the new blocks
+            synchronizer.client = Arc::new(client_with_hashes(4, &[3, 4], 1));
+            synchronizer.handle_reorg(4).await.unwrap();
+            
+            let state = synchronizer.runtime.lock().await.get_state();
+            assert_eq!(state.get(b"ancestor"), Some(&2u32.to_le_bytes().to_vec()));
+        });
+        assert_eq!(synchronizer.get_current_height(), 4);
+    }
+    
+    #[test]
+    fn test_raw_block_delivered_to_transform() {
+        // A transform that stores the raw block under the `raw` state key
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__get_block_raw" (func $get_block_raw (result i32)))
+                (import "env" "__load" (func $load (param i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\03\00\00\00raw")
+                (func (export "process_block") (result i32)
+                    (local $len i32)
+                    (local.set $len (call $get_block_raw))
+                    (i32.store (i32.const 16) (local.get $len))
+                    (call $load (i32.const 20))
+                    (drop (call $set_state (i32.const 0) (i32.const 16)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        let runtime = WasmRuntime::from_bytes(&wasm_bytes).unwrap();
+        assert!(runtime.uses_block_raw());
+        
+        let mut client = client_with_hashes(2, &[], 0);
+        client.set_block(1, vec![0xde, 0xad, 0xbe, 0xef]);
+        client.set_block(2, vec![0x01, 0x02]);
+        
+        let mut synchronizer = BlockSynchronizer::new(client, runtime, Box::new(NullSink::new()), 6).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            synchronizer.process_block(1).await.unwrap();
+            let state = synchronizer.runtime.lock().await.get_state();
+            assert_eq!(state.get(b"raw"), Some(&vec![0xde, 0xad, 0xbe, 0xef]));
+            
+            synchronizer.process_block(2).await.unwrap();
+            let state = synchronizer.runtime.lock().await.get_state();
+            assert_eq!(state.get(b"raw"), Some(&vec![0x01, 0x02]));
+        });
+        
+        // Transforms that don't read raw blocks never trigger a fetch, so
+        // they process blocks metashrew has no raw data for
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1, &[], 0),
+            message_pushing_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        rt.block_on(synchronizer.process_block(1)).unwrap();
+    }
+    
+    /// A client that answers slowly for block hashes at or above `slow_from`
+    struct SlowClient {
+        inner: MockMetashrewClient,
+        slow_from: u32,
+        delay: Duration,
+    }
+    
+    #[async_trait]
+    impl MetashrewClient for SlowClient {
+        async fn get_height(&self) -> Result<u32> {
+            self.inner.get_height().await
+        }
+        
+        async fn get_block_hash(&self, height: u32) -> Result<Vec<u8>> {
+            if height >= self.slow_from {
+                time::sleep(self.delay).await;
+            }
+            self.inner.get_block_hash(height).await
+        }
+        
+        async fn call_view(&self, view_name: &str, params: &[u8], height: Option<u32>) -> Result<Vec<u8>> {
+            self.inner.call_view(view_name, params, height).await
+        }
+    }
+    
+    #[test]
+    fn test_heartbeat_on_quiet_chain() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(3, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_heartbeat_interval(Some(1000));
+        
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        
+        // Poll a chain that stays at block 3 every 250 ms for 2.5 seconds
+        rt.block_on(async {
+            synchronizer.start().await.unwrap();
+            for _ in 0..10 {
+                time::advance(Duration::from_millis(250)).await;
+                assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Idle);
+            }
+        });
+        
+        let heartbeats: Vec<CdcMessage> = sink.sends().into_iter().flatten().collect();
+        assert_eq!(heartbeats.len(), 2);
+        for heartbeat in &heartbeats {
+            assert_eq!(heartbeat.payload.operation, CdcOperation::Heartbeat);
+            assert_eq!(heartbeat.payload.table, DEFAULT_HEARTBEAT_TABLE);
+            assert_eq!(heartbeat.header.block_height, 3);
+        }
+        
+        // Heartbeats are not counted as CDC messages
+        assert_eq!(synchronizer.stats().total_messages(), 0);
+    }
+    
+    #[test]
+    fn test_reorg_timeout_leaves_consistent_checkpoint() {
+        let slow_client = |inner| SlowClient { inner, slow_from: 3, delay: Duration::from_millis(100) };
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            slow_client(client_with_hashes(4, &[], 0)),
+            message_pushing_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_reorg_timeout(Some(450));
+        
+        // Paused time makes the slow client's delays deterministic
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        
+        rt.block_on(async {
+            for height in 1..=4 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+        });
+        
+        // Replace blocks 3-4 with a longer fork up to block 6. Finding the
+        // ancestor among the cached blocks takes 200 ms and each replacement
+        // block 100 ms, so the deadline passes while fetching block 5, after
+        // blocks 3 and 4 have been reprocessed.
+        synchronizer.client = Arc::new(slow_client(client_with_hashes(6, &[3, 4, 5, 6], 1)));
+        let result = rt.block_on(synchronizer.handle_reorg(6));
+        
+        match result {
+            Err(Error::ReorgHandling(message)) => assert!(message.contains("timeout"), "{}", message),
+            other => panic!("Expected reorg timeout, got {:?}", other),
+        }
+        
+        // The synchronizer, cache, and runtime all agree on the checkpoint
+        assert_eq!(synchronizer.get_current_height(), 4);
+        rt.block_on(async {
+            let cache = synchronizer.cache.lock().await;
+            assert_eq!(cache.highest_height(), Some(4));
+            assert_eq!(cache.get_block_hash(4), Some(hex::encode([4u8, 1])));
+            assert_eq!(cache.get_block_hash(2), Some(hex::encode([2u8])));
+            assert_eq!(
+                synchronizer.runtime.lock().await.get_state().len(),
+                cache.get_state_snapshot(4).unwrap().len()
+            );
+        });
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_reorg_timeout_bounds_a_hanging_request() {
+        // Metashrew hangs for a minute when asked for the hash of block 3 or above
+        let slow_client = |inner| SlowClient { inner, slow_from: 3, delay: Duration::from_secs(60) };
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            slow_client(client_with_hashes(4, &[], 0)),
+            message_pushing_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_reorg_timeout(Some(350));
+        
+        for height in 1..=4 {
+            synchronizer.process_block(height).await.unwrap();
+            synchronizer.current_height = height;
+        }
+        
+        synchronizer.client = Arc::new(slow_client(client_with_hashes(6, &[3, 4, 5, 6], 1)));
+        
+        // The request is abandoned once the deadline passes, before anything is rolled back
+        let start = time::Instant::now();
+        match synchronizer.handle_reorg(6).await {
+            Err(Error::ReorgHandling(message)) => assert!(message.contains("timeout"), "{}", message),
+            other => panic!("Expected reorg timeout, got {:?}", other),
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(350));
+        
+        assert_eq!(synchronizer.get_current_height(), 4);
+        assert_eq!(synchronizer.cache.lock().await.get_block_hash(4), Some(hex::encode([4u8])));
+        
+        // The deadline only applies while the reorg is being handled
+        assert!(synchronizer.reorg_deadline.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_reorg_timeout_bounds_a_slow_transform() {
+        // A transform calling the `status` view, which metashrew answers
+        // after a delay
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__view_status" (func $view_status (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\06\00\00\00status")
+                (data (i32.const 16) "\00\00\00\00")
+                (func (export "process_block") (result i32)
+                    (drop (call $view_status (i32.const 0) (i32.const 16) (i32.const 64)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        let slow_client = |inner, delay| SlowViewClient { inner, delay };
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            slow_client(client_with_hashes(4, &[], 0), Duration::ZERO),
+            WasmRuntime::from_bytes(&wasm_bytes).unwrap(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        for height in 1..=4 {
+            synchronizer.process_block(height).await.unwrap();
+            synchronizer.current_height = height;
+        }
+        
+        // Reprocessing block 3 takes far longer than the reorg may
+        synchronizer.client = Arc::new(slow_client(client_with_hashes(6, &[3, 4, 5, 6], 1), Duration::from_secs(2)));
+        synchronizer.set_reorg_timeout(Some(300));
+        
+        let start = time::Instant::now();
+        match synchronizer.handle_reorg(6).await {
+            Err(Error::ReorgHandling(message)) => assert!(message.contains("timeout"), "{}", message),
+            other => panic!("Expected reorg timeout, got {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        
+        // Stopped at the common ancestor, before block 3 was added
+        assert_eq!(synchronizer.get_current_height(), 2);
+        assert_eq!(synchronizer.cache.lock().await.highest_height(), Some(2));
+        
+        // The abandoned transform releases the runtime once it finishes
+        let _ = synchronizer.runtime.lock().await;
+    }
+    
+    /// A sink whose sends always fail, simulating a crash before delivery
+    struct FailingSink;
+    
+    #[async_trait]
+    impl CdcSink for FailingSink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            Err(Error::Sink("Sink unavailable".to_string()))
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn test_wal_replayed_after_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_path = dir.path().join("cdc.wal");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        
+        // The first run records block 1 in the WAL but never delivers it
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            message_pushing_runtime(),
+            Box::new(FailingSink),
+            6,
+        ).unwrap();
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        assert!(rt.block_on(synchronizer.process_block(1)).is_err());
+        drop(synchronizer);
+        
+        let entries = WriteAheadLog::open(&wal_path).unwrap().entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].height, 1);
+        
+        // On restart the recorded messages are replayed before new blocks
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        
+        rt.block_on(async {
+            synchronizer.replay_wal().await.unwrap();
+            synchronizer.process_block(2).await.unwrap();
+        });
+        
+        let sends = sink.sends();
+        assert_eq!(sends.len(), 2);
+        assert_eq!(sends[0], entries[0].messages);
+        assert_eq!(sink.flushes(), 2);
+        
+        // Delivered messages are no longer in the WAL
+        assert!(WriteAheadLog::open(&wal_path).unwrap().is_empty().unwrap());
+    }
+    
+    #[test]
+    fn test_wal_replay_skips_checkpointed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_path = dir.path().join("cdc.wal");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        
+        // Blocks 1 and 2 are recorded in the WAL but never confirmed
+        for height in [1, 2] {
+            let mut synchronizer = BlockSynchronizer::new(
+                client_with_hashes(2, &[], 0),
+                message_pushing_runtime(),
+                Box::new(FailingSink),
+                6,
+            ).unwrap();
+            synchronizer.set_starting_height(height - 1);
+            synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+            assert!(rt.block_on(synchronizer.process_block(height)).is_err());
+        }
+        
+        let entries = WriteAheadLog::open(&wal_path).unwrap().entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.height).collect::<Vec<_>>(), vec![1, 2]);
+        
+        // The checkpoint already covers block 1, as after a crash between
+        // writing the state and truncating the WAL
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        rt.block_on(synchronizer.replay_wal()).unwrap();
+        
+        // Only block 2 is sent again
+        assert_eq!(sink.sends(), vec![entries[1].messages.clone()]);
+        assert!(WriteAheadLog::open(&wal_path).unwrap().is_empty().unwrap());
+    }
+    
+    #[test]
+    fn test_restart_resumes_from_checkpoint() {
+        use crate::state::load_checkpoint;
+        
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let wal_path = dir.path().join("cdc.wal");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        
+        // The first run checkpoints blocks 1 and 2
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(3, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_state_path(Some(state_path.clone()));
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        for height in 1..=2 {
+            rt.block_on(synchronizer.process_block(height)).unwrap();
+            synchronizer.current_height = height;
+        }
+        assert_eq!(load_checkpoint(&state_path).unwrap().height, Some(2));
+        
+        // The process crashed after checkpointing block 2 but before
+        // truncating the WAL, and again while sending block 3
+        WriteAheadLog::open(&wal_path).unwrap().append(2, &sink.sends()[1]).unwrap();
+        synchronizer.sink = Arc::new(Box::new(FailingSink));
+        assert!(rt.block_on(synchronizer.process_block(3)).is_err());
+        drop(synchronizer);
+        
+        let entries = WriteAheadLog::open(&wal_path).unwrap().entries().unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.height).collect::<Vec<_>>(), vec![2, 3]);
+        
+        // A restarted synchronizer resumes after block 2 without a starting
+        // height, replaying only block 3 before processing it again
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(3, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_state_path(Some(state_path.clone()));
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        rt.block_on(synchronizer.catch_up()).unwrap();
+        
+        let sends = sink.sends();
+        assert_eq!(sends.len(), 2);
+        assert_eq!(sends[0], entries[1].messages);
+        assert_eq!(synchronizer.get_current_height(), 3);
+        assert_eq!(load_checkpoint(&state_path).unwrap().height, Some(3));
+        assert!(WriteAheadLog::open(&wal_path).unwrap().is_empty().unwrap());
+    }
+    
+    /// A sink that stops the synchronizer after a number of sends
+    #[derive(Clone)]
+    struct StoppingSink {
+        handle: StopHandle,
+        stop_after: usize,
+        sends: Arc<std::sync::Mutex<usize>>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for StoppingSink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            let mut sends = self.sends.lock().unwrap();
+            *sends += 1;
+            if *sends == self.stop_after {
+                self.handle.stop();
+            }
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn test_large_jump_is_processed_in_chunks() {
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1000, &[], 0),
+            message_pushing_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_max_blocks_per_iteration(Some(10));
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // Each step processes one chunk of the jump
+            assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Processed { from: 1, to: 10 });
+            assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Processed { from: 11, to: 20 });
+        });
+        
+        // Stopping part way through a chunk ends the run at the chunk boundary
+        let sends = Arc::new(std::sync::Mutex::new(0));
+        synchronizer.sink = Arc::new(Box::new(StoppingSink {
+            handle: synchronizer.stop_handle(),
+            stop_after: 5,
+            sends: sends.clone(),
+        }));
+        synchronizer.set_polling_interval(10);
+        rt.block_on(synchronizer.run()).unwrap();
+        
+        assert_eq!(synchronizer.get_current_height(), 30);
+        assert_eq!(*sends.lock().unwrap(), 10);
+    }
+    
+    /// A sink that cancels a token after a number of sends
+    struct CancellingSink {
+        token: CancellationToken,
+        cancel_after: usize,
+        sends: Arc<std::sync::Mutex<usize>>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for CancellingSink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            let mut sends = self.sends.lock().unwrap();
+            *sends += 1;
+            if *sends == self.cancel_after {
+                self.token.cancel();
+            }
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+    
+    /// A sink that buffers messages until flushed, with a maximum batch age
+    struct AgingSink {
+        max_age: Duration,
+        buffered_since: std::sync::Mutex<Option<time::Instant>>,
+        flushes: Arc<std::sync::Mutex<Vec<(time::Instant, usize)>>>,
+        buffered: std::sync::Mutex<usize>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for AgingSink {
+        async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
+            let mut buffered = self.buffered.lock().unwrap();
+            if *buffered == 0 && !messages.is_empty() {
+                *self.buffered_since.lock().unwrap() = Some(time::Instant::now());
+            }
+            *buffered += messages.len();
+            Ok(())
+        }
+        
+        async fn flush(&self) -> Result<()> {
+            let flushed = std::mem::take(&mut *self.buffered.lock().unwrap());
+            self.buffered_since.lock().unwrap().take();
+            self.flushes.lock().unwrap().push((time::Instant::now(), flushed));
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        fn flush_deadline(&self) -> Option<time::Instant> {
+            Some((*self.buffered_since.lock().unwrap())? + self.max_age)
+        }
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_aged_batch_is_flushed_while_idle() {
+        // One message never fills a batch, but is flushed once it is old enough
+        let flushes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            message_pushing_runtime(),
+            Box::new(AgingSink {
+                max_age: Duration::from_millis(250),
+                buffered_since: std::sync::Mutex::new(None),
+                flushes: flushes.clone(),
+                buffered: std::sync::Mutex::new(0),
+            }),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        synchronizer.set_polling_interval(3_600_000);
+        
+        let started = time::Instant::now();
+        synchronizer.run_until(started + Duration::from_secs(5)).await.unwrap();
+        
+        assert_eq!(synchronizer.get_current_height(), 2);
+        assert_eq!(*flushes.lock().unwrap(), vec![(started + Duration::from_millis(250), 1)]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_run_returns_promptly() {
+        // Cancelling part way through a large jump stops after the block in progress
+        let token = CancellationToken::new();
+        let sends = Arc::new(std::sync::Mutex::new(0));
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(1000, &[], 0),
+            message_pushing_runtime(),
+            Box::new(CancellingSink {
+                token: token.clone(),
+                cancel_after: 5,
+                sends: sends.clone(),
+            }),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        synchronizer.run_with_token(token).await.unwrap();
+        
+        assert_eq!(synchronizer.get_current_height(), 6);
+        assert_eq!(*sends.lock().unwrap(), 5);
+        
+        // Cancelling while idle interrupts the polling sleep
+        synchronizer.set_polling_interval(3_600_000);
+        let token = CancellationToken::new();
+        tokio::spawn({
+            let token = token.clone();
+            async move {
+                time::sleep(Duration::from_millis(50)).await;
+                token.cancel();
+            }
+        });
+        
+        let started = time::Instant::now();
+        synchronizer.client = Arc::new(client_with_hashes(6, &[], 0));
+        synchronizer.run_with_token(token).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        
+        // A deadline ends the run the same way
+        let started = time::Instant::now();
+        synchronizer.run_until(started + Duration::from_secs(5)).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert_eq!(synchronizer.get_current_height(), 6);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_signal_finishes_block_in_progress() {
+        // Each block takes a second to fetch, and the signal arrives half way
+        // through block 3
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            SlowClient {
+                inner: client_with_hashes(5, &[], 0),
+                slow_from: 2,
+                delay: Duration::from_secs(1),
+            },
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_starting_height(1);
+        
+        let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(1500)).await;
+            let _ = signal_tx.send(());
+        });
+        
+        let started = time::Instant::now();
+        synchronizer.run_until_signal(async move {
+            let _ = signal_rx.await;
+        }).await.unwrap();
+        
+        // Block 3 is finished before run returns, and block 4 never starts
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert_eq!(synchronizer.get_current_height(), 3);
+        assert_eq!(sink.sends().len(), 2);
+        assert_eq!(synchronizer.cache.lock().await.highest_height(), Some(3));
+        
+        // Shutting down afterwards sends nothing half processed
+        synchronizer.shutdown().await.unwrap();
+        assert_eq!(sink.sends().len(), 2);
+    }
+    
+    #[test]
+    fn test_adaptive_polling_interval() {
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(0, &[], 0),
+            message_pushing_runtime(),
+            Box::new(RecordingSink::default()),
+            6,
+        ).unwrap();
+        synchronizer.set_adaptive_polling(Some(AdaptivePollingConfig {
+            min_interval_ms: 100,
+            max_interval_ms: 4000,
+        }));
+        assert_eq!(synchronizer.polling_interval(), 1000);
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // Catching up: the tip is ahead on every poll, so poll sooner
+            let mut intervals = Vec::new();
+            for tip in [2, 4, 6, 8, 10] {
+                synchronizer.client = Arc::new(client_with_hashes(tip, &[], 0));
+                assert!(matches!(synchronizer.step().await.unwrap(), StepOutcome::Processed { .. }));
+                intervals.push(synchronizer.polling_interval());
+            }
+            assert_eq!(intervals, vec![500, 250, 125, 100, 100]);
+            
+            // Idle at the tip: back off up to the maximum
+            let mut intervals = Vec::new();
+            for _ in 0..7 {
+                assert_eq!(synchronizer.step().await.unwrap(), StepOutcome::Idle);
+                intervals.push(synchronizer.polling_interval());
+            }
+            assert_eq!(intervals, vec![200, 400, 800, 1600, 3200, 4000, 4000]);
+        });
+        
+        // Without adaptive polling the interval is fixed
+        synchronizer.set_adaptive_polling(None);
+        rt.block_on(synchronizer.step()).unwrap();
+        assert_eq!(synchronizer.polling_interval(), 4000);
+    }
+    
+    /// A transform that stores the block height under the `height` state key
+    fn height_storing_runtime() -> WasmRuntime {
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__height" (func $height (result i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\06\00\00\00height")
+                (func (export "process_block") (result i32)
+                    (i32.store (i32.const 64) (i32.const 4))
+                    (i32.store (i32.const 68) (call $height))
+                    (drop (call $set_state (i32.const 0) (i32.const 64)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        WasmRuntime::from_bytes(&wasm_bytes).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_state_snapshot_divergence_is_detected() {
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(3, &[], 0),
+            height_storing_runtime(),
+            Box::new(NullSink::new()),
+            6,
+        ).unwrap();
+        synchronizer.set_verify_state_snapshots(true);
+        synchronizer.set_starting_height(1);
+        
+        // Snapshots taken from the runtime match it
+        synchronizer.step().await.unwrap();
+        assert_eq!(synchronizer.stats().state_divergences, 0);
+        assert!(synchronizer.verify_state_snapshot(3).await);
+        
+        // A runtime state that drifted from the snapshot is reported
+        let mut state = debshrew_support::TransformState::new();
+        state.set(b"height".to_vec(), b"stale".to_vec());
+        synchronizer.runtime.lock().await.set_state(state);
+        assert!(!synchronizer.verify_state_snapshot(3).await);
+        assert_eq!(synchronizer.stats().state_divergences, 1);
+        
+        // As is a block missing from the cache
+        assert!(!synchronizer.verify_state_snapshot(9).await);
+        assert_eq!(synchronizer.stats().state_divergences, 2);
+    }
+    
+    #[tokio::test]
+    async fn test_prev_block_hash_is_provided() {
+        // A transform storing the previous block hash under the `prev` key
+        let wasm_bytes = wat::parse_str(
+            r#"
+            (module
+                (import "env" "__prev_block_hash_status" (func $prev_block_hash_status (param i32) (result i32)))
+                (import "env" "__load" (func $load (param i32)))
+                (import "env" "__set_state" (func $set_state (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\04\00\00\00prev")
+                (func (export "process_block") (result i32)
+                    (drop (call $prev_block_hash_status (i32.const 56)))
+                    (i32.store (i32.const 64) (i32.load (i32.const 56)))
+                    (call $load (i32.const 68))
+                    (drop (call $set_state (i32.const 0) (i32.const 64)))
+                    (i32.const 0)
+                )
+                (func (export "rollback") (result i32)
+                    i32.const 0
+                )
+            )
+            "#,
+        )
+        .unwrap();
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(2, &[], 0),
+            WasmRuntime::from_bytes(&wasm_bytes).unwrap(),
+            Box::new(RecordingSink::default()),
+            6,
+        ).unwrap();
+        
+        // Genesis has no parent; block 1's parent is fetched, block 2's is cached
+        for (height, prev) in [(0, vec![]), (1, vec![0]), (2, vec![1])] {
+            synchronizer.process_block(height).await.unwrap();
+            assert_eq!(synchronizer.runtime.lock().await.get_state().get(b"prev"), Some(&prev));
+        }
+    }
+    
+    /// A sink that accepts every message but has only committed through a set height
+    #[derive(Clone, Default)]
+    struct CommittingSink {
+        committed: Arc<std::sync::Mutex<u32>>,
+    }
+    
+    #[async_trait]
+    impl CdcSink for CommittingSink {
+        async fn send(&self, _messages: Vec<CdcMessage>) -> Result<()> {
+            Ok(())
         }
         
-        // Test with a custom sink implementation
-        {
-            // Create a custom sink that counts messages
-            #[derive(Clone)]
-            struct CountingSink {
-                count: Arc<tokio::sync::Mutex<usize>>,
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        async fn close(&self) -> Result<()> {
+            Ok(())
+        }
+        
+        fn committed_height(&self) -> Option<u32> {
+            Some(*self.committed.lock().unwrap())
+        }
+    }
+    
+    #[test]
+    fn test_checkpoint_follows_committed_height() {
+        use crate::state::load_state;
+        
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let wal_path = dir.path().join("cdc.wal");
+        let sink = CommittingSink::default();
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(4, &[], 0),
+            height_storing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        synchronizer.set_state_path(Some(state_path.clone()));
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        
+        let wal_heights = || -> Vec<u32> {
+            WriteAheadLog::open(&wal_path).unwrap()
+                .entries()
+                .unwrap()
+                .iter()
+                .map(|entry| entry.height)
+                .collect()
+        };
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // Blocks 1 to 3 are sent, but the sink has only committed block 1
+            *sink.committed.lock().unwrap() = 1;
+            for height in 1..=3 {
+                synchronizer.process_block(height).await.unwrap();
             }
             
-            impl CountingSink {
-                fn new() -> Self {
-                    Self {
-                        count: Arc::new(tokio::sync::Mutex::new(0)),
-                    }
-                }
+            let checkpoint = load_state(&state_path).unwrap();
+            assert_eq!(checkpoint.get(b"height"), Some(&1u32.to_le_bytes().to_vec()));
+            assert_eq!(wal_heights(), vec![2, 3]);
+            
+            // A restart resumes from the committed block, not the last one sent
+            let mut restarted = BlockSynchronizer::new(
+                client_with_hashes(4, &[], 0),
+                height_storing_runtime(),
+                Box::new(CommittingSink::default()),
+                6,
+            ).unwrap();
+            restarted.set_state_path(Some(state_path.clone()));
+            restarted.restore_checkpoint().await.unwrap();
+            assert_eq!(restarted.get_current_height(), 1);
+            assert_eq!(
+                restarted.runtime.lock().await.get_state().get(b"height"),
+                Some(&1u32.to_le_bytes().to_vec())
+            );
+            
+            // Once the sink commits further, the checkpoint catches up
+            *sink.committed.lock().unwrap() = 4;
+            synchronizer.process_block(4).await.unwrap();
+            
+            let checkpoint = load_state(&state_path).unwrap();
+            assert_eq!(checkpoint.get(b"height"), Some(&4u32.to_le_bytes().to_vec()));
+            assert!(wal_heights().is_empty());
+        });
+    }
+    
+    #[test]
+    fn test_checkpoint_advances_through_empty_blocks() {
+        use crate::config::Acks;
+        use crate::sink::{KafkaProducerOptions, KafkaSink};
+        use crate::state::load_state;
+        
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let wal_path = dir.path().join("cdc.wal");
+        
+        // A durable sink that never sees a message; creating and flushing an
+        // idle producer does not contact the broker
+        let options = KafkaProducerOptions { acks: Acks::All, ..KafkaProducerOptions::default() };
+        let sink = KafkaSink::new("localhost:9092", "cdc-events", None, 100, 1000, options).unwrap();
+        
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(8, &[], 0),
+            height_storing_runtime(),
+            Box::new(sink),
+            2,
+        ).unwrap();
+        synchronizer.set_state_path(Some(state_path.clone()));
+        synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // Every block is checkpointed, long after block 1 left the cache
+            for height in 1..=8 {
+                synchronizer.process_block(height).await.unwrap();
                 
-                async fn get_count(&self) -> usize {
-                    let count = self.count.lock().await;
-                    *count
-                }
+                assert_eq!(synchronizer.sink.committed_height(), Some(height));
+                let checkpoint = load_state(&state_path).unwrap();
+                assert_eq!(checkpoint.get(b"height"), Some(&height.to_le_bytes().to_vec()));
+                assert!(WriteAheadLog::open(&wal_path).unwrap().entries().unwrap().is_empty());
             }
+        });
+    }
+    
+    #[test]
+    fn test_delivery_orders_checkpoint_and_send() {
+        use crate::state::{load_checkpoint, load_state};
+        
+        let rt = Runtime::new().unwrap();
+        
+        for delivery in [Delivery::AtLeastOnce, Delivery::AtMostOnce] {
+            let dir = tempfile::tempdir().unwrap();
+            let state_path = dir.path().join("state.json");
+            let wal_path = dir.path().join("cdc.wal");
             
-            #[async_trait]
-            impl CdcSink for CountingSink {
-                async fn send(&self, messages: Vec<CdcMessage>) -> Result<()> {
-                    let mut count = self.count.lock().await;
-                    *count += messages.len();
-                    Ok(())
-                }
-                
-                async fn flush(&self) -> Result<()> {
-                    Ok(())
+            let mut synchronizer = BlockSynchronizer::new(
+                client_with_hashes(2, &[], 0),
+                height_storing_runtime(),
+                Box::new(RecordingSink::default()),
+                6,
+            ).unwrap();
+            synchronizer.set_delivery(delivery);
+            synchronizer.set_state_path(Some(state_path.clone()));
+            synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+            rt.block_on(synchronizer.process_block(1)).unwrap();
+            
+            // The process crashes between the checkpoint and the send of block 2
+            synchronizer.sink = Arc::new(Box::new(FailingSink));
+            assert!(rt.block_on(synchronizer.process_block(2)).is_err());
+            drop(synchronizer);
+            
+            let checkpoint = load_state(&state_path).unwrap();
+            let wal_heights: Vec<u32> = WriteAheadLog::open(&wal_path).unwrap()
+                .entries()
+                .unwrap()
+                .iter()
+                .map(|entry| entry.height)
+                .collect();
+            
+            match delivery {
+                // Block 2 is still ahead of the checkpoint and in the WAL, so
+                // it is sent again on restart
+                Delivery::AtLeastOnce => {
+                    assert_eq!(checkpoint.get(b"height"), Some(&1u32.to_le_bytes().to_vec()));
+                    assert_eq!(wal_heights, vec![2]);
                 }
                 
-                async fn close(&self) -> Result<()> {
-                    Ok(())
+                // Block 2 is already checkpointed and nothing is left to
+                // replay, so its messages are lost
+                Delivery::AtMostOnce => {
+                    assert_eq!(checkpoint.get(b"height"), Some(&2u32.to_le_bytes().to_vec()));
+                    assert!(wal_heights.is_empty());
                 }
             }
             
-            let counting_sink = CountingSink::new();
-            let counting_sink_clone = counting_sink.clone();
-            let test_runtime = WasmRuntime::for_testing().unwrap();
-            let synchronizer = BlockSynchronizer::new(client.clone(), test_runtime, Box::new(counting_sink), 6).unwrap();
+            // The guarantee holds across the restart, which resumes from the
+            // persisted checkpoint
+            let sink = RecordingSink::default();
+            let mut synchronizer = BlockSynchronizer::new(
+                client_with_hashes(2, &[], 0),
+                height_storing_runtime(),
+                Box::new(sink.clone()),
+                6,
+            ).unwrap();
+            synchronizer.set_delivery(delivery);
+            synchronizer.set_state_path(Some(state_path.clone()));
+            synchronizer.set_wal(WriteAheadLog::open(&wal_path).unwrap());
+            rt.block_on(synchronizer.catch_up()).unwrap();
             
-            // Verify the sink works
-            rt.block_on(async {
-                // Get the sink from the synchronizer
-                let sink = synchronizer.get_sink();
-                
-                // Send test messages
-                let messages = vec![
-                    create_test_message(),
-                    create_test_message(),
-                    create_test_message(),
-                ];
-                
-                let result = sink.send(messages).await;
-                assert!(result.is_ok());
+            assert_eq!(synchronizer.get_current_height(), 2);
+            assert_eq!(load_checkpoint(&state_path).unwrap().height, Some(2));
+            match delivery {
+                // Block 2 is replayed from the WAL and processed again
+                Delivery::AtLeastOnce => assert_eq!(sink.sends().len(), 2),
                 
-                // Verify the count
-                assert_eq!(counting_sink_clone.get_count().await, 3);
-            });
+                // Block 2 is never sent again
+                Delivery::AtMostOnce => assert!(sink.sends().is_empty()),
+            }
         }
     }
     
+    #[test]
+    fn test_reorg_depth_histogram() {
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(8, &[], 0),
+            message_pushing_runtime(),
+            Box::new(RecordingSink::default()),
+            10,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for height in 1..=8 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+            
+            // Reorgs rolling back 1, 2 and 4 blocks from the tip at 8
+            for (fork, tag) in [(vec![8], 1), (vec![7, 8], 2), (vec![5, 6, 7, 8], 3)] {
+                synchronizer.client = Arc::new(client_with_hashes(8, &fork, tag));
+                synchronizer.handle_reorg(8).await.unwrap();
+            }
+        });
+        
+        let stats = synchronizer.stats();
+        assert_eq!(stats.reorgs, 3);
+        assert_eq!(stats.reorg_depths, [1, 1, 0, 1, 0, 0]);
+        
+        let summary = stats.to_string();
+        assert!(summary.contains("    depth 1: 1\n    depth 2: 1\n    depth 4-6: 1\n"), "{}", summary);
+        
+        // Deep reorgs land in the overflow bucket
+        let mut stats = SyncStats::new();
+        stats.record_reorg(12);
+        stats.record_reorg(13);
+        assert_eq!(stats.reorg_depths, [0, 0, 0, 0, 1, 1]);
+        assert!(stats.to_string().contains("    depth 7-12: 1\n    depth 13+: 1\n"));
+        
+        // The view cache hit ratio is only shown once views are called
+        assert!(!stats.to_string().contains("View cache"));
+        stats.record_view_cache(ViewCacheStats { hits: 3, misses: 1, entries: 1 });
+        assert_eq!(stats.view_cache_hit_ratio(), Some(0.75));
+        assert!(stats.to_string().contains("View cache hit ratio: 75.0% (3 hits, 1 misses)"));
+    }
+    
+    #[test]
+    fn test_stats_summary() {
+        let sink = RecordingSink::default();
+        let mut synchronizer = BlockSynchronizer::new(
+            client_with_hashes(3, &[], 0),
+            message_pushing_runtime(),
+            Box::new(sink.clone()),
+            6,
+        ).unwrap();
+        
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            for height in 1..=3 {
+                synchronizer.process_block(height).await.unwrap();
+                synchronizer.current_height = height;
+            }
+            
+            // Replace block 3
+            synchronizer.client = Arc::new(client_with_hashes(3, &[3], 1));
+            synchronizer.handle_reorg(3).await.unwrap();
+            
+            synchronizer.shutdown().await.unwrap();
+        });
+        
+        let stats = synchronizer.stats();
+        assert_eq!(stats.blocks_processed, 4);
+        assert_eq!(stats.reorgs, 1);
+        assert_eq!(stats.total_messages(), 5);
+        assert_eq!(stats.messages[&("test_table".to_string(), CdcOperation::Create)], 4);
+        assert_eq!(stats.messages[&("test_table".to_string(), CdcOperation::Delete)], 1);
+        
+        let summary = stats.to_string();
+        assert!(summary.contains("Blocks processed: 4"));
+        assert!(summary.contains("Reorgs: 1"));
+        assert!(summary.contains("CDC messages sent: 5"));
+        assert!(summary.contains("test_table create: 4"));
+        assert!(summary.contains("test_table delete: 1"));
+    }
+    
     // Helper function to create a test CDC message
     fn create_test_message() -> CdcMessage {
         CdcMessage {
@@ -545,6 +4547,7 @@ mod tests {
                 block_height: 123,
                 block_hash: "000000000000000000024bead8df69990852c202db0e0097c1a12ea637d7e96d".to_string(),
                 transaction_id: None,
+                event_id: None,
             },
             payload: CdcPayload {
                 operation: CdcOperation::Create,
@@ -0,0 +1,262 @@
+//! Write-ahead log for CDC messages
+//!
+//! This module defines an on-disk write-ahead log (WAL) that records CDC
+//! messages before they are sent to a sink. Entries are removed once the sink
+//! has accepted them, so anything left in the log after a crash is replayed to
+//! the sink on startup.
+
+use crate::error::{Error, Result};
+use debshrew_support::CdcMessage;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// WAL entry
+///
+/// The CDC messages for a single delivery to the sink, tagged with the block
+/// height the delivery advances the checkpoint to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// The block height of the entry
+    pub height: u32,
+    
+    /// The CDC messages of the entry
+    pub messages: Vec<CdcMessage>,
+}
+
+/// Write-ahead log
+///
+/// The log is stored as one JSON-encoded entry per line.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    /// The path of the log file
+    path: PathBuf,
+    
+    /// The log file, opened for appending
+    file: File,
+}
+
+impl WriteAheadLog {
+    /// Open a write-ahead log, creating it if it does not exist
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the log file
+    ///
+    /// # Returns
+    ///
+    /// The write-ahead log
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log file cannot be opened
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = Self::open_file(&path)?;
+        
+        Ok(Self { path, file })
+    }
+    
+    /// Append an entry to the log
+    ///
+    /// The entry is synced to disk before this method returns.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height of the entry
+    /// * `messages` - The CDC messages of the entry
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the entry was written successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written
+    pub fn append(&mut self, height: u32, messages: &[CdcMessage]) -> Result<()> {
+        let entry = WalEntry { height, messages: messages.to_vec() };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        
+        self.file.write_all(line.as_bytes())
+            .and_then(|_| self.file.sync_data())
+            .map_err(|e| Error::File(format!("Failed to write WAL entry: {}", e)))
+    }
+    
+    /// Read all entries in the log
+    ///
+    /// A partially written final entry, left behind by a crash during
+    /// `append`, is skipped with a warning.
+    ///
+    /// # Returns
+    ///
+    /// The entries in the order they were written
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be read or contains a corrupt entry
+    pub fn entries(&self) -> Result<Vec<WalEntry>> {
+        let file = File::open(&self.path)
+            .map_err(|e| Error::File(format!("Failed to open WAL: {}", e)))?;
+        let lines = BufReader::new(file)
+            .lines()
+            .collect::<std::io::Result<Vec<String>>>()
+            .map_err(|e| Error::File(format!("Failed to read WAL: {}", e)))?;
+        
+        let mut entries = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) if index + 1 == lines.len() => {
+                    warn!("Ignoring incomplete WAL entry at end of {}: {}", self.path.display(), e);
+                }
+                Err(e) => {
+                    return Err(Error::File(format!("Corrupt WAL entry on line {}: {}", index + 1, e)));
+                }
+            }
+        }
+        
+        Ok(entries)
+    }
+    
+    /// Check if the log has no entries
+    ///
+    /// # Returns
+    ///
+    /// true if the log has no entries, false otherwise
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be read
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.entries()?.is_empty())
+    }
+    
+    /// Remove entries up to and including a block height
+    ///
+    /// Called once the sink has accepted the messages for those blocks. The
+    /// remaining entries are written to a temporary file which then replaces
+    /// the log, so a crash never leaves a partially truncated log.
+    ///
+    /// # Arguments
+    ///
+    /// * `height` - The block height to truncate through
+    ///
+    /// # Returns
+    ///
+    /// Ok(()) if the log was truncated successfully
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be rewritten
+    pub fn truncate_through(&mut self, height: u32) -> Result<()> {
+        let remaining: Vec<WalEntry> = self.entries()?
+            .into_iter()
+            .filter(|entry| entry.height > height)
+            .collect();
+        
+        let mut contents = String::new();
+        for entry in &remaining {
+            contents.push_str(&serde_json::to_string(entry)?);
+            contents.push('\n');
+        }
+        
+        let temp_path = self.path.with_extension("tmp");
+        let mut temp = File::create(&temp_path)
+            .map_err(|e| Error::File(format!("Failed to create WAL: {}", e)))?;
+        temp.write_all(contents.as_bytes())
+            .and_then(|_| temp.sync_data())
+            .map_err(|e| Error::File(format!("Failed to write WAL: {}", e)))?;
+        fs::rename(&temp_path, &self.path)
+            .map_err(|e| Error::File(format!("Failed to replace WAL: {}", e)))?;
+        
+        // Reopen so appends go to the new file
+        self.file = Self::open_file(&self.path)?;
+        
+        Ok(())
+    }
+    
+    /// Open the log file for appending
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the log file
+    ///
+    /// # Returns
+    ///
+    /// The opened file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened
+    fn open_file(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| Error::File(format!("Failed to open WAL: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use debshrew_support::{CdcHeader, CdcOperation, CdcPayload};
+    use tempfile::tempdir;
+    
+    fn message(height: u32) -> CdcMessage {
+        CdcMessage {
+            header: CdcHeader {
+                source: "test".to_string(),
+                timestamp: Utc::now(),
+                block_height: height,
+                block_hash: "00".to_string(),
+                transaction_id: None,
+                event_id: None,
+            },
+            payload: CdcPayload {
+                operation: CdcOperation::Create,
+                table: "test_table".to_string(),
+                key: format!("key{}", height),
+                before: None,
+                after: Some(serde_json::json!({ "height": height })),
+            },
+        }
+    }
+    
+    #[test]
+    fn test_append_and_truncate() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cdc.wal");
+        
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        assert!(wal.is_empty().unwrap());
+        
+        wal.append(1, &[message(1)]).unwrap();
+        wal.append(2, &[message(2)]).unwrap();
+        wal.truncate_through(1).unwrap();
+        wal.append(3, &[message(3)]).unwrap();
+        
+        // Entries survive reopening
+        let wal = WriteAheadLog::open(&path).unwrap();
+        let heights: Vec<u32> = wal.entries().unwrap().iter().map(|entry| entry.height).collect();
+        assert_eq!(heights, vec![2, 3]);
+    }
+    
+    #[test]
+    fn test_incomplete_final_entry_is_ignored() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cdc.wal");
+        
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(1, &[message(1)]).unwrap();
+        wal.file.write_all(b"{\"height\":2,\"mess").unwrap();
+        
+        let entries = wal.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].height, 1);
+    }
+}
\ No newline at end of file
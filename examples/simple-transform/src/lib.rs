@@ -2,9 +2,10 @@ use debshrew_runtime::{self, DebTransform};
 use debshrew_runtime::{CdcMessage, CdcHeader, CdcOperation, CdcPayload};
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct SimpleTransform {
-    // State fields
+    /// The total amount received across all balance increases
+    total_received: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,6 +48,11 @@ impl DebTransform for SimpleTransform {
                 // Balance changed, generate update message
                 debshrew_runtime::println!("Balance changed, generating update message");
                 
+                if balance.balance > prev_balance.balance {
+                    let received = debshrew_runtime::checked_sub_balance(balance.balance, prev_balance.balance)?;
+                    self.total_received = debshrew_runtime::checked_add_balance(self.total_received, received)?;
+                }
+                
                 let message = CdcMessage {
                     header: CdcHeader {
                         source: "token_protocol".to_string(),
@@ -54,6 +60,7 @@ impl DebTransform for SimpleTransform {
                         block_height: height,
                         block_hash: hex::encode(&hash),
                         transaction_id: None,
+                        event_id: None,
                     },
                     payload: CdcPayload {
                         operation: CdcOperation::Update,
@@ -80,6 +87,7 @@ impl DebTransform for SimpleTransform {
                     block_height: height,
                     block_hash: hex::encode(&hash),
                     transaction_id: None,
+                    event_id: None,
                 },
                 payload: CdcPayload {
                     operation: CdcOperation::Create,
@@ -102,9 +110,9 @@ impl DebTransform for SimpleTransform {
         Ok(())
     }
     
-    // We don't need to implement rollback() as the default implementation
-    // will use the automatically generated inverse operations
+    // We don't implement rollback(): the transform is registered without a
+    // rollback export, so the host inverts the cached messages on a reorg
 }
 
 // Register the transform
-debshrew_runtime::declare_transform!(SimpleTransform);
\ No newline at end of file
+debshrew_runtime::declare_transform_auto_rollback!(SimpleTransform);
\ No newline at end of file